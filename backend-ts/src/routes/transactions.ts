@@ -26,7 +26,7 @@ import {
 } from "../services/transactionBuilder.js";
 import { submitTransaction, confirmTransaction } from "../services/submitter.js";
 import { setRaceMeta, getRaceMeta } from "../services/raceMetaCache.js";
-import { registerRaceFromChain, submitRaceResult } from "../store/memory.js";
+import { registerRaceFromChain, submitRaceResult, getRaceRaw } from "../store/memory.js";
 
 // ---------------------------------------------------------------------------
 // Request / response shapes (matching Python schemas.py exactly)
@@ -44,6 +44,11 @@ interface BuildBody {
   /** Base58 ephemeral session public key  when provided, delegate_session is
    *  bundled into create_race / join_race (one popup covers both). */
   session_key?: string;
+  /** Race creator (player1) wallet, needed to derive the race PDA when the
+   *  race was not created through this server. */
+  creator?: string;
+  /** Fee treasury for claim_prize; only needed while a protocol fee is set. */
+  treasury?: string;
 }
 
 interface SubmitBody {
@@ -75,13 +80,17 @@ function generateRaceId(
 function resolveRaceMeta(
   raceId: string,
   body: BuildBody,
-): { tokenMint: string; entryFeeSol: number } | null {
+): { tokenMint: string; entryFeeSol: number; creator: string } | null {
   const cached = getRaceMeta(raceId);
-  if (cached) return { tokenMint: cached.token_mint, entryFeeSol: cached.entry_fee_sol };
+  if (cached) {
+    return { tokenMint: cached.token_mint, entryFeeSol: cached.entry_fee_sol, creator: cached.creator };
+  }
 
-  if (body.token_mint && body.entry_fee_sol) {
-    setRaceMeta(raceId, { token_mint: body.token_mint, entry_fee_sol: body.entry_fee_sol });
-    return { tokenMint: body.token_mint, entryFeeSol: body.entry_fee_sol };
+  // Race PDAs are namespaced by creator, so it has to be known as well
+  const creator = body.creator ?? getRaceRaw(raceId)?.player1_wallet;
+  if (body.token_mint && body.entry_fee_sol && creator) {
+    setRaceMeta(raceId, { token_mint: body.token_mint, entry_fee_sol: body.entry_fee_sol, creator });
+    return { tokenMint: body.token_mint, entryFeeSol: body.entry_fee_sol, creator };
   }
 
   return null;
@@ -137,7 +146,7 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
           const tokenMintPk = new PublicKey(token_mint);
           const lamports = BigInt(Math.round(entry_fee_sol * 1_000_000_000));
 
-          const [racePda] = deriveRacePda(raceId, tokenMintPk, lamports);
+          const [racePda] = deriveRacePda(walletPubkey, raceId, tokenMintPk, lamports);
           const ix = buildCreateRaceIx(racePda, walletPubkey, raceId, tokenMintPk, lamports);
 
           const instructions = [ix];
@@ -176,7 +185,7 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
           const tx = await buildTransaction(instructions, walletPubkey, recentBlockhash);
           const txBase64 = serializeTransaction(tx).toString("base64");
 
-          setRaceMeta(raceId, { token_mint, entry_fee_sol });
+          setRaceMeta(raceId, { token_mint, entry_fee_sol, creator: wallet_address });
 
           app.log.info(`[TXBUILD] create_race raceId=${raceId} pda=${racePda.toBase58()}`);
 
@@ -198,14 +207,14 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
 
           const meta = resolveRaceMeta(race_id, body);
           if (!meta) {
-            return reply.status(404).send({ detail: "Race not found. Provide token_mint and entry_fee_sol or create the race first." });
+            return reply.status(404).send({ detail: "Race not found. Provide token_mint, entry_fee_sol and creator or create the race first." });
           }
 
           const tokenMintPk = new PublicKey(meta.tokenMint);
           const lamports = BigInt(Math.round(meta.entryFeeSol * 1_000_000_000));
-          const [racePda] = deriveRacePda(race_id, tokenMintPk, lamports);
+          const [racePda] = deriveRacePda(new PublicKey(meta.creator), race_id, tokenMintPk, lamports);
 
-          const ix = buildJoinRaceIx(racePda, walletPubkey);
+          const ix = buildJoinRaceIx(racePda, walletPubkey, tokenMintPk);
           const instructions = [ix];
 
           // Bundle delegate_session if a session key is provided
@@ -265,12 +274,12 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
 
           const meta = resolveRaceMeta(race_id, body);
           if (!meta) {
-            return reply.status(404).send({ detail: "Race not found. Provide token_mint and entry_fee_sol or create the race first." });
+            return reply.status(404).send({ detail: "Race not found. Provide token_mint, entry_fee_sol and creator or create the race first." });
           }
 
           const tokenMintPk = new PublicKey(meta.tokenMint);
           const lamports = BigInt(Math.round(meta.entryFeeSol * 1_000_000_000));
-          const [racePda] = deriveRacePda(race_id, tokenMintPk, lamports);
+          const [racePda] = deriveRacePda(new PublicKey(meta.creator), race_id, tokenMintPk, lamports);
 
           const inputHashBytes = Buffer.from(input_hash, "hex");
           if (inputHashBytes.length !== 32) {
@@ -327,14 +336,15 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
 
           const meta = resolveRaceMeta(race_id, body);
           if (!meta) {
-            return reply.status(404).send({ detail: "Race not found. Provide token_mint and entry_fee_sol or create the race first." });
+            return reply.status(404).send({ detail: "Race not found. Provide token_mint, entry_fee_sol and creator or create the race first." });
           }
 
           const tokenMintPk = new PublicKey(meta.tokenMint);
           const lamports = BigInt(Math.round(meta.entryFeeSol * 1_000_000_000));
-          const [racePda] = deriveRacePda(race_id, tokenMintPk, lamports);
+          const [racePda] = deriveRacePda(new PublicKey(meta.creator), race_id, tokenMintPk, lamports);
 
-          const ix = buildSettleRaceIx(racePda);
+          // The requesting wallet signs as the race's operator authority
+          const ix = buildSettleRaceIx(racePda, walletPubkey);
 
           const recentBlockhash = await getRecentBlockhash();
           const tx = await buildTransaction([ix], walletPubkey, recentBlockhash);
@@ -360,12 +370,12 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
 
           const meta = resolveRaceMeta(race_id, body);
           if (!meta) {
-            return reply.status(404).send({ detail: "Race not found. Provide token_mint and entry_fee_sol or create the race first." });
+            return reply.status(404).send({ detail: "Race not found. Provide token_mint, entry_fee_sol and creator or create the race first." });
           }
 
           const tokenMintPk = new PublicKey(meta.tokenMint);
           const lamports = BigInt(Math.round(meta.entryFeeSol * 1_000_000_000));
-          const [racePda] = deriveRacePda(race_id, tokenMintPk, lamports);
+          const [racePda] = deriveRacePda(new PublicKey(meta.creator), race_id, tokenMintPk, lamports);
 
           // Determine authority: session key (silent) or winner wallet (popup)
           let authority = walletPubkey;
@@ -379,7 +389,10 @@ export async function transactionRoutes(app: FastifyInstance): Promise<void> {
             }
           }
 
-          const ix = buildClaimPrizeIx(racePda, authority, walletPubkey, sessionPda);
+          const treasury = body.treasury ? new PublicKey(body.treasury) : null;
+          const ix = buildClaimPrizeIx(
+            racePda, authority, walletPubkey, sessionPda, tokenMintPk, treasury, new PublicKey(meta.creator),
+          );
 
           const feePayer = authority;
           const recentBlockhash = await getRecentBlockhash();
@@ -17,7 +17,12 @@ export function getProgramId(): PublicKey {
   return _programId;
 }
 
+/**
+ * Derive the Race PDA (seeds v2, namespaced by creator).
+ * Seeds: ["race", creator (player1), raceId, tokenMint, entryFee u64 LE]
+ */
 export function deriveRacePda(
+  creator: PublicKey,
   raceId: string,
   tokenMint: PublicKey,
   entryFeeLamports: bigint,
@@ -27,6 +32,7 @@ export function deriveRacePda(
 
   const seeds = [
     Buffer.from("race"),
+    creator.toBuffer(),
     Buffer.from(raceId, "utf-8"),
     tokenMint.toBuffer(),
     entryFeeBytes,
@@ -35,6 +41,49 @@ export function deriveRacePda(
   return PublicKey.findProgramAddressSync(seeds, getProgramId());
 }
 
+/** Global Config PDA. Seeds: ["config"] */
+export function deriveConfigPda(): PublicKey {
+  return PublicKey.findProgramAddressSync([Buffer.from("config")], getProgramId())[0];
+}
+
+/** ClaimDelayTiers PDA (settlement claim delays; may not exist). Seeds: ["claim_delay_tiers"] */
+export function deriveClaimDelayTiersPda(): PublicKey {
+  return PublicKey.findProgramAddressSync([Buffer.from("claim_delay_tiers")], getProgramId())[0];
+}
+
+/**
+ * Derive the protocol-wide GlobalStats PDA, which every instruction that
+ * creates, joins, settles or claims a race requires.
+ * Seeds: ["global_stats"]
+ */
+export function deriveGlobalStatsPda(): PublicKey {
+  return PublicKey.findProgramAddressSync([Buffer.from("global_stats")], getProgramId())[0];
+}
+
+/**
+ * Derive a token's TokenConfig PDA (fee/treasury override; may not exist).
+ * Seeds: ["token_config", tokenMint]
+ */
+export function deriveTokenConfigPda(tokenMint: PublicKey): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("token_config"), tokenMint.toBuffer()],
+    getProgramId(),
+  )[0];
+}
+
+/**
+ * Derive a generated map's MapConfig PDA (registered coin cap; may not exist).
+ * Seeds: ["map_config", mapSeed (u64 LE)]. Unseeded races use mapSeed 0.
+ */
+export function deriveMapConfigPda(mapSeed: bigint): PublicKey {
+  const seedBuf = Buffer.alloc(8);
+  seedBuf.writeBigUInt64LE(mapSeed);
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("map_config"), seedBuf],
+    getProgramId(),
+  )[0];
+}
+
 /** SHA-256 of the raceId string, matches the on-chain race_id_hash() helper */
 export function raceIdHash(raceId: string): Buffer {
   return crypto.createHash("sha256").update(raceId, "utf-8").digest();
@@ -1,5 +1,14 @@
 import { PublicKey, TransactionInstruction, SystemProgram } from "@solana/web3.js";
-import { getProgramId, deriveSessionPda, raceIdHash } from "./pda.js";
+import {
+  getProgramId,
+  deriveSessionPda,
+  raceIdHash,
+  deriveConfigPda,
+  deriveClaimDelayTiersPda,
+  deriveTokenConfigPda,
+  deriveMapConfigPda,
+  deriveGlobalStatsPda,
+} from "./pda.js";
 
 // Anchor sighash discriminators (first 8 bytes of SHA-256("global:<instruction>"))
 const DISC = {
@@ -41,6 +50,11 @@ export function buildCreateRaceIx(
       { pubkey: racePda, isSigner: false, isWritable: true },
       { pubkey: player1, isSigner: true,  isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: deriveConfigPda(), isSigner: false, isWritable: false },
+      { pubkey: deriveTokenConfigPda(tokenMint), isSigner: false, isWritable: false },
+      // credits (optional), then global_stats
+      { pubkey: getProgramId(), isSigner: false, isWritable: false },
+      { pubkey: deriveGlobalStatsPda(), isSigner: false, isWritable: true },
     ],
     data,
   });
@@ -49,6 +63,7 @@ export function buildCreateRaceIx(
 export function buildJoinRaceIx(
   racePda: PublicKey,
   player2: PublicKey,
+  tokenMint: PublicKey,
 ): TransactionInstruction {
   return new TransactionInstruction({
     programId: getProgramId(),
@@ -56,6 +71,11 @@ export function buildJoinRaceIx(
       { pubkey: racePda, isSigner: false, isWritable: true },
       { pubkey: player2, isSigner: true,  isWritable: true },
       { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: deriveTokenConfigPda(tokenMint), isSigner: false, isWritable: false },
+      // fee_payer and credits (optional), then global_stats
+      { pubkey: getProgramId(), isSigner: false, isWritable: false },
+      { pubkey: getProgramId(), isSigner: false, isWritable: false },
+      { pubkey: deriveGlobalStatsPda(), isSigner: false, isWritable: true },
     ],
     data: DISC.join_race,
   });
@@ -128,7 +148,7 @@ export function buildSubmitResultIx(
 
   const data = Buffer.concat([DISC.submit_result, ftBuf, ccBuf, inputHash]);
 
-  // Anchor account order: race, authority, session (optional), player_wallet
+  // Anchor account order: race, authority, session (optional), player_wallet, map_config
   const keys: { pubkey: PublicKey; isSigner: boolean; isWritable: boolean }[] = [
     { pubkey: racePda,      isSigner: false, isWritable: true  },
     { pubkey: authority,    isSigner: true,  isWritable: false },
@@ -139,6 +159,8 @@ export function buildSubmitResultIx(
     keys.push({ pubkey: sessionPda, isSigner: false, isWritable: false });
   }
   keys.push({ pubkey: playerWallet, isSigner: false, isWritable: false });
+  // submit_result is only for unseeded races, whose map_seed is 0
+  keys.push({ pubkey: deriveMapConfigPda(0n), isSigner: false, isWritable: false });
 
   return new TransactionInstruction({
     programId: getProgramId(),
@@ -147,10 +169,20 @@ export function buildSubmitResultIx(
   });
 }
 
-export function buildSettleRaceIx(racePda: PublicKey): TransactionInstruction {
+export function buildSettleRaceIx(racePda: PublicKey, authority: PublicKey): TransactionInstruction {
+  // Anchor account order: race, authority, claim_delay_tiers, claim_index
+  // (optional), global_stats, then optional accounts (consolation_vault,
+  // loser), omitted here.
+  // authority must be the operator the race was created under
   return new TransactionInstruction({
     programId: getProgramId(),
-    keys: [{ pubkey: racePda, isSigner: false, isWritable: true }],
+    keys: [
+      { pubkey: racePda,                    isSigner: false, isWritable: true  },
+      { pubkey: authority,                  isSigner: true,  isWritable: false },
+      { pubkey: deriveClaimDelayTiersPda(), isSigner: false, isWritable: false },
+      { pubkey: getProgramId(),             isSigner: false, isWritable: false },
+      { pubkey: deriveGlobalStatsPda(),     isSigner: false, isWritable: true  },
+    ],
     data: DISC.settle_race,
   });
 }
@@ -159,24 +191,38 @@ export function buildSettleRaceIx(racePda: PublicKey): TransactionInstruction {
  * Build claim_prize instruction.
  * winner_wallet is always the real wallet (funds destination).
  * authority can be the session key, with sessionPda provided.
+ * treasury receives the protocol fee; it may be null while the fee is zero.
+ * host is the race creator (player1), who receives the host cut.
  */
 export function buildClaimPrizeIx(
   racePda: PublicKey,
   authority: PublicKey,    // session key OR winner wallet
   winnerWallet: PublicKey, // always the real wallet (receives lamports)
   sessionPda: PublicKey | null,
+  tokenMint: PublicKey,
+  treasury: PublicKey | null,
+  host: PublicKey,
 ): TransactionInstruction {
-  // Anchor account order: race, authority, session (optional), winner_wallet
+  // Anchor account order: race, authority, session (optional), winner_wallet,
+  // config, token_config, treasury (optional), claim_index (optional),
+  // global_stats, host (optional)
   const keys: { pubkey: PublicKey; isSigner: boolean; isWritable: boolean }[] = [
     { pubkey: racePda,      isSigner: false, isWritable: true  },
     { pubkey: authority,    isSigner: true,  isWritable: false },
   ];
 
-  // Session PDA is optional, must come BEFORE winner_wallet to match Anchor struct
-  if (sessionPda) {
-    keys.push({ pubkey: sessionPda, isSigner: false, isWritable: false });
-  }
+  // Session PDA is optional, must come BEFORE winner_wallet to match Anchor struct.
+  // An omitted optional account is passed as the program id so later accounts keep their slots
+  keys.push({ pubkey: sessionPda ?? getProgramId(), isSigner: false, isWritable: false });
   keys.push({ pubkey: winnerWallet, isSigner: false, isWritable: true  });
+  keys.push({ pubkey: deriveConfigPda(),              isSigner: false, isWritable: false });
+  keys.push({ pubkey: deriveTokenConfigPda(tokenMint), isSigner: false, isWritable: false });
+  keys.push(treasury
+    ? { pubkey: treasury,       isSigner: false, isWritable: true  }
+    : { pubkey: getProgramId(), isSigner: false, isWritable: false });
+  keys.push({ pubkey: getProgramId(),         isSigner: false, isWritable: false });
+  keys.push({ pubkey: deriveGlobalStatsPda(), isSigner: false, isWritable: true  });
+  keys.push({ pubkey: host,           isSigner: false, isWritable: true  });
 
   return new TransactionInstruction({
     programId: getProgramId(),
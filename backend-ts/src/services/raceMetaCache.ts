@@ -3,6 +3,8 @@
 interface RaceMeta {
   token_mint: string;
   entry_fee_sol: number;
+  /** player1 wallet; part of the race PDA seeds */
+  creator: string;
 }
 
 const cache = new Map<string, RaceMeta>();
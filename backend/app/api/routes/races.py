@@ -126,6 +126,7 @@ def build_settle_race_transaction(race: Race, payer_wallet: str = None) -> dict:
     
     race_pda_str, bump = derive_race_pda_simple(
         program_id,
+        race.player1_wallet,
         race.race_id,
         race.token_mint,
         entry_fee_lamports
@@ -197,6 +197,7 @@ async def build_transaction(
             
             race_pda_str, bump = derive_race_pda_simple(
                 program_id,
+                request.wallet_address,
                 race_id,
                 request.token_mint,
                 entry_fee_lamports
@@ -251,6 +252,7 @@ async def build_transaction(
             
             race_pda_str, bump = derive_race_pda_simple(
                 program_id,
+                race.player1_wallet,
                 race.race_id,
                 race.token_mint,
                 entry_fee_lamports
@@ -306,6 +308,7 @@ async def build_transaction(
             
             race_pda_str, bump = derive_race_pda_simple(
                 program_id,
+                race.player1_wallet,
                 race.race_id,
                 race.token_mint,
                 entry_fee_lamports
@@ -388,6 +391,7 @@ async def build_transaction(
             
             race_pda_str, bump = derive_race_pda_simple(
                 program_id,
+                race.player1_wallet,
                 race.race_id,
                 race.token_mint,
                 entry_fee_lamports
@@ -602,6 +606,7 @@ async def settle_race(
         
         race_pda_str, bump = derive_race_pda_simple(
             program_id,
+            race.player1_wallet,
             race.race_id,
             race.token_mint,
             entry_fee_lamports
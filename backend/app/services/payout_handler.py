@@ -169,6 +169,7 @@ class PayoutHandler:
             
             race_pda_str, bump = derive_race_pda_simple(
                 program_id,
+                race.player1_wallet,
                 race.race_id,
                 race.token_mint,
                 entry_fee_lamports
@@ -15,6 +15,7 @@ logger = logging.getLogger(__name__)
 
 def derive_race_pda(
     program_id: Pubkey,
+    creator: Pubkey,
     race_id: str,
     token_mint: Pubkey,
     entry_fee_sol: int
@@ -22,8 +23,9 @@ def derive_race_pda(
     """
     Derive the race PDA address using the same seeds as the Solana program.
     
-    Seeds used (matching lib.rs):
+    Seeds used (matching lib.rs, race seeds v2):
     - b"race" (static seed)
+    - creator.as_ref() (player1 wallet; gives each creator its own race_id namespace)
     - race_id.as_bytes() (race ID string)
     - token_mint.as_ref() (token mint Pubkey)
     - entry_fee_sol.to_le_bytes() (entry fee in lamports, little-endian)
@@ -32,6 +34,7 @@ def derive_race_pda(
     
     Args:
         program_id: The Solana program ID
+        creator: The race creator (player1) wallet
         race_id: The deterministic race ID string
         token_mint: The token mint address (Pubkey)
         entry_fee_sol: Entry fee in lamports (u64)
@@ -45,9 +48,10 @@ def derive_race_pda(
     entry_fee_bytes = entry_fee_sol.to_bytes(8, byteorder='little')
     
     # Build seeds array (matching lib.rs exactly)
-    # seeds = [b"race", race_id.as_bytes(), token_mint.as_ref(), &entry_fee_sol.to_le_bytes()]
+    # seeds = [b"race", player1.key().as_ref(), race_id.as_bytes(), token_mint.as_ref(), &entry_fee_sol.to_le_bytes()]
     seeds = [
         b"race",
+        bytes(creator),
         race_id_bytes,
         token_mint_bytes,
         entry_fee_bytes,
@@ -66,6 +70,7 @@ def derive_race_pda(
 
 def derive_race_pda_simple(
     program_id_str: str,
+    creator_str: str,
     race_id: str,
     token_mint_str: str,
     entry_fee_sol: int
@@ -78,6 +83,7 @@ def derive_race_pda_simple(
     
     Args:
         program_id_str: The Solana program ID as a string
+        creator_str: The race creator (player1) wallet as a string
         race_id: The deterministic race ID string
         token_mint_str: The token mint address as a string
         entry_fee_sol: Entry fee in lamports (u64)
@@ -88,13 +94,14 @@ def derive_race_pda_simple(
     Raises:
         ValueError: If inputs are invalid
     """
-    logger.info(f"[derive_race_pda_simple] program_id={program_id_str}, race_id={race_id}, "
+    logger.info(f"[derive_race_pda_simple] program_id={program_id_str}, creator={creator_str}, race_id={race_id}, "
                 f"token_mint={token_mint_str}, entry_fee={entry_fee_sol}")
     
     program_id = Pubkey.from_string(program_id_str)
+    creator = Pubkey.from_string(creator_str)
     token_mint = Pubkey.from_string(token_mint_str)
     
-    pda, bump = derive_race_pda(program_id, race_id, token_mint, entry_fee_sol)
+    pda, bump = derive_race_pda(program_id, creator, race_id, token_mint, entry_fee_sol)
     
     pda_str = str(pda)
     logger.info(f"[derive_race_pda_simple] Derived PDA: {pda_str}, bump: {bump}")
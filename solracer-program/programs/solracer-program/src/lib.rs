@@ -1,7 +1,116 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
+use anchor_spl::token::{self, spl_token::native_mint, Token, TokenAccount};
+use std::cmp::Ordering;
 
 declare_id!("2g9tQ4g6Qki95UBTGN4NcQ4ggpz5XRa6eQJ8MCuznr8S");
 
+/// Max bytes of `race_id`; account space is reserved for this length.
+pub const MAX_RACE_ID_LEN: usize = 50;
+
+/// Layout version written to `Race::version`. Bump it whenever `Race`
+/// grows, so clients can tell which accounts need `realloc_race`, and list
+/// the new fields in `RACE_FIELDS_ADDED`.
+pub const RACE_VERSION: u8 = 11;
+
+/// Slots a map seed commitment is pushed back by when its slot has left
+/// the `SlotHashes` window before anyone revealed it.
+pub const MAP_SEED_RECOMMIT_SLOTS: u64 = 32;
+
+/// Largest lobby `create_lobby` accepts; lobby account space is sized for it.
+pub const MAX_LOBBY_PLAYERS: usize = 8;
+
+/// How long a lobby has to reach `min_players_to_start`; after this an
+/// unstarted lobby below its minimum can be refunded with `refund_lobby`.
+pub const LOBBY_JOIN_DEADLINE_SECS: i64 = 3600;
+
+/// How long after settlement the authority may `reassign_prize` away from
+/// an ineligible winner, as long as the prize is still unclaimed.
+pub const REASSIGN_WINDOW_SECS: i64 = 86_400;
+
+/// Longest `set_vesting` may stretch a prize's release, and the most
+/// installments it may be split into.
+pub const MAX_VESTING_SECS: i64 = 30 * 86_400;
+pub const MAX_VESTING_SLICES: u16 = 30;
+
+/// Most escrow-size tiers a `ClaimDelayTiers` account holds.
+pub const MAX_CLAIM_DELAY_TIERS: usize = 4;
+
+/// Max bytes of replay data `store_replay` keeps on-chain; longer replays stay off-chain.
+pub const MAX_REPLAY_LEN: usize = 400;
+
+/// Max races a single `claim_batch` will process, to stay within compute.
+pub const MAX_CLAIM_BATCH: usize = 10;
+
+/// Accounts per race in `claim_batch`: `[race, host]`, the host being the
+/// race's player1, who takes the host cut.
+pub const CLAIM_BATCH_GROUP: usize = 2;
+
+/// Unclaimed wins a player's `ClaimIndex` can hold; settling a race for a
+/// player whose index is full leaves it unindexed.
+pub const CLAIM_INDEX_CAPACITY: usize = 32;
+
+/// Max races a single `create_race_batch` will create. Each race costs a
+/// PDA derivation and a `create_account` CPI, and every race account has to
+/// fit in the transaction alongside the creator.
+pub const MAX_CREATE_BATCH: usize = 5;
+
+/// Compute units a transaction gets per instruction when the client does
+/// not request a budget.
+pub const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+/// Rough per-race compute cost of one `claim_batch` step (deserialize,
+/// token config PDA check, up to three lamport moves, write back).
+pub const CLAIM_BATCH_CU_PER_RACE: u64 = 25_000;
+
+/// Accounts per race in `create_race_batch`: `[race, token_config]`.
+pub const CREATE_BATCH_GROUP: usize = 2;
+
+/// Rough per-race compute cost of one `create_race_batch` step; the two
+/// PDA searches and the `create_account` CPI dominate.
+pub const CREATE_BATCH_CU_PER_RACE: u64 = 55_000;
+
+/// Max races a single `emergency_refund_batch` will refund; each takes
+/// `EMERGENCY_REFUND_GROUP` accounts.
+pub const MAX_EMERGENCY_REFUND_BATCH: usize = 5;
+
+/// Accounts per race in `emergency_refund_batch`:
+/// `[race, player1, player2_fee_payer, rent_payer]`.
+pub const EMERGENCY_REFUND_GROUP: usize = 4;
+
+/// Rough per-race compute cost of one `emergency_refund_batch` step
+/// (deserialize, up to four lamport moves, close).
+pub const EMERGENCY_REFUND_CU_PER_RACE: u64 = 20_000;
+
+/// Rating every `PlayerProfile` starts at.
+pub const DEFAULT_RATING: i32 = 1200;
+
+/// K-factor `initialize_config` sets; the authority can change it later.
+pub const DEFAULT_ELO_K_FACTOR: u16 = 32;
+
+/// Minimum seconds between two `update_chat_root` calls by the same author.
+pub const CHAT_UPDATE_MIN_INTERVAL_SECS: i64 = 2;
+
+/// Lamports each `update_chat_root` call pays to the treasury, to make
+/// spamming the chat log cost something.
+pub const CHAT_UPDATE_DEPOSIT: u64 = 5_000;
+
+/// Metaplex Token Metadata program, owner of the NFT metadata accounts
+/// `join_gated_race` reads.
+pub const METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+/// Most destinations one `claim_prize_split` can pay.
+pub const MAX_CLAIM_SPLITS: usize = 5;
+
+/// Basis points that a claim split must add up to (100%).
+pub const SPLIT_TOTAL_BPS: u16 = 10_000;
+
+/// Most times a lobby's late-join window can be extended by anti-sniping.
+pub const MAX_ANTISNIPE_EXTENSIONS: u8 = 3;
+
+/// Entries kept in each token's `ResultFeed` before the oldest is evicted.
+pub const RESULT_FEED_CAPACITY: usize = 16;
+
 #[program]
 pub mod solracer_program {
     use super::*;
@@ -12,34 +121,144 @@ pub mod solracer_program {
         token_mint: Pubkey,
         entry_fee_sol: u64,
     ) -> Result<()> {
-        let race = &mut ctx.accounts.race;
-        let clock = Clock::get()?;
+        create_race_with_config(
+            ctx,
+            race_id,
+            token_mint,
+            entry_fee_sol,
+            RaceConfig::default(),
+        )
+    }
 
-        race.race_id = race_id.clone();
-        race.token_mint = token_mint;
-        race.entry_fee_sol = entry_fee_sol;
-        race.player1 = ctx.accounts.player1.key();
-        race.player2 = None;
-        race.status = RaceStatus::Waiting;
-        race.player1_result = None;
-        race.player2_result = None;
-        race.winner = None;
-        race.escrow_amount = entry_fee_sol;
-        race.created_at = clock.unix_timestamp;
-        race.bump = ctx.bumps.race;
+    /// Same as `create_race` but with explicit per-race rules.
+    pub fn create_race_with_config(
+        ctx: Context<CreateRace>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+    ) -> Result<()> {
+        create_race_with_ref(
+            ctx,
+            race_id,
+            token_mint,
+            entry_fee_sol,
+            config,
+            [0u8; 32],
+        )
+    }
 
-        anchor_lang::solana_program::program::invoke(
-            &anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.player1.key(),
-                &race.key(),
-                entry_fee_sol,
-            ),
-            &[
-                ctx.accounts.player1.to_account_info(),
-                race.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
+    /// Same as `create_race_with_config`, also storing `external_ref`, an
+    /// opaque id from the integrator's backend echoed in `RaceCreated` and
+    /// `RaceFinalState`.
+    pub fn create_race_with_ref(
+        ctx: Context<CreateRace>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+        external_ref: [u8; 32],
+    ) -> Result<()> {
+        create_race_with_seed_commit(
+            ctx,
+            race_id,
+            token_mint,
+            entry_fee_sol,
+            config,
+            external_ref,
+            0,
+        )
+    }
+
+    /// Same as `create_race_with_ref`, but with `map_seed_delay_slots` > 0
+    /// the map seed isn't picked by the creator: the race commits to the
+    /// blockhash `map_seed_delay_slots` slots from now, and
+    /// `reveal_map_seed` derives `config.map_seed` from it once that slot
+    /// has passed. Any `config.map_seed` passed in is ignored.
+    ///
+    /// Every `create_race*` variant returns a borsh-encoded `CreatedRace`
+    /// (the race address and its stored bump), so clients need not derive
+    /// the PDA themselves.
+    pub fn create_race_with_seed_commit(
+        ctx: Context<CreateRace>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+        external_ref: [u8; 32],
+        map_seed_delay_slots: u64,
+    ) -> Result<()> {
+        create_race_with_min_coins(
+            ctx,
+            race_id,
+            token_mint,
+            entry_fee_sol,
+            config,
+            external_ref,
+            map_seed_delay_slots,
+            0,
+        )
+    }
+
+    /// Same as `create_race_with_seed_commit`, also fixing the race's
+    /// `min_coins_to_win`. It can't be changed afterwards, so whoever
+    /// joins does so under the rule the race was created with.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_race_with_min_coins(
+        ctx: Context<CreateRace>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+        external_ref: [u8; 32],
+        map_seed_delay_slots: u64,
+        min_coins_to_win: u64,
+    ) -> Result<()> {
+        check_token_not_paused(&ctx.accounts.token_config)?;
+
+        let race = &mut ctx.accounts.race;
+        open_race(
+            race,
+            &ctx.accounts.config,
+            race_id.clone(),
+            token_mint,
+            entry_fee_sol,
+            config,
+            ctx.accounts.player1.key(),
+            ctx.bumps.race,
         )?;
+        race.external_ref = external_ref;
+        race.min_coins_to_win = min_coins_to_win;
+        if map_seed_delay_slots > 0 {
+            race.config.map_seed = 0;
+            race.map_seed_slot = Clock::get()?
+                .slot
+                .checked_add(map_seed_delay_slots)
+                .ok_or(SolracerError::MathOverflow)?;
+        }
+
+        match ctx.accounts.credits.as_mut() {
+            Some(credits) => spend_credits(credits, &race.to_account_info(), entry_fee_sol)?,
+            None => transfer_lamports(
+                &ctx.accounts.player1.to_account_info(),
+                &race.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                entry_fee_sol,
+            )?,
+        }
+        ctx.accounts.global_stats.record_race(entry_fee_sol)?;
+
+        emit!(RaceCreated {
+            race: race.key(),
+            race_id: race_id.clone(),
+            player1: race.player1,
+            external_ref,
+        });
+        let created = CreatedRace {
+            race: race.key(),
+            bump: race.bump,
+        };
+        set_return_data(&created.try_to_vec()?);
 
         msg!(
             "Race created: {} by player1: {} with entry fee: {} lamports",
@@ -51,393 +270,9931 @@ pub mod solracer_program {
         Ok(())
     }
 
-    pub fn join_race(ctx: Context<JoinRace>) -> Result<()> {
+    /// Single-player race against the house. Player1 escrows their fee and
+    /// the config authority, seated as player2, escrows the counter-stake
+    /// in the same transaction. Only player1 races: finishing strictly
+    /// under `target_time_ms` wins the pot, anything else goes to the house.
+    pub fn create_house_race(
+        ctx: Context<CreateHouseRace>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        target_time_ms: u64,
+    ) -> Result<()> {
+        check_race_id(&race_id)?;
+        require!(is_valid_token_mint(&token_mint), SolracerError::InvalidTokenMint);
+        check_token_not_paused(&ctx.accounts.token_config)?;
+        check_entry_fee(&ctx.accounts.config, entry_fee_sol)?;
+
         let race = &mut ctx.accounts.race;
+        let house = ctx.accounts.authority.key();
 
-        require!(
-            race.status == RaceStatus::Waiting,
-            SolracerError::InvalidRaceStatus
+        let mut house_race = Race::new_waiting(
+            race_id.clone(),
+            token_mint,
+            entry_fee_sol,
+            RaceConfig::default(),
+            ctx.accounts.player1.key(),
+            now()?,
+            ctx.bumps.race,
         );
+        house_race.player2 = Some(house);
+        house_race.player2_fee_payer = house;
+        house_race.status = RaceStatus::Active;
+        house_race.started_at = house_race.created_at;
+        house_race.credit_escrow(entry_fee_sol)?;
+        house_race.player2_paid = entry_fee_sol;
+        house_race.authority = house;
+        house_race.house_target_ms = Some(target_time_ms);
+        race.set_inner(house_race);
 
-        require!(race.player2.is_none(), SolracerError::Player2AlreadySet);
-
-        race.player2 = Some(ctx.accounts.player2.key());
-        race.status = RaceStatus::Active;
-        race.escrow_amount += race.entry_fee_sol;
+        ctx.accounts.global_stats.record_race(entry_fee_sol)?;
+        ctx.accounts.global_stats.record_volume(entry_fee_sol)?;
 
-        anchor_lang::solana_program::program::invoke(
-            &anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.player2.key(),
-                &race.key(),
-                race.entry_fee_sol,
-            ),
-            &[
-                ctx.accounts.player2.to_account_info(),
-                race.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
+        transfer_lamports(
+            &ctx.accounts.player1.to_account_info(),
+            &race.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            entry_fee_sol,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.authority.to_account_info(),
+            &race.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            entry_fee_sol,
         )?;
 
         msg!(
-            "Player2 {} joined race: {}",
-            ctx.accounts.player2.key(),
-            race.race_id
+            "House race created: {} for player1: {} with target {} ms and entry fee: {} lamports",
+            race_id,
+            ctx.accounts.player1.key(),
+            target_time_ms,
+            entry_fee_sol
         );
 
         Ok(())
     }
 
-    /// Create a session key PDA for a player in a specific race.
-    /// Called in the same tx as create_race/join_race so only one wallet popup.
-    pub fn delegate_session(
-        ctx: Context<DelegateSession>,
-        race_id_hash: [u8; 32],
-        session_key: Pubkey,
-        duration_secs: i64,
+    /// Create and join in one transaction for a match arranged off-chain:
+    /// both players sign, both fees are escrowed and the race starts
+    /// `Active`, leaving no window for a third party to take the join.
+    pub fn create_and_join(
+        ctx: Context<CreateAndJoin>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
     ) -> Result<()> {
-        let session = &mut ctx.accounts.session;
-        session.player_wallet = ctx.accounts.player.key();
-        session.session_key = session_key;
-        session.race_id_hash = race_id_hash;
-        session.expires_at = Clock::get()?.unix_timestamp + duration_secs;
-        session.bump = ctx.bumps.session;
+        check_token_not_paused(&ctx.accounts.token_config)?;
+        // Gated races check player2's NFT, which only `join_gated_race` takes
+        require!(config.nft_collection.is_none(), SolracerError::NftGateFailed);
 
-        msg!(
-            "Session key delegated for player {} in race",
-            ctx.accounts.player.key()
-        );
+        let race = &mut ctx.accounts.race;
+        open_race(
+            race,
+            &ctx.accounts.config,
+            race_id.clone(),
+            token_mint,
+            entry_fee_sol,
+            config,
+            ctx.accounts.player1.key(),
+            ctx.bumps.race,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.player1.to_account_info(),
+            &race.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            entry_fee_sol,
+        )?;
+
+        let player2 = ctx.accounts.player2.to_account_info();
+        seat_player2(
+            race,
+            &player2,
+            &player2,
+            &ctx.accounts.system_program.to_account_info(),
+            None,
+        )?;
+        ctx.accounts.global_stats.record_race(entry_fee_sol)?;
+        ctx.accounts.global_stats.record_volume(race.player2_paid)?;
+
+        emit!(RaceCreated {
+            race: race.key(),
+            race_id,
+            player1: race.player1,
+            external_ref: [0u8; 32],
+        });
         Ok(())
     }
 
-    /// Submit race result accepts either the player wallet directly or a valid session key
-    pub fn submit_result(
-        ctx: Context<SubmitResult>,
-        finish_time_ms: u64,
-        coins_collected: u64,
-        input_hash: [u8; 32],
-    ) -> Result<()> {
-        let race = &mut ctx.accounts.race;
+    pub fn join_race(ctx: Context<JoinRace>) -> Result<()> {
+        check_token_not_paused(&ctx.accounts.token_config)?;
+        let before = ctx.accounts.race.status.clone();
 
+        // Gated races are joined with `join_gated_race`, which takes the NFT accounts
         require!(
-            race.status == RaceStatus::Active,
-            SolracerError::InvalidRaceStatus
+            ctx.accounts.race.config.nft_collection.is_none(),
+            SolracerError::NftGateFailed
         );
 
-        // Resolve the actual player: session key or direct wallet
-        let actual_player = if let Some(session) = &ctx.accounts.session {
-            require!(
-                Clock::get()?.unix_timestamp < session.expires_at,
-                SolracerError::SessionExpired
-            );
-            require!(
-                session.session_key == ctx.accounts.authority.key(),
-                SolracerError::InvalidSessionKey
-            );
-            session.player_wallet
-        } else {
-            ctx.accounts.authority.key()
+        // A relayer may pay the fee on player2's behalf; refunds follow the payer
+        let payer = match &ctx.accounts.fee_payer {
+            Some(fee_payer) => fee_payer.to_account_info(),
+            None => ctx.accounts.player2.to_account_info(),
         };
+        let prefunded = ctx.accounts.race.player2_prefunded;
+        seat_player2(
+            &mut ctx.accounts.race,
+            &ctx.accounts.player2.to_account_info(),
+            &payer,
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.credits.as_mut(),
+        )?;
 
-        let is_player1 = actual_player == race.player1;
-        let is_player2 = race
-            .player2
-            .map(|p2| actual_player == p2)
-            .unwrap_or(false);
+        // A prefunded stake was escrowed by the operator, not by this join
+        if !prefunded {
+            ctx.accounts.global_stats.record_volume(ctx.accounts.race.player2_paid)?;
+        }
+        assert_invariants(&ctx.accounts.race, &before)
+    }
 
-        require!(is_player1 || is_player2, SolracerError::PlayerNotInRace);
+    /// `join_race` for a race gated on `RaceConfig::nft_collection`:
+    /// player2 presents a token account holding one NFT of that verified
+    /// collection, with the NFT's Metaplex metadata account.
+    pub fn join_gated_race(ctx: Context<JoinGatedRace>) -> Result<()> {
+        check_token_not_paused(&ctx.accounts.token_config)?;
+        let before = ctx.accounts.race.status.clone();
+        let collection = ctx
+            .accounts
+            .race
+            .config
+            .nft_collection
+            .ok_or(SolracerError::NftGateFailed)?;
+        check_nft_gate(
+            &collection,
+            &ctx.accounts.player2.key(),
+            &ctx.accounts.nft_token_account,
+            &ctx.accounts.nft_metadata.to_account_info(),
+        )?;
 
-        let result = RaceResult {
-            finish_time_ms,
-            coins_collected,
-            input_hash,
-        };
+        let prefunded = ctx.accounts.race.player2_prefunded;
+        let player2 = ctx.accounts.player2.to_account_info();
+        seat_player2(
+            &mut ctx.accounts.race,
+            &player2,
+            &player2,
+            &ctx.accounts.system_program.to_account_info(),
+            None,
+        )?;
 
-        if is_player1 {
-            require!(
-                race.player1_result.is_none(),
-                SolracerError::ResultAlreadySubmitted
-            );
-            race.player1_result = Some(result);
-        } else {
-            require!(
-                race.player2_result.is_none(),
-                SolracerError::ResultAlreadySubmitted
-            );
-            race.player2_result = Some(result);
+        if !prefunded {
+            ctx.accounts.global_stats.record_volume(ctx.accounts.race.player2_paid)?;
         }
+        assert_invariants(&ctx.accounts.race, &before)
+    }
 
-        msg!(
-            "Result submitted for player {} in race: {}",
-            actual_player,
-            race.race_id
-        );
+    /// One-time setup of the global config; the signer becomes the authority.
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.authority = ctx.accounts.authority.key();
+        config.elo_k_factor = DEFAULT_ELO_K_FACTOR;
+        config.antisnipe_window_secs = 0;
+        config.antisnipe_extension_secs = 0;
+        config.crank_bounty_lamports = 0;
+        config.max_rematches = 0;
+        config.min_entry_fee = 0;
+        config.max_entry_fee = 0;
+        config.fee_bps = 0;
+        config.treasury = ctx.accounts.authority.key();
+        config.server_authority = Pubkey::default();
+        config.prev_server_authority = Pubkey::default();
+        config.prev_server_expires_at = 0;
+        config.host_cut_bps = 0;
+        config.no_contest_on_no_show = false;
+        config.bump = ctx.bumps.config;
 
+        msg!("Config initialized with authority {}", config.authority);
         Ok(())
     }
 
-    pub fn settle_race(ctx: Context<SettleRace>) -> Result<()> {
-        let race = &mut ctx.accounts.race;
+    /// Change the K-factor used by `rate_race`.
+    pub fn set_elo_k_factor(ctx: Context<UpdateConfig>, elo_k_factor: u16) -> Result<()> {
+        ctx.accounts.config.elo_k_factor = elo_k_factor;
 
-        require!(
-            race.status == RaceStatus::Active,
-            SolracerError::InvalidRaceStatus
-        );
+        msg!("ELO K-factor set to {}", elo_k_factor);
+        Ok(())
+    }
+
+    /// Cap consecutive rematches between the same pair (0 is unlimited).
+    pub fn set_max_rematches(ctx: Context<UpdateConfig>, max_rematches: u16) -> Result<()> {
+        ctx.accounts.config.max_rematches = max_rematches;
+
+        msg!("Max rematches set to {}", max_rematches);
+        Ok(())
+    }
 
+    /// Bound the entry fee of new races and rematches (`max_entry_fee` 0
+    /// is unlimited). Races created earlier keep their fee but can't
+    /// rematch outside the bounds.
+    pub fn set_entry_fee_bounds(
+        ctx: Context<UpdateConfig>,
+        min_entry_fee: u64,
+        max_entry_fee: u64,
+    ) -> Result<()> {
         require!(
-            race.player1_result.is_some() && race.player2_result.is_some(),
-            SolracerError::ResultsNotComplete
+            max_entry_fee == 0 || min_entry_fee <= max_entry_fee,
+            SolracerError::InvalidEntryFeeBounds
         );
+        let config = &mut ctx.accounts.config;
+        config.min_entry_fee = min_entry_fee;
+        config.max_entry_fee = max_entry_fee;
+
+        msg!("Entry fee bounds set to {}..{}", min_entry_fee, max_entry_fee);
+        Ok(())
+    }
 
-        let player1_result = race.player1_result.as_ref().unwrap();
-        let player2_result = race.player2_result.as_ref().unwrap();
+    /// Enable permissionless `crank_settle` with this bounty per settled
+    /// race, or disable it with 0.
+    pub fn set_crank_bounty(ctx: Context<UpdateConfig>, bounty_lamports: u64) -> Result<()> {
+        ctx.accounts.config.crank_bounty_lamports = bounty_lamports;
 
-        let winner = if player1_result.finish_time_ms < player2_result.finish_time_ms {
-            race.player1
-        } else if player2_result.finish_time_ms < player1_result.finish_time_ms {
-            race.player2.unwrap()
+        msg!("Crank bounty set to {} lamports", bounty_lamports);
+        Ok(())
+    }
+
+    /// Replace the game server key that attests results. The old key keeps
+    /// working for `grace_secs` so submissions it already signed still
+    /// land; 0 cuts it off at once (e.g. when it leaked).
+    pub fn rotate_server_authority(
+        ctx: Context<UpdateConfig>,
+        new_server_authority: Pubkey,
+        grace_secs: i64,
+    ) -> Result<()> {
+        require!(grace_secs >= 0, SolracerError::InvalidGracePeriod);
+        let now = now()?;
+        let config = &mut ctx.accounts.config;
+        config.prev_server_authority = config.server_authority;
+        config.prev_server_expires_at = if grace_secs > 0 {
+            now.saturating_add(grace_secs)
         } else {
-            if player1_result.coins_collected >= player2_result.coins_collected {
-                race.player1
-            } else {
-                race.player2.unwrap()
-            }
+            0
         };
+        config.server_authority = new_server_authority;
 
-        race.winner = Some(winner);
-        race.status = RaceStatus::Settled;
+        msg!(
+            "Server authority rotated to {} ({}s grace for {})",
+            new_server_authority,
+            grace_secs,
+            config.prev_server_authority
+        );
+        Ok(())
+    }
 
-        msg!("Race {} settled. Winner: {}", race.race_id, winner);
+    /// Set the protocol fee `claim_prize` takes out of the prize and the
+    /// treasury it is paid to, for tokens without a `TokenConfig`.
+    pub fn set_fee(ctx: Context<UpdateConfig>, fee_bps: u16, treasury: Pubkey) -> Result<()> {
+        check_fee_split(fee_bps, ctx.accounts.config.host_cut_bps)?;
+        let config = &mut ctx.accounts.config;
+        config.fee_bps = fee_bps;
+        config.treasury = treasury;
 
+        msg!("Protocol fee set to {} bps paid to {}", fee_bps, treasury);
         Ok(())
     }
 
-    /// Winner claims the prize accepts either the winner wallet directly
-    /// or a valid session key funds always go to race.winner
-    pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
-        let race = &mut ctx.accounts.race;
+    /// Set the share of each prize `claim_prize` pays to the race's
+    /// creator for hosting it. Fails with `FeesExceedPrize` if it and the
+    /// global fee would take more than the whole prize; a higher token fee
+    /// shrinks the cut at claim time instead (see `claim_split`).
+    pub fn set_host_cut(ctx: Context<UpdateConfig>, host_cut_bps: u16) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        check_fee_split(config.fee_bps, host_cut_bps)?;
+        config.host_cut_bps = host_cut_bps;
 
-        require!(
-            race.status == RaceStatus::Settled,
-            SolracerError::InvalidRaceStatus
+        msg!("Host cut set to {} bps", host_cut_bps);
+        Ok(())
+    }
+
+    /// Let `declare_no_contest` refund races where neither player
+    /// submitted, instead of leaving them unsettled.
+    pub fn set_no_contest_on_no_show(ctx: Context<UpdateConfig>, enabled: bool) -> Result<()> {
+        ctx.accounts.config.no_contest_on_no_show = enabled;
+
+        msg!("No-contest on no-show {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Create the `TokenConfig` for `token_mint`, overriding the global
+    /// fee and treasury for races on that token.
+    pub fn init_token_config(
+        ctx: Context<InitTokenConfig>,
+        token_mint: Pubkey,
+        fee_bps: u16,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        check_fee_split(fee_bps, ctx.accounts.config.host_cut_bps)?;
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.token_mint = token_mint;
+        token_config.fee_bps = fee_bps;
+        token_config.treasury = treasury;
+        token_config.paused = false;
+        token_config.bump = ctx.bumps.token_config;
+
+        msg!(
+            "Token config created for {}: {} bps paid to {}",
+            token_mint,
+            fee_bps,
+            treasury
         );
+        Ok(())
+    }
 
-        // Resolve the actual player: session key or direct wallet
-        let actual_player = if let Some(session) = &ctx.accounts.session {
-            require!(
-                Clock::get()?.unix_timestamp < session.expires_at,
-                SolracerError::SessionExpired
-            );
-            require!(
-                session.session_key == ctx.accounts.authority.key(),
-                SolracerError::InvalidSessionKey
-            );
-            session.player_wallet
-        } else {
-            ctx.accounts.authority.key()
-        };
+    /// Change an existing `TokenConfig`'s fee and treasury.
+    pub fn update_token_config(
+        ctx: Context<UpdateTokenConfig>,
+        fee_bps: u16,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        check_fee_split(fee_bps, ctx.accounts.config.host_cut_bps)?;
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.fee_bps = fee_bps;
+        token_config.treasury = treasury;
 
-        require!(
-            race.winner == Some(actual_player),
-            SolracerError::NotWinner
+        msg!(
+            "Token config for {} set to {} bps paid to {}",
+            token_config.token_mint,
+            fee_bps,
+            treasury
         );
+        Ok(())
+    }
 
-        let prize_amount = race.escrow_amount;
+    /// Register the true coin count of the generated map `map_seed`, so
+    /// results on it can't claim more than `max_coins` coins.
+    pub fn register_map(ctx: Context<RegisterMap>, map_seed: u64, max_coins: u64) -> Result<()> {
+        require!(map_seed != 0, SolracerError::MapSeedMismatch);
+        let map_config = &mut ctx.accounts.map_config;
+        map_config.map_seed = map_seed;
+        map_config.max_coins = max_coins;
+        map_config.bump = ctx.bumps.map_config;
 
-        // Funds go to winner_wallet (the real wallet), not the session key
-        **race.to_account_info().try_borrow_mut_lamports()? -= prize_amount;
-        **ctx
-            .accounts
-            .winner_wallet
-            .to_account_info()
-            .try_borrow_mut_lamports()? += prize_amount;
+        msg!("Map {} registered with {} coins", map_seed, max_coins);
+        Ok(())
+    }
 
-        race.escrow_amount = 0;
+    /// Pause or resume a single token: while paused, `create_race` and
+    /// `join_race` on it fail with `TokenPaused`. Races already under way
+    /// still settle and claim.
+    pub fn set_token_paused(ctx: Context<UpdateTokenConfig>, paused: bool) -> Result<()> {
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.paused = paused;
 
         msg!(
-            "Prize of {} lamports claimed by winner {} for race: {}",
-            prize_amount,
-            actual_player,
-            race.race_id
+            "Token {} {}",
+            token_config.token_mint,
+            if paused { "paused" } else { "resumed" }
         );
+        Ok(())
+    }
 
+    /// Create the `GlobalStats` counters. Only races handled after this,
+    /// with the account passed, are counted.
+    pub fn init_global_stats(ctx: Context<InitGlobalStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.global_stats;
+        stats.total_races = 0;
+        stats.total_settled = 0;
+        stats.total_volume_lamports = 0;
+        stats.total_fees_collected = 0;
+        stats.bump = ctx.bumps.global_stats;
+
+        msg!("Global stats created");
         Ok(())
     }
-}
 
-// Accounts
+    /// Create the `BountyPool` that funds `crank_settle` bounties.
+    pub fn init_bounty_pool(ctx: Context<InitBountyPool>) -> Result<()> {
+        ctx.accounts.bounty_pool.bump = ctx.bumps.bounty_pool;
 
-#[account]
-pub struct Race {
-    pub race_id: String,
-    pub token_mint: Pubkey,
-    pub entry_fee_sol: u64,
-    pub player1: Pubkey,
-    pub player2: Option<Pubkey>,
-    pub status: RaceStatus,
-    pub player1_result: Option<RaceResult>,
-    pub player2_result: Option<RaceResult>,
-    pub winner: Option<Pubkey>,
+        msg!("Bounty pool created");
+        Ok(())
+    }
+
+    /// Top up the `BountyPool` with `amount` lamports from the signer.
+    pub fn fund_bounty_pool(ctx: Context<FundBountyPool>, amount: u64) -> Result<()> {
+        transfer_lamports(
+            &ctx.accounts.funder.to_account_info(),
+            &ctx.accounts.bounty_pool.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        msg!("Bounty pool funded with {} lamports", amount);
+        Ok(())
+    }
+
+    /// Create the `ConsolationVault` that pays losers a per-coin reward at
+    /// `settle_race`. It pays nothing until `set_consolation` and funding.
+    pub fn init_consolation_vault(ctx: Context<InitConsolationVault>) -> Result<()> {
+        let vault = &mut ctx.accounts.consolation_vault;
+        vault.consolation_per_coin = 0;
+        vault.max_per_race = 0;
+        vault.bump = ctx.bumps.consolation_vault;
+
+        msg!("Consolation vault created");
+        Ok(())
+    }
+
+    /// Set the loser's reward per coin collected and its cap per race;
+    /// a `consolation_per_coin` of 0 turns consolations off.
+    pub fn set_consolation(
+        ctx: Context<SetConsolation>,
+        consolation_per_coin: u64,
+        max_per_race: u64,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.consolation_vault;
+        vault.consolation_per_coin = consolation_per_coin;
+        vault.max_per_race = max_per_race;
+
+        msg!(
+            "Consolation set to {} lamports per coin, at most {} per race",
+            consolation_per_coin,
+            max_per_race
+        );
+        Ok(())
+    }
+
+    /// Top up the `ConsolationVault` with `amount` lamports from the signer.
+    pub fn fund_consolation_vault(ctx: Context<FundConsolationVault>, amount: u64) -> Result<()> {
+        transfer_lamports(
+            &ctx.accounts.funder.to_account_info(),
+            &ctx.accounts.consolation_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        msg!("Consolation vault funded with {} lamports", amount);
+        Ok(())
+    }
+
+    /// Create the `ClaimDelayTiers` account. Until then, and while it holds
+    /// no delays, settled prizes can be claimed at once.
+    pub fn init_claim_delay_tiers(ctx: Context<InitClaimDelayTiers>) -> Result<()> {
+        let tiers = &mut ctx.accounts.claim_delay_tiers;
+        tiers.base_delay_secs = 0;
+        tiers.tiers = Vec::new();
+        tiers.bump = ctx.bumps.claim_delay_tiers;
+
+        msg!("Claim delay tiers created");
+        Ok(())
+    }
+
+    /// Set the review window between settlement and claim: every pot waits
+    /// `base_delay_secs`, and a pot of at least a tier's `min_escrow` waits
+    /// that tier's `delay_secs` instead when longer (see
+    /// `ClaimDelayTiers::delay_for`). Applies to races settled from now on.
+    pub fn set_claim_delay_tiers(
+        ctx: Context<SetClaimDelayTiers>,
+        base_delay_secs: i64,
+        tiers: Vec<ClaimDelayTier>,
+    ) -> Result<()> {
+        require!(
+            base_delay_secs >= 0
+                && tiers.len() <= MAX_CLAIM_DELAY_TIERS
+                && tiers.iter().all(|tier| tier.delay_secs >= 0),
+            SolracerError::InvalidClaimDelayTiers
+        );
+
+        let account = &mut ctx.accounts.claim_delay_tiers;
+        account.base_delay_secs = base_delay_secs;
+        account.tiers = tiers;
+
+        msg!(
+            "Claim delay set to {}s base with {} tiers",
+            base_delay_secs,
+            account.tiers.len()
+        );
+        Ok(())
+    }
+
+    /// Configure lobby anti-sniping: a join within `window_secs` of the
+    /// late-join window closing extends it by `extension_secs`, at most
+    /// `MAX_ANTISNIPE_EXTENSIONS` times per lobby. Zero disables it.
+    pub fn set_antisnipe(
+        ctx: Context<UpdateConfig>,
+        window_secs: i64,
+        extension_secs: i64,
+    ) -> Result<()> {
+        require!(
+            window_secs >= 0 && extension_secs >= 0,
+            SolracerError::InvalidAntisnipe
+        );
+        let config = &mut ctx.accounts.config;
+        config.antisnipe_window_secs = window_secs;
+        config.antisnipe_extension_secs = extension_secs;
+
+        msg!(
+            "Anti-snipe window set to {}s with {}s extensions",
+            window_secs,
+            extension_secs
+        );
+        Ok(())
+    }
+
+    /// Create the signer's rating profile at `DEFAULT_RATING`.
+    pub fn init_profile(ctx: Context<InitProfile>) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+        profile.player = ctx.accounts.player.key();
+        profile.rating = DEFAULT_RATING;
+        profile.rated_races = 0;
+        profile.bump = ctx.bumps.profile;
+
+        msg!("Profile created for {}", profile.player);
+        Ok(())
+    }
+
+    /// Operator covers the entry fee for one or both players of a waiting
+    /// race (promo "free entry"). Player2's fee goes straight into escrow so
+    /// they join without paying; player1, who already paid at create, is
+    /// reimbursed. The prize pays out to the winner as usual.
+    pub fn authority_prefund(
+        ctx: Context<AuthorityPrefund>,
+        cover_player1: bool,
+        cover_player2: bool,
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(cover_player1 || cover_player2, SolracerError::NothingToPrefund);
+        require!(
+            !(cover_player1 && race.player1_prefunded),
+            SolracerError::AlreadyPrefunded
+        );
+        require!(
+            !(cover_player2 && race.player2_prefunded),
+            SolracerError::AlreadyPrefunded
+        );
+
+        let mut amount: u64 = 0;
+
+        if cover_player1 {
+            let fee = race.player1_paid;
+            transfer_lamports(
+                &ctx.accounts.authority.to_account_info(),
+                &ctx.accounts.player1.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                fee,
+            )?;
+            race.player1_prefunded = true;
+            amount = amount.checked_add(fee).ok_or(SolracerError::MathOverflow)?;
+        }
+
+        if cover_player2 {
+            let fee = race.player2_fee();
+            transfer_lamports(
+                &ctx.accounts.authority.to_account_info(),
+                &race.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                fee,
+            )?;
+            race.player2_prefunded = true;
+            race.player2_fee_payer = ctx.accounts.authority.key();
+            race.player2_paid = fee;
+            race.credit_escrow(fee)?;
+            amount = amount.checked_add(fee).ok_or(SolracerError::MathOverflow)?;
+        }
+
+        race.operator_funded = race
+            .operator_funded
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+
+        emit!(RacePrefunded {
+            race: race.key(),
+            authority: ctx.accounts.authority.key(),
+            player1_covered: cover_player1,
+            player2_covered: cover_player2,
+            amount,
+        });
+
+        msg!(
+            "Operator prefunded {} lamports for race: {}",
+            amount,
+            race.race_id
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Create a session key PDA for a player in a specific race.
+    /// Called in the same tx as create_race/join_race so only one wallet popup.
+    pub fn delegate_session(
+        ctx: Context<DelegateSession>,
+        race_id_hash: [u8; 32],
+        session_key: Pubkey,
+        duration_secs: i64,
+    ) -> Result<()> {
+        let session = &mut ctx.accounts.session;
+        session.player_wallet = ctx.accounts.player.key();
+        session.session_key = session_key;
+        session.race_id_hash = race_id_hash;
+        session.expires_at = now()? + duration_secs;
+        session.bump = ctx.bumps.session;
+
+        msg!(
+            "Session key delegated for player {} in race",
+            ctx.accounts.player.key()
+        );
+        Ok(())
+    }
+
+    /// Submit race result accepts either the player wallet directly or a valid session key
+    pub fn submit_result(
+        ctx: Context<SubmitResult>,
+        finish_time_ms: u64,
+        coins_collected: u64,
+        input_hash: [u8; 32],
+    ) -> Result<()> {
+        // Seeded races must echo the seed through `submit_result_on_map`
+        require!(
+            ctx.accounts.race.config.map_seed == 0,
+            SolracerError::MapSeedMismatch
+        );
+        require!(
+            !ctx.accounts.race.config.server_attested,
+            SolracerError::AttestationRequired
+        );
+        record_result(ctx.accounts, finish_time_ms, coins_collected, input_hash)
+    }
+
+    /// `submit_result` for a race created with a `map_seed`: the client
+    /// echoes the seed of the track it actually raced, so results from
+    /// different generated maps are never compared.
+    pub fn submit_result_on_map(
+        ctx: Context<SubmitResult>,
+        finish_time_ms: u64,
+        coins_collected: u64,
+        input_hash: [u8; 32],
+        map_seed: u64,
+    ) -> Result<()> {
+        require!(
+            map_seed == ctx.accounts.race.config.map_seed,
+            SolracerError::MapSeedMismatch
+        );
+        require!(
+            !ctx.accounts.race.config.server_attested,
+            SolracerError::AttestationRequired
+        );
+        record_result(ctx.accounts, finish_time_ms, coins_collected, input_hash)
+    }
+
+    /// `submit_result_on_map` co-signed by the game server, vouching for
+    /// the result. `server` must be `Config.server_authority`, or the key
+    /// it replaced while that key's grace period lasts. Pass a `map_seed`
+    /// of 0 for unseeded races.
+    pub fn submit_result_attested(
+        ctx: Context<SubmitResultAttested>,
+        finish_time_ms: u64,
+        coins_collected: u64,
+        input_hash: [u8; 32],
+        map_seed: u64,
+    ) -> Result<()> {
+        require!(
+            map_seed == ctx.accounts.submit.race.config.map_seed,
+            SolracerError::MapSeedMismatch
+        );
+        require!(
+            ctx.accounts
+                .config
+                .accepts_server_key(&ctx.accounts.server.key(), now()?),
+            SolracerError::UnknownServerAuthority
+        );
+        record_result(&mut ctx.accounts.submit, finish_time_ms, coins_collected, input_hash)
+    }
+
+    /// Permissionless: once the slot a race committed to has passed, set
+    /// `config.map_seed` from that slot's blockhash (see
+    /// `map_seed_from_blockhash`), or from the next slot that produced a
+    /// block if it was skipped. Fails with `MapSeedNotReady` before then.
+    /// If the slot has already left the `SlotHashes` window (512 slots),
+    /// the commitment moves `MAP_SEED_RECOMMIT_SLOTS` ahead instead and
+    /// must be revealed again.
+    pub fn reveal_map_seed(ctx: Context<RevealMapSeed>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        require!(
+            race.map_seed_slot != 0 && !race.map_seed_revealed,
+            SolracerError::NoMapSeedCommitment
+        );
+        let slot = Clock::get()?.slot;
+        require!(slot > race.map_seed_slot, SolracerError::MapSeedNotReady);
+
+        let slot_hashes = ctx.accounts.slot_hashes.try_borrow_data()?;
+        match slot_hash_at_or_after(&slot_hashes, race.map_seed_slot) {
+            SlotHashLookup::Found(blockhash) => {
+                race.config.map_seed = map_seed_from_blockhash(&race.key(), &blockhash);
+                race.map_seed_revealed = true;
+
+                msg!(
+                    "Map seed {} revealed for race {} from slot {}",
+                    race.config.map_seed,
+                    race.race_id,
+                    race.map_seed_slot
+                );
+            }
+            SlotHashLookup::Pending => return err!(SolracerError::MapSeedNotReady),
+            SlotHashLookup::Expired => {
+                race.map_seed_slot = slot
+                    .checked_add(MAP_SEED_RECOMMIT_SLOTS)
+                    .ok_or(SolracerError::MathOverflow)?;
+
+                msg!(
+                    "Map seed commitment of race {} expired; moved to slot {}",
+                    race.race_id,
+                    race.map_seed_slot
+                );
+            }
+        }
+        assert_invariants(race, &before)
+    }
+
+    /// Decide the race on both results. Once the race's submission
+    /// deadline has passed, a lone result wins by forfeit instead (see
+    /// `Race::forfeit_winner`). Signed by the operator the race was created
+    /// under (`Race::authority`); `crank_settle` stays open to anyone.
+    ///
+    /// When the winner's `ClaimIndex` is passed, the race is added to it.
+    /// When the `ConsolationVault` and the loser's wallet are passed, the
+    /// loser gets a consolation for their coins out of the vault (see
+    /// `consolation_amount`), never out of the prize; an empty vault pays
+    /// nothing and the race still settles.
+    pub fn settle_race(ctx: Context<SettleRace>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let Some(winner) = settle_on_results(race, &ctx.accounts.claim_delay_tiers)? else {
+            return assert_invariants(race, &before);
+        };
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+            require_keys_eq!(claim_index.player, winner, SolracerError::ClaimIndexMismatch);
+            if !claim_index.add(race.key()) {
+                msg!("Claim index of {} is full; race {} not indexed", winner, race.race_id);
+            }
+        }
+
+        if let (Some(vault), Some(loser_wallet)) =
+            (ctx.accounts.consolation_vault.as_ref(), ctx.accounts.loser.as_ref())
+        {
+            let (loser, loser_result) = if winner == race.player1 {
+                (race.player2.unwrap(), &race.player2_result)
+            } else {
+                (race.player1, &race.player1_result)
+            };
+            require_keys_eq!(loser_wallet.key(), loser, SolracerError::LoserMismatch);
+
+            let vault_info = vault.to_account_info();
+            let available = vault_info
+                .lamports()
+                .saturating_sub(Rent::get()?.minimum_balance(vault_info.data_len()));
+            let coins = loser_result.as_ref().map_or(0, |result| result.coins_collected);
+            let amount = consolation_amount(coins, vault, available);
+            if amount > 0 {
+                pay_from_escrow(&vault_info, &loser_wallet.to_account_info(), amount)?;
+            }
+
+            emit!(ConsolationPaid {
+                race: race.key(),
+                race_id: race.race_id.clone(),
+                loser,
+                amount,
+            });
+        }
+        assert_invariants(race, &before)
+    }
+
+    /// Permissionless settlement for a race with both results in, so it
+    /// doesn't sit `Active` waiting for someone to call `settle_race`. The
+    /// same checks apply (a flagged race goes `Disputed` and pays nothing);
+    /// on settlement the cranker earns `Config.crank_bounty_lamports` out of
+    /// the `BountyPool`, so the prize is untouched. A pool short of the
+    /// bounty pays what it holds above rent, and an empty one pays nothing
+    /// but the race still settles. Fails with `CrankDisabled` while the
+    /// bounty is 0.
+    pub fn crank_settle(ctx: Context<CrankSettle>) -> Result<()> {
+        let bounty = ctx.accounts.config.crank_bounty_lamports;
+        require!(bounty > 0, SolracerError::CrankDisabled);
+
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        if settle_on_results(race, &ctx.accounts.claim_delay_tiers)?.is_none() {
+            return assert_invariants(race, &before);
+        }
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        let pool = ctx.accounts.bounty_pool.to_account_info();
+        let available = pool
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(pool.data_len()));
+        let paid = bounty.min(available);
+        if paid > 0 {
+            pay_from_escrow(&pool, &ctx.accounts.cranker.to_account_info(), paid)?;
+        }
+
+        emit!(CrankBountyPaid {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            cranker: ctx.accounts.cranker.key(),
+            amount: paid,
+        });
+
+        msg!(
+            "Crank bounty of {} lamports paid to {} for race: {}",
+            paid,
+            ctx.accounts.cranker.key(),
+            race.race_id
+        );
+        assert_invariants(race, &before)
+    }
+
+    /// Opt in to (escrowing the next entry fee) or out of (refunding it) an
+    /// automatic rematch. Opting in is only possible before settlement.
+    pub fn set_auto_rematch(ctx: Context<SetAutoRematch>, enabled: bool) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let player = ctx.accounts.player.key();
+
+        require!(race.rematch.is_none(), SolracerError::RematchAlreadyCreated);
+
+        let is_player1 = player == race.player1;
+        require!(
+            is_player1 || race.player2 == Some(player),
+            SolracerError::PlayerNotInRace
+        );
+
+        let opted_in = if is_player1 {
+            race.player1_auto_rematch
+        } else {
+            race.player2_auto_rematch
+        };
+        if opted_in == enabled {
+            return assert_invariants(race, &before);
+        }
+        let fee = if is_player1 {
+            race.entry_fee_sol
+        } else {
+            race.player2_fee()
+        };
+
+        if enabled {
+            require!(
+                matches!(race.status, RaceStatus::Waiting | RaceStatus::Active),
+                SolracerError::InvalidRaceStatus
+            );
+            require!(
+                !rematch_limit_reached(race.rematch_count, ctx.accounts.config.max_rematches),
+                SolracerError::RematchLimitReached
+            );
+            check_entry_fee(&ctx.accounts.config, fee)?;
+            transfer_lamports(
+                &ctx.accounts.player.to_account_info(),
+                &race.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                fee,
+            )?;
+        } else {
+            pay_from_escrow(&race.to_account_info(), &ctx.accounts.player.to_account_info(), fee)?;
+        }
+
+        if is_player1 {
+            race.player1_auto_rematch = enabled;
+        } else {
+            race.player2_auto_rematch = enabled;
+        }
+
+        msg!(
+            "Auto-rematch {} for player {} in race: {}",
+            if enabled { "enabled" } else { "disabled" },
+            player,
+            race.race_id
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// `settle_race`, then, if both players opted in via `set_auto_rematch`,
+    /// create the rematch race (`rematch_race_id`, same mint, fee and
+    /// config) and move both held fees into it as an already `Active` race.
+    /// `payer` covers the new account's rent. Without both opt-ins this is
+    /// a plain settlement.
+    pub fn settle_race_with_rematch(ctx: Context<SettleRaceWithRematch>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        if settle_on_results(race, &ctx.accounts.claim_delay_tiers)?.is_none() {
+            return assert_invariants(race, &before);
+        }
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        if !(race.player1_auto_rematch && race.player2_auto_rematch) {
+            return assert_invariants(race, &before);
+        }
+
+        // The cap may have been lowered after both opted in; fees stay refundable
+        if rematch_limit_reached(race.rematch_count, ctx.accounts.config.max_rematches) {
+            msg!("Rematch limit reached for race {}", race.race_id);
+            return assert_invariants(race, &before);
+        }
+
+        // Likewise if the fee bounds moved past this race's fee
+        if check_entry_fee(&ctx.accounts.config, race.entry_fee_sol).is_err()
+            || check_entry_fee(&ctx.accounts.config, race.player2_fee()).is_err()
+        {
+            msg!("Entry fee of race {} is out of bounds for a rematch", race.race_id);
+            return assert_invariants(race, &before);
+        }
+
+        let Some(rematch_id) = rematch_race_id(&race.race_id) else {
+            msg!("Race id {} is too long to derive a rematch", race.race_id);
+            return assert_invariants(race, &before);
+        };
+
+        // A rematch of a pre-validation race moves it onto the native mint
+        let token_mint = race.denomination();
+        let (pda, bump) = race_pda(
+            &race.player1,
+            &rematch_id,
+            &token_mint,
+            race.entry_fee_sol,
+        );
+        let rematch_info = ctx.accounts.rematch_race.to_account_info();
+        require_keys_eq!(rematch_info.key(), pda, SolracerError::InvalidRaceAccount);
+
+        // Someone funded the address first; settle anyway and keep the fees refundable
+        if rematch_info.lamports() > 0 {
+            msg!("Rematch account {} is already in use", pda);
+            return assert_invariants(race, &before);
+        }
+
+        create_race_account(
+            &ctx.accounts.payer.to_account_info(),
+            &rematch_info,
+            &ctx.accounts.system_program.to_account_info(),
+            Rent::get()?.minimum_balance(8 + Race::LEN),
+            &[
+                b"race",
+                race.player1.as_ref(),
+                rematch_id.as_bytes(),
+                token_mint.as_ref(),
+                &race.entry_fee_sol.to_le_bytes(),
+                &[bump],
+            ],
+        )?;
+
+        let player2_fee = race.player2_fee();
+        let stakes = race
+            .entry_fee_sol
+            .checked_add(player2_fee)
+            .ok_or(SolracerError::MathOverflow)?;
+        pay_from_escrow(&race.to_account_info(), &rematch_info, stakes)?;
+
+        let player2 = race.player2.unwrap();
+        let mut rematch = Race::new_waiting(
+            rematch_id.clone(),
+            token_mint,
+            race.entry_fee_sol,
+            race.config.clone(),
+            race.player1,
+            now()?,
+            bump,
+        );
+        rematch.player2 = Some(player2);
+        rematch.player2_fee_payer = player2;
+        rematch.status = RaceStatus::Active;
+        rematch.started_at = rematch.created_at;
+        rematch.player2_deadline = rematch.config.player_deadline(rematch.created_at);
+        rematch.escrow_amount = stakes;
+        rematch.total_deposited = stakes;
+        rematch.player2_paid = player2_fee;
+        rematch.house_target_ms = race.house_target_ms;
+        rematch.min_coins_to_win = race.min_coins_to_win;
+        rematch.authority = race.authority;
+        rematch.rematch_count = race.rematch_count.saturating_add(1);
+        rematch.rent_payer = ctx.accounts.payer.key();
+        rematch.try_serialize(&mut &mut rematch_info.try_borrow_mut_data()?[..])?;
+
+        race.player1_auto_rematch = false;
+        race.player2_auto_rematch = false;
+        race.rematch = Some(pda);
+
+        emit!(RematchCreated {
+            race: race.key(),
+            rematch: pda,
+            rematch_race_id: rematch_id.clone(),
+        });
+
+        msg!("Rematch {} created for race: {}", rematch_id, race.race_id);
+
+        assert_invariants(race, &before)
+    }
+
+    /// Authority decision on a `Disputed` race: `winner` (either player)
+    /// becomes the winner and the race is `Settled` so they can claim.
+    pub fn resolve_dispute(ctx: Context<ResolveDispute>, winner: Pubkey) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Disputed,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            winner == race.player1 || race.player2 == Some(winner),
+            SolracerError::PlayerNotInRace
+        );
+
+        race.winner = Some(winner);
+        race.outcome = Some(OutcomeReason::Override);
+        race.status = RaceStatus::Settled;
+        race.settled_at = now()?;
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        emit!(RaceSettled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            winner,
+            conceded: false,
+        });
+        emit_final_state(race, false)?;
+
+        msg!("Dispute on race {} resolved. Winner: {}", race.race_id, winner);
+
+        assert_invariants(race, &before)
+    }
+
+    /// Settle a `mutual_settlement` race on the players' word: both sign and
+    /// each names the winner. If the names match, that player wins
+    /// (outcome `MutualAgreement`) and can claim right away; if not, the
+    /// race goes `Disputed` (reason `Disagreement`) for `resolve_dispute`.
+    /// Results are not consulted.
+    pub fn settle_race_mutual(
+        ctx: Context<SettleRaceMutual>,
+        player1_winner: Pubkey,
+        player2_winner: Pubkey,
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            race.config.mutual_settlement,
+            SolracerError::MutualSettlementDisabled
+        );
+        for proposed in [player1_winner, player2_winner] {
+            require!(
+                proposed == race.player1 || race.player2 == Some(proposed),
+                SolracerError::PlayerNotInRace
+            );
+        }
+
+        if player1_winner != player2_winner {
+            mark_disputed(race, DisputeReason::Disagreement);
+
+            msg!("Race {} disputed: players named different winners", race.race_id);
+            return assert_invariants(race, &before);
+        }
+
+        race.winner = Some(player1_winner);
+        race.outcome = Some(OutcomeReason::MutualAgreement);
+        race.status = RaceStatus::Settled;
+        race.settled_at = now()?;
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        emit!(RaceSettled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            winner: player1_winner,
+            conceded: false,
+        });
+        emit_final_state(race, false)?;
+
+        msg!("Race {} settled by agreement. Winner: {}", race.race_id, player1_winner);
+
+        assert_invariants(race, &before)
+    }
+
+    /// A player voluntarily forfeits an active race; the opponent is
+    /// declared the winner immediately and can claim right away.
+    pub fn concede(ctx: Context<Concede>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            !matches!(race.status, RaceStatus::Settled | RaceStatus::Claimed),
+            SolracerError::AlreadySettled
+        );
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+
+        let player = ctx.accounts.player.key();
+        let player2 = race.player2.unwrap();
+
+        let winner = if player == race.player1 {
+            player2
+        } else if player == player2 {
+            race.player1
+        } else {
+            return err!(SolracerError::PlayerNotInRace);
+        };
+
+        race.winner = Some(winner);
+        race.outcome = Some(OutcomeReason::Concession);
+        race.status = RaceStatus::Settled;
+        race.settled_at = now()?;
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        emit!(RaceSettled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            winner,
+            conceded: true,
+        });
+        emit_final_state(race, true)?;
+
+        msg!(
+            "Player {} conceded race {}. Winner: {}",
+            player,
+            race.race_id,
+            winner
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Winner claims the prize accepts either the winner wallet directly
+    /// or a valid session key funds always go to race.winner
+    ///
+    /// The protocol fee (see `fee_schedule`) is taken out of the prize and
+    /// paid to `treasury`, which may be omitted while the fee is zero.
+    pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
+        let (fee_bps, treasury) =
+            load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        // Resolve the actual player: session key or direct wallet
+        let actual_player = resolve_player(ctx.accounts.session.as_deref(), &ctx.accounts.authority.key())?;
+
+        let host_cut_bps = claimant_host_cut_bps(race, &actual_player, ctx.accounts.config.host_cut_bps);
+        let prize_amount = take_prize(race, actual_player, now()?)?;
+        let (fee, host_cut, payout) = claim_split(prize_amount, fee_bps, host_cut_bps)?;
+        race.record_fees(fee, host_cut)?;
+
+        if race.status == RaceStatus::Claimed {
+            if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+                claim_index.remove(&race.key());
+            }
+        }
+        ctx.accounts.global_stats.record_fee(fee)?;
+
+        pay_protocol_fee(
+            &race.to_account_info(),
+            ctx.accounts.treasury.as_ref(),
+            &treasury,
+            fee,
+        )?;
+        pay_host_cut(
+            &race.to_account_info(),
+            ctx.accounts.host.as_ref(),
+            &race.player1,
+            host_cut,
+        )?;
+
+        // Funds go to winner_wallet (the real wallet), not the session key
+        pay_from_escrow(
+            &race.to_account_info(),
+            &ctx.accounts.winner_wallet.to_account_info(),
+            payout,
+        )?;
+
+        msg!(
+            "Prize of {} lamports claimed by winner {} for race: {} ({} lamports fee, {} lamports host cut)",
+            payout,
+            actual_player,
+            race.race_id,
+            fee,
+            host_cut
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Claim the prize of `race` and roll it straight into `new_race`, a
+    /// new race with the winner as player1, in one transaction. The stake
+    /// `entry_fee_sol` may be the whole prize after the protocol fee or
+    /// less, with the remainder paid to the winner; more fails with
+    /// `CompoundExceedsPrize`. The winner signs directly (no session key)
+    /// and pays the new race's rent.
+    pub fn claim_and_create(
+        ctx: Context<ClaimAndCreate>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+    ) -> Result<()> {
+        let (fee_bps, treasury) =
+            load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+        let winner = ctx.accounts.winner.key();
+
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let host_cut_bps = claimant_host_cut_bps(race, &winner, ctx.accounts.config.host_cut_bps);
+        let prize_amount = take_prize(race, winner, now()?)?;
+        let (fee, host_cut, payout) = claim_split(prize_amount, fee_bps, host_cut_bps)?;
+        race.record_fees(fee, host_cut)?;
+        let remainder = payout
+            .checked_sub(entry_fee_sol)
+            .ok_or(SolracerError::CompoundExceedsPrize)?;
+
+        if race.status == RaceStatus::Claimed {
+            if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+                claim_index.remove(&race.key());
+            }
+        }
+        ctx.accounts.global_stats.record_fee(fee)?;
+        ctx.accounts.global_stats.record_race(entry_fee_sol)?;
+
+        let new_race = &mut ctx.accounts.new_race;
+        open_race(
+            new_race,
+            &ctx.accounts.config,
+            race_id.clone(),
+            token_mint,
+            entry_fee_sol,
+            config,
+            winner,
+            ctx.bumps.new_race,
+        )?;
+
+        let race_info = race.to_account_info();
+        pay_protocol_fee(&race_info, ctx.accounts.treasury.as_ref(), &treasury, fee)?;
+        pay_host_cut(&race_info, ctx.accounts.host.as_ref(), &race.player1, host_cut)?;
+        pay_from_escrow(&race_info, &new_race.to_account_info(), entry_fee_sol)?;
+        if remainder > 0 {
+            pay_from_escrow(&race_info, &ctx.accounts.winner.to_account_info(), remainder)?;
+        }
+
+        emit!(RaceCreated {
+            race: new_race.key(),
+            race_id: race_id.clone(),
+            player1: winner,
+            external_ref: new_race.external_ref,
+        });
+
+        msg!(
+            "Prize of race {} rolled into race {}: {} lamports staked, {} paid out ({} lamports fee)",
+            race.race_id,
+            race_id,
+            entry_fee_sol,
+            remainder,
+            fee
+        );
+        assert_invariants(race, &before)
+    }
+
+    /// Claim every settled race in `remaining_accounts` that the signer won,
+    /// passed in groups of `[race, host]`. Races the signer didn't win, that
+    /// are already `Claimed` or that have nothing newly vested, are skipped.
+    /// Claimed races are dropped from the signer's `ClaimIndex` when passed.
+    /// Every race must be on the token of `token_config`; the protocol fee
+    /// and host cut are taken out of each prize as in `claim_prize`. The total lamports
+    /// paid out, after fees, is returned as a little-endian u64.
+    pub fn claim_batch<'info>(ctx: Context<'_, '_, 'info, 'info, ClaimBatch<'info>>) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len() % CLAIM_BATCH_GROUP == 0,
+            SolracerError::IncompleteClaimGroup
+        );
+        let groups = ctx.remaining_accounts.chunks(CLAIM_BATCH_GROUP);
+        check_batch_size(groups.len(), MAX_CLAIM_BATCH, CLAIM_BATCH_CU_PER_RACE)?;
+
+        let (fee_bps, treasury) =
+            load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+        let winner = ctx.accounts.winner.key();
+        let now = now()?;
+        let mut total_claimed: u64 = 0;
+
+        for group in groups {
+            let (race_info, host) = (&group[0], UncheckedAccount::try_from(&group[1]));
+            let mut race = with_race_context(&race_info.key().to_string(), || {
+                Account::<Race>::try_from(race_info)
+            })?;
+            let race_id = race.race_id.clone();
+            let before = race.status.clone();
+
+            let payout = with_race_context(&race_id, || {
+                require_keys_eq!(
+                    ctx.accounts.token_config.key(),
+                    token_config_pda(&race.token_mint),
+                    SolracerError::TokenConfigMismatch
+                );
+
+                let prize_amount = releasable_amount(&race, &winner, now);
+                if prize_amount == 0 {
+                    msg!("Skipping race {}", race_id);
+                    return Ok(0);
+                }
+
+                release_prize(&mut race, prize_amount)?;
+                let host_cut_bps = claimant_host_cut_bps(&race, &winner, ctx.accounts.config.host_cut_bps);
+                let (fee, host_cut, payout) = claim_split(prize_amount, fee_bps, host_cut_bps)?;
+                race.record_fees(fee, host_cut)?;
+
+                ctx.accounts.global_stats.record_fee(fee)?;
+                pay_protocol_fee(race_info, ctx.accounts.treasury.as_ref(), &treasury, fee)?;
+                pay_host_cut(race_info, Some(&host), &race.player1, host_cut)?;
+                pay_from_escrow(race_info, &ctx.accounts.winner.to_account_info(), payout)?;
+                assert_invariants(&race, &before)?;
+
+                // Persist now so a race passed twice is seen as claimed on its second pass
+                race.exit(&crate::ID)?;
+
+                msg!(
+                    "Prize of {} lamports claimed by winner {} for race: {} ({} lamports fee, {} lamports host cut)",
+                    payout,
+                    winner,
+                    race_id,
+                    fee,
+                    host_cut
+                );
+
+                Ok(payout)
+            })?;
+
+            if payout > 0 && race.status == RaceStatus::Claimed {
+                if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+                    claim_index.remove(&race_info.key());
+                }
+            }
+
+            total_claimed = total_claimed
+                .checked_add(payout)
+                .ok_or(SolracerError::MathOverflow)?;
+        }
+
+        set_return_data(&total_claimed.to_le_bytes());
+
+        msg!("Batch claim paid {} lamports to {}", total_claimed, winner);
+
+        Ok(())
+    }
+
+    /// Create a 2v2 team race. The creator takes the first team1 slot and
+    /// escrows their fee; `aggregation` decides how member times combine.
+    pub fn create_team_race(
+        ctx: Context<CreateTeamRace>,
+        race_id: String,
+        entry_fee_sol: u64,
+        aggregation: TeamAggregation,
+    ) -> Result<()> {
+        check_race_id(&race_id)?;
+
+        let race = &mut ctx.accounts.team_race;
+
+        race.status = RaceStatus::Waiting;
+        race.race_id = race_id.clone();
+        race.entry_fee_sol = entry_fee_sol;
+        race.aggregation = aggregation;
+        race.team1 = [ctx.accounts.creator.key(), Pubkey::default()];
+        race.team2 = [Pubkey::default(); 2];
+        race.team1_results = [None, None];
+        race.team2_results = [None, None];
+        race.winning_team = None;
+        race.prize_claimed = [false; 2];
+        race.escrow_amount = entry_fee_sol;
+        race.created_at = now()?;
+        race.bump = ctx.bumps.team_race;
+
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &race.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            entry_fee_sol,
+        )?;
+
+        msg!(
+            "Team race created: {} by {} with entry fee: {} lamports",
+            race_id,
+            ctx.accounts.creator.key(),
+            entry_fee_sol
+        );
+
+        Ok(())
+    }
+
+    /// Join the first open slot of `team` (1 or 2). The race goes `Active`
+    /// once all four slots are filled.
+    pub fn join_team_race(ctx: Context<JoinTeamRace>, team: u8) -> Result<()> {
+        let race = &mut ctx.accounts.team_race;
+        let player = ctx.accounts.player.key();
+
+        require!(
+            race.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            race.member_slot(&player).is_none(),
+            SolracerError::AlreadyInRace
+        );
+
+        let slots = match team {
+            1 => &mut race.team1,
+            2 => &mut race.team2,
+            _ => return err!(SolracerError::InvalidTeam),
+        };
+        let slot = slots
+            .iter_mut()
+            .find(|member| **member == Pubkey::default())
+            .ok_or(SolracerError::TeamFull)?;
+        *slot = player;
+
+        race.escrow_amount = race
+            .escrow_amount
+            .checked_add(race.entry_fee_sol)
+            .ok_or(SolracerError::MathOverflow)?;
+        if race.is_full() {
+            race.status = RaceStatus::Active;
+        }
+
+        transfer_lamports(
+            &ctx.accounts.player.to_account_info(),
+            &race.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            race.entry_fee_sol,
+        )?;
+
+        msg!(
+            "Player {} joined team {} in race: {}",
+            player,
+            team,
+            race.race_id
+        );
+
+        Ok(())
+    }
+
+    pub fn submit_team_result(
+        ctx: Context<SubmitTeamResult>,
+        finish_time_ms: u64,
+        coins_collected: u64,
+        input_hash: [u8; 32],
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.team_race;
+        let player = ctx.accounts.player.key();
+
+        require!(
+            race.status != RaceStatus::Waiting,
+            SolracerError::TeamIncomplete
+        );
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+
+        let (team, index) = race
+            .member_slot(&player)
+            .ok_or(SolracerError::PlayerNotInRace)?;
+        let results = if team == 1 {
+            &mut race.team1_results
+        } else {
+            &mut race.team2_results
+        };
+
+        require!(
+            results[index].is_none(),
+            SolracerError::ResultAlreadySubmitted
+        );
+        results[index] = Some(RaceResult {
+            finish_time_ms,
+            coins_collected,
+            input_hash,
+        });
+
+        msg!(
+            "Result submitted for player {} on team {} in race: {}",
+            player,
+            team,
+            race.race_id
+        );
+
+        Ok(())
+    }
+
+    pub fn settle_team_race(ctx: Context<SettleTeamRace>) -> Result<()> {
+        let race = &mut ctx.accounts.team_race;
+
+        require!(
+            race.status != RaceStatus::Waiting,
+            SolracerError::TeamIncomplete
+        );
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+
+        let (team1, team2) = match (race.team1_results.clone(), race.team2_results.clone()) {
+            ([Some(a), Some(b)], [Some(c), Some(d)]) => ([a, b], [c, d]),
+            _ => return err!(SolracerError::ResultsNotComplete),
+        };
+
+        let winning_team = determine_winning_team(&team1, &team2, race.aggregation);
+
+        race.winning_team = Some(winning_team);
+        race.status = RaceStatus::Settled;
+
+        emit!(TeamRaceSettled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            winning_team,
+        });
+
+        msg!(
+            "Team race {} settled. Winning team: {}",
+            race.race_id,
+            winning_team
+        );
+
+        Ok(())
+    }
+
+    /// Each member of the winning team claims half the pot. On an odd pot
+    /// the leftover lamport goes to whoever claims second.
+    pub fn claim_team_prize(ctx: Context<ClaimTeamPrize>) -> Result<()> {
+        let race = &mut ctx.accounts.team_race;
+        let player = ctx.accounts.player.key();
+
+        // A Claimed team race means both shares are already paid
+        require!(
+            race.status != RaceStatus::Claimed,
+            SolracerError::AlreadyClaimed
+        );
+        require!(
+            race.status == RaceStatus::Settled,
+            SolracerError::InvalidRaceStatus
+        );
+
+        let winners = match race.winning_team {
+            Some(1) => race.team1,
+            Some(2) => race.team2,
+            _ => return err!(SolracerError::InvalidRaceStatus),
+        };
+        let index = winners
+            .iter()
+            .position(|member| *member == player)
+            .ok_or(SolracerError::NotWinner)?;
+
+        require!(!race.prize_claimed[index], SolracerError::AlreadyClaimed);
+
+        let share = if race.prize_claimed[1 - index] {
+            race.escrow_amount
+        } else {
+            race.escrow_amount / 2
+        };
+
+        race.prize_claimed[index] = true;
+        race.escrow_amount = race
+            .escrow_amount
+            .checked_sub(share)
+            .ok_or(SolracerError::MathOverflow)?;
+        if race.prize_claimed.iter().all(|claimed| *claimed) {
+            race.status = RaceStatus::Claimed;
+        }
+
+        pay_from_escrow(
+            &race.to_account_info(),
+            &ctx.accounts.player.to_account_info(),
+            share,
+        )?;
+
+        msg!(
+            "Team prize share of {} lamports claimed by {} for race: {}",
+            share,
+            player,
+            race.race_id
+        );
+
+        Ok(())
+    }
+
+    /// Create an N-player lobby (2..=MAX_LOBBY_PLAYERS). The creator takes
+    /// the first seat and escrows their fee. The lobby starts automatically
+    /// when full, or earlier via `start_race` once `min_players_to_start`
+    /// have joined and `join_grace_secs` have passed since then. Each later
+    /// seat costs `late_join_surcharge_bps` more than the one before (see
+    /// `Lobby::fee_for_seat`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_lobby(
+        ctx: Context<CreateLobby>,
+        race_id: String,
+        entry_fee_sol: u64,
+        max_players: u8,
+        min_players_to_start: u8,
+        join_grace_secs: i64,
+        late_join_surcharge_bps: u16,
+        config: RaceConfig,
+    ) -> Result<()> {
+        check_race_id(&race_id)?;
+        require!(
+            min_players_to_start >= 2
+                && min_players_to_start <= max_players
+                && max_players as usize <= MAX_LOBBY_PLAYERS,
+            SolracerError::InvalidLobbySize
+        );
+        require!(join_grace_secs >= 0, SolracerError::InvalidJoinGrace);
+
+        let lobby = &mut ctx.accounts.lobby;
+        let creator = ctx.accounts.creator.key();
+
+        lobby.status = RaceStatus::Waiting;
+        lobby.race_id = race_id.clone();
+        lobby.entry_fee_sol = entry_fee_sol;
+        lobby.config = config;
+        lobby.creator = creator;
+        lobby.max_players = max_players;
+        lobby.min_players_to_start = min_players_to_start;
+        lobby.join_grace_secs = join_grace_secs;
+        lobby.min_reached_at = 0;
+        lobby.antisnipe_extensions = 0;
+        lobby.late_join_surcharge_bps = late_join_surcharge_bps;
+        lobby.players = vec![creator];
+        lobby.paid_fees = vec![entry_fee_sol];
+        lobby.results = vec![None];
+        lobby.winner = None;
+        lobby.escrow_amount = entry_fee_sol;
+        lobby.created_at = now()?;
+        lobby.bump = ctx.bumps.lobby;
+
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &lobby.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            entry_fee_sol,
+        )?;
+
+        msg!(
+            "Lobby created: {} by {} for {}-{} players with entry fee: {} lamports",
+            race_id,
+            creator,
+            min_players_to_start,
+            max_players,
+            entry_fee_sol
+        );
+
+        Ok(())
+    }
+
+    pub fn join_lobby(ctx: Context<JoinLobby>) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+        let player = ctx.accounts.player.key();
+
+        require!(
+            lobby.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            !lobby.players.contains(&player),
+            SolracerError::AlreadyInRace
+        );
+        require!(
+            lobby.players.len() < lobby.max_players as usize,
+            SolracerError::LobbyFull
+        );
+
+        let now = now()?;
+        require!(
+            !lobby.join_grace_expired(now),
+            SolracerError::JoinGraceExpired
+        );
+
+        let config = &ctx.accounts.config;
+        if lobby.apply_antisnipe(now, config.antisnipe_window_secs, config.antisnipe_extension_secs) {
+            msg!(
+                "Late join extended lobby {} grace to {}s",
+                lobby.race_id,
+                lobby.join_grace_secs
+            );
+        }
+
+        let fee = lobby.fee_for_seat(lobby.players.len())?;
+        lobby.players.push(player);
+        lobby.results.push(None);
+        lobby.paid_fees.push(fee);
+        lobby.escrow_amount = lobby
+            .escrow_amount
+            .checked_add(fee)
+            .ok_or(SolracerError::MathOverflow)?;
+
+        if lobby.players.len() == lobby.min_players_to_start as usize {
+            lobby.min_reached_at = now;
+        }
+
+        if lobby.players.len() == lobby.max_players as usize {
+            lobby.status = RaceStatus::Active;
+        }
+
+        transfer_lamports(
+            &ctx.accounts.player.to_account_info(),
+            &lobby.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            fee,
+        )?;
+
+        msg!(
+            "Player {} joined lobby {} for {} lamports ({}/{})",
+            player,
+            lobby.race_id,
+            fee,
+            lobby.players.len(),
+            lobby.max_players
+        );
+
+        Ok(())
+    }
+
+    /// Creator starts a lobby before it is full. Only players who have
+    /// joined take part; fees are only collected on join, so open seats
+    /// hold nothing to return.
+    pub fn start_race(ctx: Context<StartRace>) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+
+        require!(
+            lobby.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            lobby.players.len() >= lobby.min_players_to_start as usize,
+            SolracerError::NotEnoughPlayers
+        );
+        require!(
+            now()? >= lobby.min_reached_at + lobby.join_grace_secs,
+            SolracerError::JoinGraceActive
+        );
+
+        lobby.status = RaceStatus::Active;
+
+        msg!(
+            "Lobby {} started with {} players",
+            lobby.race_id,
+            lobby.players.len()
+        );
+
+        Ok(())
+    }
+
+    pub fn submit_lobby_result(
+        ctx: Context<SubmitLobbyResult>,
+        finish_time_ms: u64,
+        coins_collected: u64,
+        input_hash: [u8; 32],
+    ) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+        let player = ctx.accounts.player.key();
+
+        require!(
+            lobby.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+
+        let index = lobby
+            .players
+            .iter()
+            .position(|p| *p == player)
+            .ok_or(SolracerError::PlayerNotInRace)?;
+
+        require!(
+            lobby.results[index].is_none(),
+            SolracerError::ResultAlreadySubmitted
+        );
+        lobby.results[index] = Some(RaceResult {
+            finish_time_ms,
+            coins_collected,
+            input_hash,
+        });
+
+        msg!(
+            "Result submitted for player {} in lobby: {}",
+            player,
+            lobby.race_id
+        );
+
+        Ok(())
+    }
+
+    /// Winner takes all once every seated player has submitted.
+    pub fn settle_lobby(ctx: Context<SettleLobby>) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+
+        require!(
+            lobby.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            lobby.results.iter().all(|r| r.is_some()),
+            SolracerError::ResultsNotComplete
+        );
+
+        let ranking = rank_results(&lobby.results, &lobby.config);
+        let winner = lobby.players[ranking[0]];
+
+        lobby.winner = Some(winner);
+        lobby.status = RaceStatus::Settled;
+
+        emit!(RaceSettled {
+            race: lobby.key(),
+            race_id: lobby.race_id.clone(),
+            winner,
+            conceded: false,
+        });
+
+        msg!("Lobby {} settled. Winner: {}", lobby.race_id, winner);
+
+        Ok(())
+    }
+
+    pub fn claim_lobby_prize(ctx: Context<ClaimLobbyPrize>) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+
+        require!(
+            lobby.status != RaceStatus::Claimed,
+            SolracerError::AlreadyClaimed
+        );
+        require!(
+            lobby.status == RaceStatus::Settled,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            lobby.winner == Some(ctx.accounts.winner.key()),
+            SolracerError::NotWinner
+        );
+
+        lobby.status = RaceStatus::Claimed;
+
+        let prize_amount = lobby.escrow_amount;
+        lobby.escrow_amount = 0;
+
+        pay_from_escrow(
+            &lobby.to_account_info(),
+            &ctx.accounts.winner.to_account_info(),
+            prize_amount,
+        )?;
+
+        msg!(
+            "Lobby prize of {} lamports claimed by {} for race: {}",
+            prize_amount,
+            ctx.accounts.winner.key(),
+            lobby.race_id
+        );
+
+        Ok(())
+    }
+
+    /// Store a short raw replay for the signer's result in this race so it
+    /// can be checked on-chain with `verify_result`.
+    pub fn store_replay(ctx: Context<StoreReplay>, data: Vec<u8>) -> Result<()> {
+        let race = &ctx.accounts.race;
+        let player = ctx.accounts.player.key();
+
+        require!(data.len() <= MAX_REPLAY_LEN, SolracerError::ReplayTooLarge);
+        require!(
+            player == race.player1 || race.player2 == Some(player),
+            SolracerError::PlayerNotInRace
+        );
+
+        let replay = &mut ctx.accounts.replay;
+        replay.race = race.key();
+        replay.player = player;
+        replay.verified = false;
+        replay.bump = ctx.bumps.replay;
+        replay.data = data;
+
+        msg!(
+            "Replay of {} bytes stored for player {} in race: {}",
+            replay.data.len(),
+            player,
+            race.race_id
+        );
+
+        Ok(())
+    }
+
+    /// Permissionless check that a stored replay hashes (via
+    /// `result_input_hash`) to the submitted `input_hash`.
+    pub fn verify_result(ctx: Context<VerifyResult>) -> Result<()> {
+        let race = &ctx.accounts.race;
+        let replay = &mut ctx.accounts.replay;
+
+        let result = if replay.player == race.player1 {
+            race.player1_result.as_ref()
+        } else if race.player2 == Some(replay.player) {
+            race.player2_result.as_ref()
+        } else {
+            return err!(SolracerError::PlayerNotInRace);
+        }
+        .ok_or(SolracerError::ResultNotSubmitted)?;
+
+        let expected = result_input_hash(
+            &race.key(),
+            &race.race_id,
+            result.finish_time_ms,
+            result.coins_collected,
+            &replay.data,
+        );
+        if expected != result.input_hash {
+            let unbound = unbound_input_hash(
+                &race.race_id,
+                result.finish_time_ms,
+                result.coins_collected,
+                &replay.data,
+            );
+            if unbound == result.input_hash {
+                return err!(SolracerError::HashNotBoundToRace);
+            }
+            return err!(SolracerError::InputHashMismatch);
+        }
+
+        replay.verified = true;
+
+        msg!(
+            "Result verified for player {} in race: {}",
+            replay.player,
+            race.race_id
+        );
+
+        Ok(())
+    }
+
+    /// A player proves their `coins_collected` against the map layout
+    /// committed in `RaceConfig::coins_root`: one distinct coin id per coin
+    /// collected, in increasing order, each with its Merkle proof. Optional;
+    /// a valid proof sets the player's `coins_verified` flag, and a count or
+    /// proof that doesn't match the layout marks the race `Disputed`.
+    pub fn verify_coins(
+        ctx: Context<VerifyCoins>,
+        coin_ids: Vec<u16>,
+        proofs: Vec<Vec<[u8; 32]>>,
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let player = ctx.accounts.player.key();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        let root = race.config.coins_root;
+        require!(root != [0; 32], SolracerError::CoinProofInvalid);
+        require!(
+            coin_ids.len() == proofs.len() && coin_ids.windows(2).all(|ids| ids[0] < ids[1]),
+            SolracerError::CoinProofInvalid
+        );
+
+        let is_player1 = player == race.player1;
+        require!(
+            is_player1 || race.player2 == Some(player),
+            SolracerError::PlayerNotInRace
+        );
+        let result = if is_player1 {
+            race.player1_result.as_ref()
+        } else {
+            race.player2_result.as_ref()
+        }
+        .ok_or(SolracerError::ResultNotSubmitted)?;
+
+        let proven = coin_ids.len() as u64 == result.coins_collected
+            && coin_ids
+                .iter()
+                .zip(&proofs)
+                .all(|(id, proof)| merkle_verify(coin_leaf(*id), proof, root));
+
+        if !proven {
+            mark_disputed(race, DisputeReason::CoinProof);
+
+            msg!("Race {} disputed: coins of {} don't match the map", race.race_id, player);
+            return assert_invariants(race, &before);
+        }
+
+        if is_player1 {
+            race.player1_coins_verified = true;
+        } else {
+            race.player2_coins_verified = true;
+        }
+
+        msg!(
+            "Coins verified for player {} in race: {}",
+            player,
+            race.race_id
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Close a replay account and return its rent to the player.
+    pub fn close_replay(_ctx: Context<CloseReplay>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Like `claim_prize`, but pays the prize after the protocol fee and
+    /// host cut into the winner's wSOL token account and syncs its native
+    /// balance.
+    pub fn claim_prize_wsol(ctx: Context<ClaimPrizeWsol>) -> Result<()> {
+        let (fee_bps, treasury) =
+            load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        let actual_player = resolve_player(ctx.accounts.session.as_deref(), &ctx.accounts.authority.key())?;
+
+        require!(
+            ctx.accounts.winner_token_account.owner == actual_player,
+            SolracerError::InvalidTokenAccountOwner
+        );
+
+        let host_cut_bps = claimant_host_cut_bps(race, &actual_player, ctx.accounts.config.host_cut_bps);
+        let prize_amount = take_prize(race, actual_player, now()?)?;
+        let (fee, host_cut, payout) = claim_split(prize_amount, fee_bps, host_cut_bps)?;
+        race.record_fees(fee, host_cut)?;
+        if race.status == RaceStatus::Claimed {
+            if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+                claim_index.remove(&race.key());
+            }
+        }
+        ctx.accounts.global_stats.record_fee(fee)?;
+
+        pay_protocol_fee(
+            &race.to_account_info(),
+            ctx.accounts.treasury.as_ref(),
+            &treasury,
+            fee,
+        )?;
+        pay_host_cut(
+            &race.to_account_info(),
+            ctx.accounts.host.as_ref(),
+            &race.player1,
+            host_cut,
+        )?;
+        pay_from_escrow(
+            &race.to_account_info(),
+            &ctx.accounts.winner_token_account.to_account_info(),
+            payout,
+        )?;
+
+        token::sync_native(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token::SyncNative {
+                account: ctx.accounts.winner_token_account.to_account_info(),
+            },
+        ))?;
+
+        msg!(
+            "Prize of {} lamports claimed as wSOL by winner {} for race: {} ({} lamports fee, {} lamports host cut)",
+            payout,
+            actual_player,
+            race.race_id,
+            fee,
+            host_cut
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Create the signer's `ChatAuthor`, which paces their chat messages.
+    pub fn init_chat_author(ctx: Context<InitChatAuthor>) -> Result<()> {
+        let chat_author = &mut ctx.accounts.chat_author;
+        chat_author.author = ctx.accounts.author.key();
+        chat_author.last_posted_at = 0;
+        chat_author.bump = ctx.bumps.chat_author;
+
+        msg!("Chat author created: {}", chat_author.author);
+        Ok(())
+    }
+
+    /// Anchor the next spectator chat message. Anyone with a `ChatAuthor`
+    /// may call this; each call pays `CHAT_UPDATE_DEPOSIT` to the treasury,
+    /// never into the prize, and an author's calls are spaced at least
+    /// `CHAT_UPDATE_MIN_INTERVAL_SECS` apart.
+    pub fn update_chat_root(ctx: Context<UpdateChatRoot>, message_hash: [u8; 32]) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let now = now()?;
+
+        // The chat log closes once the outcome is fixed
+        require!(!race.escrow_locked(), SolracerError::EscrowLocked);
+        require!(!race.imported, SolracerError::ImportedRace);
+        ctx.accounts.chat_author.post(now)?;
+
+        race.chat_root = next_chat_root(&race.chat_root, &message_hash);
+        race.chat_updated_at = now;
+
+        transfer_lamports(
+            &ctx.accounts.author.to_account_info(),
+            &ctx.accounts.treasury.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            CHAT_UPDATE_DEPOSIT,
+        )?;
+
+        emit!(ChatRootUpdated {
+            race: race.key(),
+            author: ctx.accounts.author.key(),
+            message_hash,
+            chat_root: race.chat_root,
+        });
+
+        assert_invariants(race, &before)
+    }
+
+    /// Create up to `MAX_CREATE_BATCH` races in one transaction, e.g. the
+    /// qualifiers of an event. Pass each race PDA (derived exactly as for
+    /// `create_race`) as a writable remaining account followed by its
+    /// token's `TokenConfig` PDA, in the same order as `races`. Each race
+    /// gets the checks and side effects of `create_race`. The creator is
+    /// player1 of, and escrows the fee for, every race; each one is then
+    /// joined independently.
+    pub fn create_race_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateRaceBatch<'info>>,
+        races: Vec<RaceSpec>,
+    ) -> Result<()> {
+        check_batch_size(races.len(), MAX_CREATE_BATCH, CREATE_BATCH_CU_PER_RACE)?;
+        require!(
+            ctx.remaining_accounts.len() == races.len() * CREATE_BATCH_GROUP,
+            SolracerError::InvalidRaceAccount
+        );
+
+        let creator = &ctx.accounts.creator;
+        let rent = Rent::get()?.minimum_balance(8 + Race::LEN);
+        let groups = ctx.remaining_accounts.chunks(CREATE_BATCH_GROUP);
+
+        for (spec, group) in races.into_iter().zip(groups) {
+            let (race_info, token_config) = (&group[0], &group[1]);
+            with_race_context(&spec.race_id, || {
+                require_keys_eq!(
+                    token_config.key(),
+                    token_config_pda(&spec.token_mint),
+                    SolracerError::TokenConfigMismatch
+                );
+                check_token_not_paused(token_config)?;
+
+                let (pda, bump) = race_pda(
+                    &creator.key(),
+                    &spec.race_id,
+                    &spec.token_mint,
+                    spec.entry_fee_sol,
+                );
+                require_keys_eq!(race_info.key(), pda, SolracerError::InvalidRaceAccount);
+                let race = new_race(
+                    &ctx.accounts.config,
+                    spec.race_id.clone(),
+                    spec.token_mint,
+                    spec.entry_fee_sol,
+                    spec.config,
+                    creator.key(),
+                    bump,
+                )?;
+
+                // Rent and the entry fee are funded in the same CPI
+                let funding = rent
+                    .checked_add(spec.entry_fee_sol)
+                    .ok_or(SolracerError::MathOverflow)?;
+                create_race_account(
+                    &creator.to_account_info(),
+                    race_info,
+                    &ctx.accounts.system_program.to_account_info(),
+                    funding,
+                    &[
+                        b"race",
+                        creator.key.as_ref(),
+                        spec.race_id.as_bytes(),
+                        spec.token_mint.as_ref(),
+                        &spec.entry_fee_sol.to_le_bytes(),
+                        &[bump],
+                    ],
+                )?;
+
+                race.try_serialize(&mut &mut race_info.try_borrow_mut_data()?[..])?;
+                ctx.accounts.global_stats.record_race(spec.entry_fee_sol)?;
+
+                emit!(RaceCreated {
+                    race: pda,
+                    race_id: spec.race_id.clone(),
+                    player1: creator.key(),
+                    external_ref: race.external_ref,
+                });
+
+                msg!(
+                    "Race created: {} by player1: {} with entry fee: {} lamports",
+                    spec.race_id,
+                    creator.key(),
+                    spec.entry_fee_sol
+                );
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply a settled 1v1 race's outcome to both players' ratings using
+    /// `elo_update` and the config K-factor. Permissionless; each race is
+    /// rated once. House races carry no rating.
+    pub fn rate_race(ctx: Context<RateRace>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+
+        require!(
+            matches!(race.status, RaceStatus::Settled | RaceStatus::Claimed),
+            SolracerError::InvalidRaceStatus
+        );
+        require!(race.house_target_ms.is_none(), SolracerError::InvalidRaceStatus);
+        require!(!race.rated, SolracerError::AlreadyRated);
+
+        let player1 = &mut ctx.accounts.player1_profile;
+        let player2 = &mut ctx.accounts.player2_profile;
+        let k = ctx.accounts.config.elo_k_factor;
+
+        if race.winner == Some(race.player1) {
+            (player1.rating, player2.rating) = elo_update(player1.rating, player2.rating, k);
+        } else {
+            (player2.rating, player1.rating) = elo_update(player2.rating, player1.rating, k);
+        }
+        player1.rated_races = player1.rated_races.saturating_add(1);
+        player2.rated_races = player2.rated_races.saturating_add(1);
+        race.rated = true;
+
+        msg!(
+            "Race {} rated: player1 {} player2 {}",
+            race.race_id,
+            player1.rating,
+            player2.rating
+        );
+
+        Ok(())
+    }
+
+    /// Refund a lobby that never reached `min_players_to_start` by its
+    /// join deadline. Permissionless: pass every joined player's wallet as
+    /// a writable remaining account, in join order. Each gets back exactly
+    /// what they paid (`paid_fees`) and the account is closed to the creator.
+    pub fn refund_lobby<'info>(ctx: Context<'_, '_, 'info, 'info, RefundLobby<'info>>) -> Result<()> {
+        let lobby = &mut ctx.accounts.lobby;
+
+        require!(
+            lobby.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            lobby.players.len() < lobby.min_players_to_start as usize,
+            SolracerError::MinimumReached
+        );
+        require!(
+            now()? > lobby.join_deadline(),
+            SolracerError::JoinDeadlineNotReached
+        );
+        require!(
+            ctx.remaining_accounts.len() == lobby.players.len(),
+            SolracerError::PlayerNotInRace
+        );
+
+        let lobby_info = lobby.to_account_info();
+        let mut escrow = lobby.escrow_amount;
+        let seats = lobby.players.iter().zip(&lobby.paid_fees);
+        for ((player, paid), wallet) in seats.zip(ctx.remaining_accounts.iter()) {
+            require_keys_eq!(wallet.key(), *player, SolracerError::PlayerNotInRace);
+
+            pay_from_escrow(&lobby_info, wallet, *paid)?;
+            escrow = escrow
+                .checked_sub(*paid)
+                .ok_or(SolracerError::EscrowMismatch)?;
+        }
+
+        require!(escrow == 0, SolracerError::EscrowMismatch);
+        lobby.escrow_amount = 0;
+
+        msg!(
+            "Lobby {} refunded to {} players",
+            lobby.race_id,
+            lobby.players.len()
+        );
+
+        Ok(())
+    }
+
+    /// Create the signer's empty `ClaimIndex`.
+    pub fn init_claim_index(ctx: Context<InitClaimIndex>) -> Result<()> {
+        let claim_index = &mut ctx.accounts.claim_index;
+        claim_index.player = ctx.accounts.player.key();
+        claim_index.races = Vec::new();
+        claim_index.bump = ctx.bumps.claim_index;
+
+        msg!("Claim index created for player: {}", claim_index.player);
+        Ok(())
+    }
+
+    /// Create the signer's empty `CreditAccount`.
+    pub fn init_credit_account(ctx: Context<InitCreditAccount>) -> Result<()> {
+        let credits = &mut ctx.accounts.credits;
+        credits.player = ctx.accounts.player.key();
+        credits.balance = 0;
+        credits.bump = ctx.bumps.credits;
+
+        msg!("Credit account created for player: {}", credits.player);
+        Ok(())
+    }
+
+    /// Prepay `amount` lamports of entry fees into the signer's
+    /// `CreditAccount`. Credits are spent by passing the account to
+    /// `create_race` or `join_race` and can't be withdrawn.
+    pub fn buy_credits(ctx: Context<BuyCredits>, amount: u64) -> Result<()> {
+        transfer_lamports(
+            &ctx.accounts.player.to_account_info(),
+            &ctx.accounts.credits.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+        let credits = &mut ctx.accounts.credits;
+        credits.balance = credits
+            .balance
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+
+        msg!(
+            "Player {} bought {} lamports of credits, balance {}",
+            credits.player,
+            amount,
+            credits.balance
+        );
+        Ok(())
+    }
+
+    /// Create the `ResultFeed` for `token_mint`. Permissionless; the payer
+    /// only covers rent.
+    pub fn init_result_feed(ctx: Context<InitResultFeed>, token_mint: Pubkey) -> Result<()> {
+        let feed = &mut ctx.accounts.result_feed;
+        feed.token_mint = token_mint;
+        feed.head = 0;
+        feed.len = 0;
+        feed.entries = [FeedEntry::default(); RESULT_FEED_CAPACITY];
+        feed.bump = ctx.bumps.result_feed;
+
+        msg!("Result feed created for token: {}", token_mint);
+        Ok(())
+    }
+
+    /// `settle_race`, then append the outcome to the token's `ResultFeed`.
+    /// A race that ends up `Disputed` is not recorded.
+    pub fn settle_race_with_feed(ctx: Context<SettleRaceWithFeed>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let Some(winner) = settle_on_results(race, &ctx.accounts.claim_delay_tiers)? else {
+            return assert_invariants(race, &before);
+        };
+
+        ctx.accounts.global_stats.record_settlement()?;
+
+        ctx.accounts.result_feed.push(FeedEntry {
+            race_id_hash: solana_sha256_hasher::hash(race.race_id.as_bytes()).to_bytes(),
+            winner,
+            winner_time_ms: winner_time_ms(race, &winner),
+        });
+
+        msg!("Race {} recorded in result feed", race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// `settle_race`, then CPI into the race's `winner_hook` program with
+    /// `winner_hook_data` so it can react (grant XP, mint a trophy). The
+    /// race account is passed first, read-only and signed for by its PDA,
+    /// followed by the remaining accounts as given. A disputed race calls
+    /// no hook. A failing hook reverts the settlement with it; for a
+    /// non-strict hook, `settle_race` remains as the fallback.
+    pub fn settle_race_with_hook<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleRaceWithHook<'info>>,
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let hook = race.config.winner_hook.ok_or(SolracerError::WinnerHookMismatch)?;
+        require_keys_eq!(
+            ctx.accounts.hook_program.key(),
+            hook,
+            SolracerError::WinnerHookMismatch
+        );
+
+        let Some(winner) = decide_race(race, &ctx.accounts.claim_delay_tiers)? else {
+            return assert_invariants(race, &before);
+        };
+        ctx.accounts.global_stats.record_settlement()?;
+        assert_invariants(race, &before)?;
+        // Persist the settlement so the hook reads the final state
+        race.exit(&crate::ID)?;
+
+        let race_info = race.to_account_info();
+        let mut accounts = vec![AccountMeta::new_readonly(race.key(), true)];
+        let mut infos = vec![race_info];
+        for account in ctx.remaining_accounts {
+            accounts.push(if account.is_writable {
+                AccountMeta::new(account.key(), account.is_signer)
+            } else {
+                AccountMeta::new_readonly(account.key(), account.is_signer)
+            });
+            infos.push(account.clone());
+        }
+        infos.push(ctx.accounts.hook_program.to_account_info());
+
+        let instruction = anchor_lang::solana_program::instruction::Instruction {
+            program_id: hook,
+            accounts,
+            data: winner_hook_data(race, winner)?,
+        };
+        let fee = race.entry_fee_sol.to_le_bytes();
+        anchor_lang::solana_program::program::invoke_signed(
+            &instruction,
+            &infos,
+            &[&[
+                b"race",
+                race.player1.as_ref(),
+                race.race_id.as_bytes(),
+                race.token_mint.as_ref(),
+                &fee,
+                &[race.bump],
+            ]],
+        )?;
+
+        msg!("Winner hook {} called for race: {}", hook, race.race_id);
+        Ok(())
+    }
+
+    /// Authority-only import of a finished race from a migrated game. The
+    /// race is written already `Settled` with both results and `winner`,
+    /// marked `imported` and holds no escrow, so it can be rated but never
+    /// claimed. No lamports move apart from the account's rent.
+    #[allow(clippy::too_many_arguments)]
+    pub fn import_result(
+        ctx: Context<ImportResult>,
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        player1: Pubkey,
+        player2: Pubkey,
+        player1_result: RaceResult,
+        player2_result: RaceResult,
+        winner: Pubkey,
+    ) -> Result<()> {
+        check_race_id(&race_id)?;
+        require!(is_valid_token_mint(&token_mint), SolracerError::InvalidTokenMint);
+        require!(player1 != player2, SolracerError::AlreadyInRace);
+        require!(
+            winner == player1 || winner == player2,
+            SolracerError::PlayerNotInRace
+        );
+
+        let mut race = Race::new_waiting(
+            race_id.clone(),
+            token_mint,
+            entry_fee_sol,
+            RaceConfig::default(),
+            player1,
+            now()?,
+            ctx.bumps.race,
+        );
+        race.player2 = Some(player2);
+        race.player2_fee_payer = player2;
+        race.player1_result = Some(player1_result);
+        race.player2_result = Some(player2_result);
+        race.winner = Some(winner);
+        race.outcome = Some(OutcomeReason::Imported);
+        race.status = RaceStatus::Settled;
+        race.escrow_amount = 0;
+        race.total_deposited = 0;
+        race.player1_paid = 0;
+        race.authority = ctx.accounts.authority.key();
+        race.imported = true;
+        ctx.accounts.race.set_inner(race);
+
+        msg!("Race {} imported. Winner: {}", race_id, winner);
+        Ok(())
+    }
+
+    /// Player2 leaves an `Active` race before any result is in. Their fee
+    /// goes back to whoever paid it (`player2_fee_payer`), any rematch
+    /// opt-in is refunded, and the race returns to `Waiting` for a new
+    /// opponent. An operator-prefunded slot stays funded for the next
+    /// joiner.
+    pub fn leave_race(ctx: Context<LeaveRace>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let player2 = ctx.accounts.player2.key();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(race.player2 == Some(player2), SolracerError::PlayerNotInRace);
+        require!(race.house_target_ms.is_none(), SolracerError::InvalidRaceStatus);
+        require!(
+            race.player1_result.is_none() && race.player2_result.is_none(),
+            SolracerError::CannotLeaveAfterSubmit
+        );
+
+        if !race.player2_prefunded {
+            let fee = race.player2_paid;
+            race.debit_escrow(fee)?;
+            pay_from_escrow(
+                &race.to_account_info(),
+                &ctx.accounts.fee_payer.to_account_info(),
+                fee,
+            )?;
+            race.player2_fee_payer = Pubkey::default();
+            race.player2_paid = 0;
+        }
+
+        if race.player2_auto_rematch {
+            pay_from_escrow(
+                &race.to_account_info(),
+                &ctx.accounts.player2.to_account_info(),
+                race.player2_fee(),
+            )?;
+            race.player2_auto_rematch = false;
+        }
+
+        race.player2 = None;
+        race.status = RaceStatus::Waiting;
+        race.started_at = 0;
+        race.player2_deadline = 0;
+
+        msg!("Player2 {} left race: {}", player2, race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// Both players call off an `Active` race before either has submitted
+    /// a result. player2's fee goes back to `player2_fee_payer` and any
+    /// rematch opt-in to player2; the account is then closed to player1,
+    /// returning their fee and the rent. Races with an operator-covered
+    /// player1 seat (house or prefunded) can't be cancelled this way.
+    pub fn mutual_cancel(ctx: Context<MutualCancel>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            race.house_target_ms.is_none() && !race.player1_prefunded,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            race.player1_result.is_none() && race.player2_result.is_none(),
+            SolracerError::CannotLeaveAfterSubmit
+        );
+
+        // player1's share leaves with the account when it closes
+        let remaining = race.escrow_amount;
+        race.debit_escrow(remaining)?;
+
+        let race_info = race.to_account_info();
+        pay_from_escrow(
+            &race_info,
+            &ctx.accounts.fee_payer.to_account_info(),
+            race.player2_paid,
+        )?;
+        if race.player2_auto_rematch {
+            pay_from_escrow(
+                &race_info,
+                &ctx.accounts.player2.to_account_info(),
+                race.player2_fee(),
+            )?;
+            race.player2_auto_rematch = false;
+        }
+
+        emit!(RaceCancelled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            reason: CancelReason::Mutual,
+        });
+
+        msg!("Race {} cancelled by both players", race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// `claim_prize`, paid out across several wallets (e.g. a coach or
+    /// org). The winner signs; `bps[i]` is the share for remaining account
+    /// `i`, up to `MAX_CLAIM_SPLITS` destinations, and the shares must add
+    /// up to `SPLIT_TOTAL_BPS`. The shares divide the prize after the
+    /// protocol fee and host cut; rounding dust goes to the last destination.
+    pub fn claim_prize_split<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimPrizeSplit<'info>>,
+        bps: Vec<u16>,
+    ) -> Result<()> {
+        let destinations = ctx.remaining_accounts;
+        require!(
+            destinations.len() == bps.len(),
+            SolracerError::InvalidSplit
+        );
+
+        let (fee_bps, treasury) =
+            load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let winner = ctx.accounts.winner.key();
+        let host_cut_bps = claimant_host_cut_bps(race, &winner, ctx.accounts.config.host_cut_bps);
+        let prize_amount = take_prize(race, winner, now()?)?;
+        let (fee, host_cut, payout) = claim_split(prize_amount, fee_bps, host_cut_bps)?;
+        race.record_fees(fee, host_cut)?;
+        let amounts = split_amounts(payout, &bps)?;
+        if race.status == RaceStatus::Claimed {
+            if let Some(claim_index) = ctx.accounts.claim_index.as_mut() {
+                claim_index.remove(&race.key());
+            }
+        }
+        ctx.accounts.global_stats.record_fee(fee)?;
+
+        pay_protocol_fee(
+            &race.to_account_info(),
+            ctx.accounts.treasury.as_ref(),
+            &treasury,
+            fee,
+        )?;
+        pay_host_cut(
+            &race.to_account_info(),
+            ctx.accounts.host.as_ref(),
+            &race.player1,
+            host_cut,
+        )?;
+        for (destination, amount) in destinations.iter().zip(amounts) {
+            pay_from_escrow(&race.to_account_info(), destination, amount)?;
+            msg!("Paid {} lamports of the prize to {}", amount, destination.key());
+        }
+
+        msg!(
+            "Prize of {} lamports claimed by winner {} across {} wallets for race: {} ({} lamports fee, {} lamports host cut)",
+            payout,
+            winner,
+            destinations.len(),
+            race.race_id,
+            fee,
+            host_cut
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Authority moderation: disqualify `player` in an `Active` race. At
+    /// settlement a disqualified player loses regardless of their result;
+    /// if both are disqualified the race is `Voided` for `refund_voided`.
+    pub fn disqualify(ctx: Context<Disqualify>, player: Pubkey) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        if player == race.player1 {
+            race.player1_dq = true;
+        } else if race.player2 == Some(player) {
+            race.player2_dq = true;
+        } else {
+            return err!(SolracerError::PlayerNotInRace);
+        }
+
+        emit!(PlayerDisqualified {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            player,
+        });
+
+        msg!("Player {} disqualified from race: {}", player, race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// Authority eligibility ruling after settlement: the winner turned out
+    /// to be ineligible (banned, disqualified off-chain), so the prize goes
+    /// to the runner-up instead. Unlike `resolve_dispute` this does not
+    /// revisit the results. Only for a `Settled` race whose prize is still
+    /// unclaimed, within `REASSIGN_WINDOW_SECS` of settlement; the
+    /// ineligible player is marked disqualified and `reason` goes into
+    /// `PrizeReassigned`. When passed, the race moves from the ineligible
+    /// player's `ClaimIndex` to the runner-up's.
+    pub fn reassign_prize(ctx: Context<ReassignPrize>, reason: IneligibilityReason) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Settled,
+            SolracerError::InvalidRaceStatus
+        );
+        let now = now()?;
+        require!(
+            race.settled_at > 0 && now <= race.settled_at.saturating_add(REASSIGN_WINDOW_SECS),
+            SolracerError::ReassignWindowClosed
+        );
+        // Not once a vesting installment has been paid out
+        require!(race.claimed_so_far == 0, SolracerError::AlreadyClaimed);
+
+        // A settled race always has both seats and a winner
+        let ineligible = race.winner.unwrap();
+        let runner_up = if ineligible == race.player1 {
+            race.player2_dq = false;
+            race.player1_dq = true;
+            race.player2.unwrap()
+        } else {
+            race.player1_dq = false;
+            race.player2_dq = true;
+            race.player1
+        };
+        race.winner = Some(runner_up);
+        race.outcome = Some(OutcomeReason::Reassigned);
+
+        if let Some(claim_index) = ctx.accounts.old_claim_index.as_mut() {
+            require_keys_eq!(claim_index.player, ineligible, SolracerError::ClaimIndexMismatch);
+            claim_index.remove(&race.key());
+        }
+        if let Some(claim_index) = ctx.accounts.new_claim_index.as_mut() {
+            require_keys_eq!(claim_index.player, runner_up, SolracerError::ClaimIndexMismatch);
+            if !claim_index.add(race.key()) {
+                msg!("Claim index of {} is full; race {} not indexed", runner_up, race.race_id);
+            }
+        }
+
+        emit!(PrizeReassigned {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            ineligible,
+            winner: runner_up,
+            reason,
+            prize: race.escrow_amount,
+            settled_at: race.settled_at,
+            reassigned_at: now,
+        });
+        emit_final_state(race, false)?;
+
+        msg!(
+            "Prize of race {} reassigned from {} to {}",
+            race.race_id,
+            ineligible,
+            runner_up
+        );
+
+        assert_invariants(race, &before)
+    }
+
+    /// Release this race's prize in `vesting_slices` equal installments over
+    /// `vesting_seconds` from settlement instead of all at once; the winner
+    /// claims each as it vests (see `vested_amount`). Both `0` turns vesting
+    /// off. At most `MAX_VESTING_SECS` and `MAX_VESTING_SLICES`, and only
+    /// while the race is `Waiting`, so the joiner stakes knowing the terms.
+    pub fn set_vesting(
+        ctx: Context<SetVesting>,
+        vesting_seconds: i64,
+        vesting_slices: u16,
+    ) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Waiting,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            (vesting_seconds == 0 && vesting_slices == 0)
+                || (vesting_seconds > 0 && vesting_slices > 0),
+            SolracerError::InvalidVestingSchedule
+        );
+        require!(
+            vesting_seconds <= MAX_VESTING_SECS && vesting_slices <= MAX_VESTING_SLICES,
+            SolracerError::InvalidVestingSchedule
+        );
+        race.vesting_seconds = vesting_seconds;
+        race.vesting_slices = vesting_slices;
+
+        msg!(
+            "Race {} prize vests in {} slices over {}s",
+            race.race_id,
+            vesting_slices,
+            vesting_seconds
+        );
+        assert_invariants(race, &before)
+    }
+
+    /// Operator curation: flag the race as featured (or not) for the UI.
+    /// Clients find featured races with a `memcmp` on
+    /// `Race::FEATURED_OFFSET`. A race written by an older program must
+    /// go through `realloc_race` first.
+    pub fn set_featured(ctx: Context<SetFeatured>, featured: bool) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+        let info = race.to_account_info();
+        require!(
+            !race.needs_realloc(info.data_len()),
+            SolracerError::RaceNeedsRealloc
+        );
+        info.try_borrow_mut_data()?[Race::FEATURED_OFFSET] = featured as u8;
+
+        emit!(RaceFeatured {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            featured,
+        });
+
+        msg!("Race {} featured: {}", race.race_id, featured);
+        assert_invariants(race, &before)
+    }
+
+    /// Permissionless refund of a `Voided` race: each seat's stake
+    /// (`player1_paid` / `player2_paid`) goes back to whoever paid it
+    /// (player1, `player2_fee_payer`, or the authority for an
+    /// operator-covered player1 slot). Anything else in the escrow, such
+    /// as an older race's chat deposits, goes to the authority. Pending
+    /// rematch opt-ins are refunded by the players with
+    /// `set_auto_rematch(false)`.
+    pub fn refund_voided(ctx: Context<RefundVoided>) -> Result<()> {
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Voided,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(race.escrow_amount > 0, SolracerError::AlreadyClaimed);
+
+        refund_stakes(
+            race,
+            &ctx.accounts.player1,
+            &ctx.accounts.player2_fee_payer,
+            &ctx.accounts.authority,
+        )?;
+
+        msg!("Voided race {} refunded", race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// Permissionless, with `Config.no_contest_on_no_show` set: resolve an
+    /// `Active` 1v1 race where neither player submitted by their deadline
+    /// as `NoContest`, refunding both stakes in full (as `refund_voided`
+    /// does, no fee). Unlike a forfeit nobody wins, so ratings and
+    /// analytics can tell the two apart. Emits `RaceCancelled` with reason
+    /// `NoContest`.
+    pub fn declare_no_contest(ctx: Context<DeclareNoContest>) -> Result<()> {
+        require!(
+            ctx.accounts.config.no_contest_on_no_show,
+            SolracerError::NoContestDisabled
+        );
+        let race = &mut ctx.accounts.race;
+        let before = race.status.clone();
+
+        require!(
+            race.status == RaceStatus::Active,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(race.both_no_show(now()?), SolracerError::NotNoContest);
+
+        race.status = RaceStatus::NoContest;
+        refund_stakes(
+            race,
+            &ctx.accounts.player1,
+            &ctx.accounts.player2_fee_payer,
+            &ctx.accounts.authority,
+        )?;
+
+        emit!(RaceCancelled {
+            race: race.key(),
+            race_id: race.race_id.clone(),
+            reason: CancelReason::NoContest,
+        });
+
+        msg!("Race {} is a no-contest; both stakes refunded", race.race_id);
+        assert_invariants(race, &before)
+    }
+
+    /// Permissionless: close a race whose stakes are all paid out (`Claimed`,
+    /// `NoContest`, or `Voided` and refunded), returning its rent to `rent_payer`
+    /// whoever won. The claim itself only ever pays the escrow, so a
+    /// creator's rent never ends up in someone else's prize. Fails with
+    /// `RematchDepositHeld` while a player's rematch opt-in is still held.
+    pub fn close_race(ctx: Context<CloseRace>) -> Result<()> {
+        let race = &ctx.accounts.race;
+
+        require!(
+            matches!(
+                race.status,
+                RaceStatus::Claimed | RaceStatus::Voided | RaceStatus::NoContest
+            ) && race.escrow_amount == 0,
+            SolracerError::InvalidRaceStatus
+        );
+        require!(
+            !race.player1_auto_rematch && !race.player2_auto_rematch,
+            SolracerError::RematchDepositHeld
+        );
+
+        msg!(
+            "Race {} closed, rent returned to {}",
+            race.race_id,
+            ctx.accounts.rent_payer.key()
+        );
+        Ok(())
+    }
+
+    /// Authority-only janitor: sweep every lamport left in a program-owned
+    /// account whose data is all zero (a close that zeroed the data but
+    /// left the balance) to the config treasury. Any account with data,
+    /// i.e. anything still live, fails with `AccountStillActive`.
+    pub fn reclaim_orphan_lamports(ctx: Context<ReclaimOrphanLamports>) -> Result<()> {
+        let orphan = ctx.accounts.orphan.to_account_info();
+        require!(
+            is_orphaned(&orphan.try_borrow_data()?),
+            SolracerError::AccountStillActive
+        );
+
+        let amount = orphan.lamports();
+        **orphan.try_borrow_mut_lamports()? = 0;
+        **ctx.accounts.treasury.to_account_info().try_borrow_mut_lamports()? += amount;
+
+        msg!(
+            "Reclaimed {} lamports from {} to treasury {}",
+            amount,
+            orphan.key(),
+            ctx.accounts.treasury.key()
+        );
+        Ok(())
+    }
+
+    /// Authority-only wind-down for deprecating the program: refund and close
+    /// every race in `remaining_accounts`, passed in groups of
+    /// `[race, player1, player2_fee_payer, rent_payer]`. Whatever its status,
+    /// each seat gets back exactly what was staked for it (as in
+    /// `refund_voided`), leftovers such as older chat deposits go to the
+    /// authority and the rent to `rent_payer`. Groups that can't be refunded
+    /// exactly (see `emergency_refundable`) or whose race account is not a
+    /// writable race are skipped rather than failing the batch. Returns a
+    /// borsh-encoded `EmergencyRefundSummary`.
+    pub fn emergency_refund_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, EmergencyRefundBatch<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len() % EMERGENCY_REFUND_GROUP == 0,
+            SolracerError::IncompleteRefundGroup
+        );
+        let groups = ctx.remaining_accounts.chunks(EMERGENCY_REFUND_GROUP);
+        check_batch_size(
+            groups.len(),
+            MAX_EMERGENCY_REFUND_BATCH,
+            EMERGENCY_REFUND_CU_PER_RACE,
+        )?;
+
+        let authority = ctx.accounts.authority.to_account_info();
+        let mut summary = EmergencyRefundSummary::default();
+
+        for group in groups {
+            let (race_info, player1, player2_fee_payer, rent_payer) =
+                (&group[0], &group[1], &group[2], &group[3]);
+
+            let race = if race_info.is_writable {
+                Account::<Race>::try_from(race_info).ok()
+            } else {
+                None
+            };
+            let Some(mut race) = race.filter(|race| {
+                emergency_refundable(race, player1.key, player2_fee_payer.key, rent_payer.key)
+            }) else {
+                msg!("Skipping {}", race_info.key());
+                summary.skipped += 1;
+                continue;
+            };
+
+            let refunded = race.escrow_amount;
+            if refunded > 0 {
+                with_race_context(&race.race_id.clone(), || {
+                    refund_stakes(&mut race, player1, player2_fee_payer, &authority)
+                })?;
+            }
+
+            emit!(RaceCancelled {
+                race: race.key(),
+                race_id: race.race_id.clone(),
+                reason: CancelReason::Emergency,
+            });
+            msg!(
+                "Race {} refunded {} lamports and closed",
+                race.race_id,
+                refunded
+            );
+            race.close(rent_payer.clone())?;
+
+            summary.refunded += 1;
+            summary.lamports_refunded = summary
+                .lamports_refunded
+                .checked_add(refunded)
+                .ok_or(SolracerError::MathOverflow)?;
+        }
+
+        set_return_data(&summary.try_to_vec()?);
+
+        msg!(
+            "Emergency refund: {} races refunded ({} lamports), {} skipped",
+            summary.refunded,
+            summary.lamports_refunded,
+            summary.skipped
+        );
+        Ok(())
+    }
+
+    /// Read-only audit of the race's escrow (see `reconcile`), returned as
+    /// a borsh-encoded `EscrowReconciliation`. Fails with `EscrowImbalance`
+    /// when the books don't balance.
+    pub fn reconcile_escrow(ctx: Context<ReconcileEscrow>) -> Result<()> {
+        let race = &ctx.accounts.race;
+        let info = race.to_account_info();
+        let rent_reserve = Rent::get()?.minimum_balance(info.data_len());
+        let reconciliation = reconcile(race, info.lamports(), rent_reserve)?;
+        set_return_data(&reconciliation.try_to_vec()?);
+
+        msg!(
+            "Race {} escrow balances: {} deposited, {} paid out ({} in fees), {} held",
+            race.race_id,
+            reconciliation.total_deposited,
+            reconciliation.total_paid_out,
+            reconciliation.fees_paid,
+            reconciliation.escrow_amount
+        );
+        Ok(())
+    }
+
+    /// Grow a race written by an older program to the current `Race::LEN`
+    /// and stamp it with `RACE_VERSION`; fields its layout lacked take
+    /// their defaults, and the race is not `featured` (see
+    /// `upgrade_race_data`). `payer` covers the extra rent, so anyone may
+    /// call it. A race that is already current is left as is.
+    pub fn realloc_race(ctx: Context<ReallocRace>) -> Result<()> {
+        let info = ctx.accounts.race.to_account_info();
+        let old_len = info.data_len();
+        let new_len = 8 + Race::LEN;
+        if info.data_len() < new_len {
+            let shortfall = Rent::get()?
+                .minimum_balance(new_len)
+                .saturating_sub(info.lamports());
+            if shortfall > 0 {
+                transfer_lamports(
+                    &ctx.accounts.payer.to_account_info(),
+                    &info,
+                    &ctx.accounts.system_program.to_account_info(),
+                    shortfall,
+                )?;
+            }
+            info.resize(new_len)?;
+        }
+
+        let from_version = upgrade_race_data(&mut info.try_borrow_mut_data()?, old_len)?;
+        if from_version == RACE_VERSION {
+            return Ok(());
+        }
+        let race = Race::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+
+        msg!(
+            "Race {} upgraded from version {} to {}",
+            race.race_id,
+            from_version,
+            RACE_VERSION
+        );
+        Ok(())
+    }
+
+    /// Read-only: the protocol-wide `GlobalStats`, returned as borsh-encoded
+    /// return data (the account without its discriminator).
+    pub fn read_global_stats(ctx: Context<ReadGlobalStats>) -> Result<()> {
+        let stats = &ctx.accounts.global_stats;
+        set_return_data(&stats.try_to_vec()?);
+
+        msg!(
+            "{} races, {} settled, {} lamports escrowed, {} lamports in fees",
+            stats.total_races,
+            stats.total_settled,
+            stats.total_volume_lamports,
+            stats.total_fees_collected
+        );
+        Ok(())
+    }
+
+    /// Read-only: the lamports a claim would pay `player` from this race
+    /// right now, after the protocol fee and host cut (see
+    /// `claimable_amount`), as little-endian `u64` return data.
+    pub fn claimable_for(ctx: Context<ClaimableFor>, player: Pubkey) -> Result<()> {
+        let (fee_bps, _) = load_fee_schedule(&ctx.accounts.config, &ctx.accounts.token_config)?;
+        let amount = claimable_amount(
+            &ctx.accounts.race,
+            &player,
+            now()?,
+            fee_bps,
+            ctx.accounts.config.host_cut_bps,
+        )?;
+        set_return_data(&amount.to_le_bytes());
+
+        msg!(
+            "{} may claim {} lamports from race: {}",
+            player,
+            amount,
+            ctx.accounts.race.race_id
+        );
+        Ok(())
+    }
+
+    /// Read-only: the lobby's live leaderboard (see `lobby_standings`), as
+    /// a borsh-encoded `Vec<Standing>` in return data.
+    pub fn race_standings(ctx: Context<RaceStandings>) -> Result<()> {
+        let lobby = &ctx.accounts.lobby;
+        let standings = lobby_standings(lobby);
+        set_return_data(&standings.try_to_vec()?);
+
+        msg!(
+            "Lobby {}: {} of {} results in",
+            lobby.race_id,
+            standings.iter().filter(|s| s.place.is_some()).count(),
+            standings.len()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Winner {
+    Player1,
+    Player2,
+}
+
+/// Whether `result` collected at least `min_coins_to_win` coins.
+pub fn meets_min_coins(result: &RaceResult, min_coins_to_win: u64) -> bool {
+    result.coins_collected >= min_coins_to_win
+}
+
+/// `determine_winner` among the results that meet `min_coins_to_win`: a
+/// lone qualifier wins whatever the times, and with none the race has no
+/// winner (`None`) and is voided for `refund_voided`.
+pub fn qualified_winner(
+    player1_result: &RaceResult,
+    player2_result: &RaceResult,
+    config: &RaceConfig,
+    min_coins_to_win: u64,
+) -> Option<Winner> {
+    match (
+        meets_min_coins(player1_result, min_coins_to_win),
+        meets_min_coins(player2_result, min_coins_to_win),
+    ) {
+        (true, true) => Some(determine_winner(player1_result, player2_result, config)),
+        (true, false) => Some(Winner::Player1),
+        (false, true) => Some(Winner::Player2),
+        (false, false) => None,
+    }
+}
+
+/// Decide a 1v1 race according to `config.tiebreak`; player1 takes a full
+/// tie in every mode but `HashOrder`.
+///
+/// - `TimeThenCoins`: the faster time wins unless the gap is within
+///   `config.tie_epsilon_ms`, in which case more coins wins.
+/// - `CoinsOnly`: more coins wins; equal coins fall back to the faster time.
+/// - `Efficiency`: as `TimeThenCoins`, but a time tie goes to the higher
+///   `coins_collected / finish_time_ms`.
+/// - `HashOrder`: as `TimeThenCoins`, but a full tie goes to the smaller
+///   `input_hash`. Hashes are effectively random, so neither seat is
+///   favoured; only identical hashes fall back to player1.
+pub fn determine_winner(
+    player1_result: &RaceResult,
+    player2_result: &RaceResult,
+    config: &RaceConfig,
+) -> Winner {
+    let time1 = quantize_time(player1_result.finish_time_ms, config.time_quantum_ms);
+    let time2 = quantize_time(player2_result.finish_time_ms, config.time_quantum_ms);
+    // Greater when player1's coin count is the better one
+    let coins = if config.fewer_coins_win {
+        player2_result.coins_collected.cmp(&player1_result.coins_collected)
+    } else {
+        player1_result.coins_collected.cmp(&player2_result.coins_collected)
+    };
+
+    match config.tiebreak {
+        TiebreakMode::TimeThenCoins => {
+            if time1.abs_diff(time2) > config.tie_epsilon_ms {
+                return if time1 < time2 {
+                    Winner::Player1
+                } else {
+                    Winner::Player2
+                };
+            }
+
+            if coins != Ordering::Less {
+                Winner::Player1
+            } else {
+                Winner::Player2
+            }
+        }
+        TiebreakMode::CoinsOnly => {
+            if coins != Ordering::Equal {
+                return if coins == Ordering::Greater {
+                    Winner::Player1
+                } else {
+                    Winner::Player2
+                };
+            }
+
+            if time1 <= time2 {
+                Winner::Player1
+            } else {
+                Winner::Player2
+            }
+        }
+        TiebreakMode::Efficiency => {
+            if time1.abs_diff(time2) > config.tie_epsilon_ms {
+                return if time1 < time2 {
+                    Winner::Player1
+                } else {
+                    Winner::Player2
+                };
+            }
+
+            if compare_efficiency(player1_result, player2_result) != Ordering::Less {
+                Winner::Player1
+            } else {
+                Winner::Player2
+            }
+        }
+        TiebreakMode::HashOrder => {
+            if time1.abs_diff(time2) > config.tie_epsilon_ms {
+                return if time1 < time2 {
+                    Winner::Player1
+                } else {
+                    Winner::Player2
+                };
+            }
+
+            match coins {
+                Ordering::Greater => Winner::Player1,
+                Ordering::Less => Winner::Player2,
+                Ordering::Equal if player2_result.input_hash < player1_result.input_hash => {
+                    Winner::Player2
+                }
+                Ordering::Equal => Winner::Player1,
+            }
+        }
+    }
+}
+
+/// Order two results by `coins_collected / finish_time_ms` without
+/// dividing: the ratios are compared by cross-multiplying in `u128`, which
+/// is exact and can't overflow. A time of 0 counts as 1 ms.
+pub fn compare_efficiency(a: &RaceResult, b: &RaceResult) -> Ordering {
+    let scaled = |coins: u64, time_ms: u64| coins as u128 * time_ms.max(1) as u128;
+    scaled(a.coins_collected, b.finish_time_ms).cmp(&scaled(b.coins_collected, a.finish_time_ms))
+}
+
+/// `time_ms` rounded to the nearest multiple of `quantum_ms` (halves round
+/// up). A quantum of 0 or 1 leaves the time unchanged. The stored result
+/// keeps the raw time so it still matches its `input_hash`.
+pub fn quantize_time(time_ms: u64, quantum_ms: u64) -> u64 {
+    if quantum_ms <= 1 {
+        return time_ms;
+    }
+    let rounded_down = time_ms - time_ms % quantum_ms;
+    if time_ms % quantum_ms >= quantum_ms.div_ceil(2) {
+        rounded_down.saturating_add(quantum_ms)
+    } else {
+        rounded_down
+    }
+}
+
+/// Which rule `determine_winner` applied to these results.
+pub fn outcome_reason(
+    player1_result: &RaceResult,
+    player2_result: &RaceResult,
+    config: &RaceConfig,
+) -> OutcomeReason {
+    let time1 = quantize_time(player1_result.finish_time_ms, config.time_quantum_ms);
+    let time2 = quantize_time(player2_result.finish_time_ms, config.time_quantum_ms);
+    let coins_differ = player1_result.coins_collected != player2_result.coins_collected;
+
+    match config.tiebreak {
+        TiebreakMode::TimeThenCoins if time1.abs_diff(time2) > config.tie_epsilon_ms => {
+            OutcomeReason::FasterTime
+        }
+        TiebreakMode::CoinsOnly if !coins_differ && time1 != time2 => OutcomeReason::FasterTime,
+        TiebreakMode::Efficiency if time1.abs_diff(time2) > config.tie_epsilon_ms => {
+            OutcomeReason::FasterTime
+        }
+        TiebreakMode::Efficiency => {
+            match compare_efficiency(player1_result, player2_result) {
+                Ordering::Equal => OutcomeReason::Tie,
+                _ => OutcomeReason::Efficiency,
+            }
+        }
+        _ if coins_differ && config.fewer_coins_win => OutcomeReason::FewerCoins,
+        _ if coins_differ => OutcomeReason::MoreCoins,
+        TiebreakMode::HashOrder if player1_result.input_hash != player2_result.input_hash => {
+            OutcomeReason::HashOrder
+        }
+        _ => OutcomeReason::Tie,
+    }
+}
+
+/// Combined time for a team under the given aggregation.
+pub fn team_time(results: &[RaceResult; 2], aggregation: TeamAggregation) -> u64 {
+    match aggregation {
+        TeamAggregation::SumOfTimes => results[0]
+            .finish_time_ms
+            .saturating_add(results[1].finish_time_ms),
+        TeamAggregation::BestTime => results[0].finish_time_ms.min(results[1].finish_time_ms),
+    }
+}
+
+/// Returns the winning team (1 or 2). Lower aggregated time wins; on a tie
+/// the team with more total coins wins, and team1 takes a full tie, matching
+/// the 1v1 tiebreak.
+pub fn determine_winning_team(
+    team1: &[RaceResult; 2],
+    team2: &[RaceResult; 2],
+    aggregation: TeamAggregation,
+) -> u8 {
+    let time1 = team_time(team1, aggregation);
+    let time2 = team_time(team2, aggregation);
+
+    if time1 != time2 {
+        return if time1 < time2 { 1 } else { 2 };
+    }
+
+    let coins = |team: &[RaceResult; 2]| {
+        team[0]
+            .coins_collected
+            .saturating_add(team[1].coins_collected)
+    };
+    if coins(team1) >= coins(team2) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Indices of the submitted results, best first. Each place is picked by
+/// running `determine_winner` across the remaining results, with the
+/// earlier seat acting as player1 so ties go to whoever joined first.
+/// Unsubmitted (`None`) results are left out.
+pub fn rank_results(results: &[Option<RaceResult>], config: &RaceConfig) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..results.len())
+        .filter(|&i| results[i].is_some())
+        .collect();
+    let mut ranking = Vec::with_capacity(remaining.len());
+
+    while !remaining.is_empty() {
+        let mut best = 0;
+        for candidate in 1..remaining.len() {
+            let current = results[remaining[best]].as_ref().unwrap();
+            let challenger = results[remaining[candidate]].as_ref().unwrap();
+            if determine_winner(current, challenger, config) == Winner::Player2 {
+                best = candidate;
+            }
+        }
+        ranking.push(remaining.remove(best));
+    }
+
+    ranking
+}
+
+/// Every seat of `lobby` in join order with its result and its place by
+/// `rank_results` (1 = leading), as settlement would rank them now.
+/// Players yet to submit have no place.
+pub fn lobby_standings(lobby: &Lobby) -> Vec<Standing> {
+    let ranking = rank_results(&lobby.results, &lobby.config);
+    lobby
+        .players
+        .iter()
+        .zip(&lobby.results)
+        .enumerate()
+        .map(|(seat, (player, result))| Standing {
+            player: *player,
+            result: result.clone(),
+            place: ranking
+                .iter()
+                .position(|&ranked| ranked == seat)
+                .map(|place| place as u8 + 1),
+        })
+        .collect()
+}
+
+/// Rough compute cost of a batch of `len` items at `cu_per_item` each.
+pub fn batch_compute_estimate(len: usize, cu_per_item: u64) -> u64 {
+    cu_per_item.saturating_mul(len as u64)
+}
+
+/// Reject batches over `cap` with `BatchTooLarge` instead of letting them
+/// run into the compute ceiling, and log the estimated cost, with a warning
+/// when it exceeds the default limit so clients know to raise the budget.
+fn check_batch_size(len: usize, cap: usize, cu_per_item: u64) -> Result<()> {
+    require!(len <= cap, SolracerError::BatchTooLarge);
+
+    let estimate = batch_compute_estimate(len, cu_per_item);
+    msg!(
+        "Batch of {} (cap {}), estimated {} compute units",
+        len,
+        cap,
+        estimate
+    );
+    if estimate > DEFAULT_COMPUTE_UNIT_LIMIT {
+        msg!(
+            "Estimated cost exceeds the default {} compute units; request a higher limit",
+            DEFAULT_COMPUTE_UNIT_LIMIT
+        );
+    }
+
+    Ok(())
+}
+
+/// Run one race's share of a batch instruction, logging `race` (its
+/// `race_id`, or the account key before it is deserialized) alongside any
+/// error. The error is returned unchanged so its code stays matchable.
+fn with_race_context<T>(race: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f().map_err(|err| {
+        msg!("Batch step failed for race {}: {}", race, err);
+        err
+    })
+}
+
+/// The wallet acting in a session-or-wallet instruction: the session's
+/// player when a valid session is supplied, otherwise the signer itself.
+fn resolve_player(session: Option<&PlayerSession>, authority: &Pubkey) -> Result<Pubkey> {
+    match session {
+        Some(session) => {
+            require!(
+                now()? < session.expires_at,
+                SolracerError::SessionExpired
+            );
+            require!(
+                session.session_key == *authority,
+                SolracerError::InvalidSessionKey
+            );
+            Ok(session.player_wallet)
+        }
+        None => Ok(*authority),
+    }
+}
+
+/// Shared claim bookkeeping: checks `claimant` may claim a settled race,
+/// marks it `Claimed` before any funds move and empties the escrow.
+/// Returns the lamports the caller must pay out.
+fn take_prize(race: &mut Race, claimant: Pubkey, now: i64) -> Result<u64> {
+    require!(
+        race.status != RaceStatus::Claimed,
+        SolracerError::AlreadyClaimed
+    );
+    require!(
+        race.status == RaceStatus::Settled,
+        SolracerError::RaceNotSettledYet
+    );
+    require!(race.winner == Some(claimant), SolracerError::NotWinner);
+    require!(!race.imported, SolracerError::ImportedRace);
+    require!(now >= race.claim_opens_at(), SolracerError::ClaimDelayActive);
+
+    let prize_amount = releasable_amount(race, &claimant, now);
+    require!(
+        prize_amount > 0 || race.vesting_seconds == 0,
+        SolracerError::NothingVestedYet
+    );
+    release_prize(race, prize_amount)?;
+    Ok(prize_amount)
+}
+
+/// Pay `amount` of the prize out of the escrow, counting it in
+/// `claimed_so_far`; the race is `Claimed` once nothing is left.
+fn release_prize(race: &mut Race, amount: u64) -> Result<()> {
+    let remaining = race.escrow_amount;
+    race.debit_escrow(amount)?;
+    race.claimed_so_far = race
+        .claimed_so_far
+        .checked_add(amount)
+        .ok_or(SolracerError::MathOverflow)?;
+    if amount == remaining {
+        race.status = RaceStatus::Claimed;
+    }
+    Ok(())
+}
+
+/// The `(fee_bps, treasury)` `claim_prize` applies: the race token's
+/// `TokenConfig` when one exists, otherwise the global `Config`.
+pub fn fee_schedule(config: &Config, token_config: Option<&TokenConfig>) -> (u16, Pubkey) {
+    match token_config {
+        Some(token_config) => (token_config.fee_bps, token_config.treasury),
+        None => (config.fee_bps, config.treasury),
+    }
+}
+
+/// The account at `info`, a PDA that need not exist, or `None` while it
+/// is uninitialized.
+fn load_if_initialized<T: AccountDeserialize>(info: &AccountInfo) -> Result<Option<T>> {
+    if *info.owner != crate::ID {
+        return Ok(None);
+    }
+    Ok(Some(T::try_deserialize(&mut &info.try_borrow_data()?[..])?))
+}
+
+/// The token's `TokenConfig` from `token_config_info`, its PDA, or `None`
+/// while the PDA is uninitialized.
+fn load_token_config(token_config_info: &AccountInfo) -> Result<Option<TokenConfig>> {
+    load_if_initialized(token_config_info)
+}
+
+/// `fee_schedule` with `token_config_info`, the race token's `TokenConfig`
+/// PDA, read when it is initialized.
+fn load_fee_schedule(config: &Config, token_config_info: &AccountInfo) -> Result<(u16, Pubkey)> {
+    let token_config = load_token_config(token_config_info)?;
+    Ok(fee_schedule(config, token_config.as_ref()))
+}
+
+/// Fail with `TokenPaused` when the token's `TokenConfig` is paused.
+fn check_token_not_paused(token_config_info: &AccountInfo) -> Result<()> {
+    let paused = load_token_config(token_config_info)?
+        .is_some_and(|token_config| token_config.paused);
+    require!(!paused, SolracerError::TokenPaused);
+    Ok(())
+}
+
+/// Pay a protocol `fee` out of `escrow` to `treasury_info`, which must be
+/// `treasury` and may be omitted while the fee is zero.
+fn pay_protocol_fee(
+    escrow: &AccountInfo,
+    treasury_info: Option<&UncheckedAccount>,
+    treasury: &Pubkey,
+    fee: u64,
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+    let treasury_info = treasury_info
+        .filter(|account| account.key() == *treasury)
+        .ok_or(SolracerError::TreasuryMismatch)?;
+    pay_from_escrow(escrow, treasury_info, fee)
+}
+
+/// Empty the escrow of a race that ended without a winner: each seat's
+/// stake back to whoever paid it (`player1`, or `authority` for an
+/// operator-covered player1 slot, and `player2_fee_payer`), and anything
+/// else in it, such as an older race's chat deposits, to `authority`.
+fn refund_stakes<'info>(
+    race: &mut Account<'info, Race>,
+    player1: &AccountInfo<'info>,
+    player2_fee_payer: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+) -> Result<()> {
+    let (player1_paid, player2_paid) = (race.player1_paid, race.player2_paid);
+    let player1_refund = if race.player1_prefunded { authority } else { player1 };
+    let stakes = player1_paid
+        .checked_add(player2_paid)
+        .ok_or(SolracerError::MathOverflow)?;
+    let remainder = race
+        .escrow_amount
+        .checked_sub(stakes)
+        .ok_or(SolracerError::EscrowMismatch)?;
+
+    let escrow = race.escrow_amount;
+    race.debit_escrow(escrow)?;
+    let race_info = race.to_account_info();
+    pay_from_escrow(&race_info, player1_refund, player1_paid)?;
+    pay_from_escrow(&race_info, player2_fee_payer, player2_paid)?;
+    pay_from_escrow(&race_info, authority, remainder)
+}
+
+/// Pay `host_cut` from `escrow` to the race's creator `host`, passed as
+/// `host_info` (may be omitted while the cut is zero).
+fn pay_host_cut(
+    escrow: &AccountInfo,
+    host_info: Option<&UncheckedAccount>,
+    host: &Pubkey,
+    host_cut: u64,
+) -> Result<()> {
+    if host_cut == 0 {
+        return Ok(());
+    }
+    let host_info = host_info
+        .filter(|account| account.key() == *host)
+        .ok_or(SolracerError::HostMismatch)?;
+    pay_from_escrow(escrow, host_info, host_cut)
+}
+
+/// Fail unless `fee_bps` and `host_cut_bps` together take at most the
+/// whole prize.
+fn check_fee_split(fee_bps: u16, host_cut_bps: u16) -> Result<()> {
+    require!(fee_bps <= SPLIT_TOTAL_BPS, SolracerError::InvalidFeeBps);
+    require!(
+        fee_bps as u32 + host_cut_bps as u32 <= SPLIT_TOTAL_BPS as u32,
+        SolracerError::FeesExceedPrize
+    );
+    Ok(())
+}
+
+/// Split a claimed prize into the protocol fee, the host cut and the
+/// winner's payout, which together add up to `prize`. `set_host_cut` only
+/// checks the cut against the global fee, so a token fee can still push
+/// the two past the whole prize; the host then gets what the fee leaves
+/// rather than the claim failing.
+pub fn claim_split(prize: u64, fee_bps: u16, host_cut_bps: u16) -> Result<(u64, u64, u64)> {
+    require!(fee_bps <= SPLIT_TOTAL_BPS, SolracerError::InvalidFeeBps);
+    let host_cut_bps = host_cut_bps.min(SPLIT_TOTAL_BPS - fee_bps);
+    let fee = protocol_fee(prize, fee_bps);
+    let host_cut = protocol_fee(prize, host_cut_bps);
+    Ok((fee, host_cut, prize - fee - host_cut))
+}
+
+/// The protocol fee on a prize of `amount` at `fee_bps`, rounded down.
+pub fn protocol_fee(amount: u64, fee_bps: u16) -> u64 {
+    (amount as u128 * fee_bps as u128 / SPLIT_TOTAL_BPS as u128) as u64
+}
+
+/// Prize lamports `player` has yet to claim from `race`, before fees: the
+/// whole escrow for the winner of a settled, non-imported race, otherwise
+/// 0. Shared by the claim instructions through `releasable_amount`.
+fn unclaimed_prize(race: &Race, player: &Pubkey) -> u64 {
+    if race.status == RaceStatus::Settled && race.winner == Some(*player) && !race.imported {
+        race.escrow_amount
+    } else {
+        0
+    }
+}
+
+/// The host cut in bps a claim by `claimant` pays: the configured
+/// `host_cut_bps`, or none when the creator wins their own race.
+fn claimant_host_cut_bps(race: &Race, claimant: &Pubkey, host_cut_bps: u16) -> u16 {
+    if *claimant == race.player1 {
+        0
+    } else {
+        host_cut_bps
+    }
+}
+
+/// Lamports a claim by `player` would pay them from `race` at `now`: the
+/// `releasable_amount` less the protocol fee at `fee_bps` and the host cut
+/// at `host_cut_bps` (see `claimant_host_cut_bps`), split as `claim_split`
+/// splits it. Shared by `claimable_for`.
+pub fn claimable_amount(
+    race: &Race,
+    player: &Pubkey,
+    now: i64,
+    fee_bps: u16,
+    host_cut_bps: u16,
+) -> Result<u64> {
+    let releasable = releasable_amount(race, player, now);
+    let host_cut_bps = claimant_host_cut_bps(race, player, host_cut_bps);
+    let (_, _, payout) = claim_split(releasable, fee_bps, host_cut_bps)?;
+    Ok(payout)
+}
+
+/// Whether `emergency_refund_batch` can refund and close `race` with these
+/// refund accounts: nothing may have been claimed from it, no rematch
+/// opt-in may be held outside the escrow, the escrow must cover both
+/// stakes (or already be empty, e.g. a refunded `Voided` race) and the
+/// accounts must be the race's own.
+pub fn emergency_refundable(
+    race: &Race,
+    player1: &Pubkey,
+    player2_fee_payer: &Pubkey,
+    rent_payer: &Pubkey,
+) -> bool {
+    let stakes = race.player1_paid.saturating_add(race.player2_paid);
+    race.status != RaceStatus::Claimed
+        && race.claimed_so_far == 0
+        && !race.player1_auto_rematch
+        && !race.player2_auto_rematch
+        && (race.escrow_amount == 0 || stakes <= race.escrow_amount)
+        && *player1 == race.player1
+        && *player2_fee_payer == race.player2_fee_payer
+        && *rent_payer == race.rent_recipient()
+}
+
+/// The part of `unclaimed_prize` that has vested by `now`, before fees;
+/// all of it for a race without vesting, and nothing while the claim
+/// delay runs.
+pub fn releasable_amount(race: &Race, player: &Pubkey, now: i64) -> u64 {
+    let unclaimed = unclaimed_prize(race, player);
+    if now < race.claim_opens_at() {
+        return 0;
+    }
+    if race.vesting_seconds == 0 {
+        return unclaimed;
+    }
+    let total = unclaimed.saturating_add(race.claimed_so_far);
+    let elapsed = now.saturating_sub(race.settled_at);
+    vested_amount(total, race.vesting_seconds, race.vesting_slices, elapsed)
+        .saturating_sub(race.claimed_so_far)
+}
+
+/// How much of a `total` prize has vested `elapsed` seconds after
+/// settlement: one of `vesting_slices` equal slices per
+/// `vesting_seconds / vesting_slices`, the last slice carrying any
+/// rounding remainder. Without a schedule it is all of it.
+pub fn vested_amount(total: u64, vesting_seconds: i64, vesting_slices: u16, elapsed: i64) -> u64 {
+    if vesting_seconds <= 0 || vesting_slices == 0 {
+        return total;
+    }
+    let slices = vesting_slices as u128;
+    let vested_slices = (elapsed.max(0) as u128 * slices / vesting_seconds as u128).min(slices);
+    (total as u128 * vested_slices / slices) as u64
+}
+
+/// Chat root after appending one message: `sha256(prev_root || message_hash)`.
+/// A fresh race starts from the all-zero root.
+pub fn next_chat_root(prev_root: &[u8; 32], message_hash: &[u8; 32]) -> [u8; 32] {
+    solana_sha256_hasher::hashv(&[prev_root, message_hash]).to_bytes()
+}
+
+/// The race PDA and bump for these `create_race` inputs. `creator` is the
+/// race's player1, so each creator has its own `race_id` namespace.
+///
+/// Seeds (v2): `[b"race", creator, race_id, token_mint, entry_fee_sol (u64 LE)]`.
+/// v1 omitted `creator`; races created under v1 are not reachable with
+/// these seeds.
+pub fn race_pda(
+    creator: &Pubkey,
+    race_id: &str,
+    token_mint: &Pubkey,
+    entry_fee_sol: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"race",
+            creator.as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        &crate::ID,
+    )
+}
+
+/// The `TokenConfig` PDA of `token_mint`: `[b"token_config", token_mint]`.
+pub fn token_config_pda(token_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_config", token_mint.as_ref()], &crate::ID).0
+}
+
+/// Race id of the next rematch: `<id>/r1`, then `<id>/r2` and so on.
+/// `None` once the id would no longer fit in `MAX_RACE_ID_LEN`.
+pub fn rematch_race_id(race_id: &str) -> Option<String> {
+    let (base, round) = match race_id.rsplit_once("/r") {
+        Some((base, round)) if !round.is_empty() && round.bytes().all(|b| b.is_ascii_digit()) => {
+            (base, round.parse::<u32>().ok()?.checked_add(1)?)
+        }
+        _ => (race_id, 1),
+    };
+
+    let rematch_id = format!("{}/r{}", base, round);
+    (rematch_id.len() <= MAX_RACE_ID_LEN).then_some(rematch_id)
+}
+
+/// True once a race at `rematch_count` in its chain may not spawn another
+/// rematch under `max_rematches` (0 is unlimited).
+pub fn rematch_limit_reached(rematch_count: u16, max_rematches: u16) -> bool {
+    max_rematches > 0 && rematch_count >= max_rematches
+}
+
+/// Check `entry_fee_sol` against `config.min_entry_fee` and
+/// `config.max_entry_fee` (0 is unlimited).
+pub fn check_entry_fee(config: &Config, entry_fee_sol: u64) -> Result<()> {
+    require!(
+        entry_fee_sol >= config.min_entry_fee,
+        SolracerError::EntryFeeTooLow
+    );
+    require!(
+        config.max_entry_fee == 0 || entry_fee_sol <= config.max_entry_fee,
+        SolracerError::EntryFeeTooHigh
+    );
+    Ok(())
+}
+
+/// Create a program-owned, `Race`-sized account at the race PDA signed
+/// for by `signer_seeds`, funded with `lamports` by `payer`.
+fn create_race_account<'info>(
+    payer: &AccountInfo<'info>,
+    race_info: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    lamports: u64,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    anchor_lang::solana_program::program::invoke_signed(
+        &anchor_lang::solana_program::system_instruction::create_account(
+            payer.key,
+            race_info.key,
+            lamports,
+            (8 + Race::LEN) as u64,
+            &crate::ID,
+        ),
+        &[payer.clone(), race_info.clone(), system_program.clone()],
+        &[signer_seeds],
+    )?;
+    Ok(())
+}
+
+/// Decide an `Active` race with both results in (just player1's for a
+/// house race) and mark it `Settled`. A 1v1 whose times are further apart
+/// than `config.max_result_ratio` is marked `Disputed` instead and `None`
+/// is returned. Shared by `settle_race` and its variants; a race whose
+/// winner hook is strict only settles through `settle_race_with_hook`.
+/// The claim delay for the pot is read from `claim_delay_tiers`, the
+/// `ClaimDelayTiers` PDA, when it exists.
+fn settle_on_results(
+    race: &mut Account<Race>,
+    claim_delay_tiers: &AccountInfo,
+) -> Result<Option<Pubkey>> {
+    require!(
+        !race.config.winner_hook_strict,
+        SolracerError::WinnerHookRequired
+    );
+    decide_race(race, claim_delay_tiers)
+}
+
+/// `settle_on_results` without the winner hook check.
+fn decide_race(race: &mut Account<Race>, claim_delay_tiers: &AccountInfo) -> Result<Option<Pubkey>> {
+    require!(
+        race.status == RaceStatus::Active,
+        SolracerError::InvalidRaceStatus
+    );
+
+    let (winner, reason) = match (race.player1_dq, race.player2_dq) {
+        (true, true) => {
+            race.status = RaceStatus::Voided;
+            msg!("Race {} voided: both players disqualified", race.race_id);
+            return Ok(None);
+        }
+        // A disqualified player loses whatever they submitted
+        (true, false) => (race.player2.unwrap(), OutcomeReason::Disqualification),
+        (false, true) => (race.player1, OutcomeReason::Disqualification),
+        (false, false) => {
+            require!(
+                !race.config.mutual_settlement,
+                SolracerError::MutualSettlementRequired
+            );
+            match results_winner(race)? {
+                Some(decided) => decided,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    race.winner = Some(winner);
+    race.outcome = Some(reason);
+    race.status = RaceStatus::Settled;
+    race.settled_at = now()?;
+    race.claim_delay_secs = load_if_initialized::<ClaimDelayTiers>(claim_delay_tiers)?
+        .map_or(0, |tiers| tiers.delay_for(race.escrow_amount));
+
+    emit!(RaceSettled {
+        race: race.key(),
+        race_id: race.race_id.clone(),
+        winner,
+        conceded: false,
+    });
+    emit_final_state(race, false)?;
+
+    msg!("Race {} settled. Winner: {}", race.race_id, winner);
+
+    Ok(Some(winner))
+}
+
+/// The results-based half of `settle_on_results`: the winner and why, or
+/// `None` with the race marked `Disputed` when the results were flagged.
+/// Results still missing fail with `ResultsNotComplete` until the
+/// submission deadline, after which the race is settled by forfeit.
+fn results_winner(race: &mut Account<Race>) -> Result<Option<(Pubkey, OutcomeReason)>> {
+    let decided = match race.house_target_ms {
+        Some(_) if race.player1_result.is_none() => {
+            let winner = race.forfeit_winner(now()?).ok_or(SolracerError::ResultsNotComplete)?;
+            (winner, OutcomeReason::Forfeit)
+        }
+        Some(target_time_ms) => {
+            let player1_result = race.player1_result.as_ref().unwrap();
+
+            let winner = if player1_result.finish_time_ms < target_time_ms {
+                race.player1
+            } else {
+                race.player2.unwrap()
+            };
+            (winner, OutcomeReason::HouseTarget)
+        }
+        None if race.player1_result.is_none() || race.player2_result.is_none() => {
+            let winner = race.forfeit_winner(now()?).ok_or(SolracerError::ResultsNotComplete)?;
+            let result = race.player1_result.as_ref().or(race.player2_result.as_ref()).unwrap();
+            if !meets_min_coins(result, race.min_coins_to_win) {
+                race.status = RaceStatus::Voided;
+                msg!("Race {} voided: no qualifying result", race.race_id);
+                return Ok(None);
+            }
+            (winner, OutcomeReason::Forfeit)
+        }
+        None => {
+            let player1_result = race.player1_result.as_ref().unwrap();
+            let player2_result = race.player2_result.as_ref().unwrap();
+
+            if let Some(reason) = dispute_reason(player1_result, player2_result, &race.config) {
+                mark_disputed(race, reason);
+
+                msg!("Race {} disputed ({:?}) pending authority review", race.race_id, reason);
+                return Ok(None);
+            }
+
+            let qualifying = (
+                meets_min_coins(player1_result, race.min_coins_to_win),
+                meets_min_coins(player2_result, race.min_coins_to_win),
+            );
+            let reason = match qualifying {
+                (true, true) => outcome_reason(player1_result, player2_result, &race.config),
+                _ => OutcomeReason::BelowMinCoins,
+            };
+            let winner = match qualified_winner(
+                player1_result,
+                player2_result,
+                &race.config,
+                race.min_coins_to_win,
+            ) {
+                Some(Winner::Player1) => race.player1,
+                Some(Winner::Player2) => race.player2.unwrap(),
+                None => {
+                    race.status = RaceStatus::Voided;
+                    msg!("Race {} voided: neither result qualifies", race.race_id);
+                    return Ok(None);
+                }
+            };
+            (winner, reason)
+        }
+    };
+
+    Ok(Some(decided))
+}
+
+/// Hold a race for `resolve_dispute` and emit `RaceDisputed`; a missing
+/// result reports a time of 0.
+fn mark_disputed(race: &mut Account<Race>, reason: DisputeReason) {
+    race.status = RaceStatus::Disputed;
+
+    let time_ms = |result: &Option<RaceResult>| result.as_ref().map_or(0, |r| r.finish_time_ms);
+    emit!(RaceDisputed {
+        race: race.key(),
+        race_id: race.race_id.clone(),
+        player1_time_ms: time_ms(&race.player1_result),
+        player2_time_ms: time_ms(&race.player2_result),
+        reason,
+    });
+}
+
+/// Emit `RaceFinalState` for a race that was just settled.
+fn emit_final_state(race: &Account<Race>, conceded: bool) -> Result<()> {
+    emit!(RaceFinalState {
+        race: race.key(),
+        race_id: race.race_id.clone(),
+        token_mint: race.token_mint,
+        entry_fee_sol: race.entry_fee_sol,
+        player1: race.player1,
+        player2: race.player2,
+        player1_result: race.player1_result.clone(),
+        player2_result: race.player2_result.clone(),
+        winner: race.winner,
+        outcome: race.outcome,
+        escrow_amount: race.escrow_amount,
+        conceded,
+        created_at: race.created_at,
+        settled_at: now()?,
+        external_ref: race.external_ref,
+    });
+    Ok(())
+}
+
+/// Why these two results should go to the authority instead of paying
+/// out, if at all. Identical results (same time, coins and `input_hash`)
+/// mean one player resubmitted the other's, so they only count when the
+/// race opted in with `void_identical_results`.
+pub fn dispute_reason(
+    player1: &RaceResult,
+    player2: &RaceResult,
+    config: &RaceConfig,
+) -> Option<DisputeReason> {
+    if config.void_identical_results && player1 == player2 {
+        Some(DisputeReason::IdenticalResults)
+    } else if is_blowout(player1.finish_time_ms, player2.finish_time_ms, config.max_result_ratio) {
+        Some(DisputeReason::ResultRatio)
+    } else {
+        None
+    }
+}
+
+/// True when one finish time is more than `max_ratio` times the other,
+/// e.g. 1000ms against 150000ms with a ratio of 100. `0` disables the check.
+pub fn is_blowout(time1_ms: u64, time2_ms: u64, max_ratio: u16) -> bool {
+    if max_ratio == 0 {
+        return false;
+    }
+
+    let (faster, slower) = (time1_ms.min(time2_ms), time1_ms.max(time2_ms));
+    slower as u128 > faster as u128 * max_ratio as u128
+}
+
+/// Standard ELO update for a decided game: returns the new
+/// `(winner, loser)` ratings. The winner gains `round(k * (1 - expected))`
+/// where `expected = 1 / (1 + 10^((loser - winner) / 400))`, and the loser
+/// drops by the same amount.
+pub fn elo_update(winner_rating: i32, loser_rating: i32, k: u16) -> (i32, i32) {
+    let gap = (loser_rating as f64 - winner_rating as f64) / 400.0;
+    let expected = 1.0 / (1.0 + 10f64.powf(gap));
+    let delta = (k as f64 * (1.0 - expected)).round() as i32;
+
+    (
+        winner_rating.saturating_add(delta),
+        loser_rating.saturating_sub(delta),
+    )
+}
+
+/// The winning time recorded in the `ResultFeed`: the winner's finish time,
+/// or the target time when the house won.
+fn winner_time_ms(race: &Race, winner: &Pubkey) -> u64 {
+    let result = if *winner == race.player1 {
+        race.player1_result.as_ref()
+    } else {
+        race.player2_result.as_ref()
+    };
+    result
+        .map(|result| result.finish_time_ms)
+        .or(race.house_target_ms)
+        .unwrap_or_default()
+}
+
+/// Shared body of `submit_result` and `submit_result_on_map`: store the
+/// result in the acting player's slot of an `Active` race.
+fn record_result(
+    accounts: &mut SubmitResult,
+    finish_time_ms: u64,
+    coins_collected: u64,
+    input_hash: [u8; 32],
+) -> Result<()> {
+    let race = &mut accounts.race;
+    let before = race.status.clone();
+
+    require!(
+        race.status != RaceStatus::Waiting,
+        SolracerError::OpponentNotJoined
+    );
+    require!(
+        race.status == RaceStatus::Active,
+        SolracerError::InvalidRaceStatus
+    );
+    require!(
+        race.map_seed_slot == 0 || race.map_seed_revealed,
+        SolracerError::MapSeedNotRevealed
+    );
+    // A registered map caps coins at its true count
+    if let Some(map_config) = load_if_initialized::<MapConfig>(&accounts.map_config)? {
+        require!(
+            coins_collected <= map_config.max_coins,
+            SolracerError::CoinsExceedMapMax
+        );
+    }
+
+    let now = now()?;
+    require!(
+        race.submission_deadline().is_none_or(|deadline| now <= deadline),
+        SolracerError::SubmissionDeadlinePassed
+    );
+
+    // Resolve the actual player: session key or direct wallet
+    let actual_player = resolve_player(accounts.session.as_deref(), &accounts.authority.key())?;
+
+    let is_player1 = actual_player == race.player1;
+    let is_player2 = race
+        .player2
+        .map(|p2| actual_player == p2)
+        .unwrap_or(false);
+
+    require!(is_player1 || is_player2, SolracerError::PlayerNotInRace);
+    // The house's side of a house race is its fixed target time
+    require!(
+        is_player1 || race.house_target_ms.is_none(),
+        SolracerError::PlayerNotInRace
+    );
+
+    let player_deadline = if is_player1 {
+        race.player1_deadline
+    } else {
+        race.player2_deadline
+    };
+    require!(
+        player_deadline == 0 || now <= player_deadline,
+        SolracerError::PlayerDeadlinePassed
+    );
+
+    let last_submit_slot = if is_player1 {
+        &mut race.player1_last_submit_slot
+    } else {
+        &mut race.player2_last_submit_slot
+    };
+    note_submit_slot(last_submit_slot, Clock::get()?.slot)?;
+
+    let result = RaceResult {
+        finish_time_ms,
+        coins_collected,
+        input_hash,
+    };
+
+    let slot = if is_player1 {
+        &mut race.player1_result
+    } else {
+        &mut race.player2_result
+    };
+    if !store_result(slot, result)? {
+        msg!(
+            "Duplicate result from player {} in race: {} ignored",
+            actual_player,
+            race.race_id
+        );
+        return assert_invariants(race, &before);
+    }
+
+    msg!(
+        "Result submitted for player {} in race: {}",
+        actual_player,
+        race.race_id
+    );
+
+    assert_invariants(race, &before)
+}
+
+/// Split `amount` by basis points: one share per `bps` entry, with any
+/// rounding remainder added to the last. Fails with `InvalidSplit` unless
+/// there are 1..=`MAX_CLAIM_SPLITS` shares adding up to `SPLIT_TOTAL_BPS`.
+pub fn split_amounts(amount: u64, bps: &[u16]) -> Result<Vec<u64>> {
+    require!(
+        !bps.is_empty() && bps.len() <= MAX_CLAIM_SPLITS,
+        SolracerError::InvalidSplit
+    );
+    let total: u32 = bps.iter().map(|&share| share as u32).sum();
+    require!(total == SPLIT_TOTAL_BPS as u32, SolracerError::InvalidSplit);
+
+    let mut amounts: Vec<u64> = bps
+        .iter()
+        .map(|&share| (amount as u128 * share as u128 / SPLIT_TOTAL_BPS as u128) as u64)
+        .collect();
+    let paid: u64 = amounts.iter().sum();
+    *amounts.last_mut().unwrap() += amount - paid;
+    Ok(amounts)
+}
+
+/// Outcome of looking up a committed slot in the `SlotHashes` sysvar.
+#[derive(Debug, PartialEq)]
+enum SlotHashLookup {
+    /// Hash of the committed slot, or of the first later slot with a block
+    Found([u8; 32]),
+    /// No block at or after the committed slot is in the sysvar yet
+    Pending,
+    /// The sysvar no longer reaches back to the committed slot
+    Expired,
+}
+
+/// Look `slot` up in raw `SlotHashes` sysvar data: a `u64` entry count
+/// followed by `(slot u64, hash [u8; 32])` entries, newest first. A
+/// skipped slot resolves to the first later slot that has a block.
+fn slot_hash_at_or_after(slot_hashes: &[u8], slot: u64) -> SlotHashLookup {
+    let Some((count, entries)) = slot_hashes.split_first_chunk::<8>() else {
+        return SlotHashLookup::Pending;
+    };
+    let count = u64::from_le_bytes(*count) as usize;
+
+    // The oldest entry seen so far at or after `slot`
+    let mut later: Option<[u8; 32]> = None;
+    for entry in entries.chunks_exact(8 + 32).take(count) {
+        let entry_slot = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let hash: [u8; 32] = entry[8..].try_into().unwrap();
+        if entry_slot == slot {
+            return SlotHashLookup::Found(hash);
+        }
+        if entry_slot < slot {
+            return later.map_or(SlotHashLookup::Pending, SlotHashLookup::Found);
+        }
+        later = Some(hash);
+    }
+
+    // Every entry is newer than `slot`, so whether `slot` itself had a
+    // block can no longer be told
+    if later.is_some() {
+        SlotHashLookup::Expired
+    } else {
+        SlotHashLookup::Pending
+    }
+}
+
+/// The map seed `reveal_map_seed` derives from a committed `blockhash`:
+/// the first 8 bytes (little-endian) of `sha256(race || blockhash)`, so
+/// races committed to the same slot still get different maps. Never 0,
+/// which means "no seeded map".
+pub fn map_seed_from_blockhash(race: &Pubkey, blockhash: &[u8; 32]) -> u64 {
+    let digest = solana_sha256_hasher::hashv(&[race.as_ref(), blockhash]).to_bytes();
+    u64::from_le_bytes(digest[..8].try_into().unwrap()).max(1)
+}
+
+/// Current unix time. Every instruction reads the clock through here:
+/// on-chain it is the `Clock` sysvar, while unit tests pin it with
+/// `set_mock_now` so deadline and expiry paths are deterministic.
+#[cfg(not(test))]
+fn now() -> Result<i64> {
+    Ok(Clock::get()?.unix_timestamp)
+}
+
+#[cfg(test)]
+thread_local! {
+    static MOCK_NOW: std::cell::Cell<i64> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+fn now() -> Result<i64> {
+    Ok(MOCK_NOW.with(|now| now.get()))
+}
+
+/// Pin the time `now` returns in unit tests.
+#[cfg(test)]
+fn set_mock_now(unix_timestamp: i64) {
+    MOCK_NOW.with(|now| now.set(unix_timestamp));
+}
+
+/// Validate a new 1v1 race and write it into `race` as `Waiting` under
+/// `config.authority`. The caller escrows player1's stake.
+#[allow(clippy::too_many_arguments)]
+fn open_race(
+    race: &mut Account<Race>,
+    config: &Config,
+    race_id: String,
+    token_mint: Pubkey,
+    entry_fee_sol: u64,
+    race_config: RaceConfig,
+    player1: Pubkey,
+    bump: u8,
+) -> Result<()> {
+    race.set_inner(new_race(
+        config,
+        race_id,
+        token_mint,
+        entry_fee_sol,
+        race_config,
+        player1,
+        bump,
+    )?);
+    Ok(())
+}
+
+/// Validate a new 1v1 race and build it as `Waiting` under
+/// `config.authority`. Shared by `open_race` and `create_race_batch`,
+/// which writes into an account it created itself.
+fn new_race(
+    config: &Config,
+    race_id: String,
+    token_mint: Pubkey,
+    entry_fee_sol: u64,
+    race_config: RaceConfig,
+    player1: Pubkey,
+    bump: u8,
+) -> Result<Race> {
+    check_race_id(&race_id)?;
+    require!(is_valid_token_mint(&token_mint), SolracerError::InvalidTokenMint);
+    check_entry_fee(config, entry_fee_sol)?;
+    if race_config.player2_entry_fee > 0 {
+        check_entry_fee(config, race_config.player2_entry_fee)?;
+    }
+
+    let mut race = Race::new_waiting(
+        race_id,
+        token_mint,
+        entry_fee_sol,
+        race_config,
+        player1,
+        now()?,
+        bump,
+    );
+    race.authority = config.authority;
+    Ok(race)
+}
+
+/// Seat `player2` in a waiting race and escrow their fee from `payer`
+/// (player2 or a relayer) or from player2's `credits` when passed, unless
+/// the slot was prefunded by the operator.
+fn seat_player2<'info>(
+    race: &mut Account<'info, Race>,
+    player2: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    credits: Option<&mut Account<'info, CreditAccount>>,
+) -> Result<()> {
+    require!(
+        race.status == RaceStatus::Waiting,
+        SolracerError::InvalidRaceStatus
+    );
+
+    require!(race.player2.is_none(), SolracerError::Player2AlreadySet);
+
+    race.player2 = Some(player2.key());
+    race.status = RaceStatus::Active;
+    race.started_at = now()?;
+    race.player2_deadline = race.config.player_deadline(race.started_at);
+
+    // A prefunded slot was already escrowed by the operator
+    if !race.player2_prefunded {
+        let fee = race.player2_fee();
+
+        // Credits are player2's own prepaid lamports, so refunds go to them
+        if let Some(credits) = credits {
+            race.credit_escrow(fee)?;
+            race.player2_fee_payer = player2.key();
+            race.player2_paid = fee;
+            spend_credits(credits, &race.to_account_info(), fee)?;
+
+            msg!("Player2 {} joined race {} on credits", player2.key(), race.race_id);
+            return Ok(());
+        }
+
+        // Fail with a readable error instead of the system program's
+        if payer.lamports() < fee {
+            msg!(
+                "{} holds {} lamports but the entry fee is {}",
+                payer.key(),
+                payer.lamports(),
+                fee
+            );
+            return err!(SolracerError::InsufficientFunds);
+        }
+
+        race.credit_escrow(fee)?;
+        race.player2_fee_payer = payer.key();
+        race.player2_paid = fee;
+
+        transfer_lamports(payer, &race.to_account_info(), system_program, fee)?;
+    }
+
+    msg!("Player2 {} joined race: {}", player2.key(), race.race_id);
+
+    Ok(())
+}
+
+/// Check that `token_account` is `player`'s and holds exactly one token of
+/// a mint whose Metaplex metadata (`metadata`, the mint's metadata PDA)
+/// names `collection` as its verified collection.
+fn check_nft_gate(
+    collection: &Pubkey,
+    player: &Pubkey,
+    token_account: &TokenAccount,
+    metadata: &AccountInfo,
+) -> Result<()> {
+    require!(
+        token_account.owner == *player && token_account.amount == 1,
+        SolracerError::NftGateFailed
+    );
+
+    let (metadata_pda, _) = Pubkey::find_program_address(
+        &[
+            b"metadata",
+            METADATA_PROGRAM_ID.as_ref(),
+            token_account.mint.as_ref(),
+        ],
+        &METADATA_PROGRAM_ID,
+    );
+    require!(
+        metadata.key() == metadata_pda && *metadata.owner == METADATA_PROGRAM_ID,
+        SolracerError::NftGateFailed
+    );
+
+    let data = metadata.try_borrow_data()?;
+    require!(
+        metadata_collection(&data) == Some((token_account.mint, *collection)),
+        SolracerError::NftGateFailed
+    );
+    Ok(())
+}
+
+/// `(mint, collection)` from a Metaplex `MetadataV1` account, or `None`
+/// if it can't be read or its collection is unset or unverified. Only the
+/// fields ahead of `collection` are walked; later ones are ignored.
+pub fn metadata_collection(data: &[u8]) -> Option<(Pubkey, Pubkey)> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = bytes.split_at(n);
+        *bytes = tail;
+        Some(head)
+    }
+    fn take_u32(bytes: &mut &[u8]) -> Option<usize> {
+        Some(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?) as usize)
+    }
+    fn take_pubkey(bytes: &mut &[u8]) -> Option<Pubkey> {
+        Some(Pubkey::new_from_array(take(bytes, 32)?.try_into().ok()?))
+    }
+    fn take_string(bytes: &mut &[u8]) -> Option<()> {
+        let len = take_u32(bytes)?;
+        take(bytes, len).map(|_| ())
+    }
+    fn take_option(bytes: &mut &[u8], size: usize) -> Option<()> {
+        if take(bytes, 1)?[0] == 1 {
+            take(bytes, size)?;
+        }
+        Some(())
+    }
+
+    const METADATA_V1_KEY: u8 = 4;
+    const CREATOR_LEN: usize = 32 + 1 + 1;
+
+    let mut rest = data;
+    if take(&mut rest, 1)? != [METADATA_V1_KEY] {
+        return None;
+    }
+    take(&mut rest, 32)?; // update_authority
+    let mint = take_pubkey(&mut rest)?;
+    take_string(&mut rest)?; // name
+    take_string(&mut rest)?; // symbol
+    take_string(&mut rest)?; // uri
+    take(&mut rest, 2)?; // seller_fee_basis_points
+    if take(&mut rest, 1)?[0] == 1 {
+        let creators = take_u32(&mut rest)?;
+        take(&mut rest, creators.checked_mul(CREATOR_LEN)?)?;
+    }
+    take(&mut rest, 2)?; // primary_sale_happened, is_mutable
+    take_option(&mut rest, 1)?; // edition_nonce
+    take_option(&mut rest, 1)?; // token_standard
+    if take(&mut rest, 1)?[0] != 1 {
+        return None;
+    }
+    let verified = take(&mut rest, 1)?[0] == 1;
+    let collection = take_pubkey(&mut rest)?;
+
+    verified.then_some((mint, collection))
+}
+
+/// `token_mint` names the token a race is run and paid out on, with the
+/// native (wrapped SOL) mint meaning plain SOL. The all-zero key (the
+/// System Program id) has no defined meaning, so races can't be created
+/// with it.
+pub fn is_valid_token_mint(token_mint: &Pubkey) -> bool {
+    *token_mint != Pubkey::default()
+}
+
+/// Whether `race_id` sticks to the URL- and log-safe charset: ASCII
+/// letters, digits, `-` and `_`.
+pub fn is_valid_race_id(race_id: &str) -> bool {
+    race_id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Fail with `RaceIdTooLong` or `InvalidRaceId` unless `race_id` may name
+/// a new race.
+fn check_race_id(race_id: &str) -> Result<()> {
+    require!(race_id.len() <= MAX_RACE_ID_LEN, SolracerError::RaceIdTooLong);
+    require!(is_valid_race_id(race_id), SolracerError::InvalidRaceId);
+    Ok(())
+}
+
+/// Fill an empty result slot. A retried submission identical to the
+/// stored one is accepted without change (returns `false`) so client
+/// retries don't fail; a different second result is
+/// `ResultAlreadySubmitted`.
+fn store_result(slot: &mut Option<RaceResult>, result: RaceResult) -> Result<bool> {
+    match slot {
+        None => {
+            *slot = Some(result);
+            Ok(true)
+        }
+        Some(stored) if *stored == result => Ok(false),
+        Some(_) => err!(SolracerError::ResultAlreadySubmitted),
+    }
+}
+
+/// Allow one result submission per player per slot: repeats within a
+/// slot (even identical retries) are `SubmitRateLimited` spam.
+fn note_submit_slot(last_submit_slot: &mut u64, slot: u64) -> Result<()> {
+    require!(*last_submit_slot != slot, SolracerError::SubmitRateLimited);
+    *last_submit_slot = slot;
+    Ok(())
+}
+
+/// Merkle leaf for coin `id` in a map's coin layout.
+pub fn coin_leaf(id: u16) -> [u8; 32] {
+    solana_sha256_hasher::hashv(&[b"coin", &id.to_le_bytes()]).to_bytes()
+}
+
+/// Whether `proof` links `leaf` to `root`. Each level hashes the sorted
+/// pair, so the proof carries no left/right flags.
+pub fn merkle_verify(leaf: [u8; 32], proof: &[[u8; 32]], root: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        let (first, second) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        solana_sha256_hasher::hashv(&[&first, &second]).to_bytes()
+    });
+    computed == root
+}
+
+/// Check a race's books: everything deposited less everything paid out
+/// must equal `escrow_amount`, and the account must actually hold the
+/// escrow plus any rematch opt-ins on top of its rent reserve.
+pub fn reconcile(race: &Race, lamports: u64, rent_reserve: u64) -> Result<EscrowReconciliation> {
+    let rematch_deposits = (race.player1_auto_rematch as u64)
+        .checked_mul(race.entry_fee_sol)
+        .zip((race.player2_auto_rematch as u64).checked_mul(race.player2_fee()))
+        .and_then(|(player1, player2)| player1.checked_add(player2))
+        .ok_or(SolracerError::MathOverflow)?;
+
+    let held = race.total_deposited.checked_sub(race.total_paid_out);
+    let required = rent_reserve
+        .checked_add(race.escrow_amount)
+        .and_then(|required| required.checked_add(rematch_deposits));
+    require!(
+        held == Some(race.escrow_amount) && required.is_some_and(|required| lamports >= required),
+        SolracerError::EscrowImbalance
+    );
+
+    Ok(EscrowReconciliation {
+        total_deposited: race.total_deposited,
+        total_paid_out: race.total_paid_out,
+        escrow_amount: race.escrow_amount,
+        rematch_deposits,
+        lamports,
+        fees_paid: race.fees_paid,
+    })
+}
+
+/// The consolation for a loser with `coins` coins: `consolation_per_coin`
+/// each, capped at `max_per_race` and at the vault's `available` lamports
+/// above rent, so a short or empty vault pays what it can or nothing.
+pub fn consolation_amount(coins: u64, vault: &ConsolationVault, available: u64) -> u64 {
+    coins
+        .saturating_mul(vault.consolation_per_coin)
+        .min(vault.max_per_race)
+        .min(available)
+}
+
+/// Whether a race may move from status `from` to `to` within one
+/// instruction. Staying put is always allowed.
+pub fn legal_transition(from: &RaceStatus, to: &RaceStatus) -> bool {
+    use RaceStatus::*;
+    from == to
+        || matches!(
+            (from, to),
+            (Waiting, Active)
+                // leave_race reopens the seat
+                | (Active, Waiting)
+                | (Active, Settled | Disputed | Voided | NoContest)
+                | (Disputed, Settled)
+                | (Settled, Claimed)
+        )
+}
+
+/// The first core invariant `race` breaks after an instruction that
+/// started it at status `before`, with the account holding `lamports`
+/// over a `rent_reserve`: the books must reconcile (see `reconcile`), the
+/// status change must be legal and any winner must be one of the players.
+pub fn race_invariant_violation(
+    race: &Race,
+    before: &RaceStatus,
+    lamports: u64,
+    rent_reserve: u64,
+) -> Option<&'static str> {
+    if reconcile(race, lamports, rent_reserve).is_err() {
+        return Some("escrow does not match the account's lamports");
+    }
+    if !legal_transition(before, &race.status) {
+        return Some("illegal status transition");
+    }
+    if race
+        .winner
+        .is_some_and(|winner| winner != race.player1 && race.player2 != Some(winner))
+    {
+        return Some("winner is not a participant");
+    }
+    None
+}
+
+/// With the `strict` feature, fail with `InvariantViolated` when `race`
+/// breaks a core invariant (see `race_invariant_violation`) at the end of
+/// an instruction that found it at status `before`. Called by each race
+/// mutator; without the feature it compiles to nothing, so mainnet builds
+/// pay no compute for it.
+#[cfg(feature = "strict")]
+fn assert_invariants(race: &Account<Race>, before: &RaceStatus) -> Result<()> {
+    let info = race.to_account_info();
+    let rent_reserve = Rent::get()?.minimum_balance(info.data_len());
+    if let Some(violation) = race_invariant_violation(race, before, info.lamports(), rent_reserve) {
+        msg!("Invariant violated in race {}: {}", race.race_id, violation);
+        return err!(SolracerError::InvariantViolated);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "strict"))]
+#[inline(always)]
+fn assert_invariants(_race: &Account<Race>, _before: &RaceStatus) -> Result<()> {
+    Ok(())
+}
+
+/// Instruction data for a winner hook: the Anchor discriminator of
+/// `on_race_settled` followed by borsh `WinnerHookArgs`, so a hook written
+/// in Anchor just declares `on_race_settled(args: WinnerHookArgs)`.
+pub fn winner_hook_data(race: &Race, winner: Pubkey) -> Result<Vec<u8>> {
+    let mut data = solana_sha256_hasher::hash(b"global:on_race_settled").to_bytes()[..8].to_vec();
+    WinnerHookArgs {
+        race_id: race.race_id.clone(),
+        winner,
+        outcome: race.outcome.ok_or(SolracerError::InvalidRaceStatus)?,
+        escrow_amount: race.escrow_amount,
+    }
+    .serialize(&mut data)?;
+    Ok(data)
+}
+
+/// Whether account data holds nothing: no discriminator, no fields.
+pub fn is_orphaned(data: &[u8]) -> bool {
+    data.iter().all(|byte| *byte == 0)
+}
+
+/// Version byte leading every result preimage. Bump it whenever the layout
+/// below changes so old and new clients can't produce colliding hashes.
+pub const RESULT_PREIMAGE_VERSION: u8 = 2;
+
+/// Canonical preimage a client hashes (sha256) to produce `input_hash`.
+/// The race account address is included so a hash can't be replayed in
+/// another race, even one with the same `race_id` under another creator.
+///
+/// Layout, all integers little-endian:
+/// `version: u8 | race: [u8; 32] | race_id_len: u32 | race_id: utf8 |
+/// finish_time_ms: u64 | coins_collected: u64 | replay_len: u32 | replay`
+pub fn result_preimage(
+    race: &Pubkey,
+    race_id: &str,
+    finish_time_ms: u64,
+    coins_collected: u64,
+    replay: &[u8],
+) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(1 + 32 + 4 + race_id.len() + 8 + 8 + 4 + replay.len());
+    preimage.push(RESULT_PREIMAGE_VERSION);
+    preimage.extend_from_slice(race.as_ref());
+    preimage.extend_from_slice(&(race_id.len() as u32).to_le_bytes());
+    preimage.extend_from_slice(race_id.as_bytes());
+    preimage.extend_from_slice(&finish_time_ms.to_le_bytes());
+    preimage.extend_from_slice(&coins_collected.to_le_bytes());
+    preimage.extend_from_slice(&(replay.len() as u32).to_le_bytes());
+    preimage.extend_from_slice(replay);
+    preimage
+}
+
+/// sha256 of `result_preimage`, i.e. the expected `input_hash`.
+pub fn result_input_hash(
+    race: &Pubkey,
+    race_id: &str,
+    finish_time_ms: u64,
+    coins_collected: u64,
+    replay: &[u8],
+) -> [u8; 32] {
+    solana_sha256_hasher::hash(&result_preimage(
+        race,
+        race_id,
+        finish_time_ms,
+        coins_collected,
+        replay,
+    ))
+    .to_bytes()
+}
+
+/// The hash a version 1 client would have produced: the same fields
+/// without the race address. Only used to tell an unbound hash apart from
+/// a plain mismatch.
+fn unbound_input_hash(
+    race_id: &str,
+    finish_time_ms: u64,
+    coins_collected: u64,
+    replay: &[u8],
+) -> [u8; 32] {
+    solana_sha256_hasher::hashv(&[
+        &[1],
+        &(race_id.len() as u32).to_le_bytes(),
+        race_id.as_bytes(),
+        &finish_time_ms.to_le_bytes(),
+        &coins_collected.to_le_bytes(),
+        &(replay.len() as u32).to_le_bytes(),
+        replay,
+    ])
+    .to_bytes()
+}
+
+/// Fields recovered from a result preimage by `parse_result_preimage`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultPreimage {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub finish_time_ms: u64,
+    pub coins_collected: u64,
+    pub replay: Vec<u8>,
+}
+
+/// Inverse of `result_preimage`. Returns `None` for an unknown version,
+/// truncated input or trailing bytes.
+pub fn parse_result_preimage(bytes: &[u8]) -> Option<ResultPreimage> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = bytes.split_at(n);
+        *bytes = tail;
+        Some(head)
+    }
+
+    let mut rest = bytes;
+    if take(&mut rest, 1)? != [RESULT_PREIMAGE_VERSION] {
+        return None;
+    }
+    let race = Pubkey::new_from_array(take(&mut rest, 32)?.try_into().ok()?);
+    let race_id_len = u32::from_le_bytes(take(&mut rest, 4)?.try_into().ok()?) as usize;
+    let race_id = String::from_utf8(take(&mut rest, race_id_len)?.to_vec()).ok()?;
+    let finish_time_ms = u64::from_le_bytes(take(&mut rest, 8)?.try_into().ok()?);
+    let coins_collected = u64::from_le_bytes(take(&mut rest, 8)?.try_into().ok()?);
+    let replay_len = u32::from_le_bytes(take(&mut rest, 4)?.try_into().ok()?) as usize;
+    let replay = take(&mut rest, replay_len)?.to_vec();
+
+    if !rest.is_empty() {
+        return None;
+    }
+
+    Some(ResultPreimage {
+        race,
+        race_id,
+        finish_time_ms,
+        coins_collected,
+        replay,
+    })
+}
+
+/// System-program transfer from a signer wallet (into escrow or to a player).
+fn transfer_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    anchor_lang::solana_program::program::invoke(
+        &anchor_lang::solana_program::system_instruction::transfer(from.key, to.key, amount),
+        &[from.clone(), to.clone(), system_program.clone()],
+    )?;
+    Ok(())
+}
+
+/// Move `amount` lamports out of a program-owned escrow account.
+fn pay_from_escrow(escrow: &AccountInfo, destination: &AccountInfo, amount: u64) -> Result<()> {
+    **escrow.try_borrow_mut_lamports()? -= amount;
+    **destination.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
+
+/// Draw an entry fee of `amount` from a player's prepaid `credits` into
+/// `race`, instead of a system transfer from their wallet.
+fn spend_credits(
+    credits: &mut Account<CreditAccount>,
+    race: &AccountInfo,
+    amount: u64,
+) -> Result<()> {
+    credits.debit(amount)?;
+    pay_from_escrow(&credits.to_account_info(), race, amount)
+}
+
+/// `Race` fields through `version`, as version 1 laid them out; every later
+/// version only appends after `version`. `upgrade_race_data` reads an
+/// older account with it to find where that account's own fields end.
+type RaceHeadV1 = (
+    // status, race_id, token_mint, entry_fee_sol, config, player1, player2,
+    // player2_fee_payer, player1_result, player2_result, winner, outcome
+    (
+        RaceStatus,
+        String,
+        Pubkey,
+        u64,
+        RaceConfig,
+        Pubkey,
+        Option<Pubkey>,
+        Pubkey,
+        Option<RaceResult>,
+        Option<RaceResult>,
+        Option<Pubkey>,
+        Option<OutcomeReason>,
+    ),
+    // escrow_amount, total_deposited, total_paid_out, created_at,
+    // started_at, player1_deadline, player2_deadline, player1_prefunded,
+    // player2_prefunded, player1_paid, player2_paid, operator_funded
+    (u64, u64, u64, i64, i64, i64, i64, bool, bool, u64, u64, u64),
+    // chat_root, chat_updated_at, player1_auto_rematch,
+    // player2_auto_rematch, rematch, rematch_count, house_target_ms,
+    // authority, external_ref, rated, imported, player1_dq
+    (
+        [u8; 32],
+        i64,
+        bool,
+        bool,
+        Option<Pubkey>,
+        u16,
+        Option<u64>,
+        Pubkey,
+        [u8; 32],
+        bool,
+        bool,
+        bool,
+    ),
+    // player2_dq, player1_coins_verified, player2_coins_verified, bump,
+    // version
+    (bool, bool, bool, u8, u8),
+);
+
+/// The `Race` fields each layout version appended after `version`, as
+/// `(version, serialized bytes)`, oldest first.
+pub const RACE_FIELDS_ADDED: [(u8, usize); 8] = [
+    (2, 8 + 1),     // map_seed_slot, map_seed_revealed
+    (4, 8 + 8),     // player1_last_submit_slot, player2_last_submit_slot
+    (5, 32),        // rent_payer
+    (6, 8),         // settled_at
+    (7, 8 + 2 + 8), // vesting_seconds, vesting_slices, claimed_so_far
+    (8, 8),         // claim_delay_secs
+    (9, 8),         // min_coins_to_win
+    (11, 8),        // fees_paid
+];
+
+/// Serialized bytes of the fields layout `version` has after `version`.
+pub const fn race_tail_len(version: u8) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < RACE_FIELDS_ADDED.len() {
+        let (added_in, size) = RACE_FIELDS_ADDED[i];
+        if version >= added_in {
+            len += size;
+        }
+        i += 1;
+    }
+    len
+}
+
+/// Bring raw race account `data`, written with an older layout into an
+/// account of `old_len` bytes and since grown to `8 + Race::LEN`, to the
+/// current layout in place, and return the version it was written with.
+/// Its own fields are read with the version 1 layout (`RaceHeadV1`) and
+/// the version's `RACE_FIELDS_ADDED`; every byte past them is zeroed, so
+/// each field added since takes its default (zero) and the race is not
+/// `featured`. Those bytes are not necessarily zero before: a race whose
+/// serialization shrank (a `player2` who left, a shorter `race_id`) keeps
+/// the tail of its longer write. Races from before versioning have no
+/// `version` byte of their own and are told apart by their length.
+pub fn upgrade_race_data(data: &mut [u8], old_len: usize) -> Result<u8> {
+    require!(
+        data.starts_with(Race::DISCRIMINATOR),
+        ErrorCode::AccountDiscriminatorMismatch
+    );
+    let fields = &data[8..];
+    let mut reader = fields;
+    let (_, _, _, (_, _, _, _, version)) = RaceHeadV1::deserialize(&mut reader)?;
+    let head_len = fields.len() - reader.len();
+
+    let from_version = if old_len <= 8 + Race::UNVERSIONED_LEN {
+        0
+    } else {
+        version
+    };
+    if from_version == RACE_VERSION {
+        return Ok(from_version);
+    }
+
+    // Where `version` sits; before versioning the race ended right ahead of it
+    let version_at = 8 + head_len - 1;
+    data[version_at] = RACE_VERSION;
+    data[version_at + 1 + race_tail_len(from_version)..].fill(0);
+    Ok(from_version)
+}
+
+// Accounts
+
+#[account]
+pub struct Race {
+    /// Kept as the first field so it sits at a fixed offset
+    /// (see `Race::STATUS_OFFSET`) for `memcmp` filters.
+    pub status: RaceStatus,
+    pub race_id: String,
+    pub token_mint: Pubkey,
+    pub entry_fee_sol: u64,
+    pub config: RaceConfig,
+    pub player1: Pubkey,
+    pub player2: Option<Pubkey>,
+    /// Who actually funded player2's entry (player2, a relayer or the
+    /// operator); player2 refunds are paid here
+    pub player2_fee_payer: Pubkey,
+    pub player1_result: Option<RaceResult>,
+    pub player2_result: Option<RaceResult>,
+    pub winner: Option<Pubkey>,
+    /// Why `winner` won; set with it at settlement
+    pub outcome: Option<OutcomeReason>,
+    pub escrow_amount: u64,
+    /// Lifetime lamports credited to and paid out of `escrow_amount`; see
+    /// `Race::credit_escrow` / `Race::debit_escrow` and `reconcile_escrow`
+    pub total_deposited: u64,
+    pub total_paid_out: u64,
+    pub created_at: i64,
+    /// When the race went `Active` (0 while `Waiting`); the submission
+    /// window counts from here
+    pub started_at: i64,
+    /// Per-player submission deadlines from `player_window_secs`, counted
+    /// from when each player joined; 0 when the race has none
+    pub player1_deadline: i64,
+    pub player2_deadline: i64,
+    /// Set when the operator covered this slot's entry fee
+    pub player1_prefunded: bool,
+    pub player2_prefunded: bool,
+    /// Lamports escrowed for each seat, by whoever funded it; refunds
+    /// return exactly these. player2's is 0 until the seat is funded
+    pub player1_paid: u64,
+    pub player2_paid: u64,
+    /// Total lamports contributed by the operator rather than the players
+    pub operator_funded: u64,
+    /// Rolling hash of the off-chain spectator chat (see `next_chat_root`)
+    pub chat_root: [u8; 32],
+    pub chat_updated_at: i64,
+    /// Set via `set_auto_rematch`; each opt-in holds one entry fee in the
+    /// account (outside `escrow_amount`) until the rematch is created
+    pub player1_auto_rematch: bool,
+    pub player2_auto_rematch: bool,
+    /// The rematch race created by `settle_race_with_rematch`
+    pub rematch: Option<Pubkey>,
+    /// Position in a rematch chain: 0 for an original race, parent + 1
+    /// for each rematch
+    pub rematch_count: u16,
+    /// Set for house races: player1's time to beat, with the house as player2
+    pub house_target_ms: Option<u64>,
+    /// Operator (`Config.authority` at creation) allowed to resolve
+    /// disputes, disqualify and prefund on this race
+    pub authority: Pubkey,
+    /// Integrator's own match id from `create_race_with_ref`, never
+    /// interpreted; all zero when unset
+    pub external_ref: [u8; 32],
+    /// Set once `rate_race` has applied this race to both profiles
+    pub rated: bool,
+    /// Written by `import_result` from a migrated game; holds no escrow
+    pub imported: bool,
+    /// Set by `disqualify`; a disqualified player loses at settlement
+    pub player1_dq: bool,
+    pub player2_dq: bool,
+    /// Set by `verify_coins` once a player's coins are proven against
+    /// `RaceConfig::coins_root`
+    pub player1_coins_verified: bool,
+    pub player2_coins_verified: bool,
+    pub bump: u8,
+    /// `RACE_VERSION` of the layout this account was last written with;
+    /// 0 for races from before versioning. New fields go after this one,
+    /// so `realloc_race` can default them when it grows an older account
+    pub version: u8,
+    /// Slot whose blockhash seeds the map (`create_race_with_seed_commit`);
+    /// 0 when the creator picked `config.map_seed` directly
+    pub map_seed_slot: u64,
+    /// Set by `reveal_map_seed` once `config.map_seed` has been derived
+    /// from `map_seed_slot`; results are refused until then
+    pub map_seed_revealed: bool,
+    /// Slot of each player's last result submission, for the
+    /// one-per-slot limit in `record_result`
+    pub player1_last_submit_slot: u64,
+    pub player2_last_submit_slot: u64,
+    /// Who paid the account's rent and gets it back from `close_race`;
+    /// player1 unless someone else created the account (a rematch `payer`)
+    pub rent_payer: Pubkey,
+    /// When the race was last settled; 0 until then, for imported races and
+    /// for races settled before it was tracked. Opens `REASSIGN_WINDOW_SECS`
+    pub settled_at: i64,
+    /// Prize vesting from `set_vesting`: `vesting_slices` installments over
+    /// `vesting_seconds` from `settled_at`; both 0 pays it all at once
+    pub vesting_seconds: i64,
+    pub vesting_slices: u16,
+    /// Prize lamports paid to the winner so far, across installments
+    pub claimed_so_far: u64,
+    /// Review window after `settled_at` before the prize can be claimed,
+    /// picked for the pot from `ClaimDelayTiers` at settlement
+    pub claim_delay_secs: i64,
+    /// A result with fewer coins than this can't win, however fast (see
+    /// `qualified_winner`); 0 lets any result win. Fixed at creation by
+    /// `create_race_with_min_coins`
+    pub min_coins_to_win: u64,
+    /// Protocol fees and host cuts taken out of claimed prizes; already
+    /// counted in `total_paid_out`
+    pub fees_paid: u64,
+}
+
+impl Race {
+    /// Add `amount` lamports, already transferred in, to the escrow.
+    pub fn credit_escrow(&mut self, amount: u64) -> Result<()> {
+        self.escrow_amount = self
+            .escrow_amount
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+        self.total_deposited = self
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Record the protocol `fee` and `host_cut` taken out of a claim,
+    /// already debited from the escrow with the rest of the prize.
+    pub fn record_fees(&mut self, fee: u64, host_cut: u64) -> Result<()> {
+        self.fees_paid = fee
+            .checked_add(host_cut)
+            .and_then(|fees| self.fees_paid.checked_add(fees))
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Whether top-ups into the escrow are refused: once every result is
+    /// in, or the race is settled, disputed or paid out, the outcome (and
+    /// the prize it pays) is fixed.
+    pub fn escrow_locked(&self) -> bool {
+        let results_in = self.player1_result.is_some()
+            && (self.player2_result.is_some() || self.house_target_ms.is_some());
+        results_in || !matches!(self.status, RaceStatus::Waiting | RaceStatus::Active)
+    }
+
+    /// Take `amount` lamports, about to be paid out, off the escrow.
+    pub fn debit_escrow(&mut self, amount: u64) -> Result<()> {
+        self.escrow_amount = self
+            .escrow_amount
+            .checked_sub(amount)
+            .ok_or(SolracerError::EscrowMismatch)?;
+        self.total_paid_out = self
+            .total_paid_out
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Whether the race is denominated in SOL, i.e. `token_mint` is the
+    /// native mint. Races created before `token_mint` was validated may
+    /// hold the all-zero key instead; they were all SOL races, and the mint
+    /// seeds the PDA so it can't be rewritten, so that key reads as SOL too.
+    pub fn is_sol(&self) -> bool {
+        self.token_mint == native_mint::ID || self.token_mint == Pubkey::default()
+    }
+
+    /// `token_mint` with the legacy all-zero key mapped to the native mint,
+    /// for races derived from this one.
+    pub fn denomination(&self) -> Pubkey {
+        if self.is_sol() {
+            native_mint::ID
+        } else {
+            self.token_mint
+        }
+    }
+
+    /// Last moment a result is accepted: `config.submission_window_secs`
+    /// after the race started. `None` when the race has no window or
+    /// hasn't started.
+    pub fn submission_deadline(&self) -> Option<i64> {
+        (self.config.submission_window_secs > 0 && self.started_at > 0)
+            .then(|| self.started_at.saturating_add(self.config.submission_window_secs))
+    }
+
+    /// Stake player2 escrows to join: `config.player2_entry_fee` for a
+    /// handicapped race, otherwise `entry_fee_sol`.
+    pub fn player2_fee(&self) -> u64 {
+        if self.config.player2_entry_fee > 0 {
+            self.config.player2_entry_fee
+        } else {
+            self.entry_fee_sol
+        }
+    }
+
+    /// Who wins by forfeit at `now`: the only player with a result, once
+    /// the opponent is past the race's submission deadline or their own
+    /// (`player1_deadline` / `player2_deadline`). A house race whose
+    /// player1 missed their deadline goes to the house. `None` while the
+    /// missing player may still submit, or when nobody submitted.
+    pub fn forfeit_winner(&self, now: i64) -> Option<Pubkey> {
+        let missed = |player_deadline: i64| self.missed_deadline(player_deadline, now);
+
+        if self.house_target_ms.is_some() {
+            return (self.player1_result.is_none() && missed(self.player1_deadline))
+                .then_some(self.player2)
+                .flatten();
+        }
+        match (&self.player1_result, &self.player2_result) {
+            (Some(_), None) if missed(self.player2_deadline) => Some(self.player1),
+            (None, Some(_)) if missed(self.player1_deadline) => self.player2,
+            _ => None,
+        }
+    }
+
+    /// Whether neither player of a 1v1 race submitted and both are past
+    /// their deadline at `now`: a no-contest rather than a forfeit. A
+    /// house race never is one, since the house's side needs no result.
+    pub fn both_no_show(&self, now: i64) -> bool {
+        self.house_target_ms.is_none()
+            && self.player1_result.is_none()
+            && self.player2_result.is_none()
+            && self.missed_deadline(self.player1_deadline, now)
+            && self.missed_deadline(self.player2_deadline, now)
+    }
+
+    /// Whether a player with `player_deadline` (0 for none) can no longer
+    /// submit at `now`, by that deadline or the race's submission deadline.
+    fn missed_deadline(&self, player_deadline: i64, now: i64) -> bool {
+        self.submission_deadline().is_some_and(|deadline| now > deadline)
+            || (player_deadline > 0 && now > player_deadline)
+    }
+
+    /// A freshly created race: player1 seated with their fee escrowed,
+    /// waiting for an opponent.
+    pub fn new_waiting(
+        race_id: String,
+        token_mint: Pubkey,
+        entry_fee_sol: u64,
+        config: RaceConfig,
+        player1: Pubkey,
+        created_at: i64,
+        bump: u8,
+    ) -> Self {
+        let player1_deadline = config.player_deadline(created_at);
+        Self {
+            status: RaceStatus::Waiting,
+            race_id,
+            token_mint,
+            entry_fee_sol,
+            config,
+            player1,
+            player2: None,
+            player2_fee_payer: Pubkey::default(),
+            player1_result: None,
+            player2_result: None,
+            winner: None,
+            outcome: None,
+            escrow_amount: entry_fee_sol,
+            total_deposited: entry_fee_sol,
+            total_paid_out: 0,
+            player1_deadline,
+            player2_deadline: 0,
+            created_at,
+            started_at: 0,
+            player1_prefunded: false,
+            player2_prefunded: false,
+            player1_paid: entry_fee_sol,
+            player2_paid: 0,
+            operator_funded: 0,
+            chat_root: [0u8; 32],
+            chat_updated_at: 0,
+            player1_auto_rematch: false,
+            player2_auto_rematch: false,
+            rematch: None,
+            rematch_count: 0,
+            house_target_ms: None,
+            authority: Pubkey::default(),
+            external_ref: [0u8; 32],
+            rated: false,
+            imported: false,
+            player1_dq: false,
+            player2_dq: false,
+            player1_coins_verified: false,
+            player2_coins_verified: false,
+            bump,
+            version: RACE_VERSION,
+            map_seed_slot: 0,
+            map_seed_revealed: false,
+            player1_last_submit_slot: 0,
+            player2_last_submit_slot: 0,
+            rent_payer: player1,
+            settled_at: 0,
+            vesting_seconds: 0,
+            vesting_slices: 0,
+            claimed_so_far: 0,
+            claim_delay_secs: 0,
+            min_coins_to_win: 0,
+            fees_paid: 0,
+        }
+    }
+
+    /// Byte offset of `status` in the raw account data (right after the
+    /// 8-byte account discriminator). Fields after `race_id` shift with its
+    /// length, so anything else clients filter on must stay ahead of it or
+    /// sit at a fixed offset from the end, like `FEATURED_OFFSET`.
+    pub const STATUS_OFFSET: usize = 8;
+
+    /// Byte offset of the operator's `featured` flag (`set_featured`): the
+    /// account's last byte, which `LEN` reserves past the serialized
+    /// fields, so it stays put whatever the `race_id` length.
+    pub const FEATURED_OFFSET: usize = 8 + Self::LEN - 1;
+
+    pub const LEN: usize = 1    // status enum
+        + 4                     // race_id string discriminator
+        + MAX_RACE_ID_LEN       // race_id (max length)
+        + 32                    // token_mint pubkey
+        + 8                     // entry_fee_sol u64
+        + RaceConfig::LEN       // config
+        + 32                    // player1 pubkey
+        + 1 + 32                // player2 option<pubkey>
+        + 32                    // player2_fee_payer pubkey
+        + 1 + (8 + 8 + 32)     // player1_result option<raceresult>
+        + 1 + (8 + 8 + 32)     // player2_result option<raceresult>
+        + 1 + 32                // winner option<pubkey>
+        + 1 + 1                 // outcome option<outcomereason>
+        + 8                     // escrow_amount u64
+        + 8                     // total_deposited u64
+        + 8                     // total_paid_out u64
+        + 8                     // created_at i64
+        + 8                     // started_at i64
+        + 8                     // player1_deadline i64
+        + 8                     // player2_deadline i64
+        + 1                     // player1_prefunded bool
+        + 1                     // player2_prefunded bool
+        + 8                     // player1_paid u64
+        + 8                     // player2_paid u64
+        + 8                     // operator_funded u64
+        + 32                    // chat_root [u8; 32]
+        + 8                     // chat_updated_at i64
+        + 1                     // player1_auto_rematch bool
+        + 1                     // player2_auto_rematch bool
+        + 1 + 32                // rematch option<pubkey>
+        + 2                     // rematch_count u16
+        + 1 + 8                 // house_target_ms option<u64>
+        + 32                    // authority pubkey
+        + 32                    // external_ref [u8; 32]
+        + 1                     // rated bool
+        + 1                     // imported bool
+        + 1                     // player1_dq bool
+        + 1                     // player2_dq bool
+        + 1                     // player1_coins_verified bool
+        + 1                     // player2_coins_verified bool
+        + 1                     // bump u8
+        + 1                     // version u8
+        + 8                     // map_seed_slot u64
+        + 1                     // map_seed_revealed bool
+        + 8                     // player1_last_submit_slot u64
+        + 8                     // player2_last_submit_slot u64
+        + 32                    // rent_payer pubkey
+        + 8                     // settled_at i64
+        + 8                     // vesting_seconds i64
+        + 2                     // vesting_slices u16
+        + 8                     // claimed_so_far u64
+        + 8                     // claim_delay_secs i64
+        + 8                     // min_coins_to_win u64
+        + 8                     // fees_paid u64
+        + 1;                    // featured flag, at FEATURED_OFFSET
+
+    /// `LEN` of races from before versioning, which end at `bump`.
+    pub const UNVERSIONED_LEN: usize = Self::LEN
+        - 1                     // featured flag
+        - 1                     // version u8
+        - race_tail_len(RACE_VERSION);
+
+    /// Whether the race in the raw account `data` is flagged `featured`.
+    pub fn is_featured(data: &[u8]) -> bool {
+        data.get(Self::FEATURED_OFFSET) == Some(&1)
+    }
+
+    /// When the winner may first claim: `claim_delay_secs` after settlement.
+    pub fn claim_opens_at(&self) -> i64 {
+        self.settled_at.saturating_add(self.claim_delay_secs)
+    }
+
+    /// Where `close_race` returns the rent: `rent_payer`, or player1 for
+    /// races from before it was tracked.
+    pub fn rent_recipient(&self) -> Pubkey {
+        if self.rent_payer == Pubkey::default() {
+            self.player1
+        } else {
+            self.rent_payer
+        }
+    }
+
+    /// Whether an account of `data_len` bytes (discriminator included)
+    /// holding this race needs `realloc_race`.
+    pub fn needs_realloc(&self, data_len: usize) -> bool {
+        data_len < 8 + Self::LEN || self.version < RACE_VERSION
+    }
+}
+
+#[account]
+pub struct Config {
+    pub authority:                Pubkey, // 32
+    /// K-factor `rate_race` applies to rating changes
+    pub elo_k_factor:             u16,    //  2
+    /// A lobby join this close to the late-join window closing extends
+    /// it by `antisnipe_extension_secs` (see `Lobby::apply_antisnipe`)
+    pub antisnipe_window_secs:    i64,    //  8
+    pub antisnipe_extension_secs: i64,    //  8
+    /// Paid out of the `BountyPool`, never the escrow, to whoever settles
+    /// via `crank_settle`; 0 leaves settlement to `settle_race` only
+    pub crank_bounty_lamports:    u64,    //  8
+    /// Longest rematch chain between the same pair; 0 is unlimited
+    pub max_rematches:            u16,    //  2
+    /// Entry fee bounds for `create_race` and rematches; a
+    /// `max_entry_fee` of 0 is unlimited
+    pub min_entry_fee:            u64,    //  8
+    pub max_entry_fee:            u64,    //  8
+    /// Share of each prize `claim_prize` pays to `treasury`, for tokens
+    /// without a `TokenConfig`
+    pub fee_bps:                  u16,    //  2
+    pub treasury:                 Pubkey, // 32
+    /// Game server key that co-signs `submit_result_attested`; the
+    /// default key means none is set
+    pub server_authority:         Pubkey, // 32
+    /// The key `rotate_server_authority` replaced, still accepted until
+    /// `prev_server_expires_at`
+    pub prev_server_authority:    Pubkey, // 32
+    pub prev_server_expires_at:   i64,    //  8
+    /// Share of each prize `claim_prize` pays to the race's creator
+    /// (player1) when someone else won, on top of the protocol fee
+    pub host_cut_bps:             u16,    //  2
+    /// Lets `declare_no_contest` refund a race neither player finished
+    pub no_contest_on_no_show:    bool,   //  1
+    pub bump:                     u8,     //  1
+}
+
+impl Config {
+    pub const LEN: usize = 186;
+
+    /// Whether `key` may attest results at `now`: the current server
+    /// authority, or the previous one during its grace period.
+    pub fn accepts_server_key(&self, key: &Pubkey, now: i64) -> bool {
+        *key != Pubkey::default()
+            && (*key == self.server_authority
+                || (*key == self.prev_server_authority
+                    && now <= self.prev_server_expires_at))
+    }
+}
+
+/// A generated map registered by the authority, seeded by
+/// `[b"map_config", map_seed (u64 LE)]`. Submissions on a race with that
+/// `map_seed` are capped at `max_coins`.
+#[account]
+pub struct MapConfig {
+    pub map_seed:  u64, // 8
+    pub max_coins: u64, // 8
+    pub bump:      u8,  // 1
+}
+
+impl MapConfig {
+    pub const LEN: usize = 17;
+}
+
+/// Per-token override of the global fee and treasury, seeded by
+/// `[b"token_config", token_mint]`.
+#[account]
+pub struct TokenConfig {
+    pub token_mint: Pubkey, // 32
+    pub fee_bps:    u16,    //  2
+    pub treasury:   Pubkey, // 32
+    /// Set by `set_token_paused`; no new races on this token while set
+    pub paused:     bool,   //  1
+    pub bump:       u8,     //  1
+}
+
+impl TokenConfig {
+    pub const LEN: usize = 68;
+}
+
+/// Per-wallet rating, seeded at `DEFAULT_RATING` and updated by `rate_race`.
+#[account]
+pub struct PlayerProfile {
+    pub player:      Pubkey, // 32
+    pub rating:      i32,    //  4
+    pub rated_races: u32,    //  4
+    pub bump:        u8,     //  1
+}
+
+impl PlayerProfile {
+    pub const LEN: usize = 41;
+}
+
+#[account]
+pub struct PlayerSession {
+    pub player_wallet: Pubkey,   // 32
+    pub session_key:   Pubkey,   // 32
+    pub race_id_hash:  [u8; 32], // 32
+    pub expires_at:    i64,      //  8
+    pub bump:          u8,       //  1
+}
+
+impl PlayerSession {
+    pub const LEN: usize = 105;
+}
+
+/// A 2v2 race. Empty team slots hold `Pubkey::default()`.
+#[account]
+pub struct TeamRace {
+    pub status: RaceStatus,
+    pub race_id: String,
+    pub entry_fee_sol: u64,
+    pub aggregation: TeamAggregation,
+    pub team1: [Pubkey; 2],
+    pub team2: [Pubkey; 2],
+    pub team1_results: [Option<RaceResult>; 2],
+    pub team2_results: [Option<RaceResult>; 2],
+    /// 1 or 2 once settled
+    pub winning_team: Option<u8>,
+    /// Per winning-team member, indexed like the team slots
+    pub prize_claimed: [bool; 2],
+    pub escrow_amount: u64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl TeamRace {
+    pub const LEN: usize = 1    // status enum
+        + 4                     // race_id string discriminator
+        + MAX_RACE_ID_LEN       // race_id (max length)
+        + 8                     // entry_fee_sol u64
+        + 1                     // aggregation enum
+        + 32 * 2                // team1 pubkeys
+        + 32 * 2                // team2 pubkeys
+        + 2 * (1 + (8 + 8 + 32)) // team1_results option<raceresult>
+        + 2 * (1 + (8 + 8 + 32)) // team2_results option<raceresult>
+        + 1 + 1                 // winning_team option<u8>
+        + 2                     // prize_claimed bools
+        + 8                     // escrow_amount u64
+        + 8                     // created_at i64
+        + 1;                    // bump u8
+
+    /// (team, slot index) of `player`, if they hold a slot
+    pub fn member_slot(&self, player: &Pubkey) -> Option<(u8, usize)> {
+        if let Some(index) = self.team1.iter().position(|member| member == player) {
+            return Some((1, index));
+        }
+        self.team2
+            .iter()
+            .position(|member| member == player)
+            .map(|index| (2, index))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.team1
+            .iter()
+            .chain(self.team2.iter())
+            .all(|member| *member != Pubkey::default())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TeamAggregation {
+    /// Team time is the sum of both members' times
+    SumOfTimes,
+    /// Team time is the faster member's time
+    BestTime,
+}
+
+/// An N-player, winner-takes-all race. `results` is parallel to `players`.
+#[account]
+pub struct Lobby {
+    pub status: RaceStatus,
+    pub race_id: String,
+    pub entry_fee_sol: u64,
+    pub config: RaceConfig,
+    pub creator: Pubkey,
+    pub max_players: u8,
+    pub min_players_to_start: u8,
+    /// Late joins stay open this long after the minimum is reached
+    pub join_grace_secs: i64,
+    /// When `min_players_to_start` was reached (0 until then)
+    pub min_reached_at: i64,
+    /// How many times anti-sniping has extended `join_grace_secs`
+    pub antisnipe_extensions: u8,
+    /// Extra fee per seat after the first, in basis points of `entry_fee_sol`
+    pub late_join_surcharge_bps: u16,
+    pub players: Vec<Pubkey>,
+    pub results: Vec<Option<RaceResult>>,
+    /// What each player paid to join, in join order (parallel to `players`)
+    pub paid_fees: Vec<u64>,
+    pub winner: Option<Pubkey>,
+    pub escrow_amount: u64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl Lobby {
+    pub const LEN: usize = 1    // status enum
+        + 4                     // race_id string discriminator
+        + MAX_RACE_ID_LEN       // race_id (max length)
+        + 8                     // entry_fee_sol u64
+        + RaceConfig::LEN       // config
+        + 32                    // creator pubkey
+        + 1                     // max_players u8
+        + 1                     // min_players_to_start u8
+        + 8                     // join_grace_secs i64
+        + 8                     // min_reached_at i64
+        + 1                     // antisnipe_extensions u8
+        + 2                     // late_join_surcharge_bps u16
+        + 4 + 32 * MAX_LOBBY_PLAYERS // players vec<pubkey>
+        + 4 + (1 + (8 + 8 + 32)) * MAX_LOBBY_PLAYERS // results vec<option<raceresult>>
+        + 4 + 8 * MAX_LOBBY_PLAYERS // paid_fees vec<u64>
+        + 1 + 32                // winner option<pubkey>
+        + 8                     // escrow_amount u64
+        + 8                     // created_at i64
+        + 1;                    // bump u8
+
+    /// Fee for the seat at `seat` (0 is the creator's):
+    /// `entry_fee_sol * (1 + late_join_surcharge_bps / 10_000 * seat)`,
+    /// rounded down.
+    pub fn fee_for_seat(&self, seat: usize) -> Result<u64> {
+        let rate = (self.late_join_surcharge_bps as u128)
+            .checked_mul(seat as u128)
+            .and_then(|surcharge| surcharge.checked_add(SPLIT_TOTAL_BPS as u128))
+            .ok_or(SolracerError::MathOverflow)?;
+        u64::try_from(self.entry_fee_sol as u128 * rate / SPLIT_TOTAL_BPS as u128)
+            .map_err(|_| error!(SolracerError::MathOverflow))
+    }
+
+    /// After this, a lobby still below its minimum can be refunded.
+    pub fn join_deadline(&self) -> i64 {
+        self.created_at.saturating_add(LOBBY_JOIN_DEADLINE_SECS)
+    }
+
+    /// True once the post-minimum grace window has closed. With no grace
+    /// configured, joins stay open until the lobby starts or fills.
+    pub fn join_grace_expired(&self, now: i64) -> bool {
+        self.join_grace_secs > 0
+            && self.min_reached_at > 0
+            && now > self.min_reached_at + self.join_grace_secs
+    }
+
+    /// Extend the open late-join window by `extension_secs` when a join at
+    /// `now` lands within `window_secs` of it closing, up to
+    /// `MAX_ANTISNIPE_EXTENSIONS` times. Returns whether it was extended.
+    pub fn apply_antisnipe(&mut self, now: i64, window_secs: i64, extension_secs: i64) -> bool {
+        if window_secs <= 0
+            || extension_secs <= 0
+            || self.join_grace_secs <= 0
+            || self.min_reached_at == 0
+            || self.antisnipe_extensions >= MAX_ANTISNIPE_EXTENSIONS
+        {
+            return false;
+        }
+
+        let closes_at = self.min_reached_at.saturating_add(self.join_grace_secs);
+        if closes_at.saturating_sub(now) > window_secs {
+            return false;
+        }
+
+        self.join_grace_secs = self.join_grace_secs.saturating_add(extension_secs);
+        self.antisnipe_extensions += 1;
+        true
+    }
+}
+
+/// Raw replay bytes for one player's result in one race.
+#[account]
+pub struct Replay {
+    pub race: Pubkey,
+    pub player: Pubkey,
+    /// Set once `verify_result` matched the replay to the submitted `input_hash`
+    pub verified: bool,
+    pub bump: u8,
+    pub data: Vec<u8>,
+}
+
+impl Replay {
+    pub const LEN: usize = 32   // race pubkey
+        + 32                    // player pubkey
+        + 1                     // verified bool
+        + 1                     // bump u8
+        + 4 + MAX_REPLAY_LEN;   // data vec<u8>
+}
+
+/// One race to create in `create_race_batch`; same inputs as
+/// `create_race_with_config`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct RaceSpec {
+    pub race_id: String,
+    pub token_mint: Pubkey,
+    pub entry_fee_sol: u64,
+    pub config: RaceConfig,
+}
+
+/// Ring buffer of recent settled outcomes for one token mint, seeded by
+/// `[b"result_feed", token_mint]`. `head` is the next slot to write; once
+/// `len` reaches `RESULT_FEED_CAPACITY` each push overwrites the oldest.
+#[account]
+pub struct ResultFeed {
+    pub token_mint: Pubkey,
+    pub head: u8,
+    pub len: u8,
+    pub entries: [FeedEntry; RESULT_FEED_CAPACITY],
+    pub bump: u8,
+}
+
+impl ResultFeed {
+    pub const LEN: usize = 32                   // token_mint pubkey
+        + 1                                     // head u8
+        + 1                                     // len u8
+        + FeedEntry::LEN * RESULT_FEED_CAPACITY // entries
+        + 1;                                    // bump u8
+
+    /// Append `entry`, evicting the oldest once the feed is full.
+    pub fn push(&mut self, entry: FeedEntry) {
+        self.entries[self.head as usize] = entry;
+        self.head = ((self.head as usize + 1) % RESULT_FEED_CAPACITY) as u8;
+        if (self.len as usize) < RESULT_FEED_CAPACITY {
+            self.len += 1;
+        }
+    }
+
+    /// Stored entries, newest first.
+    pub fn recent(&self) -> impl Iterator<Item = &FeedEntry> {
+        (1..=self.len as usize).map(move |back| {
+            &self.entries[(self.head as usize + RESULT_FEED_CAPACITY - back) % RESULT_FEED_CAPACITY]
+        })
+    }
+}
+
+/// One settled race in a `ResultFeed`; `race_id_hash` is `sha256(race_id)`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeedEntry {
+    pub race_id_hash: [u8; 32],
+    pub winner: Pubkey,
+    pub winner_time_ms: u64,
+}
+
+impl FeedEntry {
+    pub const LEN: usize = 32 + 32 + 8;
+}
+
+/// A player's unclaimed wins, seeded by `[b"claim_index", player]`, so a
+/// client can list them without scanning every race. `settle_race` adds a
+/// race when the index is passed, `reassign_prize` moves it to the new
+/// winner's, and the claim instructions remove it.
+#[account]
+pub struct ClaimIndex {
+    pub player: Pubkey,
+    pub races: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl ClaimIndex {
+    pub const LEN: usize = 32                   // player pubkey
+        + 4 + 32 * CLAIM_INDEX_CAPACITY         // races vec<pubkey>
+        + 1;                                    // bump u8
+
+    /// Index `race`; false when the index is full. A race already
+    /// indexed is not added twice.
+    pub fn add(&mut self, race: Pubkey) -> bool {
+        if self.races.contains(&race) {
+            return true;
+        }
+        if self.races.len() >= CLAIM_INDEX_CAPACITY {
+            return false;
+        }
+        self.races.push(race);
+        true
+    }
+
+    /// Drop `race` from the index, if present.
+    pub fn remove(&mut self, race: &Pubkey) {
+        self.races.retain(|indexed| indexed != race);
+    }
+}
+
+/// A player's prepaid entry fees, seeded by `[b"credits", player]`. The
+/// account holds `balance` lamports on top of its rent reserve, and each
+/// credit-funded entry moves that seat's stake from here into the race.
+#[account]
+pub struct CreditAccount {
+    pub player: Pubkey,
+    pub balance: u64,
+    pub bump: u8,
+}
+
+impl CreditAccount {
+    pub const LEN: usize = 32 // player pubkey
+        + 8                   // balance u64
+        + 1;                  // bump u8
+
+    /// Take `amount` off the balance; fails with `InsufficientCredits`
+    /// rather than spending part of it.
+    pub fn debit(&mut self, amount: u64) -> Result<()> {
+        self.balance = self
+            .balance
+            .checked_sub(amount)
+            .ok_or(SolracerError::InsufficientCredits)?;
+        Ok(())
+    }
+}
+
+/// A spectator's chat pacing, seeded by `[b"chat_author", author]`, so one
+/// busy author can't lock anyone else out of a race's chat.
+#[account]
+pub struct ChatAuthor {
+    pub author: Pubkey,
+    pub last_posted_at: i64,
+    pub bump: u8,
+}
+
+impl ChatAuthor {
+    pub const LEN: usize = 32 // author pubkey
+        + 8                   // last_posted_at i64
+        + 1;                  // bump u8
+
+    /// Record a message posted at `now`; fails with `ChatRateLimited`
+    /// within `CHAT_UPDATE_MIN_INTERVAL_SECS` of the author's last one.
+    pub fn post(&mut self, now: i64) -> Result<()> {
+        require!(
+            now >= self.last_posted_at.saturating_add(CHAT_UPDATE_MIN_INTERVAL_SECS),
+            SolracerError::ChatRateLimited
+        );
+        self.last_posted_at = now;
+        Ok(())
+    }
+}
+
+/// Protocol-wide counters for dashboards, seeded by `[b"global_stats"]`.
+/// Every instruction that creates, joins, settles or claims a race updates
+/// them when the account is passed; races handled without it aren't counted.
+#[account]
+pub struct GlobalStats {
+    /// Races created
+    pub total_races: u64,
+    /// Races settled, by any settle instruction, concession or dispute ruling
+    pub total_settled: u64,
+    /// Entry fees escrowed on creation and join
+    pub total_volume_lamports: u64,
+    /// Protocol fees taken by the claim instructions
+    pub total_fees_collected: u64,
+    pub bump: u8,
+}
+
+impl GlobalStats {
+    pub const LEN: usize = 8 // total_races u64
+        + 8                  // total_settled u64
+        + 8                  // total_volume_lamports u64
+        + 8                  // total_fees_collected u64
+        + 1;                 // bump u8
+
+    /// Count a new race escrowing `stake`.
+    pub fn record_race(&mut self, stake: u64) -> Result<()> {
+        self.total_races = self
+            .total_races
+            .checked_add(1)
+            .ok_or(SolracerError::MathOverflow)?;
+        self.record_volume(stake)
+    }
+
+    pub fn record_volume(&mut self, amount: u64) -> Result<()> {
+        self.total_volume_lamports = self
+            .total_volume_lamports
+            .checked_add(amount)
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_settlement(&mut self) -> Result<()> {
+        self.total_settled = self
+            .total_settled
+            .checked_add(1)
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_fee(&mut self, fee: u64) -> Result<()> {
+        self.total_fees_collected = self
+            .total_fees_collected
+            .checked_add(fee)
+            .ok_or(SolracerError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+/// Lamports (above its rent reserve) paid out as `crank_settle` bounties,
+/// seeded by `[b"bounty_pool"]` and topped up with `fund_bounty_pool`.
+#[account]
+pub struct BountyPool {
+    pub bump: u8,
+}
+
+impl BountyPool {
+    pub const LEN: usize = 1; // bump u8
+}
+
+/// Lamports (above its rent reserve) paid to losers at `settle_race`,
+/// seeded by `[b"consolation_vault"]` and topped up with
+/// `fund_consolation_vault`.
+#[account]
+pub struct ConsolationVault {
+    pub consolation_per_coin: u64, // 8
+    pub max_per_race:         u64, // 8
+    pub bump:                 u8,  // 1
+}
+
+impl ConsolationVault {
+    pub const LEN: usize = 17;
+}
+
+/// Escrow-size tiers for the claim delay stored on each race at
+/// settlement, seeded by `[b"claim_delay_tiers"]` and set with
+/// `set_claim_delay_tiers`.
+#[account]
+pub struct ClaimDelayTiers {
+    pub base_delay_secs: i64,                 // 8
+    pub tiers:           Vec<ClaimDelayTier>, // 4 + 16 * MAX_CLAIM_DELAY_TIERS
+    pub bump:            u8,                  // 1
+}
+
+impl ClaimDelayTiers {
+    pub const LEN: usize = 8 + 4 + 16 * MAX_CLAIM_DELAY_TIERS + 1;
+
+    /// The claim delay for a pot of `escrow` lamports: the longest of
+    /// `base_delay_secs` and the delays of every tier it reaches.
+    pub fn delay_for(&self, escrow: u64) -> i64 {
+        self.tiers
+            .iter()
+            .filter(|tier| escrow >= tier.min_escrow)
+            .map(|tier| tier.delay_secs)
+            .fold(self.base_delay_secs, i64::max)
+    }
+}
+
+/// One tier of `ClaimDelayTiers`: pots of at least `min_escrow` lamports
+/// wait `delay_secs` before they can be claimed.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ClaimDelayTier {
+    pub min_escrow: u64,
+    pub delay_secs: i64,
+}
+
+/// One seat of `race_standings`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct Standing {
+    pub player: Pubkey,
+    pub result: Option<RaceResult>,
+    /// 1 for the leader; `None` until the player submits
+    pub place: Option<u8>,
+}
+
+/// Returned by the `create_race*` instructions.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct CreatedRace {
+    pub race: Pubkey,
+    /// The PDA bump, as stored in `Race::bump`
+    pub bump: u8,
+}
+
+/// Returned by `emergency_refund_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct EmergencyRefundSummary {
+    /// Races refunded and closed
+    pub refunded: u32,
+    /// Groups left untouched (see `emergency_refundable`)
+    pub skipped: u32,
+    /// Escrow returned across the refunded races, rent not included
+    pub lamports_refunded: u64,
+}
+
+/// Returned by `reconcile_escrow`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct EscrowReconciliation {
+    /// Entry fees and operator prefunds, over the race's life
+    pub total_deposited: u64,
+    /// Prizes and refunds paid out of the escrow
+    pub total_paid_out: u64,
+    pub escrow_amount: u64,
+    /// Rematch opt-ins held outside the escrow
+    pub rematch_deposits: u64,
+    /// The account's actual balance, rent included
+    pub lamports: u64,
+    /// Protocol fees and host cuts, out of `total_paid_out`
+    pub fees_paid: u64,
+}
+
+/// What `settle_race_with_hook` passes the winner hook. Fields are only
+/// ever appended.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct WinnerHookArgs {
+    pub race_id: String,
+    pub winner: Pubkey,
+    pub outcome: OutcomeReason,
+    /// The prize the winner can now claim
+    pub escrow_amount: u64,
+}
+
+/// Per-race rules fixed at creation. `Default` matches `create_race`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct RaceConfig {
+    /// Finish times within this many ms of each other count as a tie and
+    /// fall back to coins. 0 compares exact milliseconds.
+    pub tie_epsilon_ms: u64,
+    pub tiebreak: TiebreakMode,
+    /// `settle_race` marks the race `Disputed` when the slower time is more
+    /// than this many times the faster one (see `is_blowout`). 0 disables.
+    pub max_result_ratio: u16,
+    /// Seed of the generated track both players must race; results are
+    /// submitted with `submit_result_on_map`. 0 means no seeded map.
+    pub map_seed: u64,
+    /// Mark the race `Disputed` when both results, `input_hash` included,
+    /// are identical, since that implies a copied submission. Off by default.
+    pub void_identical_results: bool,
+    /// Finish times are rounded to the nearest multiple of this before
+    /// comparing, so sub-quantum jitter doesn't decide a race (see
+    /// `quantize_time`). 0 or 1 compares exact milliseconds.
+    pub time_quantum_ms: u64,
+    /// When set, only holders of an NFT from this verified Metaplex
+    /// collection may join, through `join_gated_race`
+    pub nft_collection: Option<Pubkey>,
+    /// When set, the race settles only through `settle_race_mutual`,
+    /// signed by both players; `settle_race` fails with
+    /// `MutualSettlementRequired`
+    pub mutual_settlement: bool,
+    /// Merkle root of the map's coin layout (leaves are `coin_leaf(id)`);
+    /// all zero means no layout is committed and `verify_coins` is off
+    pub coins_root: [u8; 32],
+    /// Program `settle_race_with_hook` calls with the outcome (see
+    /// `WinnerHookArgs`)
+    pub winner_hook: Option<Pubkey>,
+    /// When set, the race settles only through `settle_race_with_hook`, so
+    /// the hook can't be skipped
+    pub winner_hook_strict: bool,
+    /// Seconds after the race goes `Active` that results are accepted
+    /// (see `Race::submission_deadline`); after that a lone result wins by
+    /// forfeit. 0 means no deadline.
+    pub submission_window_secs: i64,
+    /// Seconds each player has to submit, counted from when they joined
+    /// (see `player1_deadline` / `player2_deadline`), for asynchronous
+    /// races. 0 means no per-player deadline.
+    pub player_window_secs: i64,
+    /// Flip every coin comparison so fewer coins wins (no-coin speedruns);
+    /// off, the default, more coins wins
+    pub fewer_coins_win: bool,
+    /// Results must come through `submit_result_attested`, co-signed by
+    /// the game server
+    pub server_attested: bool,
+    /// player2's stake when it differs from `entry_fee_sol` (handicap or
+    /// asymmetric-fee races); 0 means both players stake `entry_fee_sol`
+    pub player2_entry_fee: u64,
+}
+
+impl RaceConfig {
+    pub const LEN: usize = 8    // tie_epsilon_ms u64
+        + 1                     // tiebreak enum
+        + 2                     // max_result_ratio u16
+        + 8                     // map_seed u64
+        + 1                     // void_identical_results bool
+        + 8                     // time_quantum_ms u64
+        + 1 + 32                // nft_collection option<pubkey>
+        + 1                     // mutual_settlement bool
+        + 32                    // coins_root [u8; 32]
+        + 1 + 32                // winner_hook option<pubkey>
+        + 1                     // winner_hook_strict bool
+        + 8                     // submission_window_secs i64
+        + 8                     // player_window_secs i64
+        + 1                     // fewer_coins_win bool
+        + 1                     // server_attested bool
+        + 8;                    // player2_entry_fee u64
+
+    /// Submission deadline of a player who joined at `joined_at`, or 0
+    /// without a `player_window_secs`.
+    pub fn player_deadline(&self, joined_at: i64) -> i64 {
+        if self.player_window_secs > 0 {
+            joined_at.saturating_add(self.player_window_secs)
+        } else {
+            0
+        }
+    }
+}
+
+/// Generous enough that only a wildly implausible gap is flagged.
+pub const DEFAULT_MAX_RESULT_RATIO: u16 = 100;
+
+impl Default for RaceConfig {
+    fn default() -> Self {
+        Self {
+            tie_epsilon_ms: 0,
+            tiebreak: TiebreakMode::default(),
+            max_result_ratio: DEFAULT_MAX_RESULT_RATIO,
+            map_seed: 0,
+            void_identical_results: false,
+            time_quantum_ms: 0,
+            nft_collection: None,
+            mutual_settlement: false,
+            coins_root: [0; 32],
+            winner_hook: None,
+            winner_hook_strict: false,
+            submission_window_secs: 0,
+            player_window_secs: 0,
+            fewer_coins_win: false,
+            server_attested: false,
+            player2_entry_fee: 0,
+        }
+    }
+}
+
+/// How `determine_winner` ranks two results.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TiebreakMode {
+    /// Faster time wins; (near-)equal times are decided by coins
+    #[default]
+    TimeThenCoins,
+    /// Finish time is ignored except to break a coin tie
+    CoinsOnly,
+    /// Faster time wins; (near-)equal times are decided by coins per
+    /// millisecond (see `compare_efficiency`)
+    Efficiency,
+    /// As `TimeThenCoins`, but a full tie goes to the smaller `input_hash`
+    /// (byte order) rather than to player1
+    HashOrder,
+}
+
+/// Why a race's winner won, stored as `Race::outcome` at settlement.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OutcomeReason {
+    /// Finished faster, beyond `tie_epsilon_ms`
+    FasterTime,
+    /// Decided on coins: a time tie under `TimeThenCoins`, or `CoinsOnly`
+    MoreCoins,
+    /// Time and coins both tied; player1 wins by default
+    Tie,
+    /// The opponent conceded
+    Concession,
+    /// The authority decided a disputed race
+    Override,
+    /// House race decided against `house_target_ms`
+    HouseTarget,
+    /// Written by `import_result`
+    Imported,
+    /// The opponent was disqualified by the authority
+    Disqualification,
+    /// Both players named this winner in `settle_race_mutual`
+    MutualAgreement,
+    /// A time tie under `Efficiency`, decided on coins per millisecond
+    Efficiency,
+    /// A full tie under `HashOrder`, decided on `input_hash`
+    HashOrder,
+    /// The opponent had no result in by the submission deadline
+    Forfeit,
+    /// As `MoreCoins`, for a race with `fewer_coins_win` set
+    FewerCoins,
+    /// The original winner was ruled ineligible in `reassign_prize`
+    Reassigned,
+    /// The opponent collected fewer than `min_coins_to_win` coins
+    BelowMinCoins,
+}
+
+/// Why `reassign_prize` took the prize from a settled winner.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum IneligibilityReason {
+    /// The player was banned from the game
+    Banned,
+    /// The player was disqualified after the race, e.g. for cheating
+    Disqualified,
+    /// Any other ruling, documented off-chain
+    Other,
+}
+
+/// Why a race was cancelled without a winner.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CancelReason {
+    /// Both players agreed in `mutual_cancel`
+    Mutual,
+    /// Neither player submitted by the deadline (`declare_no_contest`)
+    NoContest,
+    /// Refunded by the operator in `emergency_refund_batch`
+    Emergency,
+}
+
+/// Why a race was marked `Disputed`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DisputeReason {
+    /// The slower time exceeded `max_result_ratio` times the faster one
+    ResultRatio,
+    /// Both results, `input_hash` included, were byte-identical
+    IdenticalResults,
+    /// The players named different winners in `settle_race_mutual`
+    Disagreement,
+    /// A player's coins failed `verify_coins` against the map layout
+    CoinProof,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct RaceResult {
+    pub finish_time_ms: u64,
+    pub coins_collected: u64,
+    pub input_hash: [u8; 32],
+}
+
+/// Serialized as a single byte: `Waiting` = 0, `Active` = 1, `Settled` = 2,
+/// `Claimed` = 3, `Disputed` = 4, `Voided` = 5, `NoContest` = 6. Append new variants at the
+/// end so existing values stay stable.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum RaceStatus {
+    Waiting,
+    Active,
+    Settled,
+    /// Prize paid out; set before funds move so a second claim fails fast
+    Claimed,
+    /// Results looked implausible at settlement; no payout until the
+    /// authority decides the winner with `resolve_dispute`
+    Disputed,
+    /// Both players were disqualified; entry fees are returned with
+    /// `refund_voided`
+    Voided,
+    /// Neither player submitted by the deadline; `declare_no_contest`
+    /// refunded both stakes in full
+    NoContest,
+}
+
+// Instruction contexts
+
+#[derive(Accounts)]
+#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64)]
+pub struct CreateRace<'info> {
+    #[account(
+        init,
+        payer = player1,
+        space = 8 + Race::LEN,
+        seeds = [
+            b"race",
+            player1.key().as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player1: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// to refuse a paused token
+    #[account(seeds = [b"token_config", token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// Player1's credits; when passed, the entry fee is drawn from them
+    #[account(
+        mut,
+        seeds = [b"credits", player1.key().as_ref()],
+        bump = credits.bump,
+    )]
+    pub credits: Option<Account<'info, CreditAccount>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64)]
+pub struct CreateHouseRace<'info> {
+    #[account(
+        init,
+        payer = player1,
+        space = 8 + Race::LEN,
+        seeds = [
+            b"race",
+            player1.key().as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player1: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// The house: funds the counter-stake and takes the pot if player1 misses the target
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// to refuse a paused token
+    #[account(seeds = [b"token_config", token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64)]
+pub struct CreateAndJoin<'info> {
+    #[account(
+        init,
+        payer = player1,
+        space = 8 + Race::LEN,
+        seeds = [
+            b"race",
+            player1.key().as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player1: Signer<'info>,
+
+    #[account(mut)]
+    pub player2: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// to refuse a paused token
+    #[account(seeds = [b"token_config", token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct JoinRace<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player2: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// to refuse a paused token
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// Optional relayer paying the entry fee instead of player2
+    #[account(mut)]
+    pub fee_payer: Option<Signer<'info>>,
+
+    /// Player2's credits; when passed, the entry fee is drawn from them
+    /// and `fee_payer` is ignored
+    #[account(
+        mut,
+        seeds = [b"credits", player2.key().as_ref()],
+        bump = credits.bump,
+    )]
+    pub credits: Option<Account<'info, CreditAccount>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Config::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AuthorityPrefund<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: reimbursed when covering player1's fee; must be the race's player1
+    #[account(mut, address = race.player1 @ SolracerError::PlayerNotInRace)]
+    pub player1: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id_hash: [u8; 32], session_key: Pubkey, duration_secs: i64)]
+pub struct DelegateSession<'info> {
+    #[account(
+        init,
+        payer = player,
+        space = 8 + PlayerSession::LEN,
+        seeds = [b"session", race_id_hash.as_ref(), player.key().as_ref()],
+        bump
+    )]
+    pub session: Account<'info, PlayerSession>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitResult<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The signer: either the player wallet or the session key
+    pub authority: Signer<'info>,
+
+    /// session PDA, provided when signing with session key.
+    #[account(
+        seeds = [b"session", session.race_id_hash.as_ref(), player_wallet.key().as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Option<Account<'info, PlayerSession>>,
+
+    /// CHECK: only used for PDA seed derivation when session is provided
+    pub player_wallet: UncheckedAccount<'info>,
+
+    /// CHECK: the race map's `MapConfig` PDA; read when initialized, to
+    /// cap `coins_collected`
+    #[account(seeds = [b"map_config".as_ref(), &race.config.map_seed.to_le_bytes()], bump)]
+    pub map_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitResultAttested<'info> {
+    pub submit: SubmitResult<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// The game server; checked against the config in the handler
+    pub server: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRace<'info> {
+    /// Only the operator the race was created under may settle it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: the `ClaimDelayTiers` PDA; read when initialized
+    #[account(seeds = [b"claim_delay_tiers"], bump)]
+    pub claim_delay_tiers: UncheckedAccount<'info>,
+
+    /// The winner's index; checked against the winner in the handler
+    #[account(mut)]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    #[account(mut, seeds = [b"consolation_vault"], bump = consolation_vault.bump)]
+    pub consolation_vault: Option<Account<'info, ConsolationVault>>,
+
+    /// CHECK: the loser's wallet, receiving the consolation; checked
+    /// against the race in the handler
+    #[account(mut)]
+    pub loser: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Concede<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The conceding player's wallet
+    pub player: Signer<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPrize<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The signer: either the winner wallet or the session key
+    pub authority: Signer<'info>,
+
+    /// Optional session PDA, provided when signing with session key
+    #[account(
+        seeds = [b"session", session.race_id_hash.as_ref(), winner_wallet.key().as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Option<Account<'info, PlayerSession>>,
+
+    /// CHECK: The actual winner wallet that receives funds.
+    /// When signing directly (no session), pass the same key as authority.
+    #[account(mut)]
+    pub winner_wallet: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// otherwise the global fee applies
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// CHECK: receives the protocol fee; must be the treasury from
+    /// `fee_schedule`, checked in the handler
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"claim_index", winner_wallet.key().as_ref()],
+        bump = claim_index.bump,
+    )]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    /// CHECK: receives the host cut; must be the race's player1, checked
+    /// in the handler
+    #[account(mut)]
+    pub host: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64)]
+pub struct ClaimAndCreate<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The new race, with the winner as player1
+    #[account(
+        init,
+        payer = winner,
+        space = 8 + Race::LEN,
+        seeds = [
+            b"race",
+            winner.key().as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub new_race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub winner: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the claimed race token's `TokenConfig` PDA; read when
+    /// initialized, otherwise the global fee applies
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// CHECK: receives the protocol fee; must be the treasury from
+    /// `fee_schedule`, checked in the handler
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: receives the host cut; must be the race's player1, checked
+    /// in the handler
+    #[account(mut)]
+    pub host: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"claim_index", winner.key().as_ref()],
+        bump = claim_index.bump,
+    )]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(token_mint: Pubkey)]
+pub struct InitTokenConfig<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TokenConfig::LEN,
+        seeds = [b"token_config", token_mint.as_ref()],
+        bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(map_seed: u64)]
+pub struct RegisterMap<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + MapConfig::LEN,
+        seeds = [b"map_config".as_ref(), &map_seed.to_le_bytes()],
+        bump
+    )]
+    pub map_config: Account<'info, MapConfig>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTokenConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_config", token_config.token_mint.as_ref()],
+        bump = token_config.bump,
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimBatch<'info> {
+    /// The winner wallet; signs and receives every prize in the batch
+    #[account(mut)]
+    pub winner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"claim_index", winner.key().as_ref()],
+        bump = claim_index.bump,
+    )]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the `TokenConfig` PDA of the batch's token, checked against
+    /// each race in the handler; read when initialized, otherwise the
+    /// global fee applies
+    pub token_config: UncheckedAccount<'info>,
+
+    /// CHECK: receives the protocol fee; must be the treasury from
+    /// `fee_schedule`, checked in the handler
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, entry_fee_sol: u64)]
+pub struct CreateTeamRace<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + TeamRace::LEN,
+        seeds = [b"team_race", race_id.as_bytes(), &entry_fee_sol.to_le_bytes()],
+        bump
+    )]
+    pub team_race: Account<'info, TeamRace>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct JoinTeamRace<'info> {
+    #[account(mut)]
+    pub team_race: Account<'info, TeamRace>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitTeamResult<'info> {
+    #[account(mut)]
+    pub team_race: Account<'info, TeamRace>,
+
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleTeamRace<'info> {
+    #[account(mut)]
+    pub team_race: Account<'info, TeamRace>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTeamPrize<'info> {
+    #[account(mut)]
+    pub team_race: Account<'info, TeamRace>,
+
+    /// A member of the winning team; receives their share
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, entry_fee_sol: u64)]
+pub struct CreateLobby<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + Lobby::LEN,
+        seeds = [b"lobby", race_id.as_bytes(), &entry_fee_sol.to_le_bytes()],
+        bump
+    )]
+    pub lobby: Account<'info, Lobby>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct JoinLobby<'info> {
+    #[account(mut)]
+    pub lobby: Account<'info, Lobby>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StartRace<'info> {
+    #[account(mut, has_one = creator @ SolracerError::Unauthorized)]
+    pub lobby: Account<'info, Lobby>,
+
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitLobbyResult<'info> {
+    #[account(mut)]
+    pub lobby: Account<'info, Lobby>,
+
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleLobby<'info> {
+    #[account(mut)]
+    pub lobby: Account<'info, Lobby>,
+}
+
+#[derive(Accounts)]
+pub struct RaceStandings<'info> {
+    pub lobby: Account<'info, Lobby>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLobbyPrize<'info> {
+    #[account(mut)]
+    pub lobby: Account<'info, Lobby>,
+
+    #[account(mut)]
+    pub winner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct StoreReplay<'info> {
+    pub race: Account<'info, Race>,
+
+    #[account(
+        init,
+        payer = player,
+        space = 8 + Replay::LEN,
+        seeds = [b"replay", race.key().as_ref(), player.key().as_ref()],
+        bump
+    )]
+    pub replay: Account<'info, Replay>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyResult<'info> {
+    pub race: Account<'info, Race>,
+
+    #[account(
+        mut,
+        seeds = [b"replay", race.key().as_ref(), replay.player.as_ref()],
+        bump = replay.bump,
+    )]
+    pub replay: Account<'info, Replay>,
+}
+
+#[derive(Accounts)]
+pub struct CloseReplay<'info> {
+    #[account(mut, close = player, has_one = player)]
+    pub replay: Account<'info, Replay>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPrizeWsol<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The signer: either the winner wallet or the session key
+    pub authority: Signer<'info>,
+
+    /// Optional session PDA, provided when signing with session key
+    #[account(
+        seeds = [b"session", session.race_id_hash.as_ref(), winner_token_account.owner.as_ref()],
+        bump = session.bump,
+    )]
+    pub session: Option<Account<'info, PlayerSession>>,
+
+    /// The winner's wrapped SOL token account
+    #[account(
+        mut,
+        constraint = winner_token_account.mint == native_mint::ID @ SolracerError::NotWrappedSol,
+    )]
+    pub winner_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// otherwise the global fee applies
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// CHECK: receives the protocol fee; must be the treasury from
+    /// `fee_schedule`, checked in the handler
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    /// CHECK: receives the host cut; must be the race's player1, checked
+    /// in the handler
+    #[account(mut)]
+    pub host: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"claim_index", winner_token_account.owner.as_ref()],
+        bump = claim_index.bump,
+    )]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+}
+
+#[derive(Accounts)]
+pub struct InitChatAuthor<'info> {
+    #[account(
+        init,
+        payer = author,
+        space = 8 + ChatAuthor::LEN,
+        seeds = [b"chat_author", author.key().as_ref()],
+        bump
+    )]
+    pub chat_author: Account<'info, ChatAuthor>,
+
+    #[account(mut)]
+    pub author: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateChatRoot<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// Pays the chat deposit; need not be a racer
+    #[account(mut)]
+    pub author: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"chat_author", author.key().as_ref()],
+        bump = chat_author.bump,
+    )]
+    pub chat_author: Account<'info, ChatAuthor>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: receives the chat deposit; must be the config treasury
+    #[account(mut, address = config.treasury @ SolracerError::TreasuryMismatch)]
+    pub treasury: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateRaceBatch<'info> {
+    /// player1 of every created race; pays rent and each entry fee
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoRematch<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRaceWithRematch<'info> {
+    /// Only the operator the race was created under may settle it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: must be the race PDA for `rematch_race_id(race.race_id)`;
+    /// created here when both players opted in
+    #[account(mut)]
+    pub rematch_race: UncheckedAccount<'info>,
+
+    /// Pays the rematch account's rent
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the `ClaimDelayTiers` PDA; read when initialized
+    #[account(seeds = [b"claim_delay_tiers"], bump)]
+    pub claim_delay_tiers: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitProfile<'info> {
+    #[account(
+        init,
+        payer = player,
+        space = 8 + PlayerProfile::LEN,
+        seeds = [b"profile", player.key().as_ref()],
+        bump
+    )]
+    pub profile: Account<'info, PlayerProfile>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RateRace<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"profile", race.player1.as_ref()],
+        bump = player1_profile.bump,
+    )]
+    pub player1_profile: Account<'info, PlayerProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"profile", race.player2.unwrap_or_default().as_ref()],
+        bump = player2_profile.bump,
+    )]
+    pub player2_profile: Account<'info, PlayerProfile>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct RefundLobby<'info> {
+    #[account(mut, has_one = creator @ SolracerError::Unauthorized, close = creator)]
+    pub lobby: Account<'info, Lobby>,
+
+    /// CHECK: receives the lobby's rent when it closes
+    #[account(mut)]
+    pub creator: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitClaimIndex<'info> {
+    #[account(
+        init,
+        payer = player,
+        space = 8 + ClaimIndex::LEN,
+        seeds = [b"claim_index", player.key().as_ref()],
+        bump
+    )]
+    pub claim_index: Account<'info, ClaimIndex>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitCreditAccount<'info> {
+    #[account(
+        init,
+        payer = player,
+        space = 8 + CreditAccount::LEN,
+        seeds = [b"credits", player.key().as_ref()],
+        bump
+    )]
+    pub credits: Account<'info, CreditAccount>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyCredits<'info> {
+    #[account(
+        mut,
+        seeds = [b"credits", player.key().as_ref()],
+        bump = credits.bump,
+    )]
+    pub credits: Account<'info, CreditAccount>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(token_mint: Pubkey)]
+pub struct InitResultFeed<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + ResultFeed::LEN,
+        seeds = [b"result_feed", token_mint.as_ref()],
+        bump
+    )]
+    pub result_feed: Account<'info, ResultFeed>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRaceWithFeed<'info> {
+    /// Only the operator the race was created under may settle it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"result_feed", race.token_mint.as_ref()],
+        bump = result_feed.bump,
+    )]
+    pub result_feed: Account<'info, ResultFeed>,
+
+    /// CHECK: the `ClaimDelayTiers` PDA; read when initialized
+    #[account(seeds = [b"claim_delay_tiers"], bump)]
+    pub claim_delay_tiers: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64, player1: Pubkey)]
+pub struct ImportResult<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Race::LEN,
+        seeds = [
+            b"race",
+            player1.as_ref(),
+            race_id.as_bytes(),
+            token_mint.as_ref(),
+            &entry_fee_sol.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub race: Account<'info, Race>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LeaveRace<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player2: Signer<'info>,
+
+    /// CHECK: receives the refunded entry fee; must be `race.player2_fee_payer`
+    #[account(mut, address = race.player2_fee_payer)]
+    pub fee_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrankSettle<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"bounty_pool"], bump = bounty_pool.bump)]
+    pub bounty_pool: Account<'info, BountyPool>,
+
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    /// CHECK: the `ClaimDelayTiers` PDA; read when initialized
+    #[account(seeds = [b"claim_delay_tiers"], bump)]
+    pub claim_delay_tiers: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPrizeSplit<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The winner wallet; signs to authorize the split
+    pub winner: Signer<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// otherwise the global fee applies
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    /// CHECK: receives the protocol fee; must be the treasury from
+    /// `fee_schedule`, checked in the handler
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    /// CHECK: receives the host cut; must be the race's player1, checked
+    /// in the handler
+    #[account(mut)]
+    pub host: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [b"claim_index", winner.key().as_ref()],
+        bump = claim_index.bump,
+    )]
+    pub claim_index: Option<Account<'info, ClaimIndex>>,
+}
+
+#[derive(Accounts)]
+pub struct Disqualify<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReassignPrize<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    /// The ineligible winner's index; checked against them in the handler
+    #[account(
+        mut,
+        seeds = [b"claim_index", old_claim_index.player.as_ref()],
+        bump = old_claim_index.bump,
+    )]
+    pub old_claim_index: Option<Account<'info, ClaimIndex>>,
+
+    /// The runner-up's index; checked against them in the handler
+    #[account(
+        mut,
+        seeds = [b"claim_index", new_claim_index.player.as_ref()],
+        bump = new_claim_index.bump,
+    )]
+    pub new_claim_index: Option<Account<'info, ClaimIndex>>,
+}
+
+#[derive(Accounts)]
+pub struct SetVesting<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeatured<'info> {
+    /// Only the operator the race was created under may act on it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefundVoided<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump, has_one = authority)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: refund destination; must be `race.player1`
+    #[account(mut, address = race.player1)]
+    pub player1: UncheckedAccount<'info>,
+
+    /// CHECK: refund destination; must be `race.player2_fee_payer`
+    #[account(mut, address = race.player2_fee_payer)]
+    pub player2_fee_payer: UncheckedAccount<'info>,
+
+    /// CHECK: receives operator-covered fees and leftover deposits; must
+    /// be `config.authority`
+    #[account(mut)]
+    pub authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyRefundBatch<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Receives operator-covered stakes and leftover deposits
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeclareNoContest<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump, has_one = authority)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: refund destination; must be `race.player1`
+    #[account(mut, address = race.player1)]
+    pub player1: UncheckedAccount<'info>,
+
+    /// CHECK: refund destination; must be `race.player2_fee_payer`
+    #[account(mut, address = race.player2_fee_payer)]
+    pub player2_fee_payer: UncheckedAccount<'info>,
+
+    /// CHECK: receives operator-covered fees and leftover deposits; must
+    /// be `config.authority`
+    #[account(mut)]
+    pub authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevealMapSeed<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// CHECK: the `SlotHashes` sysvar, read raw since it is too large to
+    /// deserialize whole
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID)]
+    pub slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReallocRace<'info> {
+    /// CHECK: a `Race` of any layout version, grown before it is read;
+    /// `realloc_race` checks the discriminator when deserializing
+    #[account(mut, owner = crate::ID)]
+    pub race: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimableFor<'info> {
+    pub race: Account<'info, Race>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// otherwise the global fee applies
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct JoinGatedRace<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(mut)]
+    pub player2: Signer<'info>,
+
+    /// player2's token account holding the gating NFT
+    pub nft_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: the NFT mint's Metaplex metadata PDA; parsed in `check_nft_gate`
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the race token's `TokenConfig` PDA; read when initialized,
+    /// to refuse a paused token
+    #[account(seeds = [b"token_config", race.token_mint.as_ref()], bump)]
+    pub token_config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct InitBountyPool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + BountyPool::LEN,
+        seeds = [b"bounty_pool"],
+        bump
+    )]
+    pub bounty_pool: Account<'info, BountyPool>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitClaimDelayTiers<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ClaimDelayTiers::LEN,
+        seeds = [b"claim_delay_tiers"],
+        bump
+    )]
+    pub claim_delay_tiers: Account<'info, ClaimDelayTiers>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetClaimDelayTiers<'info> {
+    #[account(mut, seeds = [b"claim_delay_tiers"], bump = claim_delay_tiers.bump)]
+    pub claim_delay_tiers: Account<'info, ClaimDelayTiers>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitConsolationVault<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ConsolationVault::LEN,
+        seeds = [b"consolation_vault"],
+        bump
+    )]
+    pub consolation_vault: Account<'info, ConsolationVault>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetConsolation<'info> {
+    #[account(mut, seeds = [b"consolation_vault"], bump = consolation_vault.bump)]
+    pub consolation_vault: Account<'info, ConsolationVault>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundConsolationVault<'info> {
+    #[account(mut, seeds = [b"consolation_vault"], bump = consolation_vault.bump)]
+    pub consolation_vault: Account<'info, ConsolationVault>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundBountyPool<'info> {
+    #[account(mut, seeds = [b"bounty_pool"], bump = bounty_pool.bump)]
+    pub bounty_pool: Account<'info, BountyPool>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRaceMutual<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    #[account(address = race.player1 @ SolracerError::PlayerNotInRace)]
+    pub player1: Signer<'info>,
+
+    #[account(
+        constraint = race.player2 == Some(player2.key()) @ SolracerError::PlayerNotInRace
+    )]
+    pub player2: Signer<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyCoins<'info> {
+    #[account(mut)]
+    pub race: Account<'info, Race>,
+
+    /// The player whose coins are proven
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MutualCancel<'info> {
+    #[account(mut, close = player1)]
+    pub race: Account<'info, Race>,
+
+    #[account(mut, address = race.player1 @ SolracerError::PlayerNotInRace)]
+    pub player1: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = race.player2 == Some(player2.key()) @ SolracerError::PlayerNotInRace
+    )]
+    pub player2: Signer<'info>,
+
+    /// CHECK: receives player2's refunded fee; must be `race.player2_fee_payer`
+    #[account(mut, address = race.player2_fee_payer)]
+    pub fee_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitGlobalStats<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GlobalStats::LEN,
+        seeds = [b"global_stats"],
+        bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReadGlobalStats<'info> {
+    #[account(seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileEscrow<'info> {
+    pub race: Account<'info, Race>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRaceWithHook<'info> {
+    /// Only the operator the race was created under may settle it
+    #[account(mut, has_one = authority @ SolracerError::WrongAuthority)]
+    pub race: Account<'info, Race>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: must be the race's `winner_hook`, checked in the handler
+    #[account(executable)]
+    pub hook_program: UncheckedAccount<'info>,
+
+    /// CHECK: the `ClaimDelayTiers` PDA; read when initialized
+    #[account(seeds = [b"claim_delay_tiers"], bump)]
+    pub claim_delay_tiers: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"global_stats"], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRace<'info> {
+    #[account(mut, close = rent_payer)]
+    pub race: Account<'info, Race>,
+
+    /// CHECK: receives the rent; must be `race.rent_recipient()`
+    #[account(mut, address = race.rent_recipient() @ SolracerError::RentPayerMismatch)]
+    pub rent_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimOrphanLamports<'info> {
+    /// CHECK: any account this program owns; its data must be zeroed
+    #[account(mut, owner = crate::ID)]
+    pub orphan: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ SolracerError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: receives the swept lamports; must be the config treasury
+    #[account(mut, address = config.treasury @ SolracerError::TreasuryMismatch)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+// Events
+
+#[event]
+pub struct RaceCreated {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub player1: Pubkey,
+    /// All zero unless created with `create_race_with_ref`
+    pub external_ref: [u8; 32],
+}
+
+#[event]
+pub struct RaceSettled {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub winner: Pubkey,
+    /// True when the loser conceded instead of the race being decided on results
+    pub conceded: bool,
+}
+
+#[event]
+pub struct RacePrefunded {
+    pub race: Pubkey,
+    pub authority: Pubkey,
+    pub player1_covered: bool,
+    pub player2_covered: bool,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TeamRaceSettled {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub winning_team: u8,
+}
+
+#[event]
+pub struct ChatRootUpdated {
+    pub race: Pubkey,
+    pub author: Pubkey,
+    pub message_hash: [u8; 32],
+    pub chat_root: [u8; 32],
+}
+
+#[event]
+pub struct RematchCreated {
+    pub race: Pubkey,
+    pub rematch: Pubkey,
+    pub rematch_race_id: String,
+}
+
+#[event]
+pub struct RaceDisputed {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub player1_time_ms: u64,
+    pub player2_time_ms: u64,
+    pub reason: DisputeReason,
+}
+
+/// Everything needed to reconstruct a 1v1 outcome, emitted alongside
+/// `RaceSettled` for indexers that missed earlier events. The results carry
+/// both players' `input_hash` for auditing.
+#[event]
+pub struct RaceFinalState {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub token_mint: Pubkey,
+    pub entry_fee_sol: u64,
+    pub player1: Pubkey,
+    pub player2: Option<Pubkey>,
+    pub player1_result: Option<RaceResult>,
+    pub player2_result: Option<RaceResult>,
+    pub winner: Option<Pubkey>,
+    pub outcome: Option<OutcomeReason>,
     pub escrow_amount: u64,
+    pub conceded: bool,
     pub created_at: i64,
-    pub bump: u8,
+    pub settled_at: i64,
+    pub external_ref: [u8; 32],
+}
+
+#[event]
+pub struct PlayerDisqualified {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub player: Pubkey,
+}
+
+#[event]
+pub struct PrizeReassigned {
+    pub race: Pubkey,
+    pub race_id: String,
+    /// The winner ruled ineligible
+    pub ineligible: Pubkey,
+    /// The runner-up, now the winner
+    pub winner: Pubkey,
+    pub reason: IneligibilityReason,
+    /// The escrow the new winner can claim
+    pub prize: u64,
+    pub settled_at: i64,
+    pub reassigned_at: i64,
+}
+
+#[event]
+pub struct RaceFeatured {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub featured: bool,
+}
+
+#[event]
+pub struct ConsolationPaid {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub loser: Pubkey,
+    /// 0 when the vault was empty or the loser had no coins
+    pub amount: u64,
+}
+
+#[event]
+pub struct CrankBountyPaid {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub cranker: Pubkey,
+    /// 0 when the pool was empty
+    pub amount: u64,
+}
+
+#[event]
+pub struct RaceCancelled {
+    pub race: Pubkey,
+    pub race_id: String,
+    pub reason: CancelReason,
+}
+
+// Error codes
+
+#[error_code]
+pub enum SolracerError {
+    #[msg("Invalid race status for this operation")]
+    InvalidRaceStatus,
+    #[msg("Player2 is already set")]
+    Player2AlreadySet,
+    #[msg("Player is not in this race")]
+    PlayerNotInRace,
+    #[msg("Result already submitted for this player")]
+    ResultAlreadySubmitted,
+    #[msg("Both results must be submitted before settling")]
+    ResultsNotComplete,
+    #[msg("Only the winner can claim the prize")]
+    NotWinner,
+    #[msg("Session key does not match registered key")]
+    InvalidSessionKey,
+    #[msg("Session has expired")]
+    SessionExpired,
+    #[msg("Race is already settled")]
+    AlreadySettled,
+    #[msg("Too many races in one batch")]
+    BatchTooLarge,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Signer is not the config authority")]
+    Unauthorized,
+    #[msg("Entry fee is already prefunded for this player")]
+    AlreadyPrefunded,
+    #[msg("Select at least one player to prefund")]
+    NothingToPrefund,
+    #[msg("Team must be 1 or 2")]
+    InvalidTeam,
+    #[msg("Team has no open slots")]
+    TeamFull,
+    #[msg("Both teams must be full first")]
+    TeamIncomplete,
+    #[msg("Player already holds a slot in this race")]
+    AlreadyInRace,
+    #[msg("Prize has already been claimed")]
+    AlreadyClaimed,
+    #[msg("race_id exceeds the maximum length")]
+    RaceIdTooLong,
+    #[msg("Lobby size must be 2..=8 with min_players_to_start <= max_players")]
+    InvalidLobbySize,
+    #[msg("Lobby is full")]
+    LobbyFull,
+    #[msg("Not enough players have joined to start")]
+    NotEnoughPlayers,
+    #[msg("Replay exceeds the on-chain size limit")]
+    ReplayTooLarge,
+    #[msg("Player has not submitted a result yet")]
+    ResultNotSubmitted,
+    #[msg("Replay does not hash to the submitted input_hash")]
+    InputHashMismatch,
+    #[msg("join_grace_secs cannot be negative")]
+    InvalidJoinGrace,
+    #[msg("The late-join grace window has closed")]
+    JoinGraceExpired,
+    #[msg("Cannot start while the late-join grace window is open")]
+    JoinGraceActive,
+    #[msg("Token account is not a wrapped SOL account")]
+    NotWrappedSol,
+    #[msg("Token account is not owned by the winner")]
+    InvalidTokenAccountOwner,
+    #[msg("This author posted to the chat too recently")]
+    ChatRateLimited,
+    #[msg("Race account does not match the expected race PDA")]
+    InvalidRaceAccount,
+    #[msg("A rematch has already been created for this race")]
+    RematchAlreadyCreated,
+    #[msg("Waiting for an opponent to join")]
+    OpponentNotJoined,
+    #[msg("Race has already been rated")]
+    AlreadyRated,
+    #[msg("Lobby reached its minimum players and should be started instead")]
+    MinimumReached,
+    #[msg("The lobby join deadline has not passed yet")]
+    JoinDeadlineNotReached,
+    #[msg("Escrow does not match the refunded entry fees")]
+    EscrowMismatch,
+    #[msg("Imported races hold no escrow and cannot be claimed")]
+    ImportedRace,
+    #[msg("Not enough lamports to pay the entry fee")]
+    InsufficientFunds,
+    #[msg("Result was not raced on this race's map seed")]
+    MapSeedMismatch,
+    #[msg("Cannot leave once a result has been submitted")]
+    CannotLeaveAfterSubmit,
+    #[msg("Anti-snipe window and extension cannot be negative")]
+    InvalidAntisnipe,
+    #[msg("Permissionless settlement is disabled")]
+    CrankDisabled,
+    #[msg("input_hash was not computed over this race's address")]
+    HashNotBoundToRace,
+    #[msg("This pair has reached the maximum number of rematches")]
+    RematchLimitReached,
+    #[msg("Claim splits must be 1-5 shares adding up to 10000 bps")]
+    InvalidSplit,
+    #[msg("Player does not hold an NFT from the race's collection")]
+    NftGateFailed,
+    #[msg("token_mint must be a real mint; use the native mint for SOL races")]
+    InvalidTokenMint,
+    #[msg("This race settles only through settle_race_mutual")]
+    MutualSettlementRequired,
+    #[msg("Mutual settlement is not enabled for this race")]
+    MutualSettlementDisabled,
+    #[msg("Coin proof is malformed or the race has no coin layout")]
+    CoinProofInvalid,
+    #[msg("Race escrow does not reconcile with its deposits and payouts")]
+    EscrowImbalance,
+    #[msg("Hook program does not match the race's winner_hook")]
+    WinnerHookMismatch,
+    #[msg("This race settles only through settle_race_with_hook")]
+    WinnerHookRequired,
+    #[msg("Account still holds data and may be live")]
+    AccountStillActive,
+    #[msg("Fee cannot exceed 10000 bps")]
+    InvalidFeeBps,
+    #[msg("Treasury account does not match the fee treasury")]
+    TreasuryMismatch,
+    #[msg("The submission deadline for this race has passed")]
+    SubmissionDeadlinePassed,
+    #[msg("Entry fee is below the configured minimum")]
+    EntryFeeTooLow,
+    #[msg("Entry fee is above the configured maximum")]
+    EntryFeeTooHigh,
+    #[msg("Minimum entry fee exceeds the maximum")]
+    InvalidEntryFeeBounds,
+    #[msg("Claim index does not belong to the race winner")]
+    ClaimIndexMismatch,
+    #[msg("Your submission deadline for this race has passed")]
+    PlayerDeadlinePassed,
+    #[msg("This race only accepts server-attested results")]
+    AttestationRequired,
+    #[msg("Signer is not an accepted server authority")]
+    UnknownServerAuthority,
+    #[msg("Grace period cannot be negative")]
+    InvalidGracePeriod,
+    #[msg("Signer is not the authority this race was created under")]
+    WrongAuthority,
+    #[msg("Not enough credits for this entry fee")]
+    InsufficientCredits,
+    #[msg("New race's entry fee exceeds the prize after fees")]
+    CompoundExceedsPrize,
+    #[msg("Race has not been settled yet")]
+    RaceNotSettledYet,
+    #[msg("New races on this token are paused")]
+    TokenPaused,
+    #[msg("Race has no map seed commitment left to reveal")]
+    NoMapSeedCommitment,
+    #[msg("The committed slot for the map seed has not passed yet")]
+    MapSeedNotReady,
+    #[msg("The map seed has not been revealed yet")]
+    MapSeedNotRevealed,
+    #[msg("More coins than the registered map holds")]
+    CoinsExceedMapMax,
+    #[msg("Only one result submission per player per slot")]
+    SubmitRateLimited,
+    #[msg("Protocol fee and host cut exceed the whole prize")]
+    FeesExceedPrize,
+    #[msg("Host account does not match the race creator")]
+    HostMismatch,
+    #[msg("A rematch opt-in is still held in the race")]
+    RematchDepositHeld,
+    #[msg("Rent payer account does not match the race")]
+    RentPayerMismatch,
+    #[msg("Race ID may only contain letters, digits, '-' and '_'")]
+    InvalidRaceId,
+    #[msg("The race's outcome is decided; its escrow takes no more deposits")]
+    EscrowLocked,
+    #[msg("No-contest resolution is not enabled")]
+    NoContestDisabled,
+    #[msg("A no-contest needs both players past their deadline with no result")]
+    NotNoContest,
+    #[msg("A race invariant was violated (strict builds only)")]
+    InvariantViolated,
+    #[msg("Loser account does not match the race")]
+    LoserMismatch,
+    #[msg("The window to reassign this race's prize has closed")]
+    ReassignWindowClosed,
+    #[msg("Vesting needs both a duration and a slice count, or neither")]
+    InvalidVestingSchedule,
+    #[msg("No more of the prize has vested yet")]
+    NothingVestedYet,
+    #[msg("Emergency refund accounts must come in groups of four per race")]
+    IncompleteRefundGroup,
+    #[msg("Claim delays must be non-negative, with at most MAX_CLAIM_DELAY_TIERS tiers")]
+    InvalidClaimDelayTiers,
+    #[msg("The prize can't be claimed until the claim delay has passed")]
+    ClaimDelayActive,
+    #[msg("Token config account does not match the race's token")]
+    TokenConfigMismatch,
+    #[msg("Claim batch accounts must come in [race, host] pairs")]
+    IncompleteClaimGroup,
+    #[msg("Race account predates the current layout; call realloc_race first")]
+    RaceNeedsRealloc,
 }
 
-impl Race {
-    pub const LEN: usize = 4    // race_id string discriminator
-        + 50                    // race_id (max length)
-        + 32                    // token_mint pubkey
-        + 8                     // entry_fee_sol u64
-        + 32                    // player1 pubkey
-        + 1 + 32                // player2 option<pubkey>
-        + 1                     // status enum
-        + 1 + (8 + 8 + 32)     // player1_result option<raceresult>
-        + 1 + (8 + 8 + 32)     // player2_result option<raceresult>
-        + 1 + 32                // winner option<pubkey>
-        + 8                     // escrow_amount u64
-        + 8                     // created_at i64
-        + 1;                    // bump u8
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_race(race_id: &str, status: RaceStatus) -> Race {
+        Race {
+            status,
+            race_id: race_id.to_string(),
+            token_mint: Pubkey::new_unique(),
+            entry_fee_sol: 1_000_000,
+            config: RaceConfig::default(),
+            player1: Pubkey::new_unique(),
+            player2: None,
+            player2_fee_payer: Pubkey::default(),
+            player1_result: None,
+            player2_result: None,
+            winner: None,
+            outcome: None,
+            escrow_amount: 1_000_000,
+            total_deposited: 1_000_000,
+            total_paid_out: 0,
+            created_at: 0,
+            started_at: 0,
+            player1_deadline: 0,
+            player2_deadline: 0,
+            player1_prefunded: false,
+            player2_prefunded: false,
+            player1_paid: 1_000_000,
+            player2_paid: 0,
+            operator_funded: 0,
+            chat_root: [0u8; 32],
+            chat_updated_at: 0,
+            player1_auto_rematch: false,
+            player2_auto_rematch: false,
+            rematch: None,
+            rematch_count: 0,
+            house_target_ms: None,
+            authority: Pubkey::default(),
+            external_ref: [0u8; 32],
+            rated: false,
+            imported: false,
+            player1_dq: false,
+            player2_dq: false,
+            player1_coins_verified: false,
+            player2_coins_verified: false,
+            bump: 255,
+            version: RACE_VERSION,
+            map_seed_slot: 0,
+            map_seed_revealed: false,
+            player1_last_submit_slot: 0,
+            player2_last_submit_slot: 0,
+            rent_payer: Pubkey::new_unique(),
+            settled_at: 0,
+            vesting_seconds: 0,
+            vesting_slices: 0,
+            claimed_so_far: 0,
+            claim_delay_secs: 0,
+            min_coins_to_win: 0,
+            fees_paid: 0,
+        }
+    }
+
+    #[test]
+    fn status_sits_at_fixed_offset() {
+        for (race_id, status, byte) in [
+            ("a", RaceStatus::Waiting, 0u8),
+            ("race_with_a_much_longer_identifier", RaceStatus::Active, 1),
+            ("", RaceStatus::Settled, 2),
+            ("claimed", RaceStatus::Claimed, 3),
+            ("disputed", RaceStatus::Disputed, 4),
+            ("voided", RaceStatus::Voided, 5),
+            ("no_contest", RaceStatus::NoContest, 6),
+        ] {
+            let mut data = Vec::new();
+            sample_race(race_id, status).try_serialize(&mut data).unwrap();
+            assert_eq!(data[Race::STATUS_OFFSET], byte);
+        }
+    }
+
+    #[test]
+    fn featured_flag_sits_past_the_serialized_race() {
+        for race in [max_race(), sample_race("a", RaceStatus::Active)] {
+            let mut data = Vec::new();
+            race.try_serialize(&mut data).unwrap();
+            assert!(data.len() <= Race::FEATURED_OFFSET);
+
+            data.resize(8 + Race::LEN, 0);
+            assert!(!Race::is_featured(&data));
+            data[Race::FEATURED_OFFSET] = 1;
+            assert!(Race::is_featured(&data));
+            let read = Race::try_deserialize(&mut &data[..]).unwrap();
+            assert_eq!(read.race_id, race.race_id);
+            assert_eq!(read.min_coins_to_win, race.min_coins_to_win);
+        }
+    }
+
+    // Every variable-size field at its largest, so serialized length must equal LEN
+    fn max_race() -> Race {
+        let full_result = Some(result(u64::MAX, u64::MAX));
+        Race {
+            race_id: "r".repeat(MAX_RACE_ID_LEN),
+            player2: Some(Pubkey::new_unique()),
+            player1_result: full_result.clone(),
+            player2_result: full_result,
+            winner: Some(Pubkey::new_unique()),
+            outcome: Some(OutcomeReason::Imported),
+            rematch: Some(Pubkey::new_unique()),
+            rematch_count: u16::MAX,
+            house_target_ms: Some(u64::MAX),
+            authority: Pubkey::new_unique(),
+            external_ref: [u8::MAX; 32],
+            config: max_race_config(),
+            ..sample_race("", RaceStatus::Settled)
+        }
+    }
+
+    fn max_race_config() -> RaceConfig {
+        RaceConfig {
+            nft_collection: Some(Pubkey::new_unique()),
+            winner_hook: Some(Pubkey::new_unique()),
+            ..RaceConfig::default()
+        }
+    }
+
+    fn serialized_len<T: AnchorSerialize>(value: &T) -> usize {
+        value.try_to_vec().unwrap().len()
+    }
+
+    #[test]
+    fn race_len_matches_max_serialized_size() {
+        // Plus the featured flag
+        assert_eq!(serialized_len(&max_race()) + 1, Race::LEN);
+    }
+
+    #[test]
+    fn race_config_len_matches_max_serialized_size() {
+        assert_eq!(serialized_len(&max_race_config()), RaceConfig::LEN);
+    }
+
+    #[test]
+    fn team_race_len_matches_max_serialized_size() {
+        let member = || Some(result(u64::MAX, u64::MAX));
+        let race = TeamRace {
+            status: RaceStatus::Settled,
+            race_id: "r".repeat(MAX_RACE_ID_LEN),
+            entry_fee_sol: 1,
+            aggregation: TeamAggregation::BestTime,
+            team1: [Pubkey::new_unique(); 2],
+            team2: [Pubkey::new_unique(); 2],
+            team1_results: [member(), member()],
+            team2_results: [member(), member()],
+            winning_team: Some(2),
+            prize_claimed: [true; 2],
+            escrow_amount: 1,
+            created_at: 1,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&race), TeamRace::LEN);
+    }
+
+    #[test]
+    fn fixed_size_account_lens_match_serialized_size() {
+        let session = PlayerSession {
+            player_wallet: Pubkey::new_unique(),
+            session_key: Pubkey::new_unique(),
+            race_id_hash: [1; 32],
+            expires_at: 1,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&session), PlayerSession::LEN);
+
+        let config = Config {
+            authority: Pubkey::new_unique(),
+            elo_k_factor: u16::MAX,
+            antisnipe_window_secs: i64::MAX,
+            antisnipe_extension_secs: i64::MAX,
+            crank_bounty_lamports: u64::MAX,
+            max_rematches: u16::MAX,
+            min_entry_fee: u64::MAX,
+            max_entry_fee: u64::MAX,
+            fee_bps: u16::MAX,
+            treasury: Pubkey::new_unique(),
+            server_authority: Pubkey::new_unique(),
+            prev_server_authority: Pubkey::new_unique(),
+            prev_server_expires_at: i64::MAX,
+            host_cut_bps: u16::MAX,
+            no_contest_on_no_show: true,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&config), Config::LEN);
+
+        let token_config = TokenConfig {
+            token_mint: Pubkey::new_unique(),
+            fee_bps: u16::MAX,
+            treasury: Pubkey::new_unique(),
+            paused: true,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&token_config), TokenConfig::LEN);
+
+        let map_config = MapConfig {
+            map_seed: u64::MAX,
+            max_coins: u64::MAX,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&map_config), MapConfig::LEN);
+
+        let consolation_vault = ConsolationVault {
+            consolation_per_coin: u64::MAX,
+            max_per_race: u64::MAX,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&consolation_vault), ConsolationVault::LEN);
+
+        let claim_delay_tiers = ClaimDelayTiers {
+            base_delay_secs: i64::MAX,
+            tiers: vec![
+                ClaimDelayTier {
+                    min_escrow: u64::MAX,
+                    delay_secs: i64::MAX,
+                };
+                MAX_CLAIM_DELAY_TIERS
+            ],
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&claim_delay_tiers), ClaimDelayTiers::LEN);
+
+        let profile = PlayerProfile {
+            player: Pubkey::new_unique(),
+            rating: i32::MIN,
+            rated_races: u32::MAX,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&profile), PlayerProfile::LEN);
+    }
+
+    fn result(finish_time_ms: u64, coins_collected: u64) -> RaceResult {
+        RaceResult {
+            finish_time_ms,
+            coins_collected,
+            input_hash: [0; 32],
+        }
+    }
+
+    #[test]
+    fn min_coins_to_win_disqualifies_coinless_results() {
+        let config = RaceConfig::default();
+        // Fastest, but short of coins
+        assert_eq!(
+            qualified_winner(&result(40_000, 4), &result(50_000, 5), &config, 5),
+            Some(Winner::Player2)
+        );
+        assert_eq!(
+            qualified_winner(&result(60_000, 9), &result(50_000, 0), &config, 5),
+            Some(Winner::Player1)
+        );
+        // Both qualify: the usual comparison
+        assert_eq!(
+            qualified_winner(&result(60_000, 9), &result(50_000, 5), &config, 5),
+            Some(Winner::Player2)
+        );
+        assert_eq!(
+            qualified_winner(&result(40_000, 4), &result(50_000, 0), &config, 5),
+            None
+        );
+
+        assert_eq!(
+            qualified_winner(&result(40_000, 0), &result(50_000, 0), &config, 0),
+            Some(Winner::Player1)
+        );
+    }
+
+    #[test]
+    fn default_config_compares_exact_times_then_coins() {
+        let config = RaceConfig::default();
+        assert_eq!(
+            determine_winner(&result(50_000, 0), &result(50_001, 99), &config),
+            Winner::Player1
+        );
+        assert_eq!(
+            determine_winner(&result(50_001, 99), &result(50_000, 0), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            determine_winner(&result(50_000, 1), &result(50_000, 2), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            determine_winner(&result(50_000, 2), &result(50_000, 2), &config),
+            Winner::Player1
+        );
+    }
+
+    #[test]
+    fn tie_epsilon_treats_near_finishes_as_ties() {
+        let config = RaceConfig {
+            tie_epsilon_ms: 100,
+            ..RaceConfig::default()
+        };
+
+        // Exactly at the boundary: tie, coins decide
+        assert_eq!(
+            determine_winner(&result(50_000, 1), &result(50_100, 5), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            determine_winner(&result(50_100, 5), &result(50_000, 1), &config),
+            Winner::Player1
+        );
+        // One ms past the boundary: time decides again
+        assert_eq!(
+            determine_winner(&result(50_000, 1), &result(50_101, 5), &config),
+            Winner::Player1
+        );
+    }
+
+    #[test]
+    fn coins_only_ignores_time_unless_coins_tie() {
+        let config = RaceConfig {
+            tiebreak: TiebreakMode::CoinsOnly,
+            ..RaceConfig::default()
+        };
+
+        // Slower player with more coins wins
+        assert_eq!(
+            determine_winner(&result(30_000, 4), &result(90_000, 12), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            determine_winner(&result(90_000, 12), &result(30_000, 4), &config),
+            Winner::Player1
+        );
+        // Equal coins: faster wins, full tie to player1
+        assert_eq!(
+            determine_winner(&result(60_000, 7), &result(59_000, 7), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            determine_winner(&result(60_000, 7), &result(60_000, 7), &config),
+            Winner::Player1
+        );
+    }
+
+    #[test]
+    fn team_time_aggregates_members() {
+        let team = [result(50_000, 3), result(40_000, 9)];
+        assert_eq!(team_time(&team, TeamAggregation::SumOfTimes), 90_000);
+        assert_eq!(team_time(&team, TeamAggregation::BestTime), 40_000);
+    }
+
+    #[test]
+    fn aggregation_mode_can_flip_the_winning_team() {
+        // team1 is consistent, team2 has one star and one laggard
+        let team1 = [result(45_000, 0), result(45_000, 0)];
+        let team2 = [result(30_000, 0), result(70_000, 0)];
+
+        assert_eq!(
+            determine_winning_team(&team1, &team2, TeamAggregation::SumOfTimes),
+            1
+        );
+        assert_eq!(
+            determine_winning_team(&team1, &team2, TeamAggregation::BestTime),
+            2
+        );
+    }
+
+    #[test]
+    fn team_time_tie_falls_back_to_total_coins() {
+        let team1 = [result(40_000, 5), result(60_000, 5)];
+        let team2 = [result(50_000, 4), result(50_000, 7)];
+        assert_eq!(
+            determine_winning_team(&team1, &team2, TeamAggregation::SumOfTimes),
+            2
+        );
+
+        let team2_equal = [result(50_000, 5), result(50_000, 5)];
+        assert_eq!(
+            determine_winning_team(&team1, &team2_equal, TeamAggregation::SumOfTimes),
+            1
+        );
+    }
+
+    #[test]
+    fn result_preimage_round_trips() {
+        let replay = [7u8, 0, 255, 3];
+        let race = Pubkey::new_unique();
+        let preimage = result_preimage(&race, "race_42", 61_250, 17, &replay);
+
+        assert_eq!(preimage[0], RESULT_PREIMAGE_VERSION);
+        assert_eq!(
+            parse_result_preimage(&preimage),
+            Some(ResultPreimage {
+                race,
+                race_id: "race_42".to_string(),
+                finish_time_ms: 61_250,
+                coins_collected: 17,
+                replay: replay.to_vec(),
+            })
+        );
+
+        let empty = result_preimage(&race, "", 0, 0, &[]);
+        assert_eq!(empty.len(), 1 + 32 + 4 + 8 + 8 + 4);
+        assert_eq!(parse_result_preimage(&empty).unwrap().replay, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn result_preimage_is_little_endian_and_field_ordered() {
+        let race = Pubkey::new_from_array([5; 32]);
+        let preimage = result_preimage(&race, "ab", 0x0102, 0x0304, &[9]);
+        let expected: Vec<u8> = [
+            &[RESULT_PREIMAGE_VERSION][..],
+            &[5; 32],
+            &[2, 0, 0, 0],
+            b"ab",
+            &[0x02, 0x01, 0, 0, 0, 0, 0, 0],
+            &[0x04, 0x03, 0, 0, 0, 0, 0, 0],
+            &[1, 0, 0, 0],
+            &[9],
+        ]
+        .concat();
+        assert_eq!(preimage, expected);
+    }
+
+    #[test]
+    fn parse_result_preimage_rejects_malformed_input() {
+        let preimage = result_preimage(&Pubkey::new_unique(), "race", 1, 2, &[3, 4]);
+
+        let mut wrong_version = preimage.clone();
+        wrong_version[0] = RESULT_PREIMAGE_VERSION + 1;
+        assert_eq!(parse_result_preimage(&wrong_version), None);
+
+        assert_eq!(parse_result_preimage(&preimage[..preimage.len() - 1]), None);
+
+        let mut trailing = preimage.clone();
+        trailing.push(0);
+        assert_eq!(parse_result_preimage(&trailing), None);
+    }
+
+    #[test]
+    fn result_input_hash_depends_on_every_field() {
+        let race = Pubkey::new_unique();
+        let base = result_input_hash(&race, "race", 1000, 5, &[1, 2]);
+        assert_eq!(base, result_input_hash(&race, "race", 1000, 5, &[1, 2]));
+        assert_ne!(base, result_input_hash(&Pubkey::new_unique(), "race", 1000, 5, &[1, 2]));
+        assert_ne!(base, result_input_hash(&race, "rac", 1000, 5, &[1, 2]));
+        assert_ne!(base, result_input_hash(&race, "race", 1001, 5, &[1, 2]));
+        assert_ne!(base, result_input_hash(&race, "race", 1000, 6, &[1, 2]));
+        assert_ne!(base, result_input_hash(&race, "race", 1000, 5, &[2, 1]));
+        assert_ne!(base, unbound_input_hash("race", 1000, 5, &[1, 2]));
+    }
+
+    #[test]
+    fn lobby_len_matches_max_serialized_size() {
+        let lobby = Lobby {
+            status: RaceStatus::Settled,
+            race_id: "r".repeat(MAX_RACE_ID_LEN),
+            entry_fee_sol: 1,
+            config: max_race_config(),
+            creator: Pubkey::new_unique(),
+            max_players: MAX_LOBBY_PLAYERS as u8,
+            min_players_to_start: 2,
+            join_grace_secs: 1,
+            min_reached_at: 1,
+            antisnipe_extensions: MAX_ANTISNIPE_EXTENSIONS,
+            late_join_surcharge_bps: u16::MAX,
+            players: vec![Pubkey::new_unique(); MAX_LOBBY_PLAYERS],
+            results: vec![Some(result(u64::MAX, u64::MAX)); MAX_LOBBY_PLAYERS],
+            paid_fees: vec![u64::MAX; MAX_LOBBY_PLAYERS],
+            winner: Some(Pubkey::new_unique()),
+            escrow_amount: 1,
+            created_at: 1,
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&lobby), Lobby::LEN);
+    }
+
+    #[test]
+    fn rank_results_orders_best_first_and_skips_pending() {
+        let results = vec![
+            Some(result(60_000, 1)),
+            None,
+            Some(result(45_000, 1)),
+            Some(result(45_000, 9)),
+            Some(result(90_000, 50)),
+        ];
+        assert_eq!(
+            rank_results(&results, &RaceConfig::default()),
+            vec![3, 2, 0, 4]
+        );
+    }
+
+    #[test]
+    fn rank_results_breaks_full_ties_by_seat_order() {
+        let results = vec![Some(result(50_000, 5)), Some(result(50_000, 5))];
+        assert_eq!(rank_results(&results, &RaceConfig::default()), vec![0, 1]);
+    }
+
+    #[test]
+    fn replay_len_matches_max_serialized_size() {
+        let replay = Replay {
+            race: Pubkey::new_unique(),
+            player: Pubkey::new_unique(),
+            verified: true,
+            bump: 255,
+            data: vec![0xAB; MAX_REPLAY_LEN],
+        };
+        assert_eq!(serialized_len(&replay), Replay::LEN);
+    }
+
+    fn sample_lobby() -> Lobby {
+        Lobby {
+            status: RaceStatus::Waiting,
+            race_id: String::new(),
+            entry_fee_sol: 1,
+            config: RaceConfig::default(),
+            creator: Pubkey::new_unique(),
+            max_players: 4,
+            min_players_to_start: 2,
+            join_grace_secs: 30,
+            min_reached_at: 0,
+            antisnipe_extensions: 0,
+            late_join_surcharge_bps: 0,
+            players: vec![],
+            results: vec![],
+            paid_fees: vec![],
+            winner: None,
+            escrow_amount: 0,
+            created_at: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn lobby_standings_rank_submitted_results_only() {
+        let players: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let lobby = Lobby {
+            status: RaceStatus::Active,
+            players: players.clone(),
+            results: vec![
+                Some(result(52_000, 5)),
+                None,
+                Some(result(48_000, 5)),
+                Some(result(50_000, 5)),
+            ],
+            ..sample_lobby()
+        };
+
+        let standings = lobby_standings(&lobby);
+        let places: Vec<Option<u8>> = standings.iter().map(|s| s.place).collect();
+        assert_eq!(places, vec![Some(3), None, Some(1), Some(2)]);
+        assert_eq!(standings[1].player, players[1]);
+        assert_eq!(standings[1].result, None);
+        assert_eq!(standings[2].result, Some(result(48_000, 5)));
+
+        // The leader is who settlement would pick
+        let ranking = rank_results(&lobby.results, &lobby.config);
+        assert_eq!(standings[ranking[0]].place, Some(1));
+    }
+
+    #[test]
+    fn lobby_join_grace_window() {
+        let mut lobby = sample_lobby();
+
+        // Minimum not reached yet
+        assert!(!lobby.join_grace_expired(10_000));
+
+        lobby.min_reached_at = 1_000;
+        assert!(!lobby.join_grace_expired(1_030));
+        assert!(lobby.join_grace_expired(1_031));
+
+        lobby.join_grace_secs = 0;
+        assert!(!lobby.join_grace_expired(1_000_000));
+    }
+
+    #[test]
+    fn lobby_join_deadline_counts_from_creation() {
+        let mut lobby = Lobby {
+            created_at: 5_000,
+            ..sample_lobby()
+        };
+        assert_eq!(lobby.join_deadline(), 5_000 + LOBBY_JOIN_DEADLINE_SECS);
+
+        lobby.created_at = i64::MAX;
+        assert_eq!(lobby.join_deadline(), i64::MAX);
+    }
+
+    #[test]
+    fn with_race_context_keeps_the_error_code() {
+        let err = with_race_context("race_7", || -> Result<()> {
+            err!(SolracerError::NotWinner)
+        })
+        .unwrap_err();
+        assert_eq!(err, error!(SolracerError::NotWinner));
+
+        assert_eq!(with_race_context("race_7", || Ok(42u64)).unwrap(), 42);
+    }
+
+    #[test]
+    fn chat_root_chains_message_hashes() {
+        let first = [1u8; 32];
+        let second = [2u8; 32];
+
+        let root1 = next_chat_root(&[0u8; 32], &first);
+        let mut preimage = [0u8; 64];
+        preimage[32..].copy_from_slice(&first);
+        assert_eq!(root1, solana_sha256_hasher::hash(&preimage).to_bytes());
+
+        // Order matters, so a reordered log yields a different root
+        let root2 = next_chat_root(&root1, &second);
+        let swapped = next_chat_root(&next_chat_root(&[0u8; 32], &second), &first);
+        assert_ne!(root2, swapped);
+    }
+
+    #[test]
+    fn rematch_ids_count_up() {
+        assert_eq!(rematch_race_id("race_1").as_deref(), Some("race_1/r1"));
+        assert_eq!(rematch_race_id("race_1/r1").as_deref(), Some("race_1/r2"));
+        assert_eq!(rematch_race_id("race_1/r9").as_deref(), Some("race_1/r10"));
+        // Only a trailing numeric round is treated as a counter
+        assert_eq!(rematch_race_id("a/rx").as_deref(), Some("a/rx/r1"));
+        assert_eq!(rematch_race_id("a/r").as_deref(), Some("a/r/r1"));
+
+        assert_eq!(
+            rematch_race_id(&"r".repeat(MAX_RACE_ID_LEN - 3)).map(|id| id.len()),
+            Some(MAX_RACE_ID_LEN)
+        );
+        assert_eq!(rematch_race_id(&"r".repeat(MAX_RACE_ID_LEN - 2)), None);
+    }
+
+    #[test]
+    fn elo_update_is_zero_sum_and_favours_upsets() {
+        // Even match: the winner takes half the K-factor
+        assert_eq!(elo_update(1200, 1200, 32), (1216, 1184));
+
+        // A favourite beating an underdog gains little, an upset gains a lot
+        let (fav, dog) = elo_update(1600, 1200, 32);
+        assert_eq!((fav - 1600, 1200 - dog), (3, 3));
+        let (dog, fav) = elo_update(1200, 1600, 32);
+        assert_eq!((dog - 1200, 1600 - fav), (29, 29));
+
+        assert_eq!(elo_update(1200, 1200, 0), (1200, 1200));
+        assert_eq!(elo_update(i32::MAX, i32::MIN, 32).0, i32::MAX);
+    }
+
+    #[test]
+    fn blowout_needs_gap_beyond_ratio() {
+        assert!(!is_blowout(1_000, 100_000, 100));
+        assert!(is_blowout(1_000, 100_001, 100));
+        assert!(is_blowout(100_001, 1_000, 100));
+        assert!(is_blowout(0, 1, 100));
+        assert!(!is_blowout(0, 0, 100));
+        assert!(!is_blowout(1, u64::MAX, 0));
+        assert!(!is_blowout(u64::MAX / 2, u64::MAX, u16::MAX));
+    }
+
+    #[test]
+    fn race_pda_is_namespaced_by_creator() {
+        let mint = Pubkey::new_unique();
+        let (a, _) = race_pda(&Pubkey::new_unique(), "match-1", &mint, 1_000);
+        let (b, _) = race_pda(&Pubkey::new_unique(), "match-1", &mint, 1_000);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn batch_size_is_capped() {
+        assert!(check_batch_size(MAX_CLAIM_BATCH, MAX_CLAIM_BATCH, CLAIM_BATCH_CU_PER_RACE).is_ok());
+        assert_eq!(
+            check_batch_size(MAX_CLAIM_BATCH + 1, MAX_CLAIM_BATCH, CLAIM_BATCH_CU_PER_RACE).unwrap_err(),
+            SolracerError::BatchTooLarge.into()
+        );
+
+        assert_eq!(batch_compute_estimate(0, CREATE_BATCH_CU_PER_RACE), 0);
+        assert_eq!(batch_compute_estimate(usize::MAX, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn result_feed_evicts_oldest() {
+        let entry = |n: u64| FeedEntry {
+            winner_time_ms: n,
+            ..FeedEntry::default()
+        };
+        let mut feed = ResultFeed {
+            token_mint: Pubkey::new_unique(),
+            head: 0,
+            len: 0,
+            entries: [FeedEntry::default(); RESULT_FEED_CAPACITY],
+            bump: 255,
+        };
+        assert_eq!(serialized_len(&feed), ResultFeed::LEN);
+        assert_eq!(feed.recent().count(), 0);
+
+        for n in 1..=3 {
+            feed.push(entry(n));
+        }
+        let times: Vec<u64> = feed.recent().map(|e| e.winner_time_ms).collect();
+        assert_eq!(times, vec![3, 2, 1]);
+
+        let total = RESULT_FEED_CAPACITY as u64 + 2;
+        for n in 4..=total {
+            feed.push(entry(n));
+        }
+        assert_eq!(feed.len as usize, RESULT_FEED_CAPACITY);
+        let times: Vec<u64> = feed.recent().map(|e| e.winner_time_ms).collect();
+        assert_eq!(times.len(), RESULT_FEED_CAPACITY);
+        assert_eq!(times[0], total);
+        assert_eq!(*times.last().unwrap(), 3);
+    }
+
+    #[test]
+    fn global_stats_count_races_volume_and_fees() {
+        let mut stats = GlobalStats {
+            total_races: 0,
+            total_settled: 0,
+            total_volume_lamports: 0,
+            total_fees_collected: 0,
+            bump: 255,
+        };
+        stats.record_race(100).unwrap();
+        stats.record_volume(150).unwrap();
+        stats.record_settlement().unwrap();
+        stats.record_fee(5).unwrap();
+        assert_eq!(stats.total_races, 1);
+        assert_eq!(stats.total_volume_lamports, 250);
+        assert_eq!(stats.total_settled, 1);
+        assert_eq!(stats.total_fees_collected, 5);
+
+        stats.total_volume_lamports = u64::MAX;
+        assert!(stats.record_race(1).is_err());
+        assert!(stats.record_volume(1).is_err());
+        assert_eq!(serialized_len(&stats), GlobalStats::LEN);
+    }
+
+    #[test]
+    fn credit_account_debits_only_what_it_holds() {
+        let mut credits = CreditAccount {
+            player: Pubkey::new_unique(),
+            balance: 150,
+            bump: 255,
+        };
+        credits.debit(100).unwrap();
+        assert_eq!(credits.balance, 50);
+
+        assert!(credits.debit(51).is_err());
+        assert_eq!(credits.balance, 50);
+
+        credits.balance = u64::MAX;
+        assert_eq!(serialized_len(&credits), CreditAccount::LEN);
+    }
+
+    #[test]
+    fn chat_author_paces_only_its_own_posts() {
+        let mut alice = ChatAuthor {
+            author: Pubkey::new_unique(),
+            last_posted_at: 0,
+            bump: 255,
+        };
+        let mut bob = ChatAuthor {
+            author: Pubkey::new_unique(),
+            ..alice
+        };
+        alice.post(1_000).unwrap();
+        // Another author isn't held up by the first
+        bob.post(1_000).unwrap();
+        assert_eq!(
+            alice.post(1_000 + CHAT_UPDATE_MIN_INTERVAL_SECS - 1).unwrap_err(),
+            SolracerError::ChatRateLimited.into()
+        );
+        assert_eq!(alice.last_posted_at, 1_000);
+        alice.post(1_000 + CHAT_UPDATE_MIN_INTERVAL_SECS).unwrap();
+        assert_eq!(serialized_len(&alice), ChatAuthor::LEN);
+    }
+
+    #[test]
+    fn claim_index_adds_removes_and_refuses_overflow() {
+        let mut index = ClaimIndex {
+            player: Pubkey::new_unique(),
+            races: Vec::new(),
+            bump: 255,
+        };
+        let first = Pubkey::new_unique();
+        assert!(index.add(first));
+        assert!(index.add(first));
+        assert_eq!(index.races, vec![first]);
+
+        while index.races.len() < CLAIM_INDEX_CAPACITY {
+            assert!(index.add(Pubkey::new_unique()));
+        }
+        assert_eq!(serialized_len(&index), ClaimIndex::LEN);
+        assert!(!index.add(Pubkey::new_unique()));
+        assert_eq!(index.races.len(), CLAIM_INDEX_CAPACITY);
+
+        index.remove(&first);
+        assert!(!index.races.contains(&first));
+        assert_eq!(index.races.len(), CLAIM_INDEX_CAPACITY - 1);
+        index.remove(&first);
+        assert_eq!(index.races.len(), CLAIM_INDEX_CAPACITY - 1);
+    }
+
+    #[test]
+    fn antisnipe_extends_buzzer_joins_up_to_cap() {
+        let mut lobby = Lobby {
+            min_reached_at: 1_000,
+            ..sample_lobby()
+        };
+
+        // Disabled, or too early to count as sniping
+        assert!(!lobby.apply_antisnipe(1_025, 0, 10));
+        assert!(!lobby.apply_antisnipe(1_010, 10, 10));
+
+        // Window closes at 1_030; a join at 1_025 extends it
+        assert!(lobby.apply_antisnipe(1_025, 10, 10));
+        assert_eq!(lobby.join_grace_secs, 40);
+        assert!(!lobby.join_grace_expired(1_035));
+
+        assert!(lobby.apply_antisnipe(1_035, 10, 10));
+        assert!(lobby.apply_antisnipe(1_045, 10, 10));
+        assert!(!lobby.apply_antisnipe(1_055, 10, 10));
+        assert_eq!(lobby.antisnipe_extensions, MAX_ANTISNIPE_EXTENSIONS);
+        assert_eq!(lobby.join_grace_secs, 60);
+    }
+
+    #[test]
+    fn identical_results_are_disputed_only_when_opted_in() {
+        let config = RaceConfig {
+            void_identical_results: true,
+            ..RaceConfig::default()
+        };
+        let copied = result(40_000, 10);
+
+        assert_eq!(
+            dispute_reason(&copied, &copied, &config),
+            Some(DisputeReason::IdenticalResults)
+        );
+        assert_eq!(dispute_reason(&copied, &copied, &RaceConfig::default()), None);
+
+        // Same time and coins from a different replay is a genuine tie
+        let mut own_run = copied.clone();
+        own_run.input_hash = [1; 32];
+        assert_eq!(dispute_reason(&copied, &own_run, &config), None);
+
+        assert_eq!(
+            dispute_reason(&result(1_000, 0), &result(200_000, 0), &config),
+            Some(DisputeReason::ResultRatio)
+        );
+    }
+
+    #[test]
+    fn rematch_limit_caps_chain_length() {
+        assert!(!rematch_limit_reached(u16::MAX, 0));
+        assert!(!rematch_limit_reached(0, 2));
+        assert!(!rematch_limit_reached(1, 2));
+        assert!(rematch_limit_reached(2, 2));
+    }
+
+    #[test]
+    fn entry_fee_bounds_are_inclusive() {
+        let mut config = Config {
+            authority: Pubkey::new_unique(),
+            elo_k_factor: DEFAULT_ELO_K_FACTOR,
+            antisnipe_window_secs: 0,
+            antisnipe_extension_secs: 0,
+            crank_bounty_lamports: 0,
+            max_rematches: 0,
+            min_entry_fee: 0,
+            max_entry_fee: 0,
+            fee_bps: 0,
+            treasury: Pubkey::new_unique(),
+            server_authority: Pubkey::default(),
+            prev_server_authority: Pubkey::default(),
+            prev_server_expires_at: 0,
+            host_cut_bps: 0,
+            no_contest_on_no_show: false,
+            bump: 255,
+        };
+        assert!(check_entry_fee(&config, 0).is_ok());
+        assert!(check_entry_fee(&config, u64::MAX).is_ok());
+
+        config.min_entry_fee = 1_000;
+        config.max_entry_fee = 5_000;
+        assert_eq!(
+            check_entry_fee(&config, 999).unwrap_err(),
+            SolracerError::EntryFeeTooLow.into()
+        );
+        assert!(check_entry_fee(&config, 1_000).is_ok());
+        assert!(check_entry_fee(&config, 5_000).is_ok());
+        assert_eq!(
+            check_entry_fee(&config, 5_001).unwrap_err(),
+            SolracerError::EntryFeeTooHigh.into()
+        );
+    }
+
+    #[test]
+    fn fewer_coins_win_flips_the_coin_tiebreak() {
+        let more = result(40_000, 9);
+        let fewer = result(40_000, 2);
+        let speedrun = RaceConfig {
+            fewer_coins_win: true,
+            ..RaceConfig::default()
+        };
+
+        assert_eq!(determine_winner(&more, &fewer, &RaceConfig::default()), Winner::Player1);
+        assert_eq!(
+            outcome_reason(&more, &fewer, &RaceConfig::default()),
+            OutcomeReason::MoreCoins
+        );
+        assert_eq!(determine_winner(&more, &fewer, &speedrun), Winner::Player2);
+        assert_eq!(determine_winner(&fewer, &more, &speedrun), Winner::Player1);
+        assert_eq!(outcome_reason(&more, &fewer, &speedrun), OutcomeReason::FewerCoins);
+
+        // Time still comes first, and a full tie still goes to player1
+        assert_eq!(determine_winner(&result(39_000, 9), &fewer, &speedrun), Winner::Player1);
+        assert_eq!(determine_winner(&more, &more, &speedrun), Winner::Player1);
+
+        let coins_only = RaceConfig {
+            tiebreak: TiebreakMode::CoinsOnly,
+            ..speedrun
+        };
+        assert_eq!(determine_winner(&result(50_000, 9), &result(60_000, 2), &coins_only), Winner::Player2);
+    }
+
+    #[test]
+    fn outcome_reason_names_the_deciding_rule() {
+        let default = RaceConfig::default();
+        let epsilon = RaceConfig {
+            tie_epsilon_ms: 100,
+            ..RaceConfig::default()
+        };
+        let coins_only = RaceConfig {
+            tiebreak: TiebreakMode::CoinsOnly,
+            ..RaceConfig::default()
+        };
+
+        assert_eq!(outcome_reason(&result(40, 1), &result(50, 9), &default), OutcomeReason::FasterTime);
+        assert_eq!(outcome_reason(&result(40, 1), &result(40, 9), &default), OutcomeReason::MoreCoins);
+        assert_eq!(outcome_reason(&result(40, 1), &result(140, 9), &epsilon), OutcomeReason::MoreCoins);
+        assert_eq!(outcome_reason(&result(40, 1), &result(40, 1), &default), OutcomeReason::Tie);
+
+        assert_eq!(outcome_reason(&result(90, 9), &result(30, 1), &coins_only), OutcomeReason::MoreCoins);
+        assert_eq!(outcome_reason(&result(90, 1), &result(30, 1), &coins_only), OutcomeReason::FasterTime);
+        assert_eq!(outcome_reason(&result(30, 1), &result(30, 1), &coins_only), OutcomeReason::Tie);
+    }
+
+    #[test]
+    fn split_amounts_shares_by_bps() {
+        assert_eq!(split_amounts(1_000, &[7_000, 3_000]).unwrap(), vec![700, 300]);
+        // Dust goes to the last share
+        assert_eq!(split_amounts(100, &[3_333, 3_333, 3_334]).unwrap(), vec![33, 33, 34]);
+        assert_eq!(split_amounts(u64::MAX, &[10_000]).unwrap(), vec![u64::MAX]);
+
+        for bad in [&[][..], &[5_000, 4_999], &[10_000, 1], &[2_000; 6]] {
+            assert_eq!(
+                split_amounts(1_000, bad).unwrap_err(),
+                SolracerError::InvalidSplit.into()
+            );
+        }
+    }
+
+    #[test]
+    fn splits_never_lose_or_create_units() {
+        let shares: [&[u16]; 4] = [
+            &[10_000],
+            &[3_333, 3_333, 3_334],
+            &[1, 9_999],
+            &[1_250, 2_500, 3_750, 1_500, 1_000],
+        ];
+        for amount in [0, 1, 7, 99, 1_001, 999_999_937, u64::MAX / 3, u64::MAX] {
+            for bps in shares {
+                let amounts = split_amounts(amount, bps).unwrap();
+                assert_eq!(amounts.len(), bps.len());
+                assert_eq!(amounts.iter().map(|&a| a as u128).sum::<u128>(), amount as u128);
+            }
+            for (fee_bps, host_cut_bps) in [(0, 0), (250, 0), (333, 667), (9_999, 1), (0, 10_000)] {
+                let (fee, host_cut, payout) = claim_split(amount, fee_bps, host_cut_bps).unwrap();
+                assert_eq!(fee as u128 + host_cut as u128 + payout as u128, amount as u128);
+            }
+        }
+    }
+
+    #[test]
+    fn resolve_player_honours_session_expiry() {
+        let wallet = Pubkey::new_unique();
+        let session_key = Pubkey::new_unique();
+        let session = PlayerSession {
+            player_wallet: wallet,
+            session_key,
+            race_id_hash: [0; 32],
+            expires_at: 1_000,
+            bump: 255,
+        };
+
+        set_mock_now(999);
+        assert_eq!(resolve_player(Some(&session), &session_key).unwrap(), wallet);
+        assert_eq!(
+            resolve_player(Some(&session), &wallet).unwrap_err(),
+            SolracerError::InvalidSessionKey.into()
+        );
+
+        set_mock_now(1_000);
+        assert_eq!(
+            resolve_player(Some(&session), &session_key).unwrap_err(),
+            SolracerError::SessionExpired.into()
+        );
+        assert_eq!(resolve_player(None, &wallet).unwrap(), wallet);
+    }
+
+    #[test]
+    fn time_quantum_rounds_jitter_away() {
+        assert_eq!(quantize_time(40_049, 0), 40_049);
+        assert_eq!(quantize_time(40_049, 1), 40_049);
+        assert_eq!(quantize_time(40_049, 100), 40_000);
+        assert_eq!(quantize_time(40_050, 100), 40_100);
+        assert_eq!(quantize_time(u64::MAX, 2), u64::MAX);
+
+        // Both round to 40_000: a tie on time, decided by coins
+        let config = RaceConfig {
+            time_quantum_ms: 100,
+            ..RaceConfig::default()
+        };
+        assert_eq!(
+            determine_winner(&result(40_010, 1), &result(40_040, 5), &config),
+            Winner::Player2
+        );
+        assert_eq!(
+            outcome_reason(&result(40_010, 1), &result(40_040, 5), &config),
+            OutcomeReason::MoreCoins
+        );
+        assert_eq!(
+            determine_winner(&result(40_010, 1), &result(40_040, 5), &RaceConfig::default()),
+            Winner::Player1
+        );
+    }
+
+    #[test]
+    fn claimable_amount_is_the_net_prize_for_the_winner_only() {
+        let winner = Pubkey::new_unique();
+        let mut race = Race {
+            winner: Some(winner),
+            escrow_amount: 2_000_000,
+            ..sample_race("claimable", RaceStatus::Settled)
+        };
+        let claimable = |race: &Race, player: &Pubkey| claimable_amount(race, player, 0, 500, 200).unwrap();
+
+        // 5% fee and a 2% host cut off the 2_000_000 escrow
+        assert_eq!(claimable(&race, &winner), 1_860_000);
+        assert_eq!(claimable(&race, &Pubkey::new_unique()), 0);
+
+        // No host cut when the creator wins
+        let creator_won = Race {
+            winner: Some(race.player1),
+            ..race.clone()
+        };
+        assert_eq!(claimable(&creator_won, &race.player1), 1_900_000);
+
+        race.imported = true;
+        assert_eq!(claimable(&race, &winner), 0);
+
+        race.imported = false;
+        assert_eq!(take_prize(&mut race, winner, 0).unwrap(), 2_000_000);
+        assert_eq!(claimable(&race, &winner), 0);
+    }
+
+    #[test]
+    fn take_prize_tells_unsettled_races_from_wrong_claimants() {
+        let winner = Pubkey::new_unique();
+        let mut race = Race {
+            winner: Some(winner),
+            ..sample_race("claim_errors", RaceStatus::Active)
+        };
+        assert_eq!(
+            take_prize(&mut race, winner, 0).unwrap_err(),
+            SolracerError::RaceNotSettledYet.into()
+        );
+
+        race.status = RaceStatus::Settled;
+        assert_eq!(
+            take_prize(&mut race, Pubkey::new_unique(), 0).unwrap_err(),
+            SolracerError::NotWinner.into()
+        );
+
+        take_prize(&mut race, winner, 0).unwrap();
+        assert_eq!(
+            take_prize(&mut race, winner, 0).unwrap_err(),
+            SolracerError::AlreadyClaimed.into()
+        );
+    }
+
+    #[test]
+    fn emergency_refund_skips_claimed_and_mismatched_races() {
+        let race = Race {
+            escrow_amount: 2_000_000,
+            player1_paid: 1_000_000,
+            player2_paid: 1_000_000,
+            rent_payer: Pubkey::default(),
+            ..sample_race("wind_down", RaceStatus::Settled)
+        };
+        let (player1, fee_payer) = (race.player1, race.player2_fee_payer);
+        assert!(emergency_refundable(&race, &player1, &fee_payer, &player1));
+        assert!(!emergency_refundable(&race, &Pubkey::new_unique(), &fee_payer, &player1));
+        assert!(!emergency_refundable(&race, &player1, &Pubkey::new_unique(), &player1));
+        assert!(!emergency_refundable(&race, &player1, &fee_payer, &Pubkey::new_unique()));
+
+        for status in [RaceStatus::Waiting, RaceStatus::Active, RaceStatus::Disputed] {
+            let race = Race { status, ..race.clone() };
+            assert!(emergency_refundable(&race, &player1, &fee_payer, &player1));
+        }
+        let claimed = Race {
+            status: RaceStatus::Claimed,
+            ..race.clone()
+        };
+        assert!(!emergency_refundable(&claimed, &player1, &fee_payer, &player1));
+        let part_vested = Race {
+            claimed_so_far: 1,
+            ..race.clone()
+        };
+        assert!(!emergency_refundable(&part_vested, &player1, &fee_payer, &player1));
+        let opted_in = Race {
+            player2_auto_rematch: true,
+            ..race.clone()
+        };
+        assert!(!emergency_refundable(&opted_in, &player1, &fee_payer, &player1));
+        let short = Race {
+            escrow_amount: 1_500_000,
+            ..race.clone()
+        };
+        assert!(!emergency_refundable(&short, &player1, &fee_payer, &player1));
+
+        // Already refunded: nothing to pay, but still closable
+        let refunded = Race {
+            status: RaceStatus::Voided,
+            escrow_amount: 0,
+            ..race
+        };
+        assert!(emergency_refundable(&refunded, &player1, &fee_payer, &player1));
+    }
+
+    #[test]
+    fn claim_delay_grows_with_the_pot() {
+        let tier = |min_escrow, delay_secs| ClaimDelayTier {
+            min_escrow,
+            delay_secs,
+        };
+        let tiers = ClaimDelayTiers {
+            base_delay_secs: 60,
+            tiers: vec![tier(100_000_000_000, 86_400), tier(10_000_000_000, 3_600)],
+            bump: 255,
+        };
+        assert_eq!(tiers.delay_for(0), 60);
+        assert_eq!(tiers.delay_for(9_999_999_999), 60);
+        assert_eq!(tiers.delay_for(10_000_000_000), 3_600);
+        // Tiers may be listed in any order; the longest reached applies
+        assert_eq!(tiers.delay_for(u64::MAX), 86_400);
+
+        let none = ClaimDelayTiers {
+            base_delay_secs: 0,
+            tiers: Vec::new(),
+            bump: 255,
+        };
+        assert_eq!(none.delay_for(u64::MAX), 0);
+    }
+
+    #[test]
+    fn take_prize_waits_out_the_claim_delay() {
+        let winner = Pubkey::new_unique();
+        let mut race = Race {
+            winner: Some(winner),
+            escrow_amount: 1_000,
+            settled_at: 1_000,
+            claim_delay_secs: 3_600,
+            ..sample_race("claim_delay", RaceStatus::Settled)
+        };
+        assert_eq!(releasable_amount(&race, &winner, 4_599), 0);
+        assert_eq!(
+            take_prize(&mut race, winner, 4_599).unwrap_err(),
+            SolracerError::ClaimDelayActive.into()
+        );
+        assert_eq!(releasable_amount(&race, &winner, 4_600), 1_000);
+        assert_eq!(take_prize(&mut race, winner, 4_600).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn vested_amount_releases_whole_slices() {
+        // 4 slices over 400s: 250 per 100s
+        assert_eq!(vested_amount(1_000, 400, 4, -5), 0);
+        assert_eq!(vested_amount(1_000, 400, 4, 99), 0);
+        assert_eq!(vested_amount(1_000, 400, 4, 100), 250);
+        assert_eq!(vested_amount(1_000, 400, 4, 399), 750);
+        assert_eq!(vested_amount(1_000, 400, 4, 400), 1_000);
+        assert_eq!(vested_amount(1_000, 400, 4, i64::MAX), 1_000);
+        // The last slice takes the rounding remainder
+        assert_eq!(vested_amount(1_001, 300, 3, 200), 667);
+        assert_eq!(vested_amount(1_001, 300, 3, 300), 1_001);
+        assert_eq!(vested_amount(u64::MAX, 10, 2, 5), u64::MAX / 2);
+        // No schedule releases everything
+        assert_eq!(vested_amount(1_000, 0, 0, 0), 1_000);
+    }
+
+    #[test]
+    fn take_prize_pays_vested_installments() {
+        let winner = Pubkey::new_unique();
+        let mut race = Race {
+            winner: Some(winner),
+            escrow_amount: 1_000,
+            settled_at: 1_000,
+            vesting_seconds: 300,
+            vesting_slices: 3,
+            claimed_so_far: 0,
+            ..sample_race("vesting", RaceStatus::Settled)
+        };
+
+        assert_eq!(
+            take_prize(&mut race, winner, 1_050).unwrap_err(),
+            SolracerError::NothingVestedYet.into()
+        );
+        assert_eq!(take_prize(&mut race, winner, 1_100).unwrap(), 333);
+        assert_eq!(race.status, RaceStatus::Settled);
+        assert_eq!(
+            take_prize(&mut race, winner, 1_150).unwrap_err(),
+            SolracerError::NothingVestedYet.into()
+        );
+
+        // Two intervals at once pay both
+        assert_eq!(take_prize(&mut race, winner, 1_310).unwrap(), 667);
+        assert_eq!(race.claimed_so_far, 1_000);
+        assert_eq!(race.escrow_amount, 0);
+        assert_eq!(race.status, RaceStatus::Claimed);
+        assert_eq!(
+            take_prize(&mut race, winner, 1_400).unwrap_err(),
+            SolracerError::AlreadyClaimed.into()
+        );
+    }
+
+    #[test]
+    fn metadata_collection_reads_verified_collection() {
+        let mint = Pubkey::new_unique();
+        let collection = Pubkey::new_unique();
+        let metadata = |creators: u32, verified: bool| {
+            let mut data = vec![4u8];
+            data.extend_from_slice(Pubkey::new_unique().as_ref());
+            data.extend_from_slice(mint.as_ref());
+            for field in ["Racer #1", "RACE", "https://example.com/1.json"] {
+                data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                data.extend_from_slice(field.as_bytes());
+            }
+            data.extend_from_slice(&500u16.to_le_bytes());
+            data.push(1);
+            data.extend_from_slice(&creators.to_le_bytes());
+            data.extend(vec![7u8; creators as usize * 34]);
+            data.extend_from_slice(&[1, 1]);
+            data.extend_from_slice(&[1, 254]); // edition_nonce
+            data.push(0); // token_standard
+            data.extend_from_slice(&[1, verified as u8]);
+            data.extend_from_slice(collection.as_ref());
+            data.extend(vec![0u8; 20]); // trailing fields are ignored
+            data
+        };
 
-#[account]
-pub struct PlayerSession {
-    pub player_wallet: Pubkey,   // 32
-    pub session_key:   Pubkey,   // 32
-    pub race_id_hash:  [u8; 32], // 32
-    pub expires_at:    i64,      //  8
-    pub bump:          u8,       //  1
-}
+        assert_eq!(metadata_collection(&metadata(2, true)), Some((mint, collection)));
+        assert_eq!(metadata_collection(&metadata(0, false)), None);
+        // Truncated or wrong-key accounts don't parse
+        assert_eq!(metadata_collection(&metadata(2, true)[..150]), None);
+        let mut edition = metadata(2, true);
+        edition[0] = 6;
+        assert_eq!(metadata_collection(&edition), None);
+    }
 
-impl PlayerSession {
-    pub const LEN: usize = 105;
-}
+    #[test]
+    fn token_mint_convention() {
+        let sol = sample_race("", RaceStatus::Waiting);
+        assert!(!sol.is_sol());
+        assert_eq!(sol.denomination(), sol.token_mint);
+        let native = Race { token_mint: native_mint::ID, ..sol.clone() };
+        assert!(native.is_sol());
+        assert_eq!(native.denomination(), native_mint::ID);
+        // Races from before the check may hold the zero key, which reads as SOL
+        let legacy = Race { token_mint: Pubkey::default(), ..sol };
+        assert!(legacy.is_sol());
+        assert_eq!(legacy.denomination(), native_mint::ID);
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
-pub struct RaceResult {
-    pub finish_time_ms: u64,
-    pub coins_collected: u64,
-    pub input_hash: [u8; 32],
-}
+        assert!(is_valid_token_mint(&native_mint::ID));
+        assert!(is_valid_token_mint(&Pubkey::new_unique()));
+        assert!(!is_valid_token_mint(&system_program::ID));
+    }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
-pub enum RaceStatus {
-    Waiting,
-    Active,
-    Settled,
-}
+    #[test]
+    fn consolation_is_per_coin_and_capped() {
+        let vault = ConsolationVault {
+            consolation_per_coin: 1_000,
+            max_per_race: 25_000,
+            bump: 255,
+        };
+        assert_eq!(consolation_amount(12, &vault, u64::MAX), 12_000);
+        assert_eq!(consolation_amount(40, &vault, u64::MAX), 25_000);
+        assert_eq!(consolation_amount(0, &vault, u64::MAX), 0);
+        assert_eq!(consolation_amount(u64::MAX, &vault, u64::MAX), 25_000);
 
-// Instruction contexts
+        // A short vault pays what it holds, an empty one nothing
+        assert_eq!(consolation_amount(12, &vault, 5_000), 5_000);
+        assert_eq!(consolation_amount(12, &vault, 0), 0);
 
-#[derive(Accounts)]
-#[instruction(race_id: String, token_mint: Pubkey, entry_fee_sol: u64)]
-pub struct CreateRace<'info> {
-    #[account(
-        init,
-        payer = player1,
-        space = 8 + Race::LEN,
-        seeds = [b"race", race_id.as_bytes(), token_mint.as_ref(), &entry_fee_sol.to_le_bytes()],
-        bump
-    )]
-    pub race: Account<'info, Race>,
+        let off = ConsolationVault {
+            consolation_per_coin: 0,
+            ..vault
+        };
+        assert_eq!(consolation_amount(12, &off, u64::MAX), 0);
+    }
 
-    #[account(mut)]
-    pub player1: Signer<'info>,
+    #[test]
+    fn invariant_checks_fire_on_broken_races() {
+        let rent = 5_000;
+        let mut race = sample_race("strict", RaceStatus::Active);
+        race.player2 = Some(Pubkey::new_unique());
+        race.credit_escrow(2_000_000).unwrap();
+        let held = rent + race.escrow_amount;
+        assert_eq!(race_invariant_violation(&race, &RaceStatus::Waiting, held, rent), None);
 
-    pub system_program: Program<'info, System>,
-}
+        // Lamports short of the escrow
+        assert_eq!(
+            race_invariant_violation(&race, &RaceStatus::Active, held - 1, rent),
+            Some("escrow does not match the account's lamports")
+        );
 
-#[derive(Accounts)]
-pub struct JoinRace<'info> {
-    #[account(mut)]
-    pub race: Account<'info, Race>,
+        // A settled race can't go back to Active
+        assert_eq!(
+            race_invariant_violation(&race, &RaceStatus::Settled, held, rent),
+            Some("illegal status transition")
+        );
 
-    #[account(mut)]
-    pub player2: Signer<'info>,
+        // A winner from outside the race
+        race.status = RaceStatus::Settled;
+        race.winner = Some(Pubkey::new_unique());
+        assert_eq!(
+            race_invariant_violation(&race, &RaceStatus::Active, held, rent),
+            Some("winner is not a participant")
+        );
+        race.winner = race.player2;
+        assert_eq!(race_invariant_violation(&race, &RaceStatus::Active, held, rent), None);
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    #[test]
+    fn legal_status_transitions() {
+        use RaceStatus::*;
+        for (from, to) in [
+            (Waiting, Active),
+            (Active, Waiting),
+            (Active, Settled),
+            (Active, Disputed),
+            (Active, Voided),
+            (Active, NoContest),
+            (Disputed, Settled),
+            (Settled, Claimed),
+            (Claimed, Claimed),
+        ] {
+            assert!(legal_transition(&from, &to), "{from:?} -> {to:?}");
+        }
+        for (from, to) in [
+            (Settled, Active),
+            (Claimed, Settled),
+            (Waiting, Settled),
+            (Voided, Active),
+            (NoContest, Active),
+            (Active, Claimed),
+        ] {
+            assert!(!legal_transition(&from, &to), "{from:?} -> {to:?}");
+        }
+    }
 
-#[derive(Accounts)]
-#[instruction(race_id_hash: [u8; 32], session_key: Pubkey, duration_secs: i64)]
-pub struct DelegateSession<'info> {
-    #[account(
-        init,
-        payer = player,
-        space = 8 + PlayerSession::LEN,
-        seeds = [b"session", race_id_hash.as_ref(), player.key().as_ref()],
-        bump
-    )]
-    pub session: Account<'info, PlayerSession>,
+    #[test]
+    fn escrow_locks_once_the_outcome_is_fixed() {
+        let mut race = sample_race("locked", RaceStatus::Active);
+        assert!(!race.escrow_locked());
+        race.player1_result = Some(result(40_000, 10));
+        assert!(!race.escrow_locked());
+        race.player2_result = Some(result(50_000, 10));
+        assert!(race.escrow_locked());
 
-    #[account(mut)]
-    pub player: Signer<'info>,
+        // A house race is complete on player1's result alone
+        let house = Race {
+            house_target_ms: Some(45_000),
+            player2_result: None,
+            ..race
+        };
+        assert!(house.escrow_locked());
 
-    pub system_program: Program<'info, System>,
-}
+        assert!(!sample_race("locked", RaceStatus::Waiting).escrow_locked());
+        for status in [
+            RaceStatus::Settled,
+            RaceStatus::Claimed,
+            RaceStatus::Disputed,
+            RaceStatus::Voided,
+            RaceStatus::NoContest,
+        ] {
+            assert!(sample_race("locked", status).escrow_locked());
+        }
+    }
 
-#[derive(Accounts)]
-pub struct SubmitResult<'info> {
-    #[account(mut)]
-    pub race: Account<'info, Race>,
+    #[test]
+    fn race_id_charset() {
+        assert!(is_valid_race_id("race_1700000000-abc"));
+        assert!(is_valid_race_id(""));
+        for id in ["race 1", "race\n1", "race/r1", "race;drop", "caf\u{e9}"] {
+            assert!(!is_valid_race_id(id), "{id:?}");
+        }
 
-    /// The signer: either the player wallet or the session key
-    pub authority: Signer<'info>,
+        assert!(check_race_id(&"a".repeat(MAX_RACE_ID_LEN)).is_ok());
+        assert_eq!(
+            check_race_id(&"a".repeat(MAX_RACE_ID_LEN + 1)).unwrap_err(),
+            SolracerError::RaceIdTooLong.into()
+        );
+        assert_eq!(
+            check_race_id("race\u{1b}[31m").unwrap_err(),
+            SolracerError::InvalidRaceId.into()
+        );
+    }
 
-    /// session PDA, provided when signing with session key.
-    #[account(
-        seeds = [b"session", session.race_id_hash.as_ref(), player_wallet.key().as_ref()],
-        bump = session.bump,
-    )]
-    pub session: Option<Account<'info, PlayerSession>>,
+    #[test]
+    fn store_result_accepts_identical_retry_only() {
+        let mut slot = None;
+        assert!(store_result(&mut slot, result(40_000, 10)).unwrap());
+        assert!(!store_result(&mut slot, result(40_000, 10)).unwrap());
+        assert_eq!(slot, Some(result(40_000, 10)));
 
-    /// CHECK: only used for PDA seed derivation when session is provided
-    pub player_wallet: UncheckedAccount<'info>,
-}
+        assert_eq!(
+            store_result(&mut slot, result(39_000, 10)).unwrap_err(),
+            SolracerError::ResultAlreadySubmitted.into()
+        );
+        assert_eq!(slot, Some(result(40_000, 10)));
+    }
 
-#[derive(Accounts)]
-pub struct SettleRace<'info> {
-    #[account(mut)]
-    pub race: Account<'info, Race>,
-}
+    #[test]
+    fn submissions_limited_to_one_per_slot() {
+        let mut last_submit_slot = 0;
+        note_submit_slot(&mut last_submit_slot, 100).unwrap();
+        assert_eq!(
+            note_submit_slot(&mut last_submit_slot, 100).unwrap_err(),
+            SolracerError::SubmitRateLimited.into()
+        );
+        assert_eq!(last_submit_slot, 100);
 
-#[derive(Accounts)]
-pub struct ClaimPrize<'info> {
-    #[account(mut)]
-    pub race: Account<'info, Race>,
+        note_submit_slot(&mut last_submit_slot, 101).unwrap();
+        assert_eq!(last_submit_slot, 101);
+    }
 
-    /// The signer: either the winner wallet or the session key
-    pub authority: Signer<'info>,
+    #[test]
+    fn lobby_fee_escalates_by_seat() {
+        let lobby = Lobby {
+            entry_fee_sol: 1_000_000,
+            late_join_surcharge_bps: 500,
+            ..sample_lobby()
+        };
+        let fees: Vec<u64> = (0..4).map(|seat| lobby.fee_for_seat(seat).unwrap()).collect();
+        assert_eq!(fees, vec![1_000_000, 1_050_000, 1_100_000, 1_150_000]);
 
-    /// Optional session PDA, provided when signing with session key
-    #[account(
-        seeds = [b"session", session.race_id_hash.as_ref(), winner_wallet.key().as_ref()],
-        bump = session.bump,
-    )]
-    pub session: Option<Account<'info, PlayerSession>>,
+        // No surcharge is a flat fee
+        assert_eq!(sample_lobby().fee_for_seat(7).unwrap(), 1);
 
-    /// CHECK: The actual winner wallet that receives funds.
-    /// When signing directly (no session), pass the same key as authority.
-    #[account(mut)]
-    pub winner_wallet: UncheckedAccount<'info>,
-}
+        let huge = Lobby {
+            entry_fee_sol: u64::MAX,
+            late_join_surcharge_bps: 1,
+            ..sample_lobby()
+        };
+        assert!(huge.fee_for_seat(1).is_err());
+    }
 
-// Error codes
+    #[test]
+    fn merkle_verify_checks_coin_proofs() {
+        let pair = |a: [u8; 32], b: [u8; 32]| {
+            let (first, second) = if a <= b { (a, b) } else { (b, a) };
+            solana_sha256_hasher::hashv(&[&first, &second]).to_bytes()
+        };
+        let leaves: Vec<[u8; 32]> = (0..4).map(coin_leaf).collect();
+        let left = pair(leaves[0], leaves[1]);
+        let right = pair(leaves[2], leaves[3]);
+        let root = pair(left, right);
 
-#[error_code]
-pub enum SolracerError {
-    #[msg("Invalid race status for this operation")]
-    InvalidRaceStatus,
-    #[msg("Player2 is already set")]
-    Player2AlreadySet,
-    #[msg("Player is not in this race")]
-    PlayerNotInRace,
-    #[msg("Result already submitted for this player")]
-    ResultAlreadySubmitted,
-    #[msg("Both results must be submitted before settling")]
-    ResultsNotComplete,
-    #[msg("Only the winner can claim the prize")]
-    NotWinner,
-    #[msg("Session key does not match registered key")]
-    InvalidSessionKey,
-    #[msg("Session has expired")]
-    SessionExpired,
+        assert!(merkle_verify(leaves[0], &[leaves[1], right], root));
+        assert!(merkle_verify(leaves[3], &[leaves[2], left], root));
+        // A coin outside the layout, or a proof for another coin, fails
+        assert!(!merkle_verify(coin_leaf(4), &[leaves[1], right], root));
+        assert!(!merkle_verify(leaves[2], &[leaves[1], right], root));
+    }
+
+    #[test]
+    fn reconcile_balances_deposits_and_payouts() {
+        let mut race = sample_race("", RaceStatus::Active);
+        race.credit_escrow(1_000_000).unwrap();
+        let held = 2_000_000;
+
+        let books = reconcile(&race, 5_000 + held, 5_000).unwrap();
+        assert_eq!(books.total_deposited, held);
+        assert_eq!(books.escrow_amount, held);
+
+        race.debit_escrow(held).unwrap();
+        race.record_fees(40_000, 20_000).unwrap();
+        let books = reconcile(&race, 5_000, 5_000).unwrap();
+        assert_eq!((books.total_paid_out, books.escrow_amount), (held, 0));
+        // Fees are broken out of the payouts, not added to them
+        assert_eq!(books.fees_paid, 60_000);
+        assert!(race.record_fees(u64::MAX, 0).is_err());
+
+        // A rematch opt-in must be held on top of the escrow
+        race.player1_auto_rematch = true;
+        assert!(reconcile(&race, 5_000, 5_000).is_err());
+        assert!(reconcile(&race, 5_000 + race.entry_fee_sol, 5_000).is_ok());
+
+        // escrow_amount moved without going through credit/debit
+        race.escrow_amount = 1;
+        assert_eq!(
+            reconcile(&race, u64::MAX, 0).unwrap_err(),
+            SolracerError::EscrowImbalance.into()
+        );
+    }
+
+    #[test]
+    fn handicapped_player2_stakes_their_own_fee() {
+        let mut race = sample_race("", RaceStatus::Active);
+        assert_eq!(race.player2_fee(), race.entry_fee_sol);
+
+        race.config.player2_entry_fee = 250_000;
+        assert_eq!(race.player2_fee(), 250_000);
+        assert_eq!(race.player1_paid, race.entry_fee_sol);
+
+        // Each opt-in is held at that player's own fee
+        race.player2_auto_rematch = true;
+        let rent = 5_000;
+        let held = rent + race.escrow_amount;
+        assert!(reconcile(&race, held + 249_999, rent).is_err());
+        assert!(reconcile(&race, held + 250_000, rent).is_ok());
+        race.player1_auto_rematch = true;
+        assert!(reconcile(&race, held + 250_000 + race.entry_fee_sol, rent).is_ok());
+    }
+
+    #[test]
+    fn efficiency_decides_equal_times() {
+        let config = RaceConfig {
+            tie_epsilon_ms: 50,
+            tiebreak: TiebreakMode::Efficiency,
+            ..RaceConfig::default()
+        };
+        // Within the epsilon with equal coins, the quicker run is more
+        // efficient; `TimeThenCoins` would hand this tie to player1
+        let slow = result(40_040, 10);
+        let fast = result(40_010, 10);
+        assert_eq!(determine_winner(&slow, &fast, &config), Winner::Player2);
+        assert_eq!(outcome_reason(&slow, &fast, &config), OutcomeReason::Efficiency);
+
+        // More coins in a slightly longer time can still be more efficient
+        let busy = result(40_040, 11);
+        assert_eq!(determine_winner(&fast, &busy, &config), Winner::Player2);
+
+        // Equal efficiency falls to player1
+        assert_eq!(determine_winner(&fast, &fast.clone(), &config), Winner::Player1);
+        assert_eq!(outcome_reason(&fast, &fast.clone(), &config), OutcomeReason::Tie);
+
+        // Outside the epsilon, time still decides
+        assert_eq!(determine_winner(&result(30_000, 0), &busy, &config), Winner::Player1);
+
+        // Extreme values compare exactly
+        let max = result(u64::MAX, u64::MAX);
+        let almost = result(u64::MAX, u64::MAX - 1);
+        assert_eq!(compare_efficiency(&max, &almost), Ordering::Greater);
+    }
+
+    #[test]
+    fn winner_hook_data_layout() {
+        let mut race = sample_race("race-7", RaceStatus::Settled);
+        race.outcome = Some(OutcomeReason::FasterTime);
+        let winner = race.player1;
+
+        let data = winner_hook_data(&race, winner).unwrap();
+        assert_eq!(
+            data[..8],
+            solana_sha256_hasher::hash(b"global:on_race_settled").to_bytes()[..8]
+        );
+        let args = WinnerHookArgs::try_from_slice(&data[8..]).unwrap();
+        assert_eq!(args.race_id, "race-7");
+        assert_eq!(args.winner, winner);
+        assert_eq!(args.outcome, OutcomeReason::FasterTime);
+        assert_eq!(args.escrow_amount, race.escrow_amount);
+    }
+
+    #[test]
+    fn only_zeroed_accounts_are_orphaned() {
+        assert!(is_orphaned(&[]));
+        assert!(is_orphaned(&[0; 64]));
+
+        let mut data = Vec::new();
+        sample_race("", RaceStatus::Claimed).try_serialize(&mut data).unwrap();
+        assert!(!is_orphaned(&data));
+        // A stray byte past a zeroed discriminator still counts as data
+        let mut tail = vec![0; 64];
+        tail[63] = 1;
+        assert!(!is_orphaned(&tail));
+    }
+
+    #[test]
+    fn hash_order_decides_full_ties() {
+        let config = RaceConfig {
+            tiebreak: TiebreakMode::HashOrder,
+            ..RaceConfig::default()
+        };
+        let hashed = |byte: u8| RaceResult {
+            input_hash: [byte; 32],
+            ..result(40_000, 10)
+        };
+
+        assert_eq!(determine_winner(&hashed(9), &hashed(3), &config), Winner::Player2);
+        assert_eq!(determine_winner(&hashed(3), &hashed(9), &config), Winner::Player1);
+        assert_eq!(outcome_reason(&hashed(9), &hashed(3), &config), OutcomeReason::HashOrder);
+
+        // Identical hashes fall back to player1
+        assert_eq!(determine_winner(&hashed(3), &hashed(3), &config), Winner::Player1);
+        assert_eq!(outcome_reason(&hashed(3), &hashed(3), &config), OutcomeReason::Tie);
+
+        // Time and coins still come first
+        let faster = RaceResult { input_hash: [9; 32], ..result(39_000, 10) };
+        assert_eq!(determine_winner(&faster, &hashed(3), &config), Winner::Player1);
+        let richer = RaceResult { input_hash: [9; 32], ..result(40_000, 11) };
+        assert_eq!(determine_winner(&hashed(3), &richer, &config), Winner::Player2);
+        assert_eq!(outcome_reason(&hashed(3), &richer, &config), OutcomeReason::MoreCoins);
+    }
+
+    #[test]
+    fn rotated_server_key_works_until_its_grace_ends() {
+        let (old_key, new_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut config = Config {
+            authority: Pubkey::new_unique(),
+            elo_k_factor: DEFAULT_ELO_K_FACTOR,
+            antisnipe_window_secs: 0,
+            antisnipe_extension_secs: 0,
+            crank_bounty_lamports: 0,
+            max_rematches: 0,
+            min_entry_fee: 0,
+            max_entry_fee: 0,
+            fee_bps: 0,
+            treasury: Pubkey::new_unique(),
+            server_authority: Pubkey::default(),
+            prev_server_authority: Pubkey::default(),
+            prev_server_expires_at: 0,
+            host_cut_bps: 0,
+            no_contest_on_no_show: false,
+            bump: 255,
+        };
+        // Nothing set: not even the default key attests
+        assert!(!config.accepts_server_key(&Pubkey::default(), 0));
+
+        config.server_authority = new_key;
+        config.prev_server_authority = old_key;
+        config.prev_server_expires_at = 1_000;
+        assert!(config.accepts_server_key(&new_key, i64::MAX));
+        assert!(config.accepts_server_key(&old_key, 1_000));
+        assert!(!config.accepts_server_key(&old_key, 1_001));
+        assert!(!config.accepts_server_key(&Pubkey::new_unique(), 0));
+    }
+
+    #[test]
+    fn token_config_overrides_the_global_fee() {
+        let config = Config {
+            authority: Pubkey::new_unique(),
+            elo_k_factor: DEFAULT_ELO_K_FACTOR,
+            antisnipe_window_secs: 0,
+            antisnipe_extension_secs: 0,
+            crank_bounty_lamports: 0,
+            max_rematches: 0,
+            min_entry_fee: 0,
+            max_entry_fee: 0,
+            fee_bps: 250,
+            treasury: Pubkey::new_unique(),
+            server_authority: Pubkey::default(),
+            prev_server_authority: Pubkey::default(),
+            prev_server_expires_at: 0,
+            host_cut_bps: 0,
+            no_contest_on_no_show: false,
+            bump: 255,
+        };
+        let token_config = TokenConfig {
+            token_mint: Pubkey::new_unique(),
+            fee_bps: 1_000,
+            treasury: Pubkey::new_unique(),
+            paused: false,
+            bump: 255,
+        };
+
+        assert_eq!(fee_schedule(&config, None), (250, config.treasury));
+        assert_eq!(
+            fee_schedule(&config, Some(&token_config)),
+            (1_000, token_config.treasury)
+        );
+
+        assert_eq!(protocol_fee(2_000_000, 250), 50_000);
+        assert_eq!(protocol_fee(2_000_000, 1_000), 200_000);
+        // Rounds down, and the extremes take nothing or everything
+        assert_eq!(protocol_fee(399, 250), 9);
+        assert_eq!(protocol_fee(u64::MAX, 0), 0);
+        assert_eq!(protocol_fee(u64::MAX, SPLIT_TOTAL_BPS), u64::MAX);
+    }
+
+    #[test]
+    fn claim_split_adds_up_to_the_prize() {
+        for prize in [0, 399, 2_000_000, u64::MAX] {
+            for (fee_bps, host_cut_bps) in [(0, 0), (250, 100), (9_999, 1), (0, SPLIT_TOTAL_BPS)] {
+                let (fee, host_cut, payout) = claim_split(prize, fee_bps, host_cut_bps).unwrap();
+                assert_eq!(fee, protocol_fee(prize, fee_bps));
+                assert_eq!(host_cut, protocol_fee(prize, host_cut_bps));
+                assert_eq!(fee as u128 + host_cut as u128 + payout as u128, prize as u128);
+            }
+        }
+        assert_eq!(claim_split(2_000_000, 250, 100).unwrap(), (50_000, 20_000, 1_930_000));
+
+        // A token fee past what set_host_cut allowed for leaves the host the rest
+        assert_eq!(claim_split(10_000, 9_000, 1_001).unwrap(), (9_000, 1_000, 0));
+        assert_eq!(claim_split(10_000, 9_500, 1_000).unwrap(), (9_500, 500, 0));
+        assert_eq!(
+            claim_split(1, SPLIT_TOTAL_BPS + 1, 0).unwrap_err(),
+            SolracerError::InvalidFeeBps.into()
+        );
+        assert_eq!(
+            check_fee_split(9_000, 1_001).unwrap_err(),
+            SolracerError::FeesExceedPrize.into()
+        );
+    }
+    #[test]
+    fn player_deadlines_run_from_each_join() {
+        let config = RaceConfig {
+            player_window_secs: 300,
+            ..RaceConfig::default()
+        };
+        assert_eq!(config.player_deadline(1_000), 1_300);
+        assert_eq!(RaceConfig::default().player_deadline(1_000), 0);
+
+        let mut race = Race::new_waiting(
+            "async".to_string(),
+            Pubkey::new_unique(),
+            1,
+            config,
+            Pubkey::new_unique(),
+            1_000,
+            255,
+        );
+        assert_eq!(race.player1_deadline, 1_300);
+        race.player2 = Some(Pubkey::new_unique());
+        race.player2_deadline = race.config.player_deadline(5_000);
+
+        // player1 submitted in time; player2 still has until 5_300
+        race.player1_result = Some(result(40_000, 3));
+        assert_eq!(race.forfeit_winner(5_300), None);
+        assert_eq!(race.forfeit_winner(5_301), Some(race.player1));
+
+        race.player1_result = None;
+        race.player2_result = Some(result(40_000, 3));
+        assert_eq!(race.forfeit_winner(1_300), None);
+        assert_eq!(race.forfeit_winner(1_301), race.player2);
+    }
+
+    #[test]
+    fn both_no_show_only_once_neither_can_submit() {
+        let mut race = Race {
+            config: RaceConfig {
+                submission_window_secs: 600,
+                ..RaceConfig::default()
+            },
+            player2: Some(Pubkey::new_unique()),
+            started_at: 1_000,
+            ..sample_race("no_show", RaceStatus::Active)
+        };
+        assert!(!race.both_no_show(1_600));
+        assert!(race.both_no_show(1_601));
+        assert_eq!(race.forfeit_winner(1_601), None);
+
+        // One result in is a forfeit, not a no-contest
+        race.player1_result = Some(result(40_000, 3));
+        assert!(!race.both_no_show(1_601));
+        race.player1_result = None;
+
+        // Per-player deadlines count on their own
+        race.config.submission_window_secs = 0;
+        race.player1_deadline = 1_100;
+        race.player2_deadline = 1_200;
+        assert!(!race.both_no_show(1_150));
+        assert!(race.both_no_show(1_201));
+
+        // The house never shows up with a result
+        race.house_target_ms = Some(45_000);
+        assert!(!race.both_no_show(i64::MAX));
+    }
+
+    #[test]
+    fn forfeit_only_after_the_submission_deadline() {
+        let mut race = Race {
+            config: RaceConfig {
+                submission_window_secs: 600,
+                ..RaceConfig::default()
+            },
+            player2: Some(Pubkey::new_unique()),
+            started_at: 1_000,
+            ..sample_race("forfeit", RaceStatus::Active)
+        };
+        assert_eq!(race.submission_deadline(), Some(1_600));
+
+        // Before the deadline a lone result waits for the other
+        race.player2_result = Some(result(40_000, 3));
+        assert_eq!(race.forfeit_winner(1_600), None);
+        assert_eq!(race.forfeit_winner(1_601), race.player2);
+
+        race.player2_result = None;
+        race.player1_result = Some(result(40_000, 3));
+        assert_eq!(race.forfeit_winner(1_601), Some(race.player1));
+
+        // Nobody to award, or both in: no forfeit
+        race.player1_result = None;
+        assert_eq!(race.forfeit_winner(1_601), None);
+        race.player1_result = Some(result(40_000, 3));
+        race.player2_result = Some(result(41_000, 3));
+        assert_eq!(race.forfeit_winner(1_601), None);
+
+        // A house race with no player1 result goes to the house
+        race.house_target_ms = Some(45_000);
+        race.player1_result = None;
+        race.player2_result = None;
+        assert_eq!(race.forfeit_winner(1_601), race.player2);
+
+        // No window, or not started, means no deadline
+        race.config.submission_window_secs = 0;
+        assert_eq!(race.submission_deadline(), None);
+        assert_eq!(race.forfeit_winner(i64::MAX), None);
+        race.config.submission_window_secs = 600;
+        race.started_at = 0;
+        assert_eq!(race.submission_deadline(), None);
+    }
+
+    #[test]
+    fn slot_hash_lookup_resolves_skipped_pending_and_expired_slots() {
+        // Newest first, with slot 102 skipped
+        let slot_hashes = |slots: &[u64]| {
+            let mut data = (slots.len() as u64).to_le_bytes().to_vec();
+            for &slot in slots {
+                data.extend_from_slice(&slot.to_le_bytes());
+                data.extend_from_slice(&[slot as u8; 32]);
+            }
+            data
+        };
+        let data = slot_hashes(&[104, 103, 101, 100]);
+
+        assert_eq!(slot_hash_at_or_after(&data, 101), SlotHashLookup::Found([101; 32]));
+        assert_eq!(slot_hash_at_or_after(&data, 102), SlotHashLookup::Found([103; 32]));
+        assert_eq!(slot_hash_at_or_after(&data, 105), SlotHashLookup::Pending);
+        assert_eq!(slot_hash_at_or_after(&data, 100), SlotHashLookup::Found([100; 32]));
+        assert_eq!(slot_hash_at_or_after(&data, 99), SlotHashLookup::Expired);
+        assert_eq!(slot_hash_at_or_after(&slot_hashes(&[]), 99), SlotHashLookup::Pending);
+    }
+
+    #[test]
+    fn map_seed_depends_on_race_and_blockhash() {
+        let race = Pubkey::new_unique();
+        let seed = map_seed_from_blockhash(&race, &[7; 32]);
+        assert_ne!(seed, 0);
+        assert_eq!(seed, map_seed_from_blockhash(&race, &[7; 32]));
+        assert_ne!(seed, map_seed_from_blockhash(&race, &[8; 32]));
+        assert_ne!(seed, map_seed_from_blockhash(&Pubkey::new_unique(), &[7; 32]));
+    }
+
+    #[test]
+    fn pre_version_race_reads_as_version_zero_once_grown() {
+        let race = max_race();
+        let mut data = Vec::new();
+        race.try_serialize(&mut data).unwrap();
+        // The layout before `version` existed: everything from it on is missing
+        data.truncate(data.len() - 1 - race_tail_len(RACE_VERSION));
+        let old_len = data.len();
+        assert_eq!(old_len, 8 + Race::UNVERSIONED_LEN);
+        let old = Race::try_deserialize(&mut &data[..]);
+        assert!(old.is_err());
+
+        // What realloc_race sees after the zero-filled resize
+        data.resize(8 + Race::LEN, 0);
+        let grown = Race::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(grown.version, 0);
+        assert!(grown.needs_realloc(old_len));
+
+        assert_eq!(upgrade_race_data(&mut data, old_len).unwrap(), 0);
+        let grown = Race::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(grown.version, RACE_VERSION);
+        assert_eq!(grown.map_seed_slot, 0);
+        assert_eq!(grown.player2_last_submit_slot, 0);
+        // An untracked rent payer falls back to player1
+        assert_eq!(grown.rent_payer, Pubkey::default());
+        assert_eq!(grown.rent_recipient(), race.player1);
+        assert_eq!(grown.settled_at, 0);
+        assert_eq!(grown.vesting_slices, 0);
+        assert_eq!(grown.claimed_so_far, 0);
+        assert_eq!(grown.claim_delay_secs, 0);
+        assert_eq!(grown.min_coins_to_win, 0);
+        assert_eq!(grown.fees_paid, 0);
+        assert_eq!(grown.bump, race.bump);
+        assert_eq!(grown.race_id, race.race_id);
+        assert!(!grown.needs_realloc(data.len()));
+
+        assert!(!race.needs_realloc(8 + Race::LEN));
+    }
+
+    #[test]
+    fn upgrade_defaults_fields_over_bytes_a_departed_player2_left() {
+        // A version 8 race, from before `min_coins_to_win`
+        let joined = Race {
+            version: 8,
+            player2: Some(Pubkey::new_unique()),
+            settled_at: 1_700_000_000,
+            vesting_seconds: 600,
+            claim_delay_secs: 7,
+            ..sample_race("left", RaceStatus::Active)
+        };
+        let write_v8 = |race: &Race, data: &mut [u8]| {
+            let mut bytes = Vec::new();
+            race.try_serialize(&mut bytes).unwrap();
+            bytes.truncate(bytes.len() - (race_tail_len(RACE_VERSION) - race_tail_len(8)));
+            data[..bytes.len()].copy_from_slice(&bytes);
+        };
+        let old_len = 8 + Race::UNVERSIONED_LEN + 1 + race_tail_len(8);
+        let mut data = vec![0; old_len];
+        write_v8(&joined, &mut data);
+        // leave_race clears the seat; the write is 32 bytes shorter
+        let left = Race {
+            status: RaceStatus::Waiting,
+            player2: None,
+            ..joined.clone()
+        };
+        write_v8(&left, &mut data);
+        data.resize(8 + Race::LEN, 0);
+
+        // Read as is, the new field picks up what player2's write left
+        let stale = Race::try_deserialize(&mut &data[..]).unwrap();
+        assert_ne!(stale.min_coins_to_win, 0);
+
+        assert_eq!(upgrade_race_data(&mut data, old_len).unwrap(), 8);
+        let upgraded = Race::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(upgraded.version, RACE_VERSION);
+        assert_eq!(upgraded.min_coins_to_win, 0);
+        assert_eq!(upgraded.fees_paid, 0);
+        assert_eq!(upgraded.player2, None);
+        assert_eq!(upgraded.settled_at, left.settled_at);
+        assert_eq!(upgraded.vesting_seconds, left.vesting_seconds);
+        assert_eq!(upgraded.claim_delay_secs, left.claim_delay_secs);
+        assert!(!Race::is_featured(&data));
+
+        // A current race is left untouched
+        let before = data.clone();
+        assert_eq!(upgrade_race_data(&mut data, 8 + Race::LEN).unwrap(), RACE_VERSION);
+        assert_eq!(data, before);
+    }
 }
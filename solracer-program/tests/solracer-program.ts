@@ -1,7 +1,14 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { SolracerProgram } from "../target/types/solracer_program";
-import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL, ComputeBudgetProgram } from "@solana/web3.js";
+import {
+  NATIVE_MINT,
+  TOKEN_PROGRAM_ID,
+  createAccount,
+  createAssociatedTokenAccount,
+  createMint,
+} from "@solana/spl-token";
 import { expect } from "chai";
 import { createHash } from "crypto";
 
@@ -17,6 +24,14 @@ describe("solracer-program", () => {
   let tokenMint: PublicKey;
   let racePda: PublicKey;
   let raceBump: number;
+  const [configPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("config")],
+    program.programId
+  );
+  const [globalStatsPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("global_stats")],
+    program.programId
+  );
   const entryFeeSol = new anchor.BN(0.1 * LAMPORTS_PER_SOL);
 
   function raceIdHash(raceId: string): number[] {
@@ -34,28 +49,222 @@ describe("solracer-program", () => {
     );
   }
 
+  // Race seeds (v2) are namespaced by the creator, who is always player1
+  function deriveRacePda(
+    id: string,
+    mint: PublicKey,
+    fee: anchor.BN = entryFeeSol,
+    creator: PublicKey = player1.publicKey
+  ): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("race"),
+        creator.toBuffer(),
+        Buffer.from(id),
+        mint.toBuffer(),
+        fee.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+  }
+
+  // Full RaceConfig with on-chain defaults, overridden by `overrides`
+  function raceConfig(overrides: Record<string, any> = {}) {
+    return {
+      tieEpsilonMs: new anchor.BN(0),
+      tiebreak: { timeThenCoins: {} },
+      maxResultRatio: 100,
+      mapSeed: new anchor.BN(0),
+      voidIdenticalResults: false,
+      timeQuantumMs: new anchor.BN(0),
+      nftCollection: null,
+      mutualSettlement: false,
+      coinsRoot: Array(32).fill(0),
+      winnerHook: null,
+      winnerHookStrict: false,
+      submissionWindowSecs: new anchor.BN(0),
+      playerWindowSecs: new anchor.BN(0),
+      fewerCoinsWin: false,
+      serverAttested: false,
+      player2EntryFee: new anchor.BN(0),
+      ...overrides,
+    };
+  }
+
+  async function fundedKeypair(sol = 2): Promise<Keypair> {
+    const keypair = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(keypair.publicKey, sol * LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    return keypair;
+  }
+
+  function deriveChatAuthorPda(author: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync([Buffer.from("chat_author"), author.toBuffer()], program.programId)[0];
+  }
+
+  // Posts to a race's chat as `author`, creating their ChatAuthor on first use
+  async function postChat(race: PublicKey, author: Keypair, messageHash: number[]) {
+    const chatAuthor = deriveChatAuthorPda(author.publicKey);
+    if ((await provider.connection.getAccountInfo(chatAuthor)) === null) {
+      await program.methods
+        .initChatAuthor()
+        .accounts({ chatAuthor, author: author.publicKey } as any)
+        .signers([author])
+        .rpc();
+    }
+    const { treasury } = await program.account.config.fetch(configPda);
+    return program.methods
+      .updateChatRoot(messageHash)
+      .accounts({
+        race,
+        author: author.publicKey,
+        chatAuthor,
+        config: configPda,
+        treasury,
+        systemProgram: SystemProgram.programId,
+      } as any)
+      .signers([author])
+      .rpc();
+  }
+
+  function deriveLobbyPda(id: string, fee: anchor.BN = entryFeeSol): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("lobby"), Buffer.from(id), fee.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  // Mirrors result_preimage / result_input_hash in lib.rs
+  function resultInputHash(
+    race: PublicKey,
+    id: string,
+    finishTimeMs: number,
+    coins: number,
+    replay: Buffer,
+    version = 2
+  ): number[] {
+    const u32 = (n: number) => {
+      const b = Buffer.alloc(4);
+      b.writeUInt32LE(n);
+      return b;
+    };
+    const idBytes = Buffer.from(id, "utf8");
+    const preimage = Buffer.concat([
+      Buffer.from([version]),
+      // Version 1 preimages did not bind the race address
+      version >= 2 ? race.toBuffer() : Buffer.alloc(0),
+      u32(idBytes.length),
+      idBytes,
+      new anchor.BN(finishTimeMs).toArrayLike(Buffer, "le", 8),
+      new anchor.BN(coins).toArrayLike(Buffer, "le", 8),
+      u32(replay.length),
+      replay,
+    ]);
+    return Array.from(createHash("sha256").update(preimage).digest());
+  }
+
+  function deriveReplayPda(race: PublicKey, player: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("replay"), race.toBuffer(), player.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // Creates a race as player1 and joins it as player2, returning the race PDA
+  async function createMatchedRace(
+    suffix: string,
+    config?: Record<string, any>
+  ): Promise<PublicKey> {
+    const id = `race_${Date.now()}_${suffix}`;
+    const mint = Keypair.generate().publicKey;
+    const pda = deriveRacePda(id, mint);
+
+    const create = config
+      ? program.methods.createRaceWithConfig(id, mint, entryFeeSol, raceConfig(config))
+      : program.methods.createRace(id, mint, entryFeeSol);
+    await create
+      .accounts({
+        race: pda,
+        player1: player1.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([player1])
+      .rpc();
+
+    await program.methods
+      .joinRace()
+      .accounts({
+        race: pda,
+        player2: player2.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([player2])
+      .rpc();
+
+    return pda;
+  }
+
+  async function submitDirect(
+    race: PublicKey,
+    player: Keypair,
+    finishTimeMs: number,
+    coins: number,
+    hashByte = 1
+  ) {
+    await program.methods
+      .submitResult(new anchor.BN(finishTimeMs), new anchor.BN(coins), Array.from(Buffer.alloc(32, hashByte)))
+      .accounts({
+        race,
+        authority: player.publicKey,
+        session: null,
+        playerWallet: player.publicKey,
+      } as any)
+      .signers([player])
+      .rpc();
+  }
+
   before(async () => {
     player1 = Keypair.generate();
     player2 = Keypair.generate();
 
     const airdrop1 = await provider.connection.requestAirdrop(
       player1.publicKey,
-      2 * LAMPORTS_PER_SOL
+      10 * LAMPORTS_PER_SOL
     );
     const airdrop2 = await provider.connection.requestAirdrop(
       player2.publicKey,
-      2 * LAMPORTS_PER_SOL
+      10 * LAMPORTS_PER_SOL
     );
 
     await provider.connection.confirmTransaction(airdrop1);
     await provider.connection.confirmTransaction(airdrop2);
 
+    // The provider wallet acts as the config authority for the whole suite
+    if ((await provider.connection.getAccountInfo(configPda)) === null) {
+      await program.methods
+        .initializeConfig()
+        .accounts({
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+    }
+    // Every instruction that creates, joins, settles or claims a race counts it here
+    if ((await provider.connection.getAccountInfo(globalStatsPda)) === null) {
+      await program.methods
+        .initGlobalStats()
+        .accounts({ globalStats: globalStatsPda, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    }
+
     raceId = `race_${Date.now()}`;
     tokenMint = Keypair.generate().publicKey;
 
     [racePda, raceBump] = PublicKey.findProgramAddressSync(
       [
         Buffer.from("race"),
+        player1.publicKey.toBuffer(),
         Buffer.from(raceId),
         tokenMint.toBuffer(),
         entryFeeSol.toArrayLike(Buffer, "le", 8),
@@ -140,6 +349,16 @@ describe("solracer-program", () => {
       expect(raceBalanceAfter).to.equal(raceBalanceBefore + entryFeeSol.toNumber());
     });
 
+    it("Can be listed with a memcmp filter on status", async () => {
+      // status is pinned right after the 8-byte discriminator; Active = 1
+      const activeRaces = await program.account.race.all([
+        { memcmp: { offset: 8, bytes: anchor.utils.bytes.bs58.encode([1]) } },
+      ]);
+      const keys = activeRaces.map((r) => r.publicKey.toString());
+      expect(keys).to.include(racePda.toString());
+      activeRaces.forEach((r) => expect(r.account.status.active).to.not.be.undefined);
+    });
+
     it("Fails if player2 tries to join twice", async () => {
       try {
         await program.methods
@@ -272,6 +491,7 @@ describe("solracer-program", () => {
       const [newRacePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("race"),
+          player1.publicKey.toBuffer(),
           Buffer.from(newRaceId),
           newTokenMint.toBuffer(),
           entryFeeSol.toArrayLike(Buffer, "le", 8),
@@ -341,6 +561,7 @@ describe("solracer-program", () => {
       const [newRacePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("race"),
+          player1.publicKey.toBuffer(),
           Buffer.from(newRaceId),
           newTokenMint.toBuffer(),
           entryFeeSol.toArrayLike(Buffer, "le", 8),
@@ -421,6 +642,7 @@ describe("solracer-program", () => {
       const [sessionRacePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("race"),
+          player1.publicKey.toBuffer(),
           Buffer.from(sessionRaceId),
           sessionTokenMint.toBuffer(),
           entryFeeSol.toArrayLike(Buffer, "le", 8),
@@ -475,6 +697,7 @@ describe("solracer-program", () => {
       [sessionRacePda] = PublicKey.findProgramAddressSync(
         [
           Buffer.from("race"),
+          player1.publicKey.toBuffer(),
           Buffer.from(sessionRaceId),
           sessionTokenMint.toBuffer(),
           entryFeeSol.toArrayLike(Buffer, "le", 8),
@@ -577,16 +800,6 @@ describe("solracer-program", () => {
       // Create a session with 0 duration (immediately expired)
       const expiredRaceId = `race_expired_${Date.now()}`;
       const expiredTokenMint = Keypair.generate().publicKey;
-      const [expiredRacePda] = PublicKey.findProgramAddressSync(
-        [
-          Buffer.from("race"),
-          Buffer.from(expiredRaceId),
-          expiredTokenMint.toBuffer(),
-          entryFeeSol.toArrayLike(Buffer, "le", 8),
-        ],
-        program.programId
-      );
-
       const expiredSessionKey = Keypair.generate();
       const expiredHash = raceIdHash(expiredRaceId);
       const [expiredSessionPda] = deriveSessionPda(expiredHash, player1.publicKey);
@@ -597,6 +810,7 @@ describe("solracer-program", () => {
       await new Promise((resolve) => setTimeout(resolve, 1000));
 
       const [freshSessionPda] = deriveSessionPda(expiredHash, freshPlayer.publicKey);
+      const expiredRacePda = deriveRacePda(expiredRaceId, expiredTokenMint, entryFeeSol, freshPlayer.publicKey);
 
       await program.methods
         .createRace(expiredRaceId, expiredTokenMint, entryFeeSol)
@@ -690,4 +904,4382 @@ describe("solracer-program", () => {
       expect(raceAfter.escrowAmount.toString()).to.equal("0");
     });
   });
+
+  describe("concede", () => {
+    it("Declares the opponent the winner when a player concedes", async () => {
+      const race = await createMatchedRace("concede");
+
+      await program.methods
+        .concede()
+        .accounts({ race, player: player1.publicKey })
+        .signers([player1])
+        .rpc();
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.settled).to.not.be.undefined;
+      expect(raceAccount.winner?.toString()).to.equal(player2.publicKey.toString());
+
+      // Opponent can claim straight away
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player2.publicKey,
+          session: null,
+          winnerWallet: player2.publicKey,
+        } as any)
+        .signers([player2])
+        .rpc();
+
+      const raceAfter = await program.account.race.fetch(race);
+      expect(raceAfter.escrowAmount.toString()).to.equal("0");
+    });
+
+    it("Fails with AlreadySettled when conceding a settled race", async () => {
+      const race = await createMatchedRace("concede_twice");
+
+      await program.methods
+        .concede()
+        .accounts({ race, player: player2.publicKey })
+        .signers([player2])
+        .rpc();
+
+      try {
+        await program.methods
+          .concede()
+          .accounts({ race, player: player1.publicKey })
+          .signers([player1])
+          .rpc();
+
+        expect.fail("Expected AlreadySettled error");
+      } catch (err: any) {
+        expect(err.message).to.include("AlreadySettled");
+      }
+    });
+
+    it("Fails if a non-player tries to concede", async () => {
+      const race = await createMatchedRace("concede_outsider");
+      const outsider = Keypair.generate();
+
+      try {
+        await program.methods
+          .concede()
+          .accounts({ race, player: outsider.publicKey })
+          .signers([outsider])
+          .rpc();
+
+        expect.fail("Expected PlayerNotInRace error");
+      } catch (err: any) {
+        expect(err.message).to.include("PlayerNotInRace");
+      }
+    });
+  });
+
+  describe("claim_batch", () => {
+    const tokenConfigFor = (mint: PublicKey) =>
+      PublicKey.findProgramAddressSync([Buffer.from("token_config"), mint.toBuffer()], program.programId)[0];
+    // every race here is hosted by player1
+    const withHosts = (races: PublicKey[]) =>
+      races.flatMap((pubkey) => [
+        { pubkey, isWritable: true, isSigner: false },
+        { pubkey: player1.publicKey, isWritable: true, isSigner: false },
+      ]);
+
+    it("Claims every race the signer won and skips the rest", async () => {
+      const wonA = await createMatchedRace("batch_a");
+      const wonB = await createMatchedRace("batch_b");
+      const lost = await createMatchedRace("batch_lost");
+
+      for (const race of [wonA, wonB]) {
+        await submitDirect(race, player1, 60000, 10);
+        await submitDirect(race, player2, 50000, 10);
+        await program.methods.settleRace().accounts({ race }).rpc();
+      }
+      await submitDirect(lost, player1, 40000, 10);
+      await submitDirect(lost, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race: lost }).rpc();
+
+      const balanceBefore = await provider.connection.getBalance(player2.publicKey);
+
+      await program.methods
+        .claimBatch()
+        .accounts({ winner: player2.publicKey, config: configPda, tokenConfig: tokenConfigFor(tokenMint) } as any)
+        .remainingAccounts(withHosts([wonA, wonB, lost, wonA]))
+        .signers([player2])
+        .rpc();
+
+      const balanceAfter = await provider.connection.getBalance(player2.publicKey);
+      const expected = entryFeeSol.mul(new anchor.BN(4)).toNumber();
+      expect(balanceAfter).to.be.greaterThan(balanceBefore + expected - 10000);
+      expect(balanceAfter).to.be.lessThan(balanceBefore + expected + 1);
+
+      for (const race of [wonA, wonB]) {
+        const raceAccount = await program.account.race.fetch(race);
+        expect(raceAccount.escrowAmount.toString()).to.equal("0");
+      }
+      const lostAccount = await program.account.race.fetch(lost);
+      expect(lostAccount.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+    });
+
+    it("Logs which account failed while keeping the error code", async () => {
+      try {
+        await program.methods
+          .claimBatch()
+          .accounts({ winner: player2.publicKey, config: configPda, tokenConfig: tokenConfigFor(tokenMint) } as any)
+          .remainingAccounts(withHosts([configPda]))
+          .signers([player2])
+          .rpc();
+
+        expect.fail("Expected AccountDiscriminatorMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("AccountDiscriminatorMismatch");
+        expect(err.logs.join("\n")).to.include(`Batch step failed for race ${configPda.toString()}`);
+      }
+    });
+
+    it("Fails with BatchTooLarge above the cap", async () => {
+      const race = await createMatchedRace("batch_cap");
+      try {
+        await program.methods
+          .claimBatch()
+          .accounts({ winner: player2.publicKey, config: configPda, tokenConfig: tokenConfigFor(tokenMint) } as any)
+          .remainingAccounts(withHosts(Array(11).fill(race)))
+          .signers([player2])
+          .rpc();
+
+        expect.fail("Expected BatchTooLarge error");
+      } catch (err: any) {
+        expect(err.message).to.include("BatchTooLarge");
+      }
+    });
+  });
+
+  describe("authority_prefund", () => {
+    it("Lets the operator cover both entry fees of a promo race", async () => {
+      const id = `race_${Date.now()}_promo`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+
+      const player1Before = await provider.connection.getBalance(player1.publicKey);
+
+      await program.methods
+        .authorityPrefund(true, true)
+        .accounts({
+          race,
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          player1: player1.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+
+      const player1After = await provider.connection.getBalance(player1.publicKey);
+      expect(player1After).to.equal(player1Before + entryFeeSol.toNumber());
+
+      const player2Before = await provider.connection.getBalance(player2.publicKey);
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player2])
+        .rpc();
+      const player2After = await provider.connection.getBalance(player2.publicKey);
+      expect(player2After).to.equal(player2Before);
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.active).to.not.be.undefined;
+      expect(raceAccount.player1Prefunded).to.be.true;
+      expect(raceAccount.player2Prefunded).to.be.true;
+      expect(raceAccount.operatorFunded.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+      expect(raceAccount.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+    });
+
+    it("Rejects prefunding from a non-authority signer", async () => {
+      const id = `race_${Date.now()}_promo_unauth`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+
+      try {
+        await program.methods
+          .authorityPrefund(false, true)
+          .accounts({
+            race,
+            config: configPda,
+            authority: player2.publicKey,
+            player1: player1.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player2])
+          .rpc();
+
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("team races (2v2)", () => {
+    let members: Keypair[];
+    let teamRace: PublicKey;
+    const teamRaceId = `team_${Date.now()}`;
+
+    before(async () => {
+      members = [player1, player2, Keypair.generate(), Keypair.generate()];
+      for (const member of members.slice(2)) {
+        const sig = await provider.connection.requestAirdrop(member.publicKey, 2 * LAMPORTS_PER_SOL);
+        await provider.connection.confirmTransaction(sig);
+      }
+      [teamRace] = PublicKey.findProgramAddressSync(
+        [Buffer.from("team_race"), Buffer.from(teamRaceId), entryFeeSol.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+    });
+
+    it("Fills both teams and goes Active with four escrowed fees", async () => {
+      await program.methods
+        .createTeamRace(teamRaceId, entryFeeSol, { sumOfTimes: {} })
+        .accounts({
+          teamRace,
+          creator: members[0].publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .signers([members[0]])
+        .rpc();
+
+      // members[0] + members[2] on team 1, members[1] + members[3] on team 2
+      for (const [member, team] of [
+        [members[1], 2],
+        [members[2], 1],
+        [members[3], 2],
+      ] as [Keypair, number][]) {
+        await program.methods
+          .joinTeamRace(team)
+          .accounts({ teamRace, player: member.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([member])
+          .rpc();
+      }
+
+      const raceAccount = await program.account.teamRace.fetch(teamRace);
+      expect(raceAccount.status.active).to.not.be.undefined;
+      expect(raceAccount.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(4)).toString());
+    });
+
+    it("Rejects settling before every member submitted", async () => {
+      try {
+        await program.methods.settleTeamRace().accounts({ teamRace } as any).rpc();
+        expect.fail("Expected ResultsNotComplete error");
+      } catch (err: any) {
+        expect(err.message).to.include("ResultsNotComplete");
+      }
+    });
+
+    it("Aggregates member times and splits the pot between the winners", async () => {
+      // Team 1 sum = 90s, team 2 sum = 100s (team 2 has the single fastest lap)
+      const times = [45000, 30000, 45000, 70000];
+      for (let i = 0; i < members.length; i++) {
+        await program.methods
+          .submitTeamResult(new anchor.BN(times[i]), new anchor.BN(10), Array.from(Buffer.alloc(32, i)))
+          .accounts({ teamRace, player: members[i].publicKey } as any)
+          .signers([members[i]])
+          .rpc();
+      }
+
+      await program.methods.settleTeamRace().accounts({ teamRace } as any).rpc();
+      const settled = await program.account.teamRace.fetch(teamRace);
+      expect(settled.winningTeam).to.equal(1);
+
+      const share = entryFeeSol.mul(new anchor.BN(2)).toNumber();
+      for (const winner of [members[0], members[2]]) {
+        const before = await provider.connection.getBalance(winner.publicKey);
+        await program.methods
+          .claimTeamPrize()
+          .accounts({ teamRace, player: winner.publicKey } as any)
+          .signers([winner])
+          .rpc();
+        const after = await provider.connection.getBalance(winner.publicKey);
+        expect(after).to.equal(before + share);
+      }
+
+      const claimed = await program.account.teamRace.fetch(teamRace);
+      expect(claimed.escrowAmount.toString()).to.equal("0");
+    });
+
+    it("Rejects claims from the losing team", async () => {
+      try {
+        await program.methods
+          .claimTeamPrize()
+          .accounts({ teamRace, player: members[1].publicKey } as any)
+          .signers([members[1]])
+          .rpc();
+        expect.fail("Expected NotWinner error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotWinner");
+      }
+    });
+  });
+
+  describe("tie_epsilon_ms", () => {
+    it("Treats a near-finish within epsilon as a tie decided by coins", async () => {
+      const race = await createMatchedRace("epsilon_tie", { tieEpsilonMs: new anchor.BN(100) });
+      await submitDirect(race, player1, 50000, 3);
+      await submitDirect(race, player2, 50100, 8);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.winner?.toString()).to.equal(player2.publicKey.toString());
+    });
+
+    it("Falls back to time once the gap exceeds epsilon", async () => {
+      const race = await createMatchedRace("epsilon_gap", { tieEpsilonMs: new anchor.BN(100) });
+      await submitDirect(race, player1, 50000, 3);
+      await submitDirect(race, player2, 50101, 8);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.winner?.toString()).to.equal(player1.publicKey.toString());
+    });
+  });
+
+  describe("relayed join (player2_fee_payer)", () => {
+    it("Records player2 as fee payer on a direct join", async () => {
+      const race = await createMatchedRace("direct_payer");
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.player2FeePayer.toString()).to.equal(player2.publicKey.toString());
+    });
+
+    it("Charges and records the relayer when one pays the fee", async () => {
+      const id = `race_${Date.now()}_relayed`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      const relayer = Keypair.generate();
+      const sig = await provider.connection.requestAirdrop(relayer.publicKey, LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(sig);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+
+      const player2Before = await provider.connection.getBalance(player2.publicKey);
+      const relayerBefore = await provider.connection.getBalance(relayer.publicKey);
+
+      await program.methods
+        .joinRace()
+        .accounts({
+          race,
+          player2: player2.publicKey,
+          systemProgram: SystemProgram.programId,
+          feePayer: relayer.publicKey,
+        } as any)
+        .signers([player2, relayer])
+        .rpc();
+
+      expect(await provider.connection.getBalance(player2.publicKey)).to.equal(player2Before);
+      expect(await provider.connection.getBalance(relayer.publicKey)).to.equal(
+        relayerBefore - entryFeeSol.toNumber()
+      );
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.player2?.toString()).to.equal(player2.publicKey.toString());
+      expect(raceAccount.player2FeePayer.toString()).to.equal(relayer.publicKey.toString());
+    });
+  });
+
+  describe("TiebreakMode::CoinsOnly", () => {
+    it("Awards a slower player who collected more coins", async () => {
+      const race = await createMatchedRace("coins_only", { tiebreak: { coinsOnly: {} } });
+      await submitDirect(race, player1, 30000, 4);
+      await submitDirect(race, player2, 60000, 12);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.winner?.toString()).to.equal(player2.publicKey.toString());
+    });
+  });
+
+  describe("lobbies (min_players_to_start)", () => {
+    let extra: Keypair;
+    let lobby: PublicKey;
+
+    before(async () => {
+      extra = await fundedKeypair();
+      const id = `lobby_${Date.now()}`;
+      lobby = deriveLobbyPda(id);
+
+      await program.methods
+        .createLobby(id, entryFeeSol, 4, 3, new anchor.BN(0), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinLobby()
+        .accounts({ lobby, player: player2.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player2])
+        .rpc();
+    });
+
+    it("Rejects starting below the minimum with NotEnoughPlayers", async () => {
+      try {
+        await program.methods
+          .startRace()
+          .accounts({ lobby, creator: player1.publicKey } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected NotEnoughPlayers error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotEnoughPlayers");
+      }
+    });
+
+    it("Starts once the minimum has joined, before the lobby is full", async () => {
+      await program.methods
+        .joinLobby()
+        .accounts({ lobby, player: extra.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([extra])
+        .rpc();
+
+      await program.methods
+        .startRace()
+        .accounts({ lobby, creator: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      const lobbyAccount = await program.account.lobby.fetch(lobby);
+      expect(lobbyAccount.status.active).to.not.be.undefined;
+      expect(lobbyAccount.players.length).to.equal(3);
+      expect(lobbyAccount.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(3)).toString());
+    });
+
+    it("Excludes players who did not join before the start", async () => {
+      const late = await fundedKeypair();
+      try {
+        await program.methods
+          .joinLobby()
+          .accounts({ lobby, player: late.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([late])
+          .rpc();
+        expect.fail("Expected InvalidRaceStatus error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidRaceStatus");
+      }
+    });
+
+    it("Settles to the best result and pays the whole pot", async () => {
+      const entrants: [Keypair, number][] = [
+        [player1, 52000],
+        [player2, 48000],
+        [extra, 50000],
+      ];
+      for (const [player, time] of entrants) {
+        await program.methods
+          .submitLobbyResult(new anchor.BN(time), new anchor.BN(5), Array.from(Buffer.alloc(32, 1)))
+          .accounts({ lobby, player: player.publicKey } as any)
+          .signers([player])
+          .rpc();
+      }
+
+      await program.methods.settleLobby().accounts({ lobby } as any).rpc();
+      const settled = await program.account.lobby.fetch(lobby);
+      expect(settled.winner?.toString()).to.equal(player2.publicKey.toString());
+
+      const before = await provider.connection.getBalance(player2.publicKey);
+      await program.methods
+        .claimLobbyPrize()
+        .accounts({ lobby, winner: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+      const after = await provider.connection.getBalance(player2.publicKey);
+      expect(after).to.equal(before + entryFeeSol.mul(new anchor.BN(3)).toNumber());
+    });
+  });
+
+  describe("double claim protection", () => {
+    it("Marks the race Claimed and rejects a second claim with AlreadyClaimed", async () => {
+      const race = await createMatchedRace("double_claim");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const claim = () =>
+        program.methods
+          .claimPrize()
+          .accounts({
+            race,
+            authority: player1.publicKey,
+            session: null,
+            winnerWallet: player1.publicKey,
+          } as any)
+          .signers([player1])
+          .rpc();
+
+      await claim();
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.claimed).to.not.be.undefined;
+
+      try {
+        await claim();
+        expect.fail("Expected AlreadyClaimed error");
+      } catch (err: any) {
+        expect(err.message).to.include("AlreadyClaimed");
+      }
+    });
+  });
+
+  describe("replays (store_replay / verify_result)", () => {
+    const replayBytes = Buffer.from([3, 1, 4, 1, 5, 9, 2, 6]);
+
+    it("Verifies a stored replay against the submitted input_hash", async () => {
+      const race = await createMatchedRace("replay_ok");
+      const { raceId: id } = await program.account.race.fetch(race);
+      const replay = deriveReplayPda(race, player1.publicKey);
+
+      await program.methods
+        .submitResult(new anchor.BN(51000), new anchor.BN(7), resultInputHash(race, id, 51000, 7, replayBytes))
+        .accounts({ race, authority: player1.publicKey, session: null, playerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      await program.methods
+        .storeReplay(replayBytes)
+        .accounts({ race, replay, player: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      await program.methods.verifyResult().accounts({ race, replay } as any).rpc();
+      expect((await program.account.replay.fetch(replay)).verified).to.be.true;
+
+      await program.methods
+        .closeReplay()
+        .accounts({ replay, player: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      expect(await provider.connection.getAccountInfo(replay)).to.be.null;
+    });
+
+    it("Rejects a replay that does not match the input_hash", async () => {
+      const race = await createMatchedRace("replay_bad");
+      const replay = deriveReplayPda(race, player2.publicKey);
+      await submitDirect(race, player2, 49000, 3, 9);
+
+      await program.methods
+        .storeReplay(replayBytes)
+        .accounts({ race, replay, player: player2.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player2])
+        .rpc();
+
+      try {
+        await program.methods.verifyResult().accounts({ race, replay } as any).rpc();
+        expect.fail("Expected InputHashMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("InputHashMismatch");
+      }
+    });
+
+    it("Rejects an input_hash that does not bind the race address", async () => {
+      const race = await createMatchedRace("replay_unbound");
+      const { raceId: id } = await program.account.race.fetch(race);
+      const replay = deriveReplayPda(race, player1.publicKey);
+
+      await program.methods
+        .submitResult(new anchor.BN(51000), new anchor.BN(7), resultInputHash(race, id, 51000, 7, replayBytes, 1))
+        .accounts({ race, authority: player1.publicKey, session: null, playerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .storeReplay(replayBytes)
+        .accounts({ race, replay, player: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      try {
+        await program.methods.verifyResult().accounts({ race, replay } as any).rpc();
+        expect.fail("Expected HashNotBoundToRace error");
+      } catch (err: any) {
+        expect(err.message).to.include("HashNotBoundToRace");
+      }
+    });
+
+    it("Rejects replays over the size limit", async () => {
+      const race = await createMatchedRace("replay_big");
+      try {
+        await program.methods
+          .storeReplay(Buffer.alloc(401, 1))
+          .accounts({
+            race,
+            replay: deriveReplayPda(race, player1.publicKey),
+            player: player1.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected ReplayTooLarge error");
+      } catch (err: any) {
+        expect(err.message).to.include("ReplayTooLarge");
+      }
+    });
+  });
+
+  describe("lobby join grace", () => {
+    it("Admits a join inside the grace window and rejects one just after", async () => {
+      const [third, fourth] = [await fundedKeypair(), await fundedKeypair()];
+      const id = `lobby_${Date.now()}_grace`;
+      const lobby = deriveLobbyPda(id);
+      const join = (player: Keypair) =>
+        program.methods
+          .joinLobby()
+          .accounts({ lobby, player: player.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player])
+          .rpc();
+
+      await program.methods
+        .createLobby(id, entryFeeSol, 5, 2, new anchor.BN(3), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      // Reaching the minimum opens the 3s grace window
+      await join(player2);
+
+      try {
+        await program.methods
+          .startRace()
+          .accounts({ lobby, creator: player1.publicKey } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected JoinGraceActive error");
+      } catch (err: any) {
+        expect(err.message).to.include("JoinGraceActive");
+      }
+
+      await join(third);
+
+      await new Promise((resolve) => setTimeout(resolve, 5000));
+      try {
+        await join(fourth);
+        expect.fail("Expected JoinGraceExpired error");
+      } catch (err: any) {
+        expect(err.message).to.include("JoinGraceExpired");
+      }
+
+      await program.methods
+        .startRace()
+        .accounts({ lobby, creator: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      const lobbyAccount = await program.account.lobby.fetch(lobby);
+      expect(lobbyAccount.status.active).to.not.be.undefined;
+      expect(lobbyAccount.players.length).to.equal(3);
+    });
+  });
+
+  describe("claim_prize_wsol", () => {
+    it("Pays the prize into the winner's wSOL token account", async () => {
+      const race = await createMatchedRace("wsol");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const wsolAccount = await createAssociatedTokenAccount(
+        provider.connection,
+        player1,
+        NATIVE_MINT,
+        player1.publicKey
+      );
+
+      await program.methods
+        .claimPrizeWsol()
+        .accounts({
+          race,
+          authority: player1.publicKey,
+          session: null,
+          winnerTokenAccount: wsolAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        } as any)
+        .signers([player1])
+        .rpc();
+
+      const balance = await provider.connection.getTokenAccountBalance(wsolAccount);
+      expect(balance.value.amount).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.claimed).to.not.be.undefined;
+    });
+
+    it("Rejects a token account that is not wSOL", async () => {
+      const race = await createMatchedRace("wsol_wrong_mint");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const otherMint = await createMint(provider.connection, player1, player1.publicKey, null, 0);
+      const otherAccount = await createAccount(provider.connection, player1, otherMint, player1.publicKey);
+
+      try {
+        await program.methods
+          .claimPrizeWsol()
+          .accounts({
+            race,
+            authority: player1.publicKey,
+            session: null,
+            winnerTokenAccount: otherAccount,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected NotWrappedSol error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotWrappedSol");
+      }
+    });
+  });
+
+  describe("spectator chat root", () => {
+    it("Chains message hashes into chat_root and pays the deposit to the treasury", async () => {
+      const race = await createMatchedRace("chat");
+      const spectator = await fundedKeypair();
+      const messageHash = Array.from(createHash("sha256").update("gg", "utf8").digest());
+      // A funded treasury no other signer pays transaction fees from
+      const treasury = (await fundedKeypair(1)).publicKey;
+      await program.methods
+        .setFee(0, treasury)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+      const before = await program.account.race.fetch(race);
+      const treasuryBefore = await provider.connection.getBalance(treasury);
+      await postChat(race, spectator, messageHash);
+
+      const expected = createHash("sha256")
+        .update(Buffer.concat([Buffer.alloc(32), Buffer.from(messageHash)]))
+        .digest();
+      const after = await program.account.race.fetch(race);
+      expect(Buffer.from(after.chatRoot).equals(expected)).to.be.true;
+      // The deposit never reaches the prize
+      expect(after.escrowAmount.toString()).to.equal(before.escrowAmount.toString());
+      expect((await provider.connection.getBalance(treasury)) - treasuryBefore).to.equal(5000);
+    });
+
+    it("Rate-limits each author without holding up the others", async () => {
+      const race = await createMatchedRace("chat_rate");
+      const spectator = await fundedKeypair();
+      const other = await fundedKeypair();
+
+      await postChat(race, spectator, Array(32).fill(1));
+      await postChat(race, other, Array(32).fill(2));
+      try {
+        await postChat(race, spectator, Array(32).fill(3));
+        expect.fail("Expected ChatRateLimited error");
+      } catch (err: any) {
+        expect(err.message).to.include("ChatRateLimited");
+      }
+    });
+  });
+
+  describe("create_race_batch", () => {
+    // Each race PDA followed by its token's TokenConfig PDA
+    const batchAccounts = (specs: { tokenMint: PublicKey }[], pdas: PublicKey[]) =>
+      specs.flatMap((spec, i) => [
+        { pubkey: pdas[i], isWritable: true, isSigner: false },
+        {
+          pubkey: PublicKey.findProgramAddressSync(
+            [Buffer.from("token_config"), spec.tokenMint.toBuffer()],
+            program.programId
+          )[0],
+          isWritable: false,
+          isSigner: false,
+        },
+      ]);
+
+    it("Creates several independently joinable races", async () => {
+      const creator = await fundedKeypair();
+      const base = `race_${Date.now()}_qual`;
+      const specs = [0, 1, 2].map((i) => ({
+        raceId: `${base}_${i}`,
+        tokenMint: Keypair.generate().publicKey,
+        entryFeeSol,
+        config: raceConfig(),
+      }));
+      const pdas = specs.map((s) => deriveRacePda(s.raceId, s.tokenMint, entryFeeSol, creator.publicKey));
+
+      await program.methods
+        .createRaceBatch(specs)
+        .accounts({
+          creator: creator.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .remainingAccounts(batchAccounts(specs, pdas))
+        .signers([creator])
+        .rpc();
+
+      for (const [i, pda] of pdas.entries()) {
+        const race = await program.account.race.fetch(pda);
+        expect(race.raceId).to.equal(specs[i].raceId);
+        expect(race.player1.toString()).to.equal(creator.publicKey.toString());
+        expect(race.escrowAmount.toString()).to.equal(entryFeeSol.toString());
+        expect(race.status.waiting).to.not.be.undefined;
+      }
+
+      await program.methods
+        .joinRace()
+        .accounts({
+          race: pdas[1],
+          player2: player2.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([player2])
+        .rpc();
+
+      expect((await program.account.race.fetch(pdas[1])).status.active).to.not.be.undefined;
+      expect((await program.account.race.fetch(pdas[0])).status.waiting).to.not.be.undefined;
+    });
+
+    it("Rejects a race account that is not the spec's PDA", async () => {
+      const creator = await fundedKeypair();
+      const spec = {
+        raceId: `race_${Date.now()}_qual_bad`,
+        tokenMint: Keypair.generate().publicKey,
+        entryFeeSol,
+        config: raceConfig(),
+      };
+
+      try {
+        await program.methods
+          .createRaceBatch([spec])
+          .accounts({
+            creator: creator.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .remainingAccounts(batchAccounts([spec], [Keypair.generate().publicKey]))
+          .signers([creator])
+          .rpc();
+        expect.fail("Expected InvalidRaceAccount error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidRaceAccount");
+      }
+    });
+
+    it("Checks player2's fee against the entry fee bounds like create_race", async () => {
+      const setBounds = (min: anchor.BN) =>
+        program.methods
+          .setEntryFeeBounds(min, new anchor.BN(0))
+          .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+          .rpc();
+      const creator = await fundedKeypair();
+      const spec = {
+        raceId: `race_${Date.now()}_qual_p2_fee`,
+        tokenMint: Keypair.generate().publicKey,
+        entryFeeSol,
+        config: raceConfig({ player2EntryFee: new anchor.BN(1) }),
+      };
+
+      await setBounds(entryFeeSol);
+      try {
+        await program.methods
+          .createRaceBatch([spec])
+          .accounts({ creator: creator.publicKey, systemProgram: SystemProgram.programId } as any)
+          .remainingAccounts(
+            batchAccounts([spec], [deriveRacePda(spec.raceId, spec.tokenMint, entryFeeSol, creator.publicKey)])
+          )
+          .signers([creator])
+          .rpc();
+        expect.fail("Expected EntryFeeTooLow error");
+      } catch (err: any) {
+        expect(err.message).to.include("EntryFeeTooLow");
+      } finally {
+        await setBounds(new anchor.BN(0));
+      }
+    });
+  });
+
+  describe("auto rematch", () => {
+    const setAutoRematch = (race: PublicKey, player: Keypair, enabled: boolean) =>
+      program.methods
+        .setAutoRematch(enabled)
+        .accounts({
+          race,
+          player: player.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .signers([player])
+        .rpc();
+
+    it("Seats both players in a funded rematch when both opted in", async () => {
+      const race = await createMatchedRace("rematch");
+      await setAutoRematch(race, player1, true);
+      await setAutoRematch(race, player2, true);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      const settled = await program.account.race.fetch(race);
+      const rematchPda = deriveRacePda(`${settled.raceId}/r1`, settled.tokenMint);
+
+      await program.methods
+        .settleRaceWithRematch()
+        .accounts({
+          race,
+          rematchRace: rematchPda,
+          payer: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+
+      const original = await program.account.race.fetch(race);
+      expect(original.status.settled).to.not.be.undefined;
+      expect(original.rematch.toString()).to.equal(rematchPda.toString());
+
+      const rematch = await program.account.race.fetch(rematchPda);
+      expect(rematch.status.active).to.not.be.undefined;
+      expect(rematch.player1.toString()).to.equal(player1.publicKey.toString());
+      expect(rematch.player2.toString()).to.equal(player2.publicKey.toString());
+      expect(rematch.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+    });
+
+    it("Settles without a rematch when only one player opted in, and refunds on opt-out", async () => {
+      const race = await createMatchedRace("rematch_one");
+      await setAutoRematch(race, player1, true);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      const settled = await program.account.race.fetch(race);
+      const rematchPda = deriveRacePda(`${settled.raceId}/r1`, settled.tokenMint);
+
+      await program.methods
+        .settleRaceWithRematch()
+        .accounts({
+          race,
+          rematchRace: rematchPda,
+          payer: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+
+      expect((await program.account.race.fetch(race)).rematch).to.be.null;
+      expect(await provider.connection.getAccountInfo(rematchPda)).to.be.null;
+
+      const before = await provider.connection.getBalance(player1.publicKey);
+      await setAutoRematch(race, player1, false);
+      const after = await provider.connection.getBalance(player1.publicKey);
+      expect(after).to.be.greaterThan(before);
+    });
+  });
+
+  describe("submit before opponent joins", () => {
+    it("Rejects a result on a Waiting race with OpponentNotJoined", async () => {
+      const id = `race_${Date.now()}_no_opponent`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([player1])
+        .rpc();
+
+      try {
+        await submitDirect(race, player1, 40000, 10);
+        expect.fail("Expected OpponentNotJoined error");
+      } catch (err: any) {
+        expect(err.message).to.include("OpponentNotJoined");
+      }
+    });
+  });
+
+  describe("house races", () => {
+    async function createHouseRace(suffix: string, targetTimeMs: number, house?: Keypair) {
+      const id = `race_${Date.now()}_${suffix}`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createHouseRace(id, mint, entryFeeSol, new anchor.BN(targetTimeMs))
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          config: configPda,
+          authority: house ? house.publicKey : provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .signers(house ? [player1, house] : [player1])
+        .rpc();
+
+      return race;
+    }
+
+    it("Pays player1 when they beat the target", async () => {
+      const race = await createHouseRace("house_win", 45000);
+
+      const created = await program.account.race.fetch(race);
+      expect(created.status.active).to.not.be.undefined;
+      expect(created.player2.toString()).to.equal(provider.wallet.publicKey.toString());
+      expect(created.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+
+      await submitDirect(race, player1, 40000, 0);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const settled = await program.account.race.fetch(race);
+      expect(settled.winner.toString()).to.equal(player1.publicKey.toString());
+    });
+
+    it("Awards the pot to the house when player1 misses the target", async () => {
+      const race = await createHouseRace("house_loss", 45000);
+      await submitDirect(race, player1, 45000, 99);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const settled = await program.account.race.fetch(race);
+      expect(settled.winner.toString()).to.equal(provider.wallet.publicKey.toString());
+    });
+
+    it("Rejects a house that is not the config authority", async () => {
+      const impostor = await fundedKeypair();
+      try {
+        await createHouseRace("house_impostor", 45000, impostor);
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("ratings", () => {
+    const deriveProfilePda = (player: PublicKey) =>
+      PublicKey.findProgramAddressSync([Buffer.from("profile"), player.toBuffer()], program.programId)[0];
+
+    before(async () => {
+      for (const player of [player1, player2]) {
+        await program.methods
+          .initProfile()
+          .accounts({
+            profile: deriveProfilePda(player.publicKey),
+            player: player.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player])
+          .rpc();
+      }
+    });
+
+    it("Seeds new profiles at 1200", async () => {
+      const profile = await program.account.playerProfile.fetch(deriveProfilePda(player1.publicKey));
+      expect(profile.rating).to.equal(1200);
+      expect(profile.ratedRaces).to.equal(0);
+    });
+
+    it("Moves both ratings once per settled race", async () => {
+      const race = await createMatchedRace("rated");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const rate = () =>
+        program.methods
+          .rateRace()
+          .accounts({
+            race,
+            config: configPda,
+            player1Profile: deriveProfilePda(player1.publicKey),
+            player2Profile: deriveProfilePda(player2.publicKey),
+          } as any)
+          .rpc();
+
+      const before1 = (await program.account.playerProfile.fetch(deriveProfilePda(player1.publicKey))).rating;
+      const before2 = (await program.account.playerProfile.fetch(deriveProfilePda(player2.publicKey))).rating;
+      await rate();
+      const after1 = (await program.account.playerProfile.fetch(deriveProfilePda(player1.publicKey))).rating;
+      const after2 = (await program.account.playerProfile.fetch(deriveProfilePda(player2.publicKey))).rating;
+
+      expect(after1).to.be.greaterThan(before1);
+      expect(after1 - before1).to.equal(before2 - after2);
+
+      try {
+        await rate();
+        expect.fail("Expected AlreadyRated error");
+      } catch (err: any) {
+        expect(err.message).to.include("AlreadyRated");
+      }
+    });
+
+    it("Only lets the authority change the K-factor", async () => {
+      const impostor = await fundedKeypair();
+      try {
+        await program.methods
+          .setEloKFactor(64)
+          .accounts({ config: configPda, authority: impostor.publicKey } as any)
+          .signers([impostor])
+          .rpc();
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("blowout disputes", () => {
+    it("Flags an extreme time gap as Disputed until the authority resolves it", async () => {
+      const race = await createMatchedRace("blowout");
+      await submitDirect(race, player1, 100, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      let raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.disputed).to.not.be.undefined;
+      expect(raceAccount.winner).to.be.null;
+
+      // The flagged fast time is treated as cheating: the opponent wins
+      await program.methods
+        .resolveDispute(player2.publicKey)
+        .accounts({ race, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+      raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.settled).to.not.be.undefined;
+      expect(raceAccount.winner.toString()).to.equal(player2.publicKey.toString());
+    });
+
+    it("Settles normally when the check is disabled", async () => {
+      const race = await createMatchedRace("blowout_off", { maxResultRatio: 0 });
+      await submitDirect(race, player1, 100, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const raceAccount = await program.account.race.fetch(race);
+      expect(raceAccount.status.settled).to.not.be.undefined;
+      expect(raceAccount.winner.toString()).to.equal(player1.publicKey.toString());
+    });
+  });
+
+  describe("creator-namespaced race ids", () => {
+    it("Lets two creators use the same race_id", async () => {
+      const id = `match_${Date.now()}`;
+      const mint = Keypair.generate().publicKey;
+      const otherCreator = await fundedKeypair();
+
+      for (const creator of [player1, otherCreator]) {
+        await program.methods
+          .createRace(id, mint, entryFeeSol)
+          .accounts({
+            race: deriveRacePda(id, mint, entryFeeSol, creator.publicKey),
+            player1: creator.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([creator])
+          .rpc();
+      }
+
+      const first = await program.account.race.fetch(deriveRacePda(id, mint, entryFeeSol, player1.publicKey));
+      const second = await program.account.race.fetch(deriveRacePda(id, mint, entryFeeSol, otherCreator.publicKey));
+      expect(first.player1.toString()).to.equal(player1.publicKey.toString());
+      expect(second.player1.toString()).to.equal(otherCreator.publicKey.toString());
+    });
+  });
+
+  describe("refund_lobby", () => {
+    const refund = (lobby: PublicKey, players: PublicKey[]) =>
+      program.methods
+        .refundLobby()
+        .accounts({ lobby, creator: player1.publicKey } as any)
+        .remainingAccounts(players.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false })))
+        .rpc();
+
+    it("Rejects a refund before the join deadline", async () => {
+      const id = `lobby_${Date.now()}_refund_early`;
+      const lobby = deriveLobbyPda(id);
+      await program.methods
+        .createLobby(id, entryFeeSol, 4, 3, new anchor.BN(0), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      try {
+        await refund(lobby, [player1.publicKey]);
+        expect.fail("Expected JoinDeadlineNotReached error");
+      } catch (err: any) {
+        expect(err.message).to.include("JoinDeadlineNotReached");
+      }
+    });
+
+    it("Rejects a refund once the minimum was reached", async () => {
+      const id = `lobby_${Date.now()}_refund_min`;
+      const lobby = deriveLobbyPda(id);
+      await program.methods
+        .createLobby(id, entryFeeSol, 4, 2, new anchor.BN(60), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinLobby()
+        .accounts({ lobby, player: player2.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player2])
+        .rpc();
+
+      try {
+        await refund(lobby, [player1.publicKey, player2.publicKey]);
+        expect.fail("Expected MinimumReached error");
+      } catch (err: any) {
+        expect(err.message).to.include("MinimumReached");
+      }
+    });
+  });
+
+  describe("RaceFinalState event", () => {
+    it("Carries the full outcome, including both input hashes", async () => {
+      const race = await createMatchedRace("final_state");
+      await submitDirect(race, player1, 40000, 10, 7);
+      await submitDirect(race, player2, 50000, 10, 8);
+
+      const sig = await program.methods
+        .settleRace()
+        .accounts({ race })
+        .rpc({ commitment: "confirmed" });
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+
+      const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+      const event = [...parser.parseLogs(tx.meta.logMessages)].find(
+        (e) => e.name.toLowerCase() === "racefinalstate"
+      );
+      expect(event).to.not.be.undefined;
+      expect(event.data.winner.toString()).to.equal(player1.publicKey.toString());
+      expect(event.data.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+      expect(Array.from(event.data.player1Result.inputHash)).to.deep.equal(Array(32).fill(7));
+      expect(Array.from(event.data.player2Result.inputHash)).to.deep.equal(Array(32).fill(8));
+    });
+  });
+
+  describe("ResultFeed", () => {
+    it("Records settled races for the race's token", async () => {
+      const race = await createMatchedRace("feed");
+      const { tokenMint: mint, raceId: id } = await program.account.race.fetch(race);
+      const [resultFeed] = PublicKey.findProgramAddressSync(
+        [Buffer.from("result_feed"), mint.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .initResultFeed(mint)
+        .accounts({ resultFeed, payer: provider.wallet.publicKey, systemProgram: SystemProgram.programId } as any)
+        .rpc();
+
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRaceWithFeed().accounts({ race, resultFeed } as any).rpc();
+
+      const feed = await program.account.resultFeed.fetch(resultFeed);
+      expect(feed.len).to.equal(1);
+      expect(feed.head).to.equal(1);
+      expect(feed.entries[0].winner.toString()).to.equal(player1.publicKey.toString());
+      expect(feed.entries[0].winnerTimeMs.toNumber()).to.equal(40000);
+      expect(Array.from(feed.entries[0].raceIdHash)).to.deep.equal(raceIdHash(id));
+    });
+  });
+
+  describe("import_result", () => {
+    const imported = (finishTimeMs: number) => ({
+      finishTimeMs: new anchor.BN(finishTimeMs),
+      coinsCollected: new anchor.BN(10),
+      inputHash: Array(32).fill(0),
+    });
+    const importRace = (id: string, mint: PublicKey, authority: Keypair | null) => {
+      const signer = authority ? authority.publicKey : provider.wallet.publicKey;
+      const call = program.methods
+        .importResult(
+          id,
+          mint,
+          entryFeeSol,
+          player1.publicKey,
+          player2.publicKey,
+          imported(40000),
+          imported(50000),
+          player1.publicKey
+        )
+        .accounts({
+          race: deriveRacePda(id, mint),
+          config: configPda,
+          authority: signer,
+          systemProgram: SystemProgram.programId,
+        } as any);
+      return authority ? call.signers([authority]).rpc() : call.rpc();
+    };
+
+    it("Writes a settled, unclaimable race", async () => {
+      const id = `race_${Date.now()}_import`;
+      const mint = Keypair.generate().publicKey;
+      await importRace(id, mint, null);
+
+      const race = await program.account.race.fetch(deriveRacePda(id, mint));
+      expect(race.imported).to.be.true;
+      expect(race.status).to.deep.equal({ settled: {} });
+      expect(race.winner.toString()).to.equal(player1.publicKey.toString());
+      expect(race.escrowAmount.toNumber()).to.equal(0);
+
+      try {
+        await program.methods
+          .claimPrize()
+          .accounts({
+            race: deriveRacePda(id, mint),
+            authority: player1.publicKey,
+            session: null,
+            winnerWallet: player1.publicKey,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected ImportedRace error");
+      } catch (err: any) {
+        expect(err.message).to.include("ImportedRace");
+      }
+    });
+
+    it("Rejects imports not signed by the config authority", async () => {
+      try {
+        await importRace(`race_${Date.now()}_import_bad`, Keypair.generate().publicKey, player2);
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("join_race funds check", () => {
+    it("Rejects an underfunded player2 with InsufficientFunds", async () => {
+      const id = `race_${Date.now()}_underfunded`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      const broke = await fundedKeypair(0.05);
+      try {
+        await program.methods
+          .joinRace()
+          .accounts({ race, player2: broke.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([broke])
+          .rpc();
+        expect.fail("Expected InsufficientFunds error");
+      } catch (err: any) {
+        expect(err.message).to.include("InsufficientFunds");
+      }
+    });
+  });
+
+  describe("map_seed", () => {
+    const submitOnMap = (race: PublicKey, player: Keypair, mapSeed: number) =>
+      program.methods
+        .submitResultOnMap(new anchor.BN(40000), new anchor.BN(10), Array(32).fill(1), new anchor.BN(mapSeed))
+        .accounts({
+          race,
+          authority: player.publicKey,
+          session: null,
+          playerWallet: player.publicKey,
+        } as any)
+        .signers([player])
+        .rpc();
+
+    it("Only accepts results raced on the race's map seed", async () => {
+      const race = await createMatchedRace("map_seed", { mapSeed: new anchor.BN(42) });
+
+      for (const attempt of [() => submitOnMap(race, player1, 41), () => submitDirect(race, player1, 40000, 10)]) {
+        try {
+          await attempt();
+          expect.fail("Expected MapSeedMismatch error");
+        } catch (err: any) {
+          expect(err.message).to.include("MapSeedMismatch");
+        }
+      }
+
+      await submitOnMap(race, player1, 42);
+      const state = await program.account.race.fetch(race);
+      expect(state.player1Result.finishTimeMs.toNumber()).to.equal(40000);
+    });
+  });
+
+  describe("leave_race", () => {
+    const leave = (race: PublicKey, player: Keypair) =>
+      program.methods
+        .leaveRace()
+        .accounts({ race, player2: player.publicKey, feePayer: player.publicKey } as any)
+        .signers([player])
+        .rpc();
+
+    it("Refunds player2 and reopens the race", async () => {
+      const race = await createMatchedRace("leave");
+      const before = await provider.connection.getBalance(player2.publicKey);
+
+      await leave(race, player2);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ waiting: {} });
+      expect(state.player2).to.be.null;
+      expect(state.escrowAmount.toString()).to.equal(entryFeeSol.toString());
+      const after = await provider.connection.getBalance(player2.publicKey);
+      expect(after - before).to.be.greaterThan(entryFeeSol.toNumber() - 10000);
+
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player2])
+        .rpc();
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ active: {} });
+    });
+
+    it("Rejects leaving after a result was submitted", async () => {
+      const race = await createMatchedRace("leave_late");
+      await submitDirect(race, player1, 40000, 10);
+
+      try {
+        await leave(race, player2);
+        expect.fail("Expected CannotLeaveAfterSubmit error");
+      } catch (err: any) {
+        expect(err.message).to.include("CannotLeaveAfterSubmit");
+      }
+    });
+  });
+
+  describe("lobby anti-sniping", () => {
+    const setAntisnipe = (window: number, extension: number) =>
+      program.methods
+        .setAntisnipe(new anchor.BN(window), new anchor.BN(extension))
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    after(() => setAntisnipe(0, 0));
+
+    it("Extends the late-join window for a buzzer-beating join", async () => {
+      // Every join after the minimum counts as last-second with this window
+      await setAntisnipe(3600, 30);
+
+      const id = `lobby_${Date.now()}_antisnipe`;
+      const lobby = deriveLobbyPda(id);
+      await program.methods
+        .createLobby(id, entryFeeSol, 4, 2, new anchor.BN(60), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      const join = async (player: Keypair) =>
+        program.methods
+          .joinLobby()
+          .accounts({ lobby, config: configPda, player: player.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player])
+          .rpc();
+
+      // Reaching the minimum opens the window without extending it
+      await join(player2);
+      let state = await program.account.lobby.fetch(lobby);
+      expect(state.joinGraceSecs.toNumber()).to.equal(60);
+
+      await join(await fundedKeypair());
+      state = await program.account.lobby.fetch(lobby);
+      expect(state.joinGraceSecs.toNumber()).to.equal(90);
+      expect(state.antisnipeExtensions).to.equal(1);
+    });
+  });
+
+  describe("void_identical_results", () => {
+    it("Disputes byte-identical results when opted in", async () => {
+      const race = await createMatchedRace("identical", { voidIdenticalResults: true });
+      await submitDirect(race, player1, 40000, 10, 9);
+      await submitDirect(race, player2, 40000, 10, 9);
+
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ disputed: {} });
+      expect(state.winner).to.be.null;
+    });
+  });
+
+  describe("crank_settle", () => {
+    const setBounty = (lamports: number) =>
+      program.methods
+        .setCrankBounty(new anchor.BN(lamports))
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const [bountyPool] = PublicKey.findProgramAddressSync([Buffer.from("bounty_pool")], program.programId);
+    const crank = (race: PublicKey, cranker: Keypair) =>
+      program.methods
+        .crankSettle()
+        .accounts({ race, config: configPda, bountyPool, cranker: cranker.publicKey } as any)
+        .signers([cranker])
+        .rpc();
+
+    before(() =>
+      program.methods
+        .initBountyPool()
+        .accounts({
+          bountyPool,
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc()
+    );
+    after(() => setBounty(0));
+
+    it("Is rejected while the bounty is 0", async () => {
+      await setBounty(0);
+      const race = await createMatchedRace("crank_off");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      try {
+        await crank(race, await fundedKeypair());
+        expect.fail("Expected CrankDisabled error");
+      } catch (err: any) {
+        expect(err.message).to.include("CrankDisabled");
+      }
+    });
+
+    it("Still settles, paying nothing, while the pool is empty", async () => {
+      await setBounty(10000);
+      const race = await createMatchedRace("crank_empty_pool");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      const cranker = await fundedKeypair();
+      const before = await provider.connection.getBalance(cranker.publicKey);
+      await crank(race, cranker);
+
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ settled: {} });
+      expect(await provider.connection.getBalance(cranker.publicKey)).to.equal(before);
+    });
+
+    it("Settles and pays the cranker its bounty from the pool", async () => {
+      await setBounty(10000);
+      await program.methods
+        .fundBountyPool(new anchor.BN(50000))
+        .accounts({
+          bountyPool,
+          funder: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      const race = await createMatchedRace("crank");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      const cranker = await fundedKeypair();
+      const before = await provider.connection.getBalance(cranker.publicKey);
+      const poolBefore = await provider.connection.getBalance(bountyPool);
+      await crank(race, cranker);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ settled: {} });
+      // The prize is untouched
+      expect(state.escrowAmount.toNumber()).to.equal(entryFeeSol.toNumber() * 2);
+      const after = await provider.connection.getBalance(cranker.publicKey);
+      // The provider wallet pays the transaction fee
+      expect(after - before).to.equal(10000);
+      expect(poolBefore - (await provider.connection.getBalance(bountyPool))).to.equal(10000);
+    });
+  });
+
+  describe("max_rematches", () => {
+    const setMaxRematches = (max: number) =>
+      program.methods
+        .setMaxRematches(max)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const optIn = (race: PublicKey, player: Keypair) =>
+      program.methods
+        .setAutoRematch(true)
+        .accounts({ race, config: configPda, player: player.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player])
+        .rpc();
+
+    after(() => setMaxRematches(0));
+
+    it("Rejects opting into a rematch past the cap", async () => {
+      await setMaxRematches(1);
+      const race = await createMatchedRace("rematch_cap");
+      await optIn(race, player1);
+      await optIn(race, player2);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      const { raceId, tokenMint } = await program.account.race.fetch(race);
+      const rematch = deriveRacePda(`${raceId}/r1`, tokenMint);
+      await program.methods
+        .settleRaceWithRematch()
+        .accounts({
+          race,
+          config: configPda,
+          rematchRace: rematch,
+          payer: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      expect((await program.account.race.fetch(rematch)).rematchCount).to.equal(1);
+
+      try {
+        await optIn(rematch, player1);
+        expect.fail("Expected RematchLimitReached error");
+      } catch (err: any) {
+        expect(err.message).to.include("RematchLimitReached");
+      }
+    });
+  });
+
+  describe("entry fee bounds", () => {
+    const setBounds = (min: anchor.BN, max: anchor.BN) =>
+      program.methods
+        .setEntryFeeBounds(min, max)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    after(() => setBounds(new anchor.BN(0), new anchor.BN(0)));
+
+    it("Rejects fresh races outside the bounds", async () => {
+      await setBounds(entryFeeSol.muln(2), entryFeeSol.muln(4));
+      for (const [fee, error] of [
+        [entryFeeSol, "EntryFeeTooLow"],
+        [entryFeeSol.muln(5), "EntryFeeTooHigh"],
+      ] as const) {
+        const id = `race_${Date.now()}_fee_bounds`;
+        const mint = Keypair.generate().publicKey;
+        try {
+          await program.methods
+            .createRace(id, mint, fee)
+            .accounts({ race: deriveRacePda(id, mint), player1: player1.publicKey, systemProgram: SystemProgram.programId })
+            .signers([player1])
+            .rpc();
+          expect.fail(`Expected ${error} error`);
+        } catch (err: any) {
+          expect(err.message).to.include(error);
+        }
+      }
+    });
+
+    it("Rejects a house race outside the bounds", async () => {
+      await setBounds(entryFeeSol.muln(2), entryFeeSol.muln(4));
+      const id = `race_${Date.now()}_house_fee_bounds`;
+      const mint = Keypair.generate().publicKey;
+      try {
+        await program.methods
+          .createHouseRace(id, mint, entryFeeSol, new anchor.BN(45000))
+          .accounts({
+            race: deriveRacePda(id, mint),
+            player1: player1.publicKey,
+            config: configPda,
+            authority: provider.wallet.publicKey,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected EntryFeeTooLow error");
+      } catch (err: any) {
+        expect(err.message).to.include("EntryFeeTooLow");
+      }
+    });
+
+    it("Rejects a rematch below the minimum fee", async () => {
+      const race = await createMatchedRace("rematch_dust");
+      await setBounds(entryFeeSol.muln(2), new anchor.BN(0));
+
+      try {
+        await program.methods
+          .setAutoRematch(true)
+          .accounts({ race, config: configPda, player: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected EntryFeeTooLow error");
+      } catch (err: any) {
+        expect(err.message).to.include("EntryFeeTooLow");
+      }
+    });
+  });
+
+  describe("outcome reason", () => {
+    it("Records why the winner won", async () => {
+      const byTime = await createMatchedRace("outcome_time");
+      await submitDirect(byTime, player1, 40000, 1);
+      await submitDirect(byTime, player2, 50000, 9);
+      await program.methods.settleRace().accounts({ race: byTime }).rpc();
+      expect((await program.account.race.fetch(byTime)).outcome).to.deep.equal({ fasterTime: {} });
+
+      const byCoins = await createMatchedRace("outcome_coins");
+      await submitDirect(byCoins, player1, 40000, 1);
+      await submitDirect(byCoins, player2, 40000, 9);
+      await program.methods.settleRace().accounts({ race: byCoins }).rpc();
+      expect((await program.account.race.fetch(byCoins)).outcome).to.deep.equal({ moreCoins: {} });
+
+      const conceded = await createMatchedRace("outcome_concede");
+      await program.methods
+        .concede()
+        .accounts({ race: conceded, player: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+      expect((await program.account.race.fetch(conceded)).outcome).to.deep.equal({ concession: {} });
+    });
+  });
+
+  describe("claim_prize_split", () => {
+    const claimSplit = (race: PublicKey, bps: number[], destinations: PublicKey[]) =>
+      program.methods
+        .claimPrizeSplit(bps)
+        .accounts({ race, winner: player1.publicKey } as any)
+        .remainingAccounts(destinations.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false })))
+        .signers([player1])
+        .rpc();
+
+    async function settledRace(suffix: string): Promise<PublicKey> {
+      const race = await createMatchedRace(suffix);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      return race;
+    }
+
+    it("Pays a 70/30 split to two wallets", async () => {
+      const race = await settledRace("split");
+      const coach = Keypair.generate().publicKey;
+      const org = Keypair.generate().publicKey;
+
+      await claimSplit(race, [7000, 3000], [coach, org]);
+
+      const pot = entryFeeSol.toNumber() * 2;
+      expect(await provider.connection.getBalance(coach)).to.equal((pot * 7) / 10);
+      expect(await provider.connection.getBalance(org)).to.equal((pot * 3) / 10);
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ claimed: {} });
+    });
+
+    it("Rejects splits that do not add up to 10000 bps", async () => {
+      const race = await settledRace("split_bad");
+      try {
+        await claimSplit(race, [7000, 2000], [Keypair.generate().publicKey, Keypair.generate().publicKey]);
+        expect.fail("Expected InvalidSplit error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidSplit");
+      }
+    });
+  });
+
+  describe("time_quantum_ms", () => {
+    it("Treats times within one quantum as a tie", async () => {
+      const race = await createMatchedRace("quantum", { timeQuantumMs: new anchor.BN(100) });
+      await submitDirect(race, player1, 40010, 1);
+      await submitDirect(race, player2, 40040, 5);
+
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.toString()).to.equal(player2.publicKey.toString());
+      expect(state.outcome).to.deep.equal({ moreCoins: {} });
+      // The stored result keeps the raw time
+      expect(state.player1Result.finishTimeMs.toNumber()).to.equal(40010);
+    });
+  });
+
+  describe("disqualify", () => {
+    const dq = (race: PublicKey, player: PublicKey) =>
+      program.methods
+        .disqualify(player)
+        .accounts({ race, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    it("Awards the race to the opponent of a disqualified player", async () => {
+      const race = await createMatchedRace("dq");
+      await submitDirect(race, player1, 40000, 10);
+      await dq(race, player1.publicKey);
+
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.toString()).to.equal(player2.publicKey.toString());
+      expect(state.outcome).to.deep.equal({ disqualification: {} });
+    });
+
+    it("Voids and refunds a race where both players are disqualified", async () => {
+      const race = await createMatchedRace("dq_both");
+      await dq(race, player1.publicKey);
+      await dq(race, player2.publicKey);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ voided: {} });
+
+      const before1 = await provider.connection.getBalance(player1.publicKey);
+      const before2 = await provider.connection.getBalance(player2.publicKey);
+      await program.methods
+        .refundVoided()
+        .accounts({
+          race,
+          config: configPda,
+          player1: player1.publicKey,
+          player2FeePayer: player2.publicKey,
+          authority: provider.wallet.publicKey,
+        } as any)
+        .rpc();
+
+      expect((await provider.connection.getBalance(player1.publicKey)) - before1).to.equal(entryFeeSol.toNumber());
+      expect((await provider.connection.getBalance(player2.publicKey)) - before2).to.equal(entryFeeSol.toNumber());
+      expect((await program.account.race.fetch(race)).escrowAmount.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("claimable_for", () => {
+    const claimableFor = async (race: PublicKey, player: PublicKey): Promise<number> => {
+      const { raw } = await program.methods.claimableFor(player).accounts({ race } as any).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      return Number(Buffer.from(line.split(" ").pop(), "base64").readBigUInt64LE());
+    };
+
+    it("Reports the winner's claimable escrow and 0 for anyone else", async () => {
+      const race = await createMatchedRace("claimable");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      expect(await claimableFor(race, player1.publicKey)).to.equal(0);
+
+      await program.methods.settleRace().accounts({ race }).rpc();
+      expect(await claimableFor(race, player1.publicKey)).to.equal(entryFeeSol.toNumber() * 2);
+      expect(await claimableFor(race, player2.publicKey)).to.equal(0);
+    });
+
+    it("Reports the prize after the protocol fee", async () => {
+      const setFee = (feeBps: number) =>
+        program.methods
+          .setFee(feeBps, provider.wallet.publicKey)
+          .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+          .rpc();
+      const race = await createMatchedRace("claimable_net");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      await setFee(500);
+      try {
+        const prize = entryFeeSol.toNumber() * 2;
+        expect(await claimableFor(race, player1.publicKey)).to.equal(prize - prize / 20);
+      } finally {
+        await setFee(0);
+      }
+    });
+  });
+
+  describe("NFT-gated races", () => {
+    const METADATA_PROGRAM_ID = new PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+    const collection = Keypair.generate().publicKey;
+
+    async function createGatedRace(suffix: string): Promise<PublicKey> {
+      const id = `race_${Date.now()}_${suffix}`;
+      const mint = Keypair.generate().publicKey;
+      const pda = deriveRacePda(id, mint);
+      await program.methods
+        .createRaceWithConfig(id, mint, entryFeeSol, raceConfig({ nftCollection: collection }))
+        .accounts({
+          race: pda,
+          player1: player1.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([player1])
+        .rpc();
+      return pda;
+    }
+
+    it("rejects join_race on a gated race", async () => {
+      const race = await createGatedRace("gated_plain");
+
+      try {
+        await program.methods
+          .joinRace()
+          .accounts({
+            race,
+            player2: player2.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err: any) {
+        expect(err.message).to.include("NftGateFailed");
+      }
+    });
+
+    it("rejects a joiner who doesn't hold the NFT", async () => {
+      const race = await createGatedRace("gated_empty");
+      const nftMint = await createMint(provider.connection, player2, player2.publicKey, null, 0);
+      const emptyAccount = await createAccount(provider.connection, player2, nftMint, player2.publicKey);
+      const [metadata] = PublicKey.findProgramAddressSync(
+        [Buffer.from("metadata"), METADATA_PROGRAM_ID.toBuffer(), nftMint.toBuffer()],
+        METADATA_PROGRAM_ID
+      );
+
+      try {
+        await program.methods
+          .joinGatedRace()
+          .accounts({
+            race,
+            player2: player2.publicKey,
+            nftTokenAccount: emptyAccount,
+            nftMetadata: metadata,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err: any) {
+        expect(err.message).to.include("NftGateFailed");
+      }
+
+      const account = await program.account.race.fetch(race);
+      expect(account.player2).to.be.null;
+    });
+  });
+
+  describe("token_mint", () => {
+    it("Rejects the all-zero mint", async () => {
+      const id = `race_${Date.now()}_zero_mint`;
+      const race = deriveRacePda(id, PublicKey.default);
+
+      try {
+        await program.methods
+          .createRace(id, PublicKey.default, entryFeeSol)
+          .accounts({
+            race,
+            player1: player1.publicKey,
+            systemProgram: SystemProgram.programId,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected InvalidTokenMint error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidTokenMint");
+      }
+    });
+
+    it("Accepts the native mint for a SOL race", async () => {
+      const id = `race_${Date.now()}_native_mint`;
+      const race = deriveRacePda(id, NATIVE_MINT);
+      await program.methods
+        .createRace(id, NATIVE_MINT, entryFeeSol)
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .signers([player1])
+        .rpc();
+
+      expect((await program.account.race.fetch(race)).tokenMint.equals(NATIVE_MINT)).to.be.true;
+    });
+  });
+
+  describe("submit_result retries", () => {
+    // A distinct instruction list so the retry isn't deduplicated as the same transaction
+    const resubmit = (race: PublicKey, player: Keypair, finishTimeMs: number, coins: number) =>
+      program.methods
+        .submitResult(new anchor.BN(finishTimeMs), new anchor.BN(coins), Array.from(Buffer.alloc(32, 1)))
+        .accounts({
+          race,
+          authority: player.publicKey,
+          session: null,
+          playerWallet: player.publicKey,
+        } as any)
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 300_000 })])
+        .signers([player])
+        .rpc();
+
+    it("Accepts an identical resubmission", async () => {
+      const race = await createMatchedRace("retry_same");
+      await submitDirect(race, player1, 40000, 10);
+      await resubmit(race, player1, 40000, 10);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.player1Result.finishTimeMs.toNumber()).to.equal(40000);
+      expect(state.status).to.deep.equal({ active: {} });
+    });
+
+    it("Rejects a different second result", async () => {
+      const race = await createMatchedRace("retry_conflict");
+      await submitDirect(race, player1, 40000, 10);
+
+      try {
+        await resubmit(race, player1, 39000, 10);
+        expect.fail("Expected ResultAlreadySubmitted error");
+      } catch (err: any) {
+        expect(err.message).to.include("ResultAlreadySubmitted");
+      }
+      expect((await program.account.race.fetch(race)).player1Result.finishTimeMs.toNumber()).to.equal(40000);
+    });
+  });
+
+  describe("lobby late-join surcharge", () => {
+    it("Charges each successive joiner more", async () => {
+      const id = `lobby_${Date.now()}_surcharge`;
+      const lobby = deriveLobbyPda(id);
+      // 10% per seat after the creator's
+      await program.methods
+        .createLobby(id, entryFeeSol, 4, 2, new anchor.BN(0), 1000, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+
+      const joiners = [player2, await fundedKeypair(), await fundedKeypair()];
+      for (const joiner of joiners) {
+        await program.methods
+          .joinLobby()
+          .accounts({ lobby, player: joiner.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([joiner])
+          .rpc();
+      }
+
+      const fee = entryFeeSol.toNumber();
+      const expected = [0, 1, 2, 3].map((seat) => fee + (fee * seat) / 10);
+      const state = await program.account.lobby.fetch(lobby);
+      expect(state.paidFees.map((paid: anchor.BN) => paid.toNumber())).to.deep.equal(expected);
+      expect(state.escrowAmount.toNumber()).to.equal(expected.reduce((a, b) => a + b));
+    });
+  });
+
+  describe("settle_race_mutual", () => {
+    const settleMutual = (race: PublicKey, player1Winner: PublicKey, player2Winner: PublicKey) =>
+      program.methods
+        .settleRaceMutual(player1Winner, player2Winner)
+        .accounts({ race, player1: player1.publicKey, player2: player2.publicKey } as any)
+        .signers([player1, player2])
+        .rpc();
+
+    it("Rejects settle_race on a mutual-settlement race", async () => {
+      const race = await createMatchedRace("mutual_plain", { mutualSettlement: true });
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      try {
+        await program.methods.settleRace().accounts({ race }).rpc();
+        expect.fail("Expected MutualSettlementRequired error");
+      } catch (err: any) {
+        expect(err.message).to.include("MutualSettlementRequired");
+      }
+    });
+
+    it("Settles when both players name the same winner", async () => {
+      const race = await createMatchedRace("mutual_agree", { mutualSettlement: true });
+      await settleMutual(race, player2.publicKey, player2.publicKey);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ settled: {} });
+      expect(state.winner.equals(player2.publicKey)).to.be.true;
+      expect(state.outcome).to.deep.equal({ mutualAgreement: {} });
+    });
+
+    it("Goes to Disputed when the players disagree", async () => {
+      const race = await createMatchedRace("mutual_disagree", { mutualSettlement: true });
+      await settleMutual(race, player1.publicKey, player2.publicKey);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ disputed: {} });
+      expect(state.winner).to.be.null;
+    });
+
+    it("Is rejected on a race without the toggle", async () => {
+      const race = await createMatchedRace("mutual_off");
+
+      try {
+        await settleMutual(race, player1.publicKey, player1.publicKey);
+        expect.fail("Expected MutualSettlementDisabled error");
+      } catch (err: any) {
+        expect(err.message).to.include("MutualSettlementDisabled");
+      }
+    });
+  });
+
+  describe("verify_coins", () => {
+    const sha256 = (...parts: Buffer[]) => createHash("sha256").update(Buffer.concat(parts)).digest();
+    const coinLeaf = (id: number) => {
+      const le = Buffer.alloc(2);
+      le.writeUInt16LE(id);
+      return sha256(Buffer.from("coin"), le);
+    };
+    const pair = (a: Buffer, b: Buffer) => (Buffer.compare(a, b) <= 0 ? sha256(a, b) : sha256(b, a));
+
+    // Four-coin layout: root = pair(pair(c0, c1), pair(c2, c3))
+    const leaves = [0, 1, 2, 3].map(coinLeaf);
+    const left = pair(leaves[0], leaves[1]);
+    const right = pair(leaves[2], leaves[3]);
+    const coinsRoot = Array.from(pair(left, right));
+    const proofs = [
+      [leaves[1], right],
+      [leaves[0], right],
+      [leaves[3], left],
+      [leaves[2], left],
+    ].map((proof) => proof.map((node) => Array.from(node)));
+
+    const verify = (race: PublicKey, player: Keypair, ids: number[]) =>
+      program.methods
+        .verifyCoins(ids, ids.map((id) => proofs[id]))
+        .accounts({ race, player: player.publicKey } as any)
+        .signers([player])
+        .rpc();
+
+    it("Marks a player's coins verified with valid proofs", async () => {
+      const race = await createMatchedRace("coins_ok", { coinsRoot });
+      await submitDirect(race, player1, 40000, 2);
+      await verify(race, player1, [0, 3]);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.player1CoinsVerified).to.be.true;
+      expect(state.status).to.deep.equal({ active: {} });
+    });
+
+    it("Disputes the race when the proofs don't cover the claimed coins", async () => {
+      const race = await createMatchedRace("coins_bad", { coinsRoot });
+      // Claims 5 coins on a 4-coin map
+      await submitDirect(race, player2, 40000, 5);
+      await verify(race, player2, [0, 1, 2, 3]);
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ disputed: {} });
+      expect(state.player2CoinsVerified).to.be.false;
+    });
+
+    it("Rejects a race with no coin layout", async () => {
+      const race = await createMatchedRace("coins_none");
+      await submitDirect(race, player1, 40000, 1);
+
+      try {
+        await verify(race, player1, [0]);
+        expect.fail("Expected CoinProofInvalid error");
+      } catch (err: any) {
+        expect(err.message).to.include("CoinProofInvalid");
+      }
+    });
+  });
+
+  describe("mutual_cancel", () => {
+    const cancel = (race: PublicKey) =>
+      program.methods
+        .mutualCancel()
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          player2: player2.publicKey,
+          feePayer: player2.publicKey,
+        } as any)
+        .signers([player1, player2])
+        .rpc();
+
+    it("Refunds both fees and closes the race", async () => {
+      const race = await createMatchedRace("mutual_cancel");
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        (await provider.connection.getAccountInfo(race)).data.length
+      );
+      const before1 = await provider.connection.getBalance(player1.publicKey);
+      const before2 = await provider.connection.getBalance(player2.publicKey);
+
+      await cancel(race);
+
+      const gained1 = (await provider.connection.getBalance(player1.publicKey)) - before1;
+      const gained2 = (await provider.connection.getBalance(player2.publicKey)) - before2;
+      // The provider wallet pays the transaction fee; player1 also gets the rent back
+      expect(gained2).to.equal(entryFeeSol.toNumber());
+      expect(gained1).to.equal(gained2 + rent);
+      expect(await provider.connection.getAccountInfo(race)).to.be.null;
+    });
+
+    it("Rejects a race with a submitted result", async () => {
+      const race = await createMatchedRace("mutual_cancel_late");
+      await submitDirect(race, player1, 40000, 10);
+
+      try {
+        await cancel(race);
+        expect.fail("Expected CannotLeaveAfterSubmit error");
+      } catch (err: any) {
+        expect(err.message).to.include("CannotLeaveAfterSubmit");
+      }
+    });
+  });
+
+  describe("reconcile_escrow", () => {
+    const reconcile = async (race: PublicKey) => {
+      const { raw } = await program.methods.reconcileEscrow().accounts({ race } as any).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      const data = Buffer.from(line.split(" ").pop(), "base64");
+      const [deposited, paidOut, escrow] = [0, 8, 16].map((offset) => Number(data.readBigUInt64LE(offset)));
+      return { deposited, paidOut, escrow };
+    };
+    // fees_paid follows total_deposited, total_paid_out, escrow_amount, rematch_deposits and lamports
+    const feesPaid = async (race: PublicKey) => {
+      const { raw } = await program.methods.reconcileEscrow().accounts({ race } as any).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      return Number(Buffer.from(line.split(" ").pop(), "base64").readBigUInt64LE(40));
+    };
+
+    it("Balances the entry fees through to the claim, without chat deposits", async () => {
+      const race = await createMatchedRace("reconcile");
+      const spectator = await fundedKeypair();
+      await postChat(race, spectator, Array(32).fill(7));
+
+      const { escrowAmount } = await program.account.race.fetch(race);
+      const deposited = escrowAmount.toNumber();
+      // Two entry fees; the spectator's deposit went to the treasury
+      expect(deposited).to.equal(entryFeeSol.toNumber() * 2);
+      expect(await reconcile(race)).to.deep.equal({ deposited, paidOut: 0, escrow: deposited });
+
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      await program.methods
+        .claimPrize()
+        .accounts({ race, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      expect(await reconcile(race)).to.deep.equal({ deposited, paidOut: deposited, escrow: 0 });
+    });
+
+    it("Reports the fee taken out of the claim on its own line", async () => {
+      const setFee = (feeBps: number, treasury: PublicKey) =>
+        program.methods
+          .setFee(feeBps, treasury)
+          .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+          .rpc();
+      const race = await createMatchedRace("reconcile_fee");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      const { tokenMint } = await program.account.race.fetch(race);
+
+      await setFee(500, provider.wallet.publicKey);
+      try {
+        await program.methods
+          .claimPrize()
+          .accounts({
+            race,
+            authority: player1.publicKey,
+            session: null,
+            winnerWallet: player1.publicKey,
+            config: configPda,
+            tokenConfig: PublicKey.findProgramAddressSync(
+              [Buffer.from("token_config"), tokenMint.toBuffer()],
+              program.programId
+            )[0],
+            treasury: provider.wallet.publicKey,
+          } as any)
+          .signers([player1])
+          .rpc();
+      } finally {
+        await setFee(0, provider.wallet.publicKey);
+      }
+
+      const prize = entryFeeSol.toNumber() * 2;
+      expect(await reconcile(race)).to.deep.equal({ deposited: prize, paidOut: prize, escrow: 0 });
+      expect(await feesPaid(race)).to.equal(prize / 20);
+    });
+  });
+
+  describe("efficiency tiebreak", () => {
+    it("Decides a time tie on coins per millisecond", async () => {
+      const race = await createMatchedRace("efficiency", {
+        tieEpsilonMs: new anchor.BN(50),
+        tiebreak: { efficiency: {} },
+      });
+      // Equal coins inside the epsilon: player2's quicker run is more efficient
+      await submitDirect(race, player1, 40040, 10);
+      await submitDirect(race, player2, 40010, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.equals(player2.publicKey)).to.be.true;
+      expect(state.outcome).to.deep.equal({ efficiency: {} });
+    });
+  });
+
+  describe("winner hook", () => {
+    // Any executable will do for the checks made before the CPI
+    const hook = TOKEN_PROGRAM_ID;
+
+    it("Rejects settle_race on a race with a strict hook", async () => {
+      const race = await createMatchedRace("hook_strict", { winnerHook: hook, winnerHookStrict: true });
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      try {
+        await program.methods.settleRace().accounts({ race }).rpc();
+        expect.fail("Expected WinnerHookRequired error");
+      } catch (err: any) {
+        expect(err.message).to.include("WinnerHookRequired");
+      }
+    });
+
+    it("Rejects a hook program other than the race's", async () => {
+      const race = await createMatchedRace("hook_mismatch", { winnerHook: hook });
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      try {
+        await program.methods
+          .settleRaceWithHook()
+          .accounts({ race, hookProgram: SystemProgram.programId } as any)
+          .rpc();
+        expect.fail("Expected WinnerHookMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("WinnerHookMismatch");
+      }
+    });
+
+    it("Falls back to settle_race for a non-strict hook", async () => {
+      const race = await createMatchedRace("hook_fallback", { winnerHook: hook });
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ settled: {} });
+    });
+  });
+
+  describe("reclaim_orphan_lamports", () => {
+    const configTreasury = async () => (await program.account.config.fetch(configPda)).treasury;
+
+    it("Refuses to sweep a live race", async () => {
+      const race = await createMatchedRace("orphan_live");
+
+      try {
+        await program.methods
+          .reclaimOrphanLamports()
+          .accounts({ orphan: race, config: configPda, authority: provider.wallet.publicKey, treasury: await configTreasury() } as any)
+          .rpc();
+        expect.fail("Expected AccountStillActive error");
+      } catch (err: any) {
+        expect(err.message).to.include("AccountStillActive");
+      }
+    });
+
+    it("Is authority-only", async () => {
+      const race = await createMatchedRace("orphan_auth");
+
+      try {
+        await program.methods
+          .reclaimOrphanLamports()
+          .accounts({ orphan: race, config: configPda, authority: player1.publicKey, treasury: await configTreasury() } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("hash order tiebreak", () => {
+    it("Decides a full tie on the smaller input hash", async () => {
+      const race = await createMatchedRace("hashorder", {
+        tiebreak: { hashOrder: {} },
+      });
+      await submitDirect(race, player1, 40000, 10, 9);
+      await submitDirect(race, player2, 40000, 10, 3);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.equals(player2.publicKey)).to.be.true;
+      expect(state.outcome).to.deep.equal({ hashOrder: {} });
+    });
+  });
+
+  describe("protocol fee", () => {
+    const globalTreasury = Keypair.generate().publicKey;
+    const tokenTreasury = Keypair.generate().publicKey;
+    const setFee = (feeBps: number, treasury: PublicKey) =>
+      program.methods
+        .setFee(feeBps, treasury)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const deriveTokenConfigPda = (mint: PublicKey) =>
+      PublicKey.findProgramAddressSync([Buffer.from("token_config"), mint.toBuffer()], program.programId)[0];
+
+    // player1 wins the race; returns what reached the winner and the treasury
+    async function claimWithFee(race: PublicKey, treasury: PublicKey) {
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const { tokenMint } = await program.account.race.fetch(race);
+      const winnerBefore = await provider.connection.getBalance(player1.publicKey);
+      const treasuryBefore = await provider.connection.getBalance(treasury);
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player1.publicKey,
+          session: null,
+          winnerWallet: player1.publicKey,
+          config: configPda,
+          tokenConfig: deriveTokenConfigPda(tokenMint),
+          treasury,
+        } as any)
+        .signers([player1])
+        .rpc();
+
+      return {
+        // player1 also paid the transaction fee
+        winner: (await provider.connection.getBalance(player1.publicKey)) - winnerBefore + 5000,
+        treasury: (await provider.connection.getBalance(treasury)) - treasuryBefore,
+      };
+    }
+
+    after(() => setFee(0, provider.wallet.publicKey));
+
+    it("Takes the global fee for tokens without a TokenConfig", async () => {
+      await setFee(500, globalTreasury);
+      const race = await createMatchedRace("fee_global");
+
+      const prize = entryFeeSol.toNumber() * 2;
+      const paid = await claimWithFee(race, globalTreasury);
+      expect(paid.treasury).to.equal(prize / 20);
+      expect(paid.winner).to.equal(prize - prize / 20);
+    });
+
+    it("Uses the token's fee and treasury when it has a TokenConfig", async () => {
+      await setFee(500, globalTreasury);
+      const race = await createMatchedRace("fee_token");
+      const { tokenMint } = await program.account.race.fetch(race);
+      await program.methods
+        .initTokenConfig(tokenMint, 100, globalTreasury)
+        .accounts({
+          tokenConfig: deriveTokenConfigPda(tokenMint),
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      await program.methods
+        .updateTokenConfig(1000, tokenTreasury)
+        .accounts({
+          tokenConfig: deriveTokenConfigPda(tokenMint),
+          config: configPda,
+          authority: provider.wallet.publicKey,
+        } as any)
+        .rpc();
+
+      const prize = entryFeeSol.toNumber() * 2;
+      const paid = await claimWithFee(race, tokenTreasury);
+      expect(paid.treasury).to.equal(prize / 10);
+      expect(paid.winner).to.equal(prize - prize / 10);
+    });
+
+    it("Rejects a claim that pays the fee elsewhere", async () => {
+      await setFee(500, globalTreasury);
+      const race = await createMatchedRace("fee_wrong_treasury");
+
+      try {
+        await claimWithFee(race, tokenTreasury);
+        expect.fail("Expected TreasuryMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("TreasuryMismatch");
+      }
+    });
+
+    it("Takes the fee when the prize is split or batch-claimed", async () => {
+      await setFee(500, globalTreasury);
+      const prize = entryFeeSol.toNumber() * 2;
+      const [split, batched] = [await createMatchedRace("fee_split"), await createMatchedRace("fee_batch")];
+      for (const race of [split, batched]) {
+        await submitDirect(race, player1, 40000, 10);
+        await submitDirect(race, player2, 50000, 10);
+        await program.methods.settleRace().accounts({ race }).rpc();
+      }
+      const { tokenMint } = await program.account.race.fetch(split);
+      const feeAccounts = { config: configPda, tokenConfig: deriveTokenConfigPda(tokenMint), treasury: globalTreasury };
+
+      const destination = Keypair.generate().publicKey;
+      const treasuryBefore = await provider.connection.getBalance(globalTreasury);
+      await program.methods
+        .claimPrizeSplit([10000])
+        .accounts({ race: split, winner: player1.publicKey, ...feeAccounts } as any)
+        .remainingAccounts([{ pubkey: destination, isWritable: true, isSigner: false }])
+        .signers([player1])
+        .rpc();
+      expect(await provider.connection.getBalance(destination)).to.equal(prize - prize / 20);
+
+      await program.methods
+        .claimBatch()
+        .accounts({ winner: player1.publicKey, ...feeAccounts } as any)
+        .remainingAccounts([
+          { pubkey: batched, isWritable: true, isSigner: false },
+          { pubkey: player1.publicKey, isWritable: true, isSigner: false },
+        ])
+        .signers([player1])
+        .rpc();
+      expect((await provider.connection.getBalance(globalTreasury)) - treasuryBefore).to.equal(2 * (prize / 20));
+    });
+
+    it("Is admin-only", async () => {
+      try {
+        await program.methods
+          .setFee(500, player1.publicKey)
+          .accounts({ config: configPda, authority: player1.publicKey } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("submission deadline", () => {
+    it("Awards a lone result by forfeit once the window closes", async () => {
+      const race = await createMatchedRace("forfeit", { submissionWindowSecs: new anchor.BN(2) });
+      await submitDirect(race, player1, 40000, 10);
+
+      try {
+        await program.methods.settleRace().accounts({ race }).rpc();
+        expect.fail("Expected ResultsNotComplete error");
+      } catch (err: any) {
+        expect(err.message).to.include("ResultsNotComplete");
+      }
+
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      try {
+        await submitDirect(race, player2, 30000, 10);
+        expect.fail("Expected SubmissionDeadlinePassed error");
+      } catch (err: any) {
+        expect(err.message).to.include("SubmissionDeadlinePassed");
+      }
+
+      await program.methods.settleRace().accounts({ race }).rpc();
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.equals(player1.publicKey)).to.be.true;
+      expect(state.outcome).to.deep.equal({ forfeit: {} });
+    });
+  });
+
+  describe("claim index", () => {
+    it("Indexes a win at settlement and drops it on claim", async () => {
+      const [claimIndex] = PublicKey.findProgramAddressSync(
+        [Buffer.from("claim_index"), player1.publicKey.toBuffer()],
+        program.programId
+      );
+      if ((await provider.connection.getAccountInfo(claimIndex)) === null) {
+        await program.methods
+          .initClaimIndex()
+          .accounts({ claimIndex, player: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player1])
+          .rpc();
+      }
+
+      const race = await createMatchedRace("claim_index");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race, claimIndex } as any).rpc();
+
+      let index = await program.account.claimIndex.fetch(claimIndex);
+      expect(index.races.some((r: PublicKey) => r.equals(race))).to.be.true;
+
+      const { tokenMint } = await program.account.race.fetch(race);
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player1.publicKey,
+          session: null,
+          winnerWallet: player1.publicKey,
+          config: configPda,
+          tokenConfig: PublicKey.findProgramAddressSync(
+            [Buffer.from("token_config"), tokenMint.toBuffer()],
+            program.programId
+          )[0],
+          treasury: null,
+          claimIndex,
+        } as any)
+        .signers([player1])
+        .rpc();
+
+      index = await program.account.claimIndex.fetch(claimIndex);
+      expect(index.races.some((r: PublicKey) => r.equals(race))).to.be.false;
+    });
+
+    it("Drops a win claimed through claim_prize_split", async () => {
+      const [claimIndex] = PublicKey.findProgramAddressSync(
+        [Buffer.from("claim_index"), player1.publicKey.toBuffer()],
+        program.programId
+      );
+      const race = await createMatchedRace("claim_index_split");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race, claimIndex } as any).rpc();
+
+      await program.methods
+        .claimPrizeSplit([10000])
+        .accounts({ race, winner: player1.publicKey, claimIndex } as any)
+        .remainingAccounts([{ pubkey: player1.publicKey, isWritable: true, isSigner: false }])
+        .signers([player1])
+        .rpc();
+
+      const index = await program.account.claimIndex.fetch(claimIndex);
+      expect(index.races.some((r: PublicKey) => r.equals(race))).to.be.false;
+    });
+
+    it("Rejects the loser's index", async () => {
+      const [loserIndex] = PublicKey.findProgramAddressSync(
+        [Buffer.from("claim_index"), player2.publicKey.toBuffer()],
+        program.programId
+      );
+      if ((await provider.connection.getAccountInfo(loserIndex)) === null) {
+        await program.methods
+          .initClaimIndex()
+          .accounts({ claimIndex: loserIndex, player: player2.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player2])
+          .rpc();
+      }
+
+      const race = await createMatchedRace("claim_index_loser");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      try {
+        await program.methods.settleRace().accounts({ race, claimIndex: loserIndex } as any).rpc();
+        expect.fail("Expected ClaimIndexMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("ClaimIndexMismatch");
+      }
+    });
+  });
+
+  describe("per-player deadline", () => {
+    it("Runs each player's submission clock from their own join", async () => {
+      const id = `race_${Date.now()}_async`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRaceWithConfig(id, mint, entryFeeSol, raceConfig({ playerWindowSecs: new anchor.BN(2) }))
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+
+      // player1's window closes before player2 even joins
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player2])
+        .rpc();
+
+      await submitDirect(race, player2, 40000, 10);
+      try {
+        await submitDirect(race, player1, 30000, 10);
+        expect.fail("Expected PlayerDeadlinePassed error");
+      } catch (err: any) {
+        expect(err.message).to.include("PlayerDeadlinePassed");
+      }
+
+      const state = await program.account.race.fetch(race);
+      expect(state.player2Deadline.gt(state.player1Deadline)).to.be.true;
+      expect(state.player2Result).to.not.be.null;
+    });
+  });
+
+  describe("external ref", () => {
+    it("Stores the integrator's match id and echoes it in RaceCreated", async () => {
+      const id = `race_${Date.now()}_ext_ref`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      const externalRef = Array.from(Buffer.alloc(32, 0xab));
+
+      const sig = await program.methods
+        .createRaceWithRef(id, mint, entryFeeSol, raceConfig(), externalRef)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc({ commitment: "confirmed" });
+
+      const state = await program.account.race.fetch(race);
+      expect(Array.from(state.externalRef)).to.deep.equal(externalRef);
+
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+      const event = [...parser.parseLogs(tx.meta.logMessages)].find(
+        (e) => e.name.toLowerCase() === "racecreated"
+      );
+      expect(event).to.not.be.undefined;
+      expect(Array.from(event.data.externalRef)).to.deep.equal(externalRef);
+    });
+  });
+
+  describe("handicap stakes", () => {
+    const player2Fee = entryFeeSol.divn(4);
+
+    it("Refunds each player exactly their own stake on a void", async () => {
+      const race = await createMatchedRace("handicap_void", { player2EntryFee: player2Fee });
+      const state = await program.account.race.fetch(race);
+      expect(state.player1Paid.toString()).to.equal(entryFeeSol.toString());
+      expect(state.player2Paid.toString()).to.equal(player2Fee.toString());
+      expect(state.escrowAmount.toString()).to.equal(entryFeeSol.add(player2Fee).toString());
+
+      for (const player of [player1, player2]) {
+        await program.methods
+          .disqualify(player.publicKey)
+          .accounts({ race, config: configPda, authority: provider.wallet.publicKey } as any)
+          .rpc();
+      }
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const before1 = await provider.connection.getBalance(player1.publicKey);
+      const before2 = await provider.connection.getBalance(player2.publicKey);
+      await program.methods
+        .refundVoided()
+        .accounts({
+          race,
+          config: configPda,
+          player1: player1.publicKey,
+          player2FeePayer: player2.publicKey,
+          authority: provider.wallet.publicKey,
+        } as any)
+        .rpc();
+
+      expect((await provider.connection.getBalance(player1.publicKey)) - before1).to.equal(entryFeeSol.toNumber());
+      expect((await provider.connection.getBalance(player2.publicKey)) - before2).to.equal(player2Fee.toNumber());
+    });
+
+    it("Refunds player2's smaller stake on a mutual cancel", async () => {
+      const race = await createMatchedRace("handicap_cancel", { player2EntryFee: player2Fee });
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        (await provider.connection.getAccountInfo(race)).data.length
+      );
+      const before1 = await provider.connection.getBalance(player1.publicKey);
+      const before2 = await provider.connection.getBalance(player2.publicKey);
+
+      await program.methods
+        .mutualCancel()
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          player2: player2.publicKey,
+          feePayer: player2.publicKey,
+        } as any)
+        .signers([player1, player2])
+        .rpc();
+
+      expect((await provider.connection.getBalance(player1.publicKey)) - before1).to.equal(entryFeeSol.toNumber() + rent);
+      expect((await provider.connection.getBalance(player2.publicKey)) - before2).to.equal(player2Fee.toNumber());
+    });
+  });
+
+  describe("server authority rotation", () => {
+    const oldServer = Keypair.generate();
+    const newServer = Keypair.generate();
+    const rotate = (key: PublicKey, graceSecs: number) =>
+      program.methods
+        .rotateServerAuthority(key, new anchor.BN(graceSecs))
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const submitAttested = (race: PublicKey, player: Keypair, server: Keypair, finishTimeMs: number) =>
+      program.methods
+        .submitResultAttested(new anchor.BN(finishTimeMs), new anchor.BN(10), Array.from(Buffer.alloc(32, 1)), new anchor.BN(0))
+        .accounts({
+          submit: { race, authority: player.publicKey, session: null, playerWallet: player.publicKey },
+          config: configPda,
+          server: server.publicKey,
+        } as any)
+        .signers([player, server])
+        .rpc();
+
+    after(() => rotate(PublicKey.default, 0));
+
+    it("Accepts the old key during the grace period only", async () => {
+      await rotate(oldServer.publicKey, 0);
+      const race = await createMatchedRace("attested", { serverAttested: true });
+
+      try {
+        await submitDirect(race, player1, 40000, 10);
+        expect.fail("Expected AttestationRequired error");
+      } catch (err: any) {
+        expect(err.message).to.include("AttestationRequired");
+      }
+      await submitAttested(race, player1, oldServer, 40000);
+
+      // A result the old key signed before the rotation still lands
+      await rotate(newServer.publicKey, 2);
+      await submitAttested(race, player2, oldServer, 50000);
+
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      const late = await createMatchedRace("attested_late", { serverAttested: true });
+      try {
+        await submitAttested(late, player1, oldServer, 40000);
+        expect.fail("Expected UnknownServerAuthority error");
+      } catch (err: any) {
+        expect(err.message).to.include("UnknownServerAuthority");
+      }
+      await submitAttested(late, player1, newServer, 40000);
+    });
+  });
+
+  describe("coin tiebreak direction", () => {
+    it("Awards a time tie to fewer coins when fewer_coins_win is set", async () => {
+      const race = await createMatchedRace("fewer_coins", { fewerCoinsWin: true });
+      await submitDirect(race, player1, 40000, 9);
+      await submitDirect(race, player2, 40000, 2);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.winner.equals(player2.publicKey)).to.be.true;
+      expect(state.outcome).to.deep.equal({ fewerCoins: {} });
+    });
+
+    it("Keeps more coins winning by default", async () => {
+      const race = await createMatchedRace("more_coins");
+      await submitDirect(race, player1, 40000, 9);
+      await submitDirect(race, player2, 40000, 2);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      expect((await program.account.race.fetch(race)).winner.equals(player1.publicKey)).to.be.true;
+    });
+  });
+
+  describe("race authority", () => {
+    // Any key other than the one the race was created under
+    const otherOperator = Keypair.generate();
+
+    it("Rejects another operator acting on a race it didn't create", async () => {
+      const race = await createMatchedRace("race_authority");
+      expect((await program.account.race.fetch(race)).authority.equals(provider.wallet.publicKey)).to.be.true;
+
+      try {
+        await program.methods
+          .disqualify(player1.publicKey)
+          .accounts({ race, authority: otherOperator.publicKey } as any)
+          .signers([otherOperator])
+          .rpc();
+        expect.fail("Expected WrongAuthority error");
+      } catch (err: any) {
+        expect(err.message).to.include("WrongAuthority");
+      }
+
+      // The operator it was created under still can
+      await program.methods
+        .disqualify(player1.publicKey)
+        .accounts({ race, authority: provider.wallet.publicKey } as any)
+        .rpc();
+      expect((await program.account.race.fetch(race)).player1Dq).to.be.true;
+    });
+
+    it("Rejects another operator settling a race it didn't create", async () => {
+      const race = await createMatchedRace("race_authority_settle");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+
+      try {
+        await program.methods
+          .settleRace()
+          .accounts({ race, authority: otherOperator.publicKey } as any)
+          .signers([otherOperator])
+          .rpc();
+        expect.fail("Expected WrongAuthority error");
+      } catch (err: any) {
+        expect(err.message).to.include("WrongAuthority");
+      }
+
+      await program.methods.settleRace().accounts({ race, authority: provider.wallet.publicKey } as any).rpc();
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ settled: {} });
+    });
+  });
+
+  describe("account upgrades", () => {
+    it("Leaves a current race as is on realloc_race", async () => {
+      const race = await createMatchedRace("realloc_race");
+      const before = await provider.connection.getAccountInfo(race);
+
+      await program.methods
+        .reallocRace()
+        .accounts({ race, payer: provider.wallet.publicKey } as any)
+        .rpc();
+
+      const after = await provider.connection.getAccountInfo(race);
+      expect(after!.data.length).to.equal(before!.data.length);
+      expect(after!.lamports).to.equal(before!.lamports);
+      expect((await program.account.race.fetch(race)).version).to.equal(2);
+    });
+  });
+
+  describe("race credits", () => {
+    const creditsPda = (player: PublicKey) =>
+      PublicKey.findProgramAddressSync([Buffer.from("credits"), player.toBuffer()], program.programId)[0];
+
+    before(async () => {
+      for (const player of [player1, player2]) {
+        await program.methods
+          .initCreditAccount()
+          .accounts({ credits: creditsPda(player.publicKey), player: player.publicKey } as any)
+          .signers([player])
+          .rpc();
+      }
+      await program.methods
+        .buyCredits(entryFeeSol.muln(2))
+        .accounts({ credits: creditsPda(player1.publicKey), player: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+    });
+
+    it("Funds both entries from credits and pays the winner out of them", async () => {
+      await program.methods
+        .buyCredits(entryFeeSol)
+        .accounts({ credits: creditsPda(player2.publicKey), player: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+
+      const id = `race_${Date.now()}_credits`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      const player1Before = await provider.connection.getBalance(player1.publicKey);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, credits: creditsPda(player1.publicKey) } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, credits: creditsPda(player2.publicKey) } as any)
+        .signers([player2])
+        .rpc();
+
+      expect((await program.account.creditAccount.fetch(creditsPda(player1.publicKey))).balance.eq(entryFeeSol)).to.be.true;
+      expect((await program.account.creditAccount.fetch(creditsPda(player2.publicKey))).balance.toNumber()).to.equal(0);
+      const state = await program.account.race.fetch(race);
+      expect(state.escrowAmount.eq(entryFeeSol.muln(2))).to.be.true;
+      expect(state.player2FeePayer.equals(player2.publicKey)).to.be.true;
+      // Player1's wallet only paid the race rent, not the fee
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        (await provider.connection.getAccountInfo(race))!.data.length
+      );
+      expect(player1Before - (await provider.connection.getBalance(player1.publicKey))).to.be.at.most(rent + 10_000);
+
+      await submitDirect(race, player1, 30000, 5);
+      await submitDirect(race, player2, 40000, 5);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const winnerBefore = await provider.connection.getBalance(player1.publicKey);
+      await program.methods
+        .claimPrize()
+        .accounts({ race, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      expect((await provider.connection.getBalance(player1.publicKey)) - winnerBefore).to.be.above(
+        entryFeeSol.muln(2).toNumber() - 10_000
+      );
+    });
+
+    it("Rejects a credit-funded join without enough credits", async () => {
+      const id = `race_${Date.now()}_no_credits`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      try {
+        await program.methods
+          .joinRace()
+          .accounts({ race, player2: player2.publicKey, credits: creditsPda(player2.publicKey) } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Expected InsufficientCredits error");
+      } catch (err: any) {
+        expect(err.message).to.include("InsufficientCredits");
+      }
+    });
+  });
+
+  describe("global stats", () => {
+    const globalStats = globalStatsPda;
+    const readStats = async () => {
+      const { raw } = await program.methods.readGlobalStats().accounts({ globalStats } as any).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      const data = Buffer.from(line.split(" ").pop(), "base64");
+      const [races, settled, volume, fees] = [0, 8, 16, 24].map((offset) => Number(data.readBigUInt64LE(offset)));
+      return { races, settled, volume, fees };
+    };
+
+    it("Counts escrowed volume across create, join and settle", async () => {
+      const before = await readStats();
+      const id = `race_${Date.now()}_stats`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, globalStats } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, globalStats } as any)
+        .signers([player2])
+        .rpc();
+      await submitDirect(race, player1, 30000, 5);
+      await submitDirect(race, player2, 40000, 5);
+      await program.methods.settleRace().accounts({ race, globalStats } as any).rpc();
+
+      const after = await readStats();
+      expect(after.races - before.races).to.equal(1);
+      expect(after.settled - before.settled).to.equal(1);
+      expect(after.volume - before.volume).to.equal(
+        (await program.account.race.fetch(race)).escrowAmount.toNumber()
+      );
+      expect(after.volume - before.volume).to.equal(entryFeeSol.toNumber() * 2);
+    });
+
+    it("Counts a settlement by concession", async () => {
+      const before = await readStats();
+      const race = await createMatchedRace("stats_concede");
+      await program.methods
+        .concede()
+        .accounts({ race, player: player2.publicKey, globalStats } as any)
+        .signers([player2])
+        .rpc();
+
+      const after = await readStats();
+      expect(after.settled - before.settled).to.equal(1);
+    });
+
+    it("Refuses a join without the stats account", async () => {
+      const id = `race_${Date.now()}_stats_missing`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      try {
+        await program.methods
+          .joinRace()
+          .accounts({ race, player2: player2.publicKey, globalStats: Keypair.generate().publicKey } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Should have thrown");
+      } catch (err: any) {
+        expect(err.message).to.include("AccountNotInitialized");
+      }
+      expect((await program.account.race.fetch(race)).player2).to.be.null;
+    });
+  });
+
+  describe("claim_and_create", () => {
+    const tokenConfigPda = (mint: PublicKey) =>
+      PublicKey.findProgramAddressSync([Buffer.from("token_config"), mint.toBuffer()], program.programId)[0];
+
+    async function wonRace(suffix: string) {
+      const race = await createMatchedRace(suffix);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      return { race, tokenMint: (await program.account.race.fetch(race)).tokenMint };
+    }
+
+    const claimAndCreate = (race: PublicKey, tokenMint: PublicKey, id: string, fee: anchor.BN) =>
+      program.methods
+        .claimAndCreate(id, tokenMint, fee, raceConfig())
+        .accounts({
+          race,
+          newRace: deriveRacePda(id, tokenMint),
+          winner: player1.publicKey,
+          config: configPda,
+          tokenConfig: tokenConfigPda(tokenMint),
+          treasury: null,
+        } as any)
+        .signers([player1])
+        .rpc();
+
+    it("Rolls part of the prize into a new race and pays out the rest", async () => {
+      const { race, tokenMint } = await wonRace("compound");
+      const id = `race_${Date.now()}_compounded`;
+      const winnerBefore = await provider.connection.getBalance(player1.publicKey);
+
+      await claimAndCreate(race, tokenMint, id, entryFeeSol);
+
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ claimed: {} });
+      const compounded = await program.account.race.fetch(deriveRacePda(id, tokenMint));
+      expect(compounded.player1.equals(player1.publicKey)).to.be.true;
+      expect(compounded.status).to.deep.equal({ waiting: {} });
+      expect(compounded.escrowAmount.eq(entryFeeSol)).to.be.true;
+      expect(compounded.player1Paid.eq(entryFeeSol)).to.be.true;
+
+      // The other half of the prize, less the new race's rent and the tx fee
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        (await provider.connection.getAccountInfo(deriveRacePda(id, tokenMint)))!.data.length
+      );
+      expect((await provider.connection.getBalance(player1.publicKey)) - winnerBefore).to.equal(
+        entryFeeSol.toNumber() - rent - 5000
+      );
+    });
+
+    it("Rejects a stake larger than the prize", async () => {
+      const { race, tokenMint } = await wonRace("compound_over");
+      try {
+        await claimAndCreate(race, tokenMint, `race_${Date.now()}_over`, entryFeeSol.muln(3));
+        expect.fail("Expected CompoundExceedsPrize error");
+      } catch (err: any) {
+        expect(err.message).to.include("CompoundExceedsPrize");
+      }
+    });
+  });
+
+  describe("claim errors", () => {
+    const claim = (race: PublicKey, claimant: Keypair) =>
+      program.methods
+        .claimPrize()
+        .accounts({ race, authority: claimant.publicKey, session: null, winnerWallet: claimant.publicKey } as any)
+        .signers([claimant])
+        .rpc();
+
+    it("Rejects a claim on a race that hasn't settled with RaceNotSettledYet", async () => {
+      const race = await createMatchedRace("claim_unsettled");
+      await submitDirect(race, player1, 40000, 10);
+      try {
+        await claim(race, player1);
+        expect.fail("Expected RaceNotSettledYet error");
+      } catch (err: any) {
+        expect(err.message).to.include("RaceNotSettledYet");
+      }
+    });
+
+    it("Rejects the loser of a settled race with NotWinner", async () => {
+      const race = await createMatchedRace("claim_loser");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      try {
+        await claim(race, player2);
+        expect.fail("Expected NotWinner error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotWinner");
+      }
+    });
+  });
+
+  describe("token pause", () => {
+    const pausedMint = Keypair.generate().publicKey;
+    const tokenConfig = PublicKey.findProgramAddressSync(
+      [Buffer.from("token_config"), pausedMint.toBuffer()],
+      program.programId
+    )[0];
+    const setPaused = (paused: boolean) =>
+      program.methods
+        .setTokenPaused(paused)
+        .accounts({ tokenConfig, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const create = (id: string, mint: PublicKey) =>
+      program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race: deriveRacePda(id, mint), player1: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+    before(async () => {
+      await program.methods
+        .initTokenConfig(pausedMint, 0, provider.wallet.publicKey)
+        .accounts({ tokenConfig, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    });
+
+    it("Blocks new races and joins on the paused mint only", async () => {
+      const waitingId = `race_${Date.now()}_pause_waiting`;
+      await create(waitingId, pausedMint);
+      await setPaused(true);
+
+      try {
+        await create(`race_${Date.now()}_paused`, pausedMint);
+        expect.fail("Expected TokenPaused error");
+      } catch (err: any) {
+        expect(err.message).to.include("TokenPaused");
+      }
+      try {
+        const raceId = `race_${Date.now()}_paused_batch`;
+        await program.methods
+          .createRaceBatch([{ raceId, tokenMint: pausedMint, entryFeeSol, config: raceConfig() }])
+          .accounts({ creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+          .remainingAccounts([
+            { pubkey: deriveRacePda(raceId, pausedMint), isWritable: true, isSigner: false },
+            { pubkey: tokenConfig, isWritable: false, isSigner: false },
+          ])
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected TokenPaused error");
+      } catch (err: any) {
+        expect(err.message).to.include("TokenPaused");
+      }
+      try {
+        await program.methods
+          .joinRace()
+          .accounts({ race: deriveRacePda(waitingId, pausedMint), player2: player2.publicKey } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Expected TokenPaused error");
+      } catch (err: any) {
+        expect(err.message).to.include("TokenPaused");
+      }
+
+      // Other tokens are unaffected
+      await createMatchedRace("pause_other_mint");
+
+      await setPaused(false);
+      await create(`race_${Date.now()}_resumed`, pausedMint);
+    });
+
+    it("Blocks gated joins and house races on the paused mint", async () => {
+      const METADATA_PROGRAM_ID = new PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+      const gatedId = `race_${Date.now()}_pause_gated`;
+      const gatedRace = deriveRacePda(gatedId, pausedMint);
+      await program.methods
+        .createRaceWithConfig(gatedId, pausedMint, entryFeeSol, raceConfig({ nftCollection: Keypair.generate().publicKey }))
+        .accounts({ race: gatedRace, player1: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+      const nftMint = await createMint(provider.connection, player2, player2.publicKey, null, 0);
+      const nftTokenAccount = await createAccount(provider.connection, player2, nftMint, player2.publicKey);
+      const [nftMetadata] = PublicKey.findProgramAddressSync(
+        [Buffer.from("metadata"), METADATA_PROGRAM_ID.toBuffer(), nftMint.toBuffer()],
+        METADATA_PROGRAM_ID
+      );
+
+      await setPaused(true);
+      try {
+        try {
+          await program.methods
+            .joinGatedRace()
+            .accounts({ race: gatedRace, player2: player2.publicKey, nftTokenAccount, nftMetadata } as any)
+            .signers([player2])
+            .rpc();
+          expect.fail("Expected TokenPaused error");
+        } catch (err: any) {
+          expect(err.message).to.include("TokenPaused");
+        }
+        try {
+          const houseId = `race_${Date.now()}_pause_house`;
+          await program.methods
+            .createHouseRace(houseId, pausedMint, entryFeeSol, new anchor.BN(45000))
+            .accounts({
+              race: deriveRacePda(houseId, pausedMint),
+              player1: player1.publicKey,
+              config: configPda,
+              authority: provider.wallet.publicKey,
+            } as any)
+            .signers([player1])
+            .rpc();
+          expect.fail("Expected TokenPaused error");
+        } catch (err: any) {
+          expect(err.message).to.include("TokenPaused");
+        }
+      } finally {
+        await setPaused(false);
+      }
+    });
+  });
+
+  describe("committed map seed", () => {
+    const reveal = (race: PublicKey) =>
+      program.methods
+        .revealMapSeed()
+        .accounts({ race, slotHashes: anchor.web3.SYSVAR_SLOT_HASHES_PUBKEY } as any)
+        .rpc();
+
+    it("Refuses results until the committed blockhash is revealed", async () => {
+      const id = `race_${Date.now()}_seed_commit`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRaceWithSeedCommit(id, mint, entryFeeSol, raceConfig({ mapSeed: new anchor.BN(42) }), Array(32).fill(0), new anchor.BN(5))
+        .accounts({ race, player1: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+
+      let state = await program.account.race.fetch(race);
+      expect(state.mapSeedSlot.toNumber()).to.be.above(0);
+      expect(state.config.mapSeed.toNumber()).to.equal(0);
+
+      try {
+        await reveal(race);
+        expect.fail("Expected MapSeedNotReady error");
+      } catch (err: any) {
+        expect(err.message).to.include("MapSeedNotReady");
+      }
+      try {
+        await submitDirect(race, player1, 40000, 10);
+        expect.fail("Expected MapSeedNotRevealed error");
+      } catch (err: any) {
+        expect(err.message).to.include("MapSeedNotRevealed");
+      }
+
+      while ((await provider.connection.getSlot()) <= state.mapSeedSlot.toNumber()) {
+        await new Promise((resolve) => setTimeout(resolve, 400));
+      }
+      await reveal(race);
+
+      state = await program.account.race.fetch(race);
+      expect(state.mapSeedRevealed).to.be.true;
+      expect(state.config.mapSeed.toNumber()).to.not.equal(0);
+
+      await program.methods
+        .submitResultOnMap(new anchor.BN(40000), new anchor.BN(10), Array(32).fill(1), state.config.mapSeed)
+        .accounts({ race, authority: player1.publicKey, session: null, playerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+    });
+  });
+
+  describe("featured races", () => {
+    // Race::FEATURED_OFFSET: the account's last byte, past every serialized field
+    const FEATURED_OFFSET = 826;
+    const isFeatured = async (race: PublicKey) =>
+      (await provider.connection.getAccountInfo(race))!.data[FEATURED_OFFSET] === 1;
+    const setFeatured = (race: PublicKey, featured: boolean, signer?: Keypair) =>
+      program.methods
+        .setFeatured(featured)
+        .accounts({ race, authority: signer?.publicKey ?? provider.wallet.publicKey } as any)
+        .signers(signer ? [signer] : [])
+        .rpc();
+
+    it("Lets only the race's authority feature it", async () => {
+      const race = await createMatchedRace("featured_auth");
+      try {
+        await setFeatured(race, true, player1);
+        expect.fail("Expected WrongAuthority error");
+      } catch (err: any) {
+        expect(err.message).to.include("WrongAuthority");
+      }
+
+      await setFeatured(race, true);
+      expect(await isFeatured(race)).to.be.true;
+      // The flag leaves the serialized race alone
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ active: {} });
+    });
+
+    it("Can be listed with a memcmp filter on featured", async () => {
+      const featured = await createMatchedRace("featured_listed");
+      const plain = await createMatchedRace("featured_plain");
+      await setFeatured(featured, true);
+
+      const keys = (
+        await program.account.race.all([
+          { memcmp: { offset: FEATURED_OFFSET, bytes: anchor.utils.bytes.bs58.encode([1]) } },
+        ])
+      ).map((r) => r.publicKey.toString());
+      expect(keys).to.include(featured.toString());
+      expect(keys).to.not.include(plain.toString());
+
+      await setFeatured(featured, false);
+      expect(await isFeatured(featured)).to.be.false;
+    });
+  });
+
+  describe("registered maps", () => {
+    const mapSeed = new anchor.BN(Date.now());
+    const mapConfig = PublicKey.findProgramAddressSync(
+      [Buffer.from("map_config"), mapSeed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    const submitCoins = (race: PublicKey, player: Keypair, coins: number) =>
+      program.methods
+        .submitResultOnMap(new anchor.BN(40000), new anchor.BN(coins), Array(32).fill(1), mapSeed)
+        .accounts({ race, authority: player.publicKey, session: null, playerWallet: player.publicKey, mapConfig } as any)
+        .signers([player])
+        .rpc();
+
+    before(async () => {
+      await program.methods
+        .registerMap(mapSeed, new anchor.BN(10))
+        .accounts({ mapConfig, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    });
+
+    it("Caps coins at the registered map's count", async () => {
+      const race = await createMatchedRace("registered_map", { mapSeed });
+      try {
+        await submitCoins(race, player1, 11);
+        expect.fail("Expected CoinsExceedMapMax error");
+      } catch (err: any) {
+        expect(err.message).to.include("CoinsExceedMapMax");
+      }
+
+      await submitCoins(race, player1, 10);
+      expect((await program.account.race.fetch(race)).player1Result.coinsCollected.toNumber()).to.equal(10);
+    });
+
+    it("Only lets the authority register maps", async () => {
+      const seed = new anchor.BN(Date.now() + 1);
+      try {
+        await program.methods
+          .registerMap(seed, new anchor.BN(10))
+          .accounts({
+            mapConfig: PublicKey.findProgramAddressSync(
+              [Buffer.from("map_config"), seed.toArrayLike(Buffer, "le", 8)],
+              program.programId
+            )[0],
+            config: configPda,
+            authority: player1.publicKey,
+          } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("create_and_join", () => {
+    it("Starts an arranged match Active with both fees escrowed", async () => {
+      const id = `race_${Date.now()}_arranged`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+
+      await program.methods
+        .createAndJoin(id, mint, entryFeeSol, raceConfig({}))
+        .accounts({
+          race,
+          player1: player1.publicKey,
+          player2: player2.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .signers([player1, player2])
+        .rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ active: {} });
+      expect(state.player1.toString()).to.equal(player1.publicKey.toString());
+      expect(state.player2!.toString()).to.equal(player2.publicKey.toString());
+      expect(state.escrowAmount.toString()).to.equal(entryFeeSol.mul(new anchor.BN(2)).toString());
+      expect(state.player2Paid.toString()).to.equal(entryFeeSol.toString());
+    });
+  });
+
+  describe("submit rate limit", () => {
+    it("Rejects a second submission in the same slot", async () => {
+      const race = await createMatchedRace("rate_limit");
+      const submit = () =>
+        program.methods
+          .submitResult(new anchor.BN(40000), new anchor.BN(10), Array.from(Buffer.alloc(32, 1)))
+          .accounts({
+            race,
+            authority: player1.publicKey,
+            session: null,
+            playerWallet: player1.publicKey,
+          } as any)
+          .signers([player1]);
+
+      // Both instructions in one transaction always share a slot
+      try {
+        await submit()
+          .postInstructions([await submit().instruction()])
+          .rpc();
+        expect.fail("Expected SubmitRateLimited error");
+      } catch (err: any) {
+        expect(err.message).to.include("SubmitRateLimited");
+      }
+      expect((await program.account.race.fetch(race)).player1Result).to.be.null;
+
+      await submit().rpc();
+      expect((await program.account.race.fetch(race)).player1Result.finishTimeMs.toNumber()).to.equal(40000);
+    });
+  });
+
+  describe("host cut", () => {
+    const treasury = Keypair.generate().publicKey;
+    const setHostCut = (hostCutBps: number) =>
+      program.methods
+        .setHostCut(hostCutBps)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const setFee = (feeBps: number) =>
+      program.methods
+        .setFee(feeBps, treasury)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    after(async () => {
+      await setHostCut(0);
+      await setFee(0);
+    });
+
+    it("Pays the creator a cut when player2 wins", async () => {
+      await setFee(500);
+      await setHostCut(200);
+      const race = await createMatchedRace("host_cut");
+      await submitDirect(race, player1, 50000, 10);
+      await submitDirect(race, player2, 40000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const { tokenMint } = await program.account.race.fetch(race);
+      const balances = () =>
+        Promise.all([player1.publicKey, player2.publicKey, treasury].map((key) => provider.connection.getBalance(key)));
+      const before = await balances();
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player2.publicKey,
+          session: null,
+          winnerWallet: player2.publicKey,
+          config: configPda,
+          tokenConfig: PublicKey.findProgramAddressSync(
+            [Buffer.from("token_config"), tokenMint.toBuffer()],
+            program.programId
+          )[0],
+          treasury,
+          host: player1.publicKey,
+        } as any)
+        .signers([player2])
+        .rpc();
+      const after = await balances();
+
+      const prize = entryFeeSol.toNumber() * 2;
+      const host = after[0] - before[0];
+      // player2 also paid the transaction fee
+      const winner = after[1] - before[1] + 5000;
+      const fee = after[2] - before[2];
+      expect(fee).to.equal(prize / 20);
+      expect(host).to.equal(prize / 50);
+      expect(fee + host + winner).to.equal(prize);
+    });
+
+    it("Pays the cut on a split claim too", async () => {
+      await setHostCut(200);
+      const race = await createMatchedRace("host_cut_split");
+      await submitDirect(race, player1, 50000, 10);
+      await submitDirect(race, player2, 40000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const destination = Keypair.generate().publicKey;
+      const hostBefore = await provider.connection.getBalance(player1.publicKey);
+      await program.methods
+        .claimPrizeSplit([10000])
+        .accounts({ race, winner: player2.publicKey, host: player1.publicKey } as any)
+        .remainingAccounts([{ pubkey: destination, isWritable: true, isSigner: false }])
+        .signers([player2])
+        .rpc();
+
+      const prize = entryFeeSol.toNumber() * 2;
+      expect((await provider.connection.getBalance(player1.publicKey)) - hostBefore).to.equal(prize / 50);
+      expect(await provider.connection.getBalance(destination)).to.equal(prize - prize / 50);
+    });
+
+    it("Shrinks the cut to what a higher token fee leaves", async () => {
+      await setFee(0);
+      const race = await createMatchedRace("host_cut_token_fee");
+      await submitDirect(race, player1, 50000, 10);
+      await submitDirect(race, player2, 40000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const { tokenMint } = await program.account.race.fetch(race);
+      const tokenConfig = PublicKey.findProgramAddressSync(
+        [Buffer.from("token_config"), tokenMint.toBuffer()],
+        program.programId
+      )[0];
+      await program.methods
+        .initTokenConfig(tokenMint, 9000, treasury)
+        .accounts({
+          tokenConfig,
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      // Only checked against the global fee, so it passes
+      await setHostCut(2000);
+
+      const hostBefore = await provider.connection.getBalance(player1.publicKey);
+      const treasuryBefore = await provider.connection.getBalance(treasury);
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player2.publicKey,
+          session: null,
+          winnerWallet: player2.publicKey,
+          config: configPda,
+          tokenConfig,
+          treasury,
+          host: player1.publicKey,
+        } as any)
+        .signers([player2])
+        .rpc();
+
+      const prize = entryFeeSol.toNumber() * 2;
+      expect((await provider.connection.getBalance(treasury)) - treasuryBefore).to.equal((prize * 9) / 10);
+      expect((await provider.connection.getBalance(player1.publicKey)) - hostBefore).to.equal(prize / 10);
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ claimed: {} });
+    });
+
+    it("Refuses a host cut that with the fee exceeds the prize", async () => {
+      await setFee(9000);
+      try {
+        await setHostCut(1001);
+        expect.fail("Expected FeesExceedPrize error");
+      } catch (err: any) {
+        expect(err.message).to.include("FeesExceedPrize");
+      }
+      await setFee(0);
+    });
+  });
+
+  describe("race_standings", () => {
+    // Borsh Vec<Standing>: player, Option<RaceResult>, Option<u8> place
+    function parseStandings(data: Buffer) {
+      const standings: { player: PublicKey; finishTimeMs: number | null; place: number | null }[] = [];
+      let offset = 4;
+      for (let i = 0; i < data.readUInt32LE(0); i++) {
+        const player = new PublicKey(data.subarray(offset, offset + 32));
+        offset += 32;
+        let finishTimeMs: number | null = null;
+        if (data[offset++] === 1) {
+          finishTimeMs = Number(data.readBigUInt64LE(offset));
+          offset += 48;
+        }
+        const place = data[offset++] === 1 ? data[offset++] : null;
+        standings.push({ player, finishTimeMs, place });
+      }
+      return standings;
+    }
+
+    it("Ranks submitted results and leaves pending players unranked", async () => {
+      const extra = await fundedKeypair();
+      const id = `lobby_${Date.now()}_standings`;
+      const lobby = deriveLobbyPda(id);
+      await program.methods
+        .createLobby(id, entryFeeSol, 3, 3, new anchor.BN(0), 0, raceConfig())
+        .accounts({ lobby, creator: player1.publicKey, systemProgram: SystemProgram.programId } as any)
+        .signers([player1])
+        .rpc();
+      for (const player of [player2, extra]) {
+        await program.methods
+          .joinLobby()
+          .accounts({ lobby, player: player.publicKey, systemProgram: SystemProgram.programId } as any)
+          .signers([player])
+          .rpc();
+      }
+      for (const [player, time] of [[player1, 52000], [extra, 48000]] as [Keypair, number][]) {
+        await program.methods
+          .submitLobbyResult(new anchor.BN(time), new anchor.BN(5), Array.from(Buffer.alloc(32, 1)))
+          .accounts({ lobby, player: player.publicKey } as any)
+          .signers([player])
+          .rpc();
+      }
+
+      const { raw } = await program.methods.raceStandings().accounts({ lobby } as any).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      const standings = parseStandings(Buffer.from(line.split(" ").pop(), "base64"));
+
+      expect(standings.map((s) => s.player.toString())).to.deep.equal(
+        [player1, player2, extra].map((p) => p.publicKey.toString())
+      );
+      expect(standings.map((s) => s.place)).to.deep.equal([2, null, 1]);
+      expect(standings.map((s) => s.finishTimeMs)).to.deep.equal([52000, null, 48000]);
+    });
+  });
+
+  describe("close_race", () => {
+    it("Returns the rent to the creator even when player2 wins", async () => {
+      const race = await createMatchedRace("close_rent");
+      await submitDirect(race, player1, 50000, 10);
+      await submitDirect(race, player2, 40000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      try {
+        await program.methods.closeRace().accounts({ race, rentPayer: player1.publicKey } as any).rpc();
+        expect.fail("Expected InvalidRaceStatus error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidRaceStatus");
+      }
+
+      const { tokenMint } = await program.account.race.fetch(race);
+      await program.methods
+        .claimPrize()
+        .accounts({
+          race,
+          authority: player2.publicKey,
+          session: null,
+          winnerWallet: player2.publicKey,
+          config: configPda,
+          tokenConfig: PublicKey.findProgramAddressSync(
+            [Buffer.from("token_config"), tokenMint.toBuffer()],
+            program.programId
+          )[0],
+        } as any)
+        .signers([player2])
+        .rpc();
+
+      try {
+        await program.methods.closeRace().accounts({ race, rentPayer: player2.publicKey } as any).rpc();
+        expect.fail("Expected RentPayerMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("RentPayerMismatch");
+      }
+
+      const rent = await provider.connection.getBalance(race);
+      const creatorBefore = await provider.connection.getBalance(player1.publicKey);
+      const winnerBefore = await provider.connection.getBalance(player2.publicKey);
+      await program.methods.closeRace().accounts({ race, rentPayer: player1.publicKey } as any).rpc();
+
+      expect(await provider.connection.getAccountInfo(race)).to.be.null;
+      expect(await provider.connection.getBalance(player1.publicKey)).to.equal(creatorBefore + rent);
+      expect(await provider.connection.getBalance(player2.publicKey)).to.equal(winnerBefore);
+    });
+  });
+
+  describe("race_id charset", () => {
+    it("Rejects ids outside letters, digits, dash and underscore", async () => {
+      for (const id of [`race ${Date.now()}`, `race_${Date.now()}\n`, `race/${Date.now()}`]) {
+        const mint = Keypair.generate().publicKey;
+        try {
+          await program.methods
+            .createRace(id, mint, entryFeeSol)
+            .accounts({
+              race: deriveRacePda(id, mint),
+              player1: player1.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .signers([player1])
+            .rpc();
+          expect.fail("Expected InvalidRaceId error");
+        } catch (err: any) {
+          expect(err.message).to.include("InvalidRaceId");
+        }
+      }
+    });
+
+    it("Accepts ids made of the allowed charset", async () => {
+      const id = `race-${Date.now()}_OK`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+      expect((await program.account.race.fetch(race)).raceId).to.equal(id);
+    });
+  });
+
+  describe("escrow lock", () => {
+    it("Rejects a chat deposit once the race is settled", async () => {
+      const race = await createMatchedRace("escrow_lock");
+      const spectator = await fundedKeypair();
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      const { escrowAmount } = await program.account.race.fetch(race);
+
+      try {
+        await postChat(race, spectator, Array(32).fill(9));
+        expect.fail("Expected EscrowLocked error");
+      } catch (err: any) {
+        expect(err.message).to.include("EscrowLocked");
+      }
+      expect((await program.account.race.fetch(race)).escrowAmount.toString()).to.equal(escrowAmount.toString());
+    });
+  });
+
+  describe("no-contest", () => {
+    const setNoContest = (enabled: boolean) =>
+      program.methods
+        .setNoContestOnNoShow(enabled)
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const declare = (race: PublicKey) =>
+      program.methods
+        .declareNoContest()
+        .accounts({
+          race,
+          config: configPda,
+          player1: player1.publicKey,
+          player2FeePayer: player2.publicKey,
+          authority: provider.wallet.publicKey,
+        } as any)
+        .rpc();
+
+    after(() => setNoContest(false));
+
+    it("Refunds both players in full when neither submits", async () => {
+      const race = await createMatchedRace("no_contest", { submissionWindowSecs: new anchor.BN(2) });
+
+      try {
+        await declare(race);
+        expect.fail("Expected NoContestDisabled error");
+      } catch (err: any) {
+        expect(err.message).to.include("NoContestDisabled");
+      }
+      await setNoContest(true);
+      try {
+        await declare(race);
+        expect.fail("Expected NotNoContest error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotNoContest");
+      }
+
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      const before = await Promise.all([player1, player2].map((p) => provider.connection.getBalance(p.publicKey)));
+      await declare(race);
+      const after = await Promise.all([player1, player2].map((p) => provider.connection.getBalance(p.publicKey)));
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ noContest: {} });
+      expect(state.winner).to.be.null;
+      expect(state.escrowAmount.toNumber()).to.equal(0);
+      expect(after[0] - before[0]).to.equal(entryFeeSol.toNumber());
+      expect(after[1] - before[1]).to.equal(entryFeeSol.toNumber());
+    });
+
+    it("Leaves a single no-show to the forfeit path", async () => {
+      await setNoContest(true);
+      const race = await createMatchedRace("no_contest_forfeit", { submissionWindowSecs: new anchor.BN(2) });
+      await submitDirect(race, player1, 40000, 10);
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+
+      try {
+        await declare(race);
+        expect.fail("Expected NotNoContest error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotNoContest");
+      }
+      await program.methods.settleRace().accounts({ race }).rpc();
+      expect((await program.account.race.fetch(race)).outcome).to.deep.equal({ forfeit: {} });
+    });
+  });
+
+  describe("consolation_vault", () => {
+    const [consolationVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("consolation_vault")],
+      program.programId
+    );
+    const settleWithConsolation = (race: PublicKey, loser: PublicKey) =>
+      program.methods
+        .settleRace()
+        .accounts({ race, consolationVault, loser } as any)
+        .rpc();
+
+    before(async () => {
+      await program.methods
+        .initConsolationVault()
+        .accounts({
+          consolationVault,
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      await program.methods
+        .setConsolation(new anchor.BN(1000), new anchor.BN(25000))
+        .accounts({ consolationVault, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    });
+
+    it("Still settles, paying nothing, while the vault is empty", async () => {
+      const race = await createMatchedRace("consolation_empty");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 12);
+
+      const before = await provider.connection.getBalance(player2.publicKey);
+      await settleWithConsolation(race, player2.publicKey);
+
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ settled: {} });
+      expect(await provider.connection.getBalance(player2.publicKey)).to.equal(before);
+    });
+
+    it("Pays the loser per coin and caps it per race", async () => {
+      await program.methods
+        .fundConsolationVault(new anchor.BN(100000))
+        .accounts({
+          consolationVault,
+          funder: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+
+      const race = await createMatchedRace("consolation");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 12);
+      let before = await provider.connection.getBalance(player2.publicKey);
+      await settleWithConsolation(race, player2.publicKey);
+      const state = await program.account.race.fetch(race);
+      expect(state.winner?.toString()).to.equal(player1.publicKey.toString());
+      // The prize is untouched
+      expect(state.escrowAmount.toNumber()).to.equal(entryFeeSol.toNumber() * 2);
+      expect((await provider.connection.getBalance(player2.publicKey)) - before).to.equal(12000);
+
+      const capped = await createMatchedRace("consolation_cap");
+      await submitDirect(capped, player1, 40000, 10);
+      await submitDirect(capped, player2, 50000, 40);
+      before = await provider.connection.getBalance(player2.publicKey);
+      await settleWithConsolation(capped, player2.publicKey);
+      expect((await provider.connection.getBalance(player2.publicKey)) - before).to.equal(25000);
+    });
+
+    it("Rejects a loser account that is not in the race", async () => {
+      const race = await createMatchedRace("consolation_wrong_loser");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 12);
+
+      try {
+        await settleWithConsolation(race, (await fundedKeypair()).publicKey);
+        expect.fail("Expected LoserMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("LoserMismatch");
+      }
+    });
+  });
+
+  describe("reassign_prize", () => {
+    const reassign = (race: PublicKey, reason: object) =>
+      program.methods
+        .reassignPrize(reason as any)
+        .accounts({ race, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    it("Awards an unclaimed prize to the runner-up when the winner is ineligible", async () => {
+      const race = await createMatchedRace("reassign");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      let state = await program.account.race.fetch(race);
+      expect(state.winner.toString()).to.equal(player1.publicKey.toString());
+      expect(state.settledAt.toNumber()).to.be.greaterThan(0);
+
+      await reassign(race, { banned: {} });
+
+      state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ settled: {} });
+      expect(state.winner.toString()).to.equal(player2.publicKey.toString());
+      expect(state.outcome).to.deep.equal({ reassigned: {} });
+      expect(state.player1Dq).to.equal(true);
+
+      // The ineligible player can no longer claim; the runner-up can
+      try {
+        await program.methods
+          .claimPrize()
+          .accounts({ race, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected NotWinner error");
+      } catch (err: any) {
+        expect(err.message).to.include("NotWinner");
+      }
+      await program.methods
+        .claimPrize()
+        .accounts({ race, authority: player2.publicKey, session: null, winnerWallet: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+      expect((await program.account.race.fetch(race)).status).to.deep.equal({ claimed: {} });
+    });
+
+    it("Moves the race from the ineligible winner's claim index to the runner-up's", async () => {
+      const indexOf = async (player: Keypair) => {
+        const [claimIndex] = PublicKey.findProgramAddressSync(
+          [Buffer.from("claim_index"), player.publicKey.toBuffer()],
+          program.programId
+        );
+        if ((await provider.connection.getAccountInfo(claimIndex)) === null) {
+          await program.methods
+            .initClaimIndex()
+            .accounts({ claimIndex, player: player.publicKey, systemProgram: SystemProgram.programId } as any)
+            .signers([player])
+            .rpc();
+        }
+        return claimIndex;
+      };
+      const oldClaimIndex = await indexOf(player1);
+      const newClaimIndex = await indexOf(player2);
+
+      const race = await createMatchedRace("reassign_index");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race, claimIndex: oldClaimIndex } as any).rpc();
+
+      // Passing the indexes the wrong way round names the wrong players
+      try {
+        await program.methods
+          .reassignPrize({ banned: {} } as any)
+          .accounts({
+            race,
+            authority: provider.wallet.publicKey,
+            oldClaimIndex: newClaimIndex,
+            newClaimIndex: oldClaimIndex,
+          } as any)
+          .rpc();
+        expect.fail("Expected ClaimIndexMismatch error");
+      } catch (err: any) {
+        expect(err.message).to.include("ClaimIndexMismatch");
+      }
+
+      await program.methods
+        .reassignPrize({ banned: {} } as any)
+        .accounts({ race, authority: provider.wallet.publicKey, oldClaimIndex, newClaimIndex } as any)
+        .rpc();
+
+      const oldIndex = await program.account.claimIndex.fetch(oldClaimIndex);
+      const newIndex = await program.account.claimIndex.fetch(newClaimIndex);
+      expect(oldIndex.races.some((r: PublicKey) => r.equals(race))).to.be.false;
+      expect(newIndex.races.some((r: PublicKey) => r.equals(race))).to.be.true;
+    });
+
+    it("Is rejected once the prize has been claimed", async () => {
+      const race = await createMatchedRace("reassign_claimed");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      await program.methods
+        .claimPrize()
+        .accounts({ race, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      try {
+        await reassign(race, { disqualified: {} });
+        expect.fail("Expected InvalidRaceStatus error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidRaceStatus");
+      }
+    });
+
+    it("Is rejected for a signer other than the race's authority", async () => {
+      const race = await createMatchedRace("reassign_unauth");
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      try {
+        await program.methods
+          .reassignPrize({ other: {} } as any)
+          .accounts({ race, authority: player2.publicKey } as any)
+          .signers([player2])
+          .rpc();
+        expect.fail("Expected WrongAuthority error");
+      } catch (err: any) {
+        expect(err.message).to.include("WrongAuthority");
+      }
+    });
+  });
+
+  describe("vesting", () => {
+    const claim = (race: PublicKey) =>
+      program.methods
+        .claimPrize()
+        .accounts({ race, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+    const setVesting = (race: PublicKey, seconds: number, slices: number) =>
+      program.methods
+        .setVesting(new anchor.BN(seconds), slices)
+        .accounts({ race, authority: provider.wallet.publicKey } as any)
+        .rpc();
+
+    async function waitingRace(suffix: string): Promise<PublicKey> {
+      const id = `race_${Date.now()}_${suffix}`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race, player1: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+      return race;
+    }
+
+    it("Releases the prize slice by slice as it vests", async () => {
+      const race = await waitingRace("vesting");
+      await setVesting(race, 6, 2);
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey } as any)
+        .signers([player2])
+        .rpc();
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race }).rpc();
+      const prize = (await program.account.race.fetch(race)).escrowAmount.toNumber();
+
+      try {
+        await claim(race);
+        expect.fail("Expected NothingVestedYet error");
+      } catch (err: any) {
+        expect(err.message).to.include("NothingVestedYet");
+      }
+
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      await claim(race);
+      let state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ settled: {} });
+      expect(state.claimedSoFar.toNumber()).to.equal(Math.floor(prize / 2));
+
+      await new Promise((resolve) => setTimeout(resolve, 4000));
+      await claim(race);
+      state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ claimed: {} });
+      expect(state.claimedSoFar.toNumber()).to.equal(prize);
+      expect(state.escrowAmount.toNumber()).to.equal(0);
+    });
+
+    it("Rejects a schedule with only one of duration and slices, or past the caps", async () => {
+      const race = await waitingRace("vesting_half");
+      for (const [seconds, slices] of [
+        [60, 0],
+        [31 * 86_400, 2],
+        [60, 31],
+      ]) {
+        try {
+          await setVesting(race, seconds, slices);
+          expect.fail("Expected InvalidVestingSchedule error");
+        } catch (err: any) {
+          expect(err.message).to.include("InvalidVestingSchedule");
+        }
+      }
+    });
+
+    it("Rejects a schedule once both players have staked", async () => {
+      const race = await createMatchedRace("vesting_active");
+      try {
+        await setVesting(race, 60, 2);
+        expect.fail("Expected InvalidRaceStatus error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidRaceStatus");
+      }
+    });
+  });
+
+  describe("emergency_refund_batch", () => {
+    const group = (race: PublicKey, feePayer: PublicKey) =>
+      [race, player1.publicKey, feePayer, player1.publicKey].map((pubkey, i) => ({
+        pubkey,
+        isSigner: false,
+        isWritable: i !== 2 || !feePayer.equals(PublicKey.default),
+      }));
+    const refundBatch = (accounts: any[]) =>
+      program.methods
+        .emergencyRefundBatch()
+        .accounts({ config: configPda, authority: provider.wallet.publicKey } as any)
+        .remainingAccounts(accounts);
+
+    it("Refunds and closes waiting, active and settled-unclaimed races, skipping claimed ones", async () => {
+      const id = `race_${Date.now()}_wind_down_waiting`;
+      const mint = Keypair.generate().publicKey;
+      const waiting = deriveRacePda(id, mint);
+      await program.methods
+        .createRace(id, mint, entryFeeSol)
+        .accounts({ race: waiting, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+
+      const active = await createMatchedRace("wind_down_active");
+
+      const settled = await createMatchedRace("wind_down_settled");
+      await submitDirect(settled, player1, 40000, 10);
+      await submitDirect(settled, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race: settled }).rpc();
+
+      const claimed = await createMatchedRace("wind_down_claimed");
+      await submitDirect(claimed, player1, 40000, 10);
+      await submitDirect(claimed, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race: claimed }).rpc();
+      await program.methods
+        .claimPrize()
+        .accounts({ race: claimed, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+        .signers([player1])
+        .rpc();
+
+      const accounts = [
+        ...group(waiting, PublicKey.default),
+        ...group(active, player2.publicKey),
+        ...group(settled, player2.publicKey),
+        ...group(claimed, player2.publicKey),
+      ];
+      const { raw } = await refundBatch(accounts).simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      const data = Buffer.from(line.split(" ").pop(), "base64");
+      expect(data.readUInt32LE(0)).to.equal(3);
+      expect(data.readUInt32LE(4)).to.equal(1);
+      expect(Number(data.readBigUInt64LE(8))).to.equal(entryFeeSol.toNumber() * 5);
+
+      // Each race account holds its rent plus the escrowed stakes
+      const balances = await Promise.all([waiting, active, settled].map((race) => provider.connection.getBalance(race)));
+      const held = balances.reduce((a, b) => a + b, 0);
+      const before1 = await provider.connection.getBalance(player1.publicKey);
+      const before2 = await provider.connection.getBalance(player2.publicKey);
+      await refundBatch(accounts).rpc();
+
+      // player2 gets their two stakes back, player1 everything else
+      expect((await provider.connection.getBalance(player2.publicKey)) - before2).to.equal(entryFeeSol.toNumber() * 2);
+      expect((await provider.connection.getBalance(player1.publicKey)) - before1).to.equal(
+        held - entryFeeSol.toNumber() * 2
+      );
+      for (const race of [waiting, active, settled]) {
+        expect(await provider.connection.getAccountInfo(race)).to.be.null;
+      }
+      expect((await program.account.race.fetch(claimed)).status).to.deep.equal({ claimed: {} });
+    });
+
+    it("Is rejected for anyone but the config authority", async () => {
+      const race = await createMatchedRace("wind_down_unauth");
+      const outsider = await fundedKeypair();
+      try {
+        await program.methods
+          .emergencyRefundBatch()
+          .accounts({ config: configPda, authority: outsider.publicKey } as any)
+          .remainingAccounts(group(race, player2.publicKey))
+          .signers([outsider])
+          .rpc();
+        expect.fail("Expected Unauthorized error");
+      } catch (err: any) {
+        expect(err.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("min_coins_to_win", () => {
+    // createMatchedRace, with the threshold fixed at creation
+    async function createMinCoinsRace(suffix: string): Promise<PublicKey> {
+      const id = `race_${Date.now()}_${suffix}`;
+      const mint = Keypair.generate().publicKey;
+      const race = deriveRacePda(id, mint);
+      await program.methods
+        .createRaceWithMinCoins(id, mint, entryFeeSol, raceConfig(), Array(32).fill(0), new anchor.BN(0), new anchor.BN(5))
+        .accounts({ race, player1: player1.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player1])
+        .rpc();
+      await program.methods
+        .joinRace()
+        .accounts({ race, player2: player2.publicKey, systemProgram: SystemProgram.programId })
+        .signers([player2])
+        .rpc();
+      return race;
+    }
+
+    it("Keeps a fast but coinless player from winning", async () => {
+      const race = await createMinCoinsRace("min_coins");
+      expect((await program.account.race.fetch(race)).minCoinsToWin.toNumber()).to.equal(5);
+      await submitDirect(race, player1, 40000, 0);
+      await submitDirect(race, player2, 50000, 5);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ settled: {} });
+      expect(state.winner.toString()).to.equal(player2.publicKey.toString());
+      expect(state.outcome).to.deep.equal({ belowMinCoins: {} });
+    });
+
+    it("Voids the race when neither player qualifies", async () => {
+      const race = await createMinCoinsRace("min_coins_none");
+      await submitDirect(race, player1, 40000, 1);
+      await submitDirect(race, player2, 50000, 4);
+      await program.methods.settleRace().accounts({ race }).rpc();
+
+      const state = await program.account.race.fetch(race);
+      expect(state.status).to.deep.equal({ voided: {} });
+      expect(state.winner).to.be.null;
+    });
+
+    it("Defaults to 0 on the other create instructions", async () => {
+      const race = await createMatchedRace("min_coins_default");
+      expect((await program.account.race.fetch(race)).minCoinsToWin.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("create_race return data", () => {
+    it("Returns the initialized race's address and bump", async () => {
+      const id = `race_${Date.now()}_returned_pda`;
+      const mint = Keypair.generate().publicKey;
+      const create = () =>
+        program.methods
+          .createRace(id, mint, entryFeeSol)
+          .accounts({ race: deriveRacePda(id, mint), player1: player1.publicKey, systemProgram: SystemProgram.programId })
+          .signers([player1]);
+
+      const { raw } = await create().simulate();
+      const line = raw.find((l: string) => l.startsWith(`Program return: ${program.programId}`));
+      const data = Buffer.from(line.split(" ").pop(), "base64");
+      const returned = new PublicKey(data.subarray(0, 32));
+      const bump = data.readUInt8(32);
+
+      await create().rpc();
+      const race = await program.account.race.fetch(returned);
+      expect(race.raceId).to.equal(id);
+      expect(race.bump).to.equal(bump);
+      expect(returned.toString()).to.equal(deriveRacePda(id, mint).toString());
+    });
+  });
+
+  describe("claim_delay_tiers", () => {
+    const [claimDelayTiers] = PublicKey.findProgramAddressSync(
+      [Buffer.from("claim_delay_tiers")],
+      program.programId
+    );
+    const setTiers = (base: number, tiers: { minEscrow: anchor.BN; delaySecs: anchor.BN }[]) =>
+      program.methods
+        .setClaimDelayTiers(new anchor.BN(base), tiers)
+        .accounts({ claimDelayTiers, config: configPda, authority: provider.wallet.publicKey } as any)
+        .rpc();
+    const settled = async (suffix: string, config?: Record<string, any>) => {
+      const race = await createMatchedRace(suffix, config);
+      await submitDirect(race, player1, 40000, 10);
+      await submitDirect(race, player2, 50000, 10);
+      await program.methods.settleRace().accounts({ race, claimDelayTiers } as any).rpc();
+      return race;
+    };
+
+    before(async () => {
+      await program.methods
+        .initClaimDelayTiers()
+        .accounts({
+          claimDelayTiers,
+          config: configPda,
+          authority: provider.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        } as any)
+        .rpc();
+      // Pots of three entry fees or more wait an hour
+      await setTiers(5, [{ minEscrow: entryFeeSol.mul(new anchor.BN(3)), delaySecs: new anchor.BN(3600) }]);
+    });
+    after(() => setTiers(0, []));
+
+    it("Gives a small pot the base delay and a large pot a longer one", async () => {
+      const small = await settled("claim_delay_small");
+      expect((await program.account.race.fetch(small)).claimDelaySecs.toNumber()).to.equal(5);
+
+      const large = await settled("claim_delay_large", { player2EntryFee: entryFeeSol.mul(new anchor.BN(2)) });
+      expect((await program.account.race.fetch(large)).claimDelaySecs.toNumber()).to.equal(3600);
+
+      try {
+        await program.methods
+          .claimPrize()
+          .accounts({ race: large, authority: player1.publicKey, session: null, winnerWallet: player1.publicKey } as any)
+          .signers([player1])
+          .rpc();
+        expect.fail("Expected ClaimDelayActive error");
+      } catch (err: any) {
+        expect(err.message).to.include("ClaimDelayActive");
+      }
+    });
+
+    it("Rejects negative delays", async () => {
+      try {
+        await setTiers(-1, []);
+        expect.fail("Expected InvalidClaimDelayTiers error");
+      } catch (err: any) {
+        expect(err.message).to.include("InvalidClaimDelayTiers");
+      }
+    });
+  });
 });